@@ -1,8 +1,88 @@
 use pkgspec::SpecStruct;
 use pkgspec_core::{Error, Manifest, Result};
+use std::fmt;
 use std::fs;
 use std::path::Path;
 
+/// An entry of the `%files` section with optional directives like `%attr`, `%config`, `%ghost`
+/// or `%verify`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RpmFile {
+    path: String,
+    attr: Option<String>,
+    config: Option<bool>,
+    ghost: bool,
+    verify: Option<String>,
+}
+
+impl RpmFile {
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the mode, user and group of the file like `%attr(0644, root, root)`. Use `-` to
+    /// leave a value unchanged.
+    pub fn attr(
+        mut self,
+        mode: impl AsRef<str>,
+        user: impl AsRef<str>,
+        group: impl AsRef<str>,
+    ) -> Self {
+        self.attr = Some(format!(
+            "{}, {}, {}",
+            mode.as_ref(),
+            user.as_ref(),
+            group.as_ref()
+        ));
+        self
+    }
+
+    /// Marks the file as a configuration file. If `noreplace` is true a modified file won't be
+    /// overwritten during an upgrade.
+    pub fn config(mut self, noreplace: bool) -> Self {
+        self.config = Some(noreplace);
+        self
+    }
+
+    /// Marks the file as a ghost file. It is owned by the package but not included in it.
+    pub fn ghost(mut self) -> Self {
+        self.ghost = true;
+        self
+    }
+
+    /// Sets the attributes checked by `rpm --verify`, for example `not md5 size mtime`.
+    pub fn verify(mut self, verify: impl Into<String>) -> Self {
+        self.verify = Some(verify.into());
+        self
+    }
+}
+
+impl fmt::Display for RpmFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(attr) = &self.attr {
+            write!(f, "%attr({}) ", attr)?;
+        }
+        match self.config {
+            Some(true) => write!(f, "%config(noreplace) ")?,
+            Some(false) => write!(f, "%config ")?,
+            None => {}
+        }
+        if self.ghost {
+            write!(f, "%ghost ")?;
+        }
+        if let Some(verify) = &self.verify {
+            write!(f, "%verify({}) ", verify)?;
+        }
+        write!(f, "\"{}\"", self.path)
+    }
+}
+
+/// Files with directives, the alias keeps the spec derive from generating a string builder.
+type RpmFiles = Vec<RpmFile>;
+
 #[derive(Clone, Debug, Default, PartialEq, Eq, SpecStruct)]
 pub struct RpmSpec {
     /// The base name of the package, which should match the SPEC filename.
@@ -83,6 +163,11 @@ pub struct RpmSpec {
     postun_script: Option<String>,
 
     files: Vec<String>,
+    /// Entries of the `%files` section written as they are, like `%config(noreplace) /etc/foo`.
+    raw_files: Vec<String>,
+    #[skip]
+    /// Entries of the `%files` section with directives like `%ghost` or `%verify`.
+    file_entries: RpmFiles,
     /// This identifies the file listed as documentation and it will be installed and labeled as such by RPM. This is
     /// often used not only for documentation about the software being packaged but also code examples and various items
     /// that should accompany documentation. In the event code examples are included, care should be taken to remove
@@ -219,6 +304,13 @@ impl Manifest for RpmSpec {
             spec.push_str(entry.as_str());
             spec.push_str("\"\n");
         }
+        for entry in &self.raw_files {
+            spec.push_str(entry.as_str());
+            spec.push('\n');
+        }
+        for entry in &self.file_entries {
+            writeln!(spec, "{}", entry)?;
+        }
         if_not_empty_entries!(file doc_files, "doc");
         if_not_empty_entries!(file license_files, "license");
         if_not_empty_entries!(file dir_files, "dir");
@@ -252,6 +344,15 @@ impl RpmSpecBuilder {
         self.inner.auto_req_prov = Some(false);
         self
     }
+
+    /// Adds entries with directives to the `%files` section.
+    pub fn add_file_entries<I>(mut self, entries: I) -> Self
+    where
+        I: IntoIterator<Item = RpmFile>,
+    {
+        self.inner.file_entries.extend(entries);
+        self
+    }
 }
 
 #[cfg(test)]
@@ -334,6 +435,8 @@ install -m 644 README /docs/README"#;
             preun_script: Some("echo 123".to_string()),
             postun_script: Some("true".to_string()),
             files: vec!["/bin/test.bin".to_string(), "/docs/README".to_string()],
+            raw_files: vec![],
+            file_entries: vec![],
             doc_files: vec!["README".to_string()],
             license_files: vec!["LICENSE".to_string()],
             dir_files: vec![],
@@ -427,4 +530,45 @@ true
         let got = spec.render().unwrap();
         assert_eq!(expect_rendered, got);
     }
+
+    #[test]
+    fn renders_file_directives() {
+        let spec = RpmSpec::builder()
+            .name("rpmspec")
+            .version("0.1.0")
+            .release("1")
+            .description("files with directives")
+            .add_files_entries(vec!["/bin/test.bin"])
+            .add_file_entries(vec![
+                RpmFile::new("/var/log/rpmspec.log").ghost(),
+                RpmFile::new("/var/lib/rpmspec/db").verify("not md5 size mtime"),
+                RpmFile::new("/etc/rpmspec.conf")
+                    .attr("0640", "root", "rpmspec")
+                    .config(true)
+                    .verify("not mtime"),
+                RpmFile::new("/etc/rpmspec.d")
+                    .attr("-", "root", "root")
+                    .config(false)
+                    .ghost(),
+            ])
+            .build();
+
+        let got = spec.render().unwrap();
+        let files = got
+            .split("\n%files\n")
+            .nth(1)
+            .unwrap()
+            .split("\n%changelog\n")
+            .next()
+            .unwrap();
+        assert_eq!(
+            files,
+            r#""/bin/test.bin"
+%ghost "/var/log/rpmspec.log"
+%verify(not md5 size mtime) "/var/lib/rpmspec/db"
+%attr(0640, root, rpmspec) %config(noreplace) %verify(not mtime) "/etc/rpmspec.conf"
+%attr(-, root, root) %config %ghost "/etc/rpmspec.d"
+"#
+        );
+    }
 }
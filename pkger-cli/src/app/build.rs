@@ -1,18 +1,38 @@
 use crate::app::{AppOutputConfig, Application};
-use crate::job::{JobCtx, JobResult};
+use crate::hooks;
+use crate::job::{summary, FailureKind, ImageFailures, JobCtx, JobResult, JsonSummary};
+use crate::metadata;
+use crate::notification;
 use crate::opts::BuildOpts;
-use pkger_core::build::{container::SESSION_LABEL_KEY, Context};
+use pkger_core::build::cache::{BuildCache, DEFAULT_CACHE_DIR};
+use pkger_core::build::container::{self, ContainerRemoval, SESSION_LABEL_KEY};
+use pkger_core::build::deps::ResolvedDeps;
+use pkger_core::build::package::clean::CleanOutput;
+use pkger_core::build::{output, remote, Context, ContextOpts, OutputLayout};
 use pkger_core::image::Image;
-use pkger_core::log::{self, debug, error, info, trace, warning, BoxedCollector};
-use pkger_core::recipe::{BuildTarget, ImageTarget, Recipe};
+use pkger_core::log::{self, debug, error, info, trace, warning, BoxedCollector, Level};
+use pkger_core::recipe::{BuildArch, BuildTarget, Env, ImageTarget, Recipe, RecipeTarget};
 use pkger_core::runtime::{self, RuntimeConnector};
+use pkger_core::ssh::SshConfig;
 use pkger_core::{err, ErrContext, Error, Result};
 
+use chrono::Utc;
 use futures::stream::FuturesUnordered;
-use std::collections::{HashMap, VecDeque};
+use notify::{RecursiveMode, Watcher};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime};
 use tokio::task;
 
+/// Time without further changes after which a watched recipe is rebuilt.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+/// How often the watcher checks whether ctrl-c was pressed while waiting for changes.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum BuildTask {
     Simple {
@@ -27,7 +47,167 @@ pub enum BuildTask {
     },
 }
 
+impl BuildTask {
+    pub fn recipe(&self) -> &Recipe {
+        match self {
+            BuildTask::Simple { recipe, .. } | BuildTask::Custom { recipe, .. } => recipe,
+        }
+    }
+
+    pub fn build_target(&self) -> BuildTarget {
+        match self {
+            BuildTask::Simple { target, .. } => *target,
+            BuildTask::Custom { target, .. } => target.build_target,
+        }
+    }
+
+    pub fn version(&self) -> &str {
+        match self {
+            BuildTask::Simple { version, .. } | BuildTask::Custom { version, .. } => version,
+        }
+    }
+}
+
+/// Returns variables available to post build hooks of a package built for the `target`.
+fn post_build_vars(
+    target: &RecipeTarget,
+    version: &str,
+    artifact: &Path,
+) -> HashMap<String, String> {
+    [
+        ("artifact", artifact.to_string_lossy().to_string()),
+        ("recipe", target.recipe().to_string()),
+        ("version", version.to_string()),
+        ("target", target.build_target().as_ref().to_string()),
+        ("image", target.image().to_string()),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_string(), v))
+    .collect()
+}
+
+/// Parses the architectures of an `--arch` build matrix, only architectures that have a container
+/// platform can be built for.
+fn parse_arches(arches: &[String]) -> Result<Vec<BuildArch>> {
+    let mut parsed: Vec<BuildArch> = vec![];
+    for arch in arches
+        .iter()
+        .map(|arch| arch.trim())
+        .filter(|arch| !arch.is_empty())
+    {
+        let arch = BuildArch::from(arch);
+        if arch.platform().is_none() {
+            return err!(
+                "can't build for architecture `{}`, expected one of x86_64, x86, arm, armv6h, armv7h, aarch64",
+                arch
+            );
+        }
+        if !parsed.contains(&arch) {
+            parsed.push(arch);
+        }
+    }
+    Ok(parsed)
+}
+
+/// Returns the recipe and image target of the build for each of the `arches`, or the build
+/// itself if there are none. The image targets keep the name of the image and carry the
+/// architecture, images and packages of different architectures are kept apart by the
+/// [runtime name](ImageTarget::runtime_image) of the image.
+fn arch_variants(
+    recipe: Recipe,
+    target: ImageTarget,
+    arches: &[BuildArch],
+) -> Vec<(Recipe, ImageTarget)> {
+    if arches.is_empty() {
+        return vec![(recipe, target)];
+    }
+    arches
+        .iter()
+        .map(|arch| {
+            let mut recipe = recipe.clone();
+            recipe.metadata.arch = arch.clone();
+            let mut target = target.clone();
+            target.platform = arch.platform().map(str::to_string);
+            target.arch = Some(arch.clone());
+            (recipe, target)
+        })
+        .collect()
+}
+
+/// Returns directories of recipes built by `tasks` mapped to the names of the recipes.
+pub fn recipe_dirs(tasks: &[BuildTask]) -> HashMap<PathBuf, String> {
+    tasks
+        .iter()
+        .map(|task| {
+            let recipe = task.recipe();
+            let dir =
+                fs::canonicalize(&recipe.recipe_dir).unwrap_or_else(|_| recipe.recipe_dir.clone());
+            (dir, recipe.metadata.name.clone())
+        })
+        .collect()
+}
+
+/// Waits for changes of files in the watched `dirs` and returns names of the affected recipes.
+/// Changes are collected until there are none for `debounce` so that saving multiple files
+/// triggers a single rebuild. Returns early when `is_running` is unset by ctrl-c.
+fn wait_for_changes(
+    rx: &mpsc::Receiver<notify::Result<notify::Event>>,
+    dirs: &HashMap<PathBuf, String>,
+    debounce: Duration,
+    is_running: &AtomicBool,
+) -> HashSet<String> {
+    let mut changed = HashSet::new();
+    loop {
+        let timeout = if changed.is_empty() {
+            WATCH_POLL_INTERVAL
+        } else {
+            debounce
+        };
+        match rx.recv_timeout(timeout) {
+            Ok(Ok(event)) => {
+                if matches!(event.kind, notify::EventKind::Access(_)) {
+                    continue;
+                }
+                for path in &event.paths {
+                    changed.extend(
+                        dirs.iter()
+                            .filter(|(dir, _)| path.starts_with(dir))
+                            .map(|(_, name)| name.clone()),
+                    );
+                }
+            }
+            Ok(Err(e)) => warning!("file watcher error: {}", e),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if !changed.is_empty() || !is_running.load(Ordering::SeqCst) {
+                    return changed;
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => return changed,
+        }
+    }
+}
+
 impl Application {
+    /// Adds versions built from tags of the git repository of the recipe if it has a tag pattern.
+    fn expand_git_tags(&self, recipe: &mut Recipe, logger: &mut BoxedCollector) -> Result<()> {
+        let url = match &recipe.metadata.git {
+            Some(git) if git.tags().is_some() => git.url().to_string(),
+            _ => return Ok(()),
+        };
+        debug!(logger => "listing tags of git repository of recipe '{}', url = {}", recipe.metadata.name, url);
+        let tags = task::block_in_place(|| {
+            remote::list_remote_tags(&url, &self.proxy, self.config.ssh.as_ref())
+        })
+        .with_context(|| format!("failed to list tags of `{}`", url))?;
+        let versions = recipe.expand_git_tags(tags.iter().map(String::as_str));
+        if versions.is_empty() {
+            warning!(logger => "no tags of recipe '{}' match the tag pattern", recipe.metadata.name);
+        } else {
+            info!(logger => "building versions of recipe '{}' from git tags: {}", recipe.metadata.name, versions.join(", "));
+        }
+        Ok(())
+    }
+
     pub fn process_build_opts(
         &mut self,
         opts: BuildOpts,
@@ -43,17 +223,119 @@ impl Application {
             self.config.output_dir = output_dir;
         }
 
+        if opts.keep_on_failure {
+            debug!(logger => "keeping containers of failed builds");
+            self.config.container_removal = Some(ContainerRemoval::OnSuccess);
+        }
+
+        if let Some(policy) = opts.container_removal {
+            debug!(logger => "overriding container removal policy, policy = {}", policy.as_ref());
+            self.config.container_removal = Some(policy);
+        }
+
+        if self.config.container_removal() == ContainerRemoval::Never {
+            warning!(logger => "build containers are never removed, they will accumulate until removed manually");
+        }
+
+        if opts.build_cache {
+            debug!(logger => "using build cache");
+            self.config.build_cache = true;
+        }
+
+        if opts.forward_ssh_agent {
+            debug!(logger => "forwarding ssh agent to the build containers");
+            self.config
+                .ssh
+                .get_or_insert_with(SshConfig::default)
+                .forward_agent = true;
+        }
+
+        if !opts.notify.is_empty() {
+            debug!(logger => "notifying about results, notifiers = {:?}", opts.notify);
+            self.notifiers = opts.notify.clone();
+        }
+
+        if opts.read_only_rootfs {
+            debug!(logger => "using read-only root filesystem in build containers");
+            self.config.read_only_rootfs = true;
+        }
+
+        if let Some(timeout) = opts.image_build_timeout {
+            debug!(logger => "overriding image build timeout, timeout = {}s", timeout);
+            self.config.image_build_timeout = Some(timeout);
+        }
+
+        if opts.share_builds {
+            debug!(logger => "sharing builds between package formats");
+            self.config.share_builds = true;
+        }
+
+        if opts.job_logs {
+            debug!(logger => "writing the output of every job to a file of its own");
+            self.config.job_logs = true;
+        }
+
+        if opts.fail_fast_image {
+            debug!(logger => "skipping other images of recipes when an image fails to build");
+            self.config.fail_fast_image = true;
+        }
+
+        if opts.artifact_dir_per_recipe {
+            debug!(logger => "saving packages in a directory per recipe");
+            self.config.output_layout = OutputLayout::PerRecipe;
+        }
+
+        if opts.new_versions_only {
+            debug!(logger => "skipping already built versions");
+            self.config.new_versions_only = true;
+        }
+
+        if opts.no_check {
+            debug!(logger => "skipping check steps");
+            self.config.no_check = true;
+        }
+
+        if opts.verify_install {
+            debug!(logger => "installing built packages to verify their dependencies");
+            self.config.verify_install = true;
+        }
+
+        if opts.auto_release {
+            debug!(logger => "incrementing releases of existing packages");
+            self.config.auto_release = true;
+        }
+
+        if let Some(release) = &opts.release {
+            debug!(logger => "using release {} for all packages", release);
+            self.release = Some(release.clone());
+        }
+
+        if !opts.arch.is_empty() {
+            self.arches = parse_arches(&opts.arch)?;
+            debug!(logger => "building for architectures: {}", self.arches.iter().map(BuildArch::as_ref).collect::<Vec<_>>().join(", "));
+        }
+
+        if opts.clean_output {
+            debug!(logger => "removing artifacts of previous builds{}", if opts.dry_run { " (dry run)" } else { "" });
+            self.clean_output = Some(CleanOutput::new(
+                self.recipes.list().context("listing recipes")?,
+                SystemTime::now(),
+                opts.dry_run,
+            ));
+        }
+
         if opts.all {
             recipes_to_build = self
                 .recipes
                 .load_all(logger)
                 .context("loading recipes")?
                 .into_iter()
-                .map(|r| {
+                .map(|mut r| {
+                    self.expand_git_tags(&mut r, logger)?;
                     let versions = r.metadata.version.versions().to_vec();
-                    (r, versions)
+                    Ok((r, versions))
                 })
-                .collect();
+                .collect::<Result<_>>()?;
         } else if !opts.recipes.is_empty() {
             for recipe_name in opts.recipes {
                 if recipe_name.contains("==") {
@@ -70,7 +352,8 @@ impl Application {
                     }
                 } else {
                     trace!(logger => "loading recipe '{}'", recipe_name);
-                    let recipe = self.recipes.load(&recipe_name).context("loading recipe")?;
+                    let mut recipe = self.recipes.load(&recipe_name).context("loading recipe")?;
+                    self.expand_git_tags(&mut recipe, logger)?;
                     let versions_to_build = recipe.metadata.version.versions().to_vec();
                     recipes_to_build.push((recipe, versions_to_build));
                 }
@@ -82,6 +365,27 @@ impl Application {
             return Ok(tasks);
         }
 
+        if let Some(path) = &opts.env_from_file {
+            debug!(logger => "loading environment variables from `{}`", path.display());
+            let contents = fs::read_to_string(path)
+                .with_context(|| format!("failed to read env file `{}`", path.display()))?;
+            let env = Env::from_dotenv(&contents)
+                .with_context(|| format!("failed to parse env file `{}`", path.display()))?;
+            for (recipe, _) in &mut recipes_to_build {
+                recipe.env.extend(env.clone());
+            }
+        }
+
+        for (recipe, _) in &recipes_to_build {
+            if opts.fail_on_warnings {
+                recipe.deny_warnings()?;
+            } else {
+                for warning in recipe.warnings() {
+                    warning!(logger => "recipe '{}': {}", recipe.metadata.name, warning);
+                }
+            }
+        }
+
         macro_rules! add_task_if_target_found {
             ($target:ident, $recipe:ident, $self:ident, $tasks:ident, $version:ident) => {
                 if let Some(target) = $self
@@ -95,6 +399,8 @@ impl Application {
                         target: target.clone(),
                         version: $version.clone(),
                     });
+                } else if let Some(profile) = &$self.config.active_profile {
+                    warning!(logger => "image '{}' of recipe '{}' not found in configuration profile '{}', skipping", $target, $recipe.metadata.name, profile);
                 } else {
                     warning!(logger => "image '{}' not found in configuration", $target);
                 }
@@ -188,6 +494,31 @@ impl Application {
             }
         }
 
+        let mut checked = HashSet::new();
+        let mut checked_versions = HashSet::new();
+        for task in &tasks {
+            let (recipe, target) = (task.recipe(), task.build_target());
+            let mut warnings = vec![];
+            if checked.insert((recipe.metadata.name.as_str(), target)) {
+                warnings.extend(recipe.target_warnings(target));
+            }
+            // only the versions selected for the build are checked
+            if checked_versions.insert((recipe.metadata.name.as_str(), target, task.version())) {
+                warnings.extend(recipe.version_warnings(target, task.version()));
+            }
+            if opts.fail_on_warnings && !warnings.is_empty() {
+                return err!(
+                    "recipe `{}` has warnings for target {}:\n  {}",
+                    recipe.metadata.name,
+                    target.as_ref(),
+                    warnings.join("\n  ")
+                );
+            }
+            for warning in warnings {
+                warning!(logger => "recipe '{}': {}", recipe.metadata.name, warning);
+            }
+        }
+
         Ok(tasks)
     }
 
@@ -200,21 +531,78 @@ impl Application {
         debug!(logger => "processing tasks");
 
         let tasks = self.build_task_queue(tasks, logger)?;
+        let targets: HashMap<_, _> = tasks
+            .iter()
+            .map(|ctx| {
+                let recipe = ctx.recipe();
+                // incremented releases are recorded once the package is built
+                let release = (self.release.is_none()
+                    && (recipe.metadata.auto_release || self.config.auto_release))
+                    .then(|| recipe.metadata.release().to_string());
+                (
+                    ctx.id().to_string(),
+                    (ctx.targets(), ctx.build_version().to_string(), release),
+                )
+            })
+            .collect();
         let results = self.run_tasks(tasks, &output_config, logger).await?;
 
         let mut task_failed = false;
 
         // process results
         results.iter().for_each(|res| match res {
-                JobResult::Failure { id, duration, reason } => {
+                JobResult::Failure { id, duration, reason, kind, .. } => {
                     task_failed = true;
-                    error!(logger => "job {} failed, duration: {}s, reason: {}", id, duration.as_secs_f32(), reason);
+                    let log = res.log_file_note();
+                    match kind {
+                        FailureKind::ImageBuild => error!(logger => "job {} failed to build image, duration: {}s, reason: {}{}", id, duration.as_secs_f32(), reason, log),
+                        FailureKind::Skipped => warning!(logger => "job {} skipped, reason: {}", id, reason),
+                        _ => error!(logger => "job {} failed, duration: {}s, reason: {}{}", id, duration.as_secs_f32(), reason, log),
+                    }
                 }
-                JobResult::Success { id, duration, output: out } => {
-                    info!(logger => "job {} succeeded, duration: {}s, output: {}", id, duration.as_secs_f32(), out);
+                JobResult::Success { id, duration, output: out, artifacts, .. } => {
+                    info!(logger => "job {} succeeded, duration: {}s, output: {}{}", id, duration.as_secs_f32(), out, res.log_file_note());
+                    if let Some((targets, version, release)) = targets.get(id) {
+                        for target in targets {
+                            self.built_versions.mark_built(target, version);
+                        }
+                        if let (Some(release), Some(target)) = (release, targets.first()) {
+                            self.releases.record(target.recipe(), version, release);
+                        }
+                        // artifacts are in the same order as the targets of the job
+                        for (target, artifact) in targets.iter().zip(artifacts) {
+                            let vars = post_build_vars(target, version, artifact);
+                            if let Err(e) = hooks::run_post_build(&self.config.post_build, &vars, logger) {
+                                task_failed = true;
+                                error!(logger => "post build hook of job {} failed, reason: {:?}", id, e);
+                            }
+                        }
+                    }
                 }
             });
 
+        info!(logger => "{}", summary(&results));
+        if output_config.summary_json {
+            println!(
+                "{}",
+                serde_json::to_string(&JsonSummary::new(&results))
+                    .context("failed to serialize summary")?
+            );
+        }
+        notification::notify(&self.notifiers, &results, logger).await;
+
+        if self.built_versions.has_changed() {
+            if let Err(e) = self.built_versions.save() {
+                error!(logger => "failed to save built versions, reason: {:?}", e);
+            }
+        }
+
+        if self.releases.has_changed() {
+            if let Err(e) = self.releases.save() {
+                error!(logger => "failed to save releases, reason: {:?}", e);
+            }
+        }
+
         // save image state
         if self.images_state.read().await.has_changed() {
             self.save_images_state(logger).await;
@@ -231,25 +619,225 @@ impl Application {
         }
     }
 
+    /// Rebuilds recipes from `recipe_dirs` whenever their files change until ctrl-c is pressed.
+    /// Recipes are reloaded from disk with the same build options before each rebuild.
+    pub async fn watch(
+        &mut self,
+        opts: BuildOpts,
+        recipe_dirs: HashMap<PathBuf, String>,
+        output_config: AppOutputConfig,
+        logger: &mut BoxedCollector,
+    ) -> Result<()> {
+        if recipe_dirs.is_empty() {
+            warning!(logger => "no recipes to watch");
+            return Ok(());
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(tx).context("failed to initialize file watcher")?;
+        for dir in recipe_dirs.keys() {
+            watcher
+                .watch(dir, RecursiveMode::Recursive)
+                .with_context(|| format!("failed to watch `{}`", dir.display()))?;
+        }
+        info!(logger => "watching {} recipe directories for changes, press ctrl-c to stop", recipe_dirs.len());
+
+        while self.is_running.load(Ordering::SeqCst) {
+            let changed = task::block_in_place(|| {
+                wait_for_changes(&rx, &recipe_dirs, WATCH_DEBOUNCE, &self.is_running)
+            });
+            if changed.is_empty() {
+                continue;
+            }
+            let mut changed: Vec<_> = changed.into_iter().collect();
+            changed.sort();
+            info!(logger => "recipes changed, rebuilding: {}", changed.join(", "));
+
+            let tasks = match self.process_build_opts(opts.clone(), logger) {
+                Ok(tasks) => tasks
+                    .into_iter()
+                    .filter(|task| changed.contains(&task.recipe().metadata.name))
+                    .collect(),
+                Err(e) => {
+                    error!(logger => "failed to reload recipes, reason: {:?}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = self
+                .process_tasks(tasks, output_config.clone(), logger)
+                .await
+            {
+                error!(logger => "{:?}", e);
+            }
+        }
+
+        info!(logger => "stopped watching recipes");
+        Ok(())
+    }
+
+    /// Prints the environment of the container of each task without running the builds.
+    pub async fn print_env(
+        &mut self,
+        tasks: Vec<BuildTask>,
+        logger: &mut BoxedCollector,
+    ) -> Result<()> {
+        debug!(logger => "printing environment of tasks");
+        let tasks = self.build_task_queue(tasks, logger)?;
+        for ctx in tasks {
+            let os = ctx.image_os().await;
+            if os.is_none() {
+                warning!(logger => "image '{}' was not built yet, the OS of job {} is unknown", ctx.target().image(), ctx.id());
+            }
+            println!("{}", ctx.id());
+            for entry in container::environment(&ctx, os.as_ref())
+                .masked()
+                .sorted_kv_vec()
+            {
+                println!("  {}", entry);
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates the output collector of the job `task`. Returns the path of the log file of the
+    /// job when every job logs to a file of its own.
     fn collector_for_task(
         &self,
-        id: &str,
+        task: &Context,
         output_config: &AppOutputConfig,
-    ) -> Result<BoxedCollector> {
-        let mut collector = if let Some(p) = &output_config.log_dir {
-            log::Config::file(p.join(format!("{}.log", id)))
-        } else if let Some(p) = &self.config.log_dir {
-            log::Config::file(p.join(format!("{}.log", id)))
+    ) -> Result<(BoxedCollector, Option<PathBuf>)> {
+        let log_dir = output_config
+            .log_dir
+            .as_ref()
+            .or(self.config.log_dir.as_ref());
+        let mut log_file = None;
+        let mut config = if let Some(p) = log_dir {
+            let path = if self.config.job_logs {
+                let target = task.target();
+                let path = p.join(format!(
+                    "{}-{}-{}-{}-{}.log",
+                    target.recipe(),
+                    task.build_version(),
+                    target.image(),
+                    target.build_target().as_ref(),
+                    Utc::now().format("%Y%m%d%H%M%S")
+                ));
+                log_file = Some(path.clone());
+                path
+            } else {
+                p.join(format!("{}.log", task.id()))
+            };
+            log::Config::file(path)
         } else {
-            log::Config::stdout()
+            output_config.terminal()
         }
         .no_color(output_config.no_color)
-        .as_collector()
-        .context("initializing output collector")?;
+        .format(output_config.format);
+        if let Some(path) = &output_config.json_logs_to {
+            config = config.json_file(path);
+        }
+        let mut collector = config
+            .as_collector()
+            .context("initializing output collector")?;
+
+        if log_file.is_some() {
+            // stream the progress of the job to the terminal next to its log file
+            let summary = output_config
+                .terminal()
+                .no_color(output_config.no_color)
+                .format(output_config.format)
+                .as_collector()
+                .context("initializing output collector")?;
+            collector = Box::new(log::ForkedCollector::new(
+                task.id(),
+                collector,
+                summary,
+                Level::Info,
+            ));
+        }
 
         collector.set_level(output_config.level);
 
-        Ok(collector)
+        Ok((collector, log_file))
+    }
+
+    /// Sets the release of the recipe to follow the last recorded release of the version and
+    /// the highest release of packages with the same version already built for the `target`
+    /// image. Packages are looked up in every directory of the output layout that can hold
+    /// packages of the recipe built on the image.
+    fn auto_release(
+        &self,
+        recipe: &mut Recipe,
+        target: &ImageTarget,
+        version: &str,
+        logger: &mut BoxedCollector,
+    ) -> Result<()> {
+        let image = target.runtime_image();
+        let packages: Vec<_> =
+            output::find_output_dirs(&self.config.output_dir, self.config.output_layout)
+                .iter()
+                .filter(|dir| dir.holds(&recipe.metadata.name, &image, target.build_target))
+                .flat_map(|dir| metadata::read_packages(dir.path()))
+                .collect();
+        let packages = packages
+            .iter()
+            .filter(|package| package.package_type() == target.build_target);
+        let built = metadata::latest_release(packages, &recipe.metadata.name, version);
+        let release = self.releases.next_release(
+            &recipe.metadata.name,
+            version,
+            recipe.metadata.release(),
+            built,
+        )?;
+        if release != recipe.metadata.release() {
+            info!(logger => "incrementing release of recipe '{}' {} for image '{}' to {}", recipe.metadata.name, version, target.image, release);
+        }
+        recipe.metadata.release = Some(release);
+        Ok(())
+    }
+
+    /// Returns the options shared by all build contexts of this run.
+    fn context_opts(&self) -> ContextOpts {
+        ContextOpts {
+            ssh: self.config.ssh.clone(),
+            proxy: self.proxy.clone(),
+            container_removal: self.config.container_removal(),
+            limits: self.config.limits.clone().unwrap_or_default(),
+            network: self.config.network.clone(),
+            registry_auth: self.config.registry_auth.clone(),
+            platform: self.config.platform.clone(),
+            build_cache: if self.config.build_cache {
+                Some(BuildCache::new(build_cache_dir()))
+            } else {
+                None
+            },
+            allow_privileged: self.config.allow_privileged,
+            checksums: self.config.checksums.clone(),
+            image_build_timeout: self.config.image_build_timeout.map(Duration::from_secs),
+            artifact_permissions: self.config.artifact_permissions.clone(),
+            output_layout: self.config.output_layout,
+            read_only_rootfs: if self.config.read_only_rootfs {
+                Some(self.config.writable_paths.clone())
+            } else {
+                None
+            },
+            retry: self.config.retry_policy(),
+            dns: self.config.dns.clone(),
+            extra_hosts: self.config.extra_hosts.clone(),
+            clean_output: self.clean_output.clone(),
+            // every run resolves the dependencies again, recipes may change between runs of
+            // `--watch`
+            resolved_deps: ResolvedDeps::default(),
+            verify_install: self.config.verify_install,
+            ..ContextOpts::new(
+                self.session_id,
+                self.runtime.connect(),
+                &self.config.output_dir,
+                &self.config.recipes_dir,
+                self.images_state.clone(),
+            )
+        }
     }
 
     /// Build a final queue of build tasks
@@ -260,6 +848,7 @@ impl Application {
     ) -> Result<VecDeque<Context>> {
         debug!(logger => "building task queue");
         let mut taskmap: HashMap<String, VecDeque<Context>> = HashMap::new();
+        let opts = self.context_opts();
 
         // first a map of tasks for each image is built
         for task in tasks {
@@ -287,6 +876,7 @@ impl Application {
                             .custom_simple_images
                             .as_ref()
                             .and_then(|c| c.name_for_target(target)),
+                        self.config.simple_image_extra_dockerfile.as_deref(),
                     )?;
                     let name = image.name.clone();
                     (
@@ -299,30 +889,63 @@ impl Application {
                 }
             };
 
-            let image_name = image.name.clone();
+            for (recipe, target) in arch_variants(recipe, target, &self.arches) {
+                let image_name = target.runtime_image();
 
-            let ctx = Context::new(
-                &self.session_id,
-                recipe,
-                image,
-                self.runtime.connect(),
-                target,
-                self.config.output_dir.as_path(),
-                self.config.recipes_dir.as_path(),
-                self.images_state.clone(),
-                is_simple,
-                self.gpg_key.clone(),
-                self.config.ssh.clone(),
-                self.proxy.clone(),
-                version,
-            );
-            let id = ctx.id().to_string();
-            info!(logger => "adding job {}", id);
+                if self.config.new_versions_only {
+                    let recipe_target =
+                        RecipeTarget::new(recipe.metadata.name.clone(), target.clone());
+                    if self.built_versions.is_built(&recipe_target, &version) {
+                        info!(logger => "skipping recipe '{}' {} for image '{}', this version was already built", recipe.metadata.name, version, image_name);
+                        continue;
+                    }
+                }
 
-            if let Some(tasks) = taskmap.get_mut(&image_name) {
-                tasks.push_back(ctx);
-            } else {
-                taskmap.insert(image_name, VecDeque::from([ctx]));
+                let mut recipe = recipe;
+                if self.config.no_check {
+                    recipe.check_script = None;
+                }
+                if let Some(release) = &self.release {
+                    recipe.metadata.release = Some(release.clone());
+                } else if recipe.metadata.auto_release || self.config.auto_release {
+                    self.auto_release(&mut recipe, &target, &version, logger)?;
+                }
+
+                let gpg_key = self
+                    .gpg_keys
+                    .key_for(&image.name, target.build_target)
+                    .cloned();
+
+                if self.config.share_builds {
+                    let shared = taskmap.get_mut(&image_name).and_then(|tasks| {
+                        tasks.iter_mut().find(|ctx| {
+                            ctx.can_share_build(&recipe, &target, &version, gpg_key.as_ref())
+                        })
+                    });
+                    if let Some(shared) = shared {
+                        info!(logger => "packaging recipe '{}' {} for target {} from job {}", recipe.metadata.name, version, target.build_target.as_ref(), shared.id());
+                        shared.add_package_target(target);
+                        continue;
+                    }
+                }
+
+                let ctx = Context::new(
+                    recipe,
+                    image.clone(),
+                    target,
+                    version.clone(),
+                    gpg_key,
+                    is_simple,
+                    opts.clone(),
+                );
+                let id = ctx.id().to_string();
+                info!(logger => "adding job {}", id);
+
+                if let Some(tasks) = taskmap.get_mut(&image_name) {
+                    tasks.push_back(ctx);
+                } else {
+                    taskmap.insert(image_name, VecDeque::from([ctx]));
+                }
             }
         }
 
@@ -387,16 +1010,33 @@ impl Application {
         let mut proccessed_jobs = 0;
         debug!(logger => "cpus: {} (max jobs at once), total jobs to process: {}", max_jobs, total_jobs);
         let start = std::time::SystemTime::now();
+        let mut image_failures = ImageFailures::new(self.config.fail_fast_image);
+        let mut job_recipes = HashMap::new();
 
         while proccessed_jobs <= total_jobs {
             while running_jobs < max_jobs {
                 if let Some(task) = tasks.pop_front() {
-                    let collector = self.collector_for_task(task.id(), output_config)?;
+                    let recipe = task.target().recipe().to_string();
+                    if let Some(failed) = image_failures.failed_image(&recipe) {
+                        proccessed_jobs += 1;
+                        results.push(JobResult::failure(
+                            task.id(),
+                            Duration::ZERO,
+                            format!("image '{}' of recipe '{}' failed to build and --fail-fast-image is set", failed, recipe),
+                            FailureKind::Skipped,
+                        ));
+                        continue;
+                    }
+                    job_recipes.insert(
+                        task.id().to_owned(),
+                        (recipe, task.target().image().to_string()),
+                    );
+                    let (collector, log_file) = self.collector_for_task(&task, output_config)?;
 
                     info!(logger => "starting job {}/{}, id: {}", proccessed_jobs+1, total_jobs, task.id());
                     jobs.push((
                         task.id().to_owned(),
-                        task::spawn(JobCtx::Build(task).run(collector)),
+                        task::spawn(JobCtx::Build(task).run(collector, log_file)),
                         false,
                     ));
                     running_jobs += 1;
@@ -422,15 +1062,20 @@ impl Application {
                             error!(logger => "failed to join task handle, reason: {:?}", e);
                             continue;
                         }
-                        results.push(res.unwrap());
+                        let res = res.unwrap();
+                        if let Some((recipe, image)) = job_recipes.get(res.id()) {
+                            image_failures.record(recipe, image, &res);
+                        }
+                        results.push(res);
                     }
                     _ = self.is_running() => {
                         results.push(
-                            JobResult::Failure {
-                                id: id.clone(),
-                                duration: start.elapsed().unwrap_or_default(),
-                                reason: "job cancelled by ctrl-c signal".to_string()
-                            }
+                            JobResult::failure(
+                                id.clone(),
+                                start.elapsed().unwrap_or_default(),
+                                "job cancelled by ctrl-c signal",
+                                FailureKind::Cancelled,
+                            )
                         );
                         should_break = true;
                     }
@@ -484,3 +1129,87 @@ impl Application {
         }
     }
 }
+
+fn build_cache_dir() -> PathBuf {
+    match dirs::cache_dir() {
+        Some(dir) => dir.join(DEFAULT_CACHE_DIR),
+        None => PathBuf::from(DEFAULT_CACHE_DIR),
+    }
+}
+
+/// Directory with the logs of jobs when logs per job are enabled without a log directory.
+static DEFAULT_JOB_LOG_DIR: &str = ".pkger.logs";
+
+pub fn default_job_log_dir() -> PathBuf {
+    match dirs::data_local_dir() {
+        Some(dir) => dir.join(DEFAULT_JOB_LOG_DIR),
+        None => PathBuf::from(DEFAULT_JOB_LOG_DIR),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pkger_core::recipe::RecipeRep;
+
+    #[test]
+    fn expands_build_for_each_arch() {
+        assert_eq!(
+            parse_arches(&["x86_64".into(), "arm64".into(), "aarch64".into()]).unwrap(),
+            vec![BuildArch::x86_64, BuildArch::Arm64]
+        );
+        assert!(parse_arches(&["all".into()]).is_err());
+        assert!(parse_arches(&["x86_64".into(), "sparc".into()]).is_err());
+
+        let rep = RecipeRep::from_yaml_bytes(
+            br#"
+metadata:
+  name: test-arch
+  version: "1.0.0"
+  description: a recipe built for several architectures
+  license: MIT
+  arch: x86_64
+build:
+  steps: []"#,
+        )
+        .unwrap();
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        let target = ImageTarget::new("rocky", BuildTarget::Rpm, None);
+
+        let single = arch_variants(recipe.clone(), target.clone(), &[]);
+        assert_eq!(single, vec![(recipe.clone(), target.clone())]);
+
+        let variants = arch_variants(recipe, target, &[BuildArch::x86_64, BuildArch::Arm64]);
+        let summary = variants
+            .iter()
+            .map(|(recipe, target)| {
+                (
+                    recipe.metadata.arch.clone(),
+                    target.image.as_str(),
+                    target.runtime_image(),
+                    target.platform.as_deref(),
+                    target.arch.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    BuildArch::x86_64,
+                    "rocky",
+                    "rocky-x86_64".to_string(),
+                    Some("linux/amd64"),
+                    Some(BuildArch::x86_64)
+                ),
+                (
+                    BuildArch::Arm64,
+                    "rocky",
+                    "rocky-aarch64".to_string(),
+                    Some("linux/arm64"),
+                    Some(BuildArch::Arm64)
+                ),
+            ]
+        );
+    }
+}
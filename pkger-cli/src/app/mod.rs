@@ -3,23 +3,36 @@ mod build;
 use crate::completions;
 use crate::config::Configuration;
 use crate::gen;
-use crate::metadata::{self, PackageMetadata};
+use crate::metadata::PackageMetadata;
+use crate::notification::Notifier;
 use crate::opts::{
-    CheckObject, Command, CopyObject, EditObject, ListObject, NewObject, Opts, RemoveObject,
+    Age, CheckObject, CleanObject, Command, CopyObject, EditObject, ListFormat, ListObject,
+    NewObject, Opts, RemoveObject, RepoObject,
 };
 use crate::table::{Cell, IntoCell, IntoTable};
-use pkger_core::gpg::GpgKey;
+use pkger_core::build::image;
+use pkger_core::build::output::{self, OutputDir};
+use pkger_core::build::package::clean::CleanOutput;
+use pkger_core::build::package::{checksums, link, pkg};
+use pkger_core::build::releases::{Releases, DEFAULT_RELEASES_FILE};
+use pkger_core::build::versions::{BuiltVersions, DEFAULT_BUILT_VERSIONS_FILE};
+use pkger_core::checksum::Checksum;
+use pkger_core::gpg::{GpgKey, GpgKeys};
 use pkger_core::image::Image;
 use pkger_core::image::{state::DEFAULT_STATE_FILE, ImagesState};
-use pkger_core::log::{error, info, trace, warning, BoxedCollector, Level};
+use pkger_core::log::{self, debug, error, info, trace, warning, BoxedCollector, Level};
 use pkger_core::proxy::ProxyConfig;
 use pkger_core::recipe;
+use pkger_core::repo;
 use pkger_core::runtime::{self, ConnectionPool};
 use pkger_core::{ErrContext, Error, Result};
+use pkgspec_core::Manifest;
 
 use async_rwlock::RwLock;
 use chrono::{offset::TimeZone, SecondsFormat, Utc};
 use colored::Color;
+use serde::Serialize;
+use std::collections::HashMap;
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -62,18 +75,41 @@ fn open_editor<P: AsRef<Path>>(path: P) -> Result<ExitStatus> {
     cmd.wait().context("failed to wait for child process")
 }
 
-fn load_gpg_key(config: &Configuration) -> Result<Option<GpgKey>> {
-    if let Some(key) = &config.gpg_key {
-        let pass = rpassword::read_password_from_tty(Some("Gpg key password:"))
-            .context("failed to read password for gpg key")?;
+fn load_gpg_keys(config: &Configuration) -> Result<GpgKeys> {
+    // the password is only asked once for every distinct key file
+    let mut passwords: HashMap<PathBuf, String> = HashMap::new();
+    let mut read_password = |path: &Path| -> Result<String> {
+        if let Some(pass) = passwords.get(path) {
+            return Ok(pass.clone());
+        }
+        let pass = rpassword::read_password_from_tty(Some(&format!(
+            "Gpg key password for `{}`:",
+            path.display()
+        )))
+        .context("failed to read password for gpg key")?;
+        passwords.insert(path.to_path_buf(), pass.clone());
+        Ok(pass)
+    };
+
+    let default = if let Some(key) = &config.gpg_key {
         if let Some(name) = &config.gpg_name {
-            Ok(Some(GpgKey::new(key, name, &pass)?))
+            Some(GpgKey::new(key, name, &read_password(key)?)?)
         } else {
-            err!("missing `gpg_name` field from configuration")
+            return err!("missing `gpg_name` field from configuration");
         }
     } else {
-        Ok(None)
+        None
+    };
+
+    let mut keys = GpgKeys::new(default);
+    for (image_or_target, key) in &config.gpg_keys {
+        keys.add_key(
+            image_or_target,
+            GpgKey::new(&key.key, &key.name, &read_password(&key.key)?)?,
+        );
     }
+
+    Ok(keys)
 }
 
 fn system_time_to_date_time(t: time::SystemTime) -> chrono::DateTime<Utc> {
@@ -92,7 +128,202 @@ fn system_time_to_date_time(t: time::SystemTime) -> chrono::DateTime<Utc> {
     Utc.timestamp(sec, nsec)
 }
 
-async fn init_runtime(
+/// Renders the manifest of the `target`, like the RPM spec or the DEB control file, using
+/// placeholders for values that are only known during a build.
+fn render_manifest(
+    recipe: &recipe::Recipe,
+    target: recipe::BuildTarget,
+    image: &str,
+    version: &str,
+    logger: &mut BoxedCollector,
+) -> Result<String> {
+    let sources = vec![format!("{}-{}.tar.gz", recipe.metadata.name, version)];
+    let files = vec!["/<files of the package>".to_string()];
+    let checksums = vec!["SKIP".to_string()];
+    let builddir = PathBuf::from(format!("/tmp/{}-build", recipe.metadata.name));
+
+    match target {
+        recipe::BuildTarget::Rpm => recipe
+            .as_rpm_spec(&sources, &files, image, version, target, logger)
+            .render(),
+        recipe::BuildTarget::Deb => recipe
+            .as_deb_control(image, None, version, target, logger)
+            .render(),
+        recipe::BuildTarget::Pkg => recipe
+            .as_pkgbuild(
+                image,
+                &sources,
+                pkg::SOURCE_CHECKSUM,
+                &checksums,
+                version,
+                target,
+                logger,
+            )
+            .render(),
+        recipe::BuildTarget::Apk => recipe
+            .as_apkbuild(image, &sources, &builddir, version, target, logger)
+            .render(),
+        recipe::BuildTarget::Gzip => {
+            return err!("gzip packages have no manifest to inspect");
+        }
+    }
+    .context("failed to render manifest")
+}
+
+/// Returns the package formats that the `recipe` is built as, either by configured `images`
+/// the recipe uses or by a section of the format in the recipe.
+fn recipe_targets(
+    recipe: &recipe::Recipe,
+    images: &[recipe::ImageTarget],
+) -> Vec<recipe::BuildTarget> {
+    use recipe::BuildTarget;
+
+    let mut targets = vec![];
+    let metadata = &recipe.metadata;
+    let sections = [
+        (BuildTarget::Rpm, metadata.rpm.is_some()),
+        (BuildTarget::Deb, metadata.deb.is_some()),
+        (BuildTarget::Pkg, metadata.pkg.is_some()),
+        (BuildTarget::Apk, metadata.apk.is_some()),
+    ];
+    for (target, has_section) in sections {
+        if has_section || !recipe_images(recipe, images, target).is_empty() {
+            targets.push(target);
+        }
+    }
+    targets
+}
+
+/// Returns the names of the configured `images` that the recipe is built on for the `target`.
+fn recipe_images<'i>(
+    recipe: &recipe::Recipe,
+    images: &'i [recipe::ImageTarget],
+    target: recipe::BuildTarget,
+) -> Vec<&'i str> {
+    images
+        .iter()
+        .filter(|image| {
+            image.build_target == target
+                && (recipe.metadata.all_images || recipe.images().contains(&image.image))
+        })
+        .map(|image| image.image.as_str())
+        .collect()
+}
+
+/// Returns the packages in the output directory `image` of the image named `image_name`,
+/// sorted by file name.
+fn image_packages(image: &Path, image_name: &str) -> std::io::Result<Vec<fs::DirEntry>> {
+    let mut packages: Vec<_> = fs::read_dir(image)?
+        .filter(|p| match p {
+            Ok(p) => link::package_target(&p.file_name().to_string_lossy()).is_some(),
+            Err(e) => {
+                error!(
+                    "failed to list package for image {}, reason {:?}",
+                    image_name, e
+                );
+                false
+            }
+        })
+        .map(|p| p.unwrap())
+        .collect();
+    packages.sort_unstable_by_key(|p| p.file_name());
+    Ok(packages)
+}
+
+/// Loads the recipes with `names` or all recipes if `names` is empty and reports their errors and
+/// warnings. Fails if any of the recipes is invalid.
+pub(crate) fn check_recipes(
+    recipes: &recipe::Loader,
+    images: &[recipe::ImageTarget],
+    mut names: Vec<String>,
+    fail_on_warnings: bool,
+    logger: &mut BoxedCollector,
+) -> Result<()> {
+    if names.is_empty() {
+        names = recipes.list()?;
+    }
+    names.sort_unstable();
+
+    let mut failed = 0;
+    for name in &names {
+        match recipes.load(name) {
+            Ok(recipe) => {
+                let mut warnings = recipe.warnings();
+                // only check targets the recipe is built for with configured images or ones it
+                // has a section of its own for
+                let targets = recipe_targets(&recipe, images);
+                if targets.contains(&recipe::BuildTarget::Deb) {
+                    warnings.extend(recipe.target_warnings(recipe::BuildTarget::Deb));
+                }
+                for warning in &warnings {
+                    warning!(logger => "recipe '{}': {}", name, warning);
+                }
+
+                let mut errors = vec![];
+                for target in targets {
+                    // targets enabled only by a section of the recipe are checked on the simple
+                    // image of the target
+                    let mut target_images = recipe_images(&recipe, images, target);
+                    if target_images.is_empty() {
+                        target_images.push(Image::simple(target).name);
+                    }
+                    for version in recipe.metadata.version.versions() {
+                        errors.extend(recipe.manifest_problems(target, version));
+                        for image in &target_images {
+                            if let Err(e) = render_manifest(&recipe, target, image, version, logger)
+                            {
+                                errors.push(format!(
+                                    "{} manifest of version {} on image {}: {:?}",
+                                    target.as_ref().to_uppercase(),
+                                    version,
+                                    image,
+                                    e
+                                ));
+                            }
+                            errors.extend(recipe.manifest_step_problems(image, target, version));
+                        }
+                    }
+                }
+                for error in &errors {
+                    error!(logger => "recipe '{}': {}", name, error);
+                }
+
+                if !errors.is_empty() || fail_on_warnings && !warnings.is_empty() {
+                    failed += 1;
+                } else {
+                    info!(logger => "recipe '{}' OK.", name);
+                }
+            }
+            Err(e) => {
+                error!(logger => "recipe '{}' is invalid, reason: {:?}", name, e);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return err!("{} out of {} recipes failed the check", failed, names.len());
+    }
+    Ok(())
+}
+
+/// Reports dangling `from` references and inheritance cycles of all recipes.
+fn check_inheritance(recipes: &recipe::Loader, logger: &mut BoxedCollector) -> Result<()> {
+    let problems = recipes
+        .inheritance_problems()
+        .context("failed to check recipe inheritance")?;
+    for problem in &problems {
+        error!(logger => "{}", problem);
+    }
+
+    if !problems.is_empty() {
+        return err!("found {} problems with recipe inheritance", problems.len());
+    }
+    info!(logger => "all `from` references resolve");
+    Ok(())
+}
+
+pub(crate) async fn init_runtime(
     opts: &Opts,
     config: &Configuration,
     logger: &mut BoxedCollector,
@@ -116,20 +347,32 @@ async fn init_runtime(
                 trace!(logger => "checking default paths `{PODMAN_SOCK}`, `{DOCKER_SOCK}`, `{DOCKER_SOCK_SECONDARY}`");
 
                 #[cfg(unix)]
-                let uri = if PathBuf::from(PODMAN_SOCK.trim_start_matches("unix://")).exists() {
-                    PODMAN_SOCK
-                } else if PathBuf::from(DOCKER_SOCK.trim_start_matches("unix://")).exists() {
-                    DOCKER_SOCK
-                } else {
-                    DOCKER_SOCK_SECONDARY
+                let uri = {
+                    let mut candidates = vec![];
+                    // rootless podman listens in the runtime directory of the user
+                    match env::var_os("XDG_RUNTIME_DIR") {
+                        Some(dir) => {
+                            let sock = runtime::podman::rootless_sock(Path::new(&dir));
+                            trace!(logger => "checking rootless podman socket `{sock}`");
+                            candidates.push(sock);
+                        }
+                        None => {
+                            trace!(logger => "XDG_RUNTIME_DIR not set, skipping rootless podman socket")
+                        }
+                    }
+                    candidates.extend([PODMAN_SOCK, DOCKER_SOCK].map(str::to_string));
+                    candidates
+                        .into_iter()
+                        .find(|uri| PathBuf::from(uri.trim_start_matches("unix://")).exists())
+                        .unwrap_or_else(|| DOCKER_SOCK_SECONDARY.to_string())
                 };
 
                 #[cfg(not(unix))]
                 // TODO: handle a case for the default socket on non unix platforms
-                let uri = DOCKER_SOCK;
+                let uri = DOCKER_SOCK.to_string();
 
                 trace!(logger => "using default runtime uri, uri: {uri}");
-                uri.to_string()
+                uri
             }
         }
     };
@@ -160,10 +403,69 @@ impl std::future::Future for IsRunning {
     }
 }
 
+fn print_json<T: Serialize>(value: &T) -> Result<()> {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).context("failed to serialize output")?
+    );
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct RecipeEntry<'r> {
+    name: &'r str,
+    versions: &'r [String],
+    arch: &'r str,
+    license: &'r str,
+    description: &'r str,
+}
+
+#[derive(Serialize)]
+struct RecipeErrorEntry<'r> {
+    name: &'r str,
+    error: String,
+}
+
+#[derive(Serialize)]
+struct ImageEntry {
+    name: String,
+    image: Option<String>,
+    tag: Option<String>,
+}
+
+#[derive(Serialize)]
+struct PackageEntry {
+    image: String,
+    file: String,
+    name: String,
+    #[serde(rename = "type")]
+    package_type: String,
+    arch: Option<String>,
+    version: String,
+    release: Option<String>,
+    created: Option<String>,
+}
+
+#[derive(Clone)]
 pub struct AppOutputConfig {
     pub level: Level,
     pub log_dir: Option<PathBuf>,
     pub no_color: bool,
+    pub format: log::Format,
+    pub json_logs_to: Option<PathBuf>,
+    /// Print a JSON summary of the results to stdout and write logs to stderr.
+    pub summary_json: bool,
+}
+
+impl AppOutputConfig {
+    /// Returns the configuration of logs written to the terminal.
+    pub fn terminal(&self) -> log::Config {
+        if self.summary_json {
+            log::Config::stderr()
+        } else {
+            log::Config::stdout()
+        }
+    }
 }
 
 pub struct Application {
@@ -171,12 +473,20 @@ pub struct Application {
     recipes: recipe::Loader,
     runtime: ConnectionPool,
     images_state: Arc<RwLock<ImagesState>>,
+    built_versions: BuiltVersions,
+    releases: Releases,
     user_images_dir: PathBuf,
     is_running: Arc<AtomicBool>,
     app_dir: TempDir,
-    gpg_key: Option<GpgKey>,
+    gpg_keys: GpgKeys,
     session_id: Uuid,
     proxy: ProxyConfig,
+    notifiers: Vec<Notifier>,
+    release: Option<String>,
+    /// Architectures of an `--arch` build matrix, empty for regular builds.
+    arches: Vec<recipe::BuildArch>,
+    /// Removal of artifacts of previous builds requested with `--clean-output`.
+    clean_output: Option<CleanOutput>,
 }
 
 impl Application {
@@ -186,8 +496,11 @@ impl Application {
         logger: &mut BoxedCollector,
     ) -> Result<Self> {
         let app_dir = create_app_dirs()?;
-        let recipes = recipe::Loader::new(&config.recipes_dir)
+        let mut recipes = recipe::Loader::new(&config.recipes_dir)
             .context("failed to initialize recipe loader")?;
+        if let Some(recipe_root) = opts.recipe_root.as_ref().or(config.recipe_root.as_ref()) {
+            recipes = recipes.base_root(recipe_root);
+        }
         let user_images_dir = config
             .images_dir
             .clone()
@@ -208,6 +521,32 @@ impl Application {
             },
         ));
 
+        let built_versions_path = match dirs::cache_dir() {
+            Some(dir) => dir.join(DEFAULT_BUILT_VERSIONS_FILE),
+            None => PathBuf::from(DEFAULT_BUILT_VERSIONS_FILE),
+        };
+        let built_versions = match BuiltVersions::load(&built_versions_path)
+            .context("failed to load built versions")
+        {
+            Ok(built) => built,
+            Err(e) => {
+                warning!(logger => "{:?}", e);
+                BuiltVersions::new(&built_versions_path)
+            }
+        };
+
+        let releases_path = match dirs::cache_dir() {
+            Some(dir) => dir.join(DEFAULT_RELEASES_FILE),
+            None => PathBuf::from(DEFAULT_RELEASES_FILE),
+        };
+        let releases = match Releases::load(&releases_path).context("failed to load releases") {
+            Ok(releases) => releases,
+            Err(e) => {
+                warning!(logger => "{:?}", e);
+                Releases::new(&releases_path)
+            }
+        };
+
         let runtime = init_runtime(opts, &config, logger).await?;
 
         let app = Application {
@@ -215,12 +554,18 @@ impl Application {
             recipes,
             runtime,
             images_state,
+            built_versions,
+            releases,
             user_images_dir,
             is_running: Arc::new(AtomicBool::new(true)),
             app_dir,
-            gpg_key: None,
+            gpg_keys: GpgKeys::default(),
             session_id: Uuid::new_v4(),
             proxy: ProxyConfig::from_env(),
+            notifiers: vec![],
+            release: None,
+            arches: vec![],
+            clean_output: None,
         };
         let is_running = app.is_running.clone();
         set_ctrlc_handler(is_running);
@@ -231,12 +576,27 @@ impl Application {
         match opts.command {
             Command::Build(build_opts) => {
                 if !build_opts.no_sign {
-                    self.gpg_key = load_gpg_key(&self.config)?;
+                    self.gpg_keys = load_gpg_keys(&self.config)?;
                 }
+                let json_logs_to = build_opts.json_logs_to.clone();
+                let summary_json = build_opts.summary_json;
+                let print_env = build_opts.print_env;
+                let watch_opts = build_opts.watch.then(|| build_opts.clone());
                 let tasks = self
                     .process_build_opts(build_opts, logger)
                     .context("processing build opts")?;
 
+                if print_env {
+                    return self.print_env(tasks, logger).await;
+                }
+                let mut log_dir = opts.log_dir;
+                if self.config.job_logs && log_dir.is_none() && self.config.log_dir.is_none() {
+                    let dir = build::default_job_log_dir();
+                    warning!(logger => "logs per job need a log directory, writing them to '{}', set `log_dir` in the configuration or pass `--log-dir` to change it", dir.display());
+                    fs::create_dir_all(&dir).context("failed to create the log directory")?;
+                    log_dir = Some(dir);
+                }
+
                 let output_config = AppOutputConfig {
                     level: if opts.trace {
                         Level::Trace
@@ -247,10 +607,26 @@ impl Application {
                     } else {
                         Level::Info
                     },
-                    log_dir: opts.log_dir,
+                    log_dir,
                     no_color: opts.no_color || self.config.no_color,
+                    format: opts.log_format,
+                    json_logs_to,
+                    summary_json,
                 };
 
+                if let Some(watch_opts) = watch_opts {
+                    let recipe_dirs = build::recipe_dirs(&tasks);
+                    if let Err(e) = self
+                        .process_tasks(tasks, output_config.clone(), logger)
+                        .await
+                    {
+                        error!(logger => "{:?}", e);
+                    }
+                    return self
+                        .watch(watch_opts, recipe_dirs, output_config, logger)
+                        .await;
+                }
+
                 self.process_tasks(tasks, output_config, logger).await?;
                 Ok(())
             }
@@ -258,16 +634,30 @@ impl Application {
                 object,
                 raw,
                 verbose,
+                format,
             } => {
                 colored::control::set_override(!raw);
                 match object {
-                    ListObject::Images => self.list_images(verbose),
-                    ListObject::Recipes => self.list_recipes(verbose),
-                    ListObject::Packages { images } => self.list_packages(images, verbose),
+                    ListObject::Images => self.list_images(verbose, format),
+                    ListObject::Recipes { errors_only } => {
+                        if errors_only {
+                            self.list_recipe_errors(format)
+                        } else {
+                            self.list_recipes(verbose, format)
+                        }
+                    }
+                    ListObject::Packages { images } => self.list_packages(images, verbose, format),
                 }
             }
             Command::CleanCache => self.clean_cache().await,
-            Command::Init { .. } => unreachable!(),
+            Command::Clean { object } => match object {
+                CleanObject::Output {
+                    older_than,
+                    image,
+                    yes,
+                } => self.clean_output(older_than, image, yes || opts.quiet, logger),
+            },
+            Command::Init { .. } | Command::Doctor => unreachable!(),
             Command::Edit { object } => self.edit(object),
             Command::New { object } => self.create(object, logger),
             Command::Copy { object } => self.copy(object),
@@ -277,6 +667,14 @@ impl Application {
                 Ok(())
             }
             Command::Check { object } => self.check(object, logger).await,
+            Command::Repo { object } => self.repo(object, logger).await,
+            Command::Validate { recipes } => self.validate(recipes),
+            Command::Inspect {
+                recipe,
+                target,
+                image,
+                version,
+            } => self.inspect(&recipe, &target, image, version, logger),
         }
     }
 
@@ -441,9 +839,32 @@ impl Application {
         Ok(())
     }
 
-    fn list_recipes(&self, verbose: bool) -> Result<()> {
+    fn list_recipes(&self, verbose: bool, format: ListFormat) -> Result<()> {
         let mut recipes = self.recipes.list()?;
         recipes.sort_unstable();
+        if format == ListFormat::Json {
+            let mut loaded = vec![];
+            for name in recipes {
+                match self.recipes.load(&name) {
+                    Ok(recipe) => loaded.push(recipe),
+                    Err(e) if verbose => {
+                        warning!("failed to load recipe {}, reason: {:?}", name, e)
+                    }
+                    _ => {}
+                }
+            }
+            let entries: Vec<_> = loaded
+                .iter()
+                .map(|recipe| RecipeEntry {
+                    name: &recipe.metadata.name,
+                    versions: recipe.metadata.version.versions(),
+                    arch: recipe.metadata.arch.as_ref(),
+                    license: &recipe.metadata.license,
+                    description: &recipe.metadata.description,
+                })
+                .collect();
+            return print_json(&entries);
+        }
         if verbose {
             let mut table = vec![];
             for name in recipes {
@@ -500,66 +921,171 @@ impl Application {
         Ok(())
     }
 
-    fn list_packages(&self, images_filter: Option<Vec<String>>, verbose: bool) -> Result<()> {
-        let mut table = vec![];
-        let images = fs::read_dir(&self.config.output_dir)?.filter_map(|e| match e {
-            Ok(e) if e.file_type().map(|ty| ty.is_dir()).unwrap_or_default() => Some(e.path()),
-            Ok(_) => None, // Skip non directory entries
-            Err(e) => {
-                if verbose {
-                    warning!("invalid entry, reason: {:?}", e);
-                }
-                None
-            }
-        });
-
-        let mut images: Vec<_> = if let Some(filter) = images_filter {
-            images
-                .filter(|image| {
-                    filter.contains(
-                        &image
-                            .file_name()
-                            .unwrap_or_default()
-                            .to_string_lossy()
-                            .to_string(),
-                    )
+    fn list_recipe_errors(&self, format: ListFormat) -> Result<()> {
+        let errors = self.recipes.load_errors()?;
+        if format == ListFormat::Json {
+            let entries: Vec<_> = errors
+                .iter()
+                .map(|(name, e)| RecipeErrorEntry {
+                    name,
+                    error: format!("{:#}", e),
                 })
-                .collect()
-        } else {
-            images.collect()
-        };
+                .collect();
+            return print_json(&entries);
+        }
+
+        let table = errors
+            .iter()
+            .map(|(name, e)| {
+                vec![
+                    name.as_str()
+                        .cell()
+                        .left()
+                        .italic()
+                        .color(Color::BrightBlue),
+                    format!("{:#}", e).cell().left().color(Color::Red),
+                ]
+            })
+            .collect::<Vec<_>>()
+            .into_table()
+            .with_headers(vec!["Name".cell().bold(), "Error".cell().bold()]);
+        table.print();
+
+        Ok(())
+    }
 
-        images.sort_unstable();
+    /// Returns the directories of packages in the output directory organized by the configured
+    /// layout along with their paths relative to the output directory, only the ones of images
+    /// named in `filter` if it's set.
+    fn output_image_dirs(&self, filter: Option<&[String]>) -> Result<Vec<(String, OutputDir)>> {
+        let root = output::output_root(&self.config.output_dir);
+        if !root.is_dir() {
+            return err!("output directory `{}` doesn't exist", root.display());
+        }
+        Ok(
+            output::find_output_dirs(&self.config.output_dir, self.config.output_layout)
+                .into_iter()
+                .filter(|dir| {
+                    filter
+                        .map(|filter| filter.iter().any(|image| image == dir.image()))
+                        .unwrap_or(true)
+                })
+                .map(|dir| {
+                    let name = dir
+                        .path()
+                        .strip_prefix(&root)
+                        .unwrap_or(dir.path())
+                        .to_string_lossy()
+                        .to_string();
+                    (name, dir)
+                })
+                .collect(),
+        )
+    }
 
-        for image in images {
-            let image_name = image
-                .file_name()
-                .unwrap_or(image.as_os_str())
-                .to_string_lossy();
+    /// Removes packages from the output directory of the `images`, or of all images if empty,
+    /// that were created more than `older_than` ago. Asks for confirmation unless `yes` is set.
+    fn clean_output(
+        &self,
+        older_than: Option<Age>,
+        images: Vec<String>,
+        yes: bool,
+        logger: &mut BoxedCollector,
+    ) -> Result<()> {
+        let filter = (!images.is_empty()).then_some(images);
+        let now = time::SystemTime::now();
+        let mut to_remove = vec![];
+
+        for (image_name, image) in self.output_image_dirs(filter.as_deref())? {
+            let packages = match image_packages(image.path(), &image_name) {
+                Ok(packages) => packages,
+                Err(e) => {
+                    error!(logger => "failed to list packages for image {}, reason {:?}", image_name, e);
+                    continue;
+                }
+            };
+            for package in packages {
+                if let Some(Age(age)) = older_than {
+                    let created = PackageMetadata::try_from_dir_entry(&package)
+                        .ok()
+                        .and_then(|metadata| metadata.created());
+                    match created.and_then(|created| now.duration_since(created).ok()) {
+                        Some(elapsed) if elapsed > age => {}
+                        Some(_) => continue,
+                        None => {
+                            warning!(logger => "unknown creation time of {}, skipping", package.path().display());
+                            continue;
+                        }
+                    }
+                }
+                to_remove.push(package.path());
+            }
+        }
 
-            match fs::read_dir(&image) {
+        if to_remove.is_empty() {
+            info!(logger => "no packages to remove");
+            return Ok(());
+        }
+
+        if !yes {
+            for path in &to_remove {
+                println!("{}", path.display());
+            }
+            println!("remove {} packages? y/n", to_remove.len());
+            let mut line = String::new();
+            std::io::stdin()
+                .read_line(&mut line)
+                .context("failed to read input from user")?;
+            if line.trim() != "y" {
+                info!(logger => "not removing any packages");
+                return Ok(());
+            }
+        }
+
+        let mut failed = 0;
+        for path in &to_remove {
+            if let Err(e) = fs::remove_file(path) {
+                error!(logger => "failed to remove `{}`: {:?}", path.display(), e);
+                failed += 1;
+                continue;
+            }
+            // checksums and signatures saved next to the package
+            let sums = Checksum::ALL
+                .iter()
+                .map(|checksum| checksums::sidecar_path(*checksum, path));
+            let mut signature = path.clone().into_os_string();
+            signature.push(".sig");
+            for sidecar in sums.chain(std::iter::once(PathBuf::from(signature))) {
+                if sidecar.exists() {
+                    if let Err(e) = fs::remove_file(&sidecar) {
+                        warning!(logger => "failed to remove `{}`: {:?}", sidecar.display(), e);
+                    }
+                }
+            }
+            debug!(logger => "removed `{}`", path.display());
+        }
+
+        info!(logger => "removed {} packages", to_remove.len() - failed);
+        if failed > 0 {
+            err!("failed to remove {} packages", failed)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn list_packages(
+        &self,
+        images_filter: Option<Vec<String>>,
+        verbose: bool,
+        format: ListFormat,
+    ) -> Result<()> {
+        let mut table = vec![];
+        let mut entries = vec![];
+        let images = self.output_image_dirs(images_filter.as_deref())?;
+
+        for (image_name, image) in images {
+            match image_packages(image.path(), &image_name) {
                 Ok(packages) => {
-                    let mut packages: Vec<_> = packages
-                        .filter(|p| match p {
-                            Ok(p) => {
-                                if let Some(extension) = p.path().extension() {
-                                    metadata::SUPPORTED_EXTENSIONS
-                                        .contains(&extension.to_string_lossy().as_ref())
-                                } else {
-                                    false
-                                }
-                            }
-                            Err(e) => {
-                                error!(
-                                    "failed to list package for image {}, reason {:?}",
-                                    image_name, e
-                                );
-                                false
-                            }
-                        })
-                        .map(|p| p.unwrap())
-                        .collect();
-                    packages.sort_unstable_by_key(|p| p.file_name());
                     if packages.is_empty() {
                         continue;
                     }
@@ -576,6 +1102,22 @@ impl Application {
                             .context("failed to parse package metadata")
                         {
                             Ok(metadata) => {
+                                entries.push(PackageEntry {
+                                    image: image_name.to_string(),
+                                    file: path
+                                        .file_name()
+                                        .map(|s| s.to_string_lossy().to_string())
+                                        .unwrap_or_default(),
+                                    name: metadata.name().to_string(),
+                                    package_type: metadata.package_type().as_ref().to_string(),
+                                    arch: metadata.arch().as_ref().map(|a| a.as_ref().to_string()),
+                                    version: metadata.version().to_string(),
+                                    release: metadata.release().clone(),
+                                    created: metadata.created().map(|c| {
+                                        system_time_to_date_time(c)
+                                            .to_rfc3339_opts(SecondsFormat::Secs, true)
+                                    }),
+                                });
                                 if verbose {
                                     let version = if let Some(release) = metadata.release() {
                                         format!("{}-{}", metadata.version(), release)
@@ -632,6 +1174,10 @@ impl Application {
             }
         }
 
+        if format == ListFormat::Json {
+            return print_json(&entries);
+        }
+
         let headers = if verbose {
             vec![
                 "Image".cell().bold(),
@@ -650,19 +1196,24 @@ impl Application {
         Ok(())
     }
 
-    fn list_images(&self, verbose: bool) -> Result<()> {
+    fn list_images(&self, verbose: bool, format: ListFormat) -> Result<()> {
+        /// Returns the base image and tag from the `FROM` instruction of the image Dockerfile.
+        fn base_image(image: &Image) -> Result<Option<(String, Option<String>)>> {
+            let dockerfile = image.load_dockerfile()?;
+            Ok(dockerfile.lines().next().and_then(|line| {
+                line.to_lowercase().split("from ").nth(1).map(|s| {
+                    let mut elems = s.trim().split(':');
+                    (
+                        elems.next().unwrap().to_string(),
+                        elems.next().map(|s| s.to_string()),
+                    )
+                })
+            }))
+        }
+
         fn process_image(image: Image, verbose: bool) -> Result<Vec<Cell>> {
             if verbose {
-                let dockerfile = image.load_dockerfile()?;
-                if let Some((docker_image, tag)) = dockerfile.lines().next().and_then(|line| {
-                    line.to_lowercase().split("from ").nth(1).map(|s| {
-                        let mut elems = s.trim().split(':');
-                        (
-                            elems.next().unwrap().to_string(),
-                            elems.next().map(|s| s.to_string()),
-                        )
-                    })
-                }) {
+                if let Some((docker_image, tag)) = base_image(&image)? {
                     return Ok(vec![
                         image.name.cell().left().color(Color::Blue),
                         docker_image.cell().left().color(Color::White),
@@ -700,6 +1251,30 @@ impl Application {
 
             entries.sort_unstable_by_key(|e| e.file_name());
 
+            if format == ListFormat::Json {
+                let mut images = vec![];
+                for e in entries {
+                    match Image::try_from_path(e.path()).and_then(|image| {
+                        let (base, tag) = match base_image(&image)? {
+                            Some((base, tag)) => (Some(base), tag),
+                            None => (None, None),
+                        };
+                        Ok(ImageEntry {
+                            name: image.name,
+                            image: base,
+                            tag,
+                        })
+                    }) {
+                        Ok(entry) => images.push(entry),
+                        Err(e) if verbose => {
+                            warning!("invalid entry, reason: {:?}", e);
+                        }
+                        _ => {}
+                    }
+                }
+                return print_json(&images);
+            }
+
             entries.into_iter().for_each(|e| {
                 match Image::try_from_path(e.path()).and_then(|image| process_image(image, verbose))
                 {
@@ -779,9 +1354,248 @@ impl Application {
                     }
                 }
             },
+            CheckObject::Recipes {
+                names,
+                fail_on_warnings,
+            } => check_recipes(
+                &self.recipes,
+                &self.config.images,
+                names,
+                fail_on_warnings,
+                logger,
+            ),
+            CheckObject::Images { names } => self.check_images(names, logger).await,
+            CheckObject::Inheritance => check_inheritance(&self.recipes, logger),
         }
     }
 
+    /// Builds the images with `names` or all images from the configuration and prints a table
+    /// with the result of every image.
+    async fn check_images(&self, names: Vec<String>, logger: &mut BoxedCollector) -> Result<()> {
+        let targets = if names.is_empty() {
+            self.config.images.iter().collect::<Vec<_>>()
+        } else {
+            names
+                .iter()
+                .map(|name| {
+                    self.config
+                        .images
+                        .iter()
+                        .find(|target| &target.image == name)
+                        .ok_or_else(|| {
+                            Error::msg(format!("image `{}` is not defined in configuration", name))
+                        })
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+        if targets.is_empty() {
+            warning!(logger => "no images defined in configuration");
+            return Ok(());
+        }
+
+        let runtime = self.runtime.connect();
+        let mut table = vec![];
+        let mut failed = 0;
+        for target in &targets {
+            let image = Image::new(
+                target.image.clone(),
+                self.user_images_dir.join(&target.image),
+            );
+            let start = time::Instant::now();
+            let result = image::check(
+                &runtime,
+                &image,
+                target,
+                &self.config.registry_auth,
+                self.config.platform.as_deref(),
+                self.config.retry_policy(),
+                logger,
+            )
+            .await;
+            let duration = format!("{:.1}s", start.elapsed().as_secs_f32());
+            let (status, details) = match result {
+                Ok(id) => {
+                    info!(logger => "image '{}' OK.", target.image);
+                    ("OK".cell().color(Color::BrightGreen), id)
+                }
+                Err(e) => {
+                    error!(logger => "image '{}' failed to build, reason: {:?}", target.image, e);
+                    failed += 1;
+                    ("FAILED".cell().color(Color::BrightRed), e.to_string())
+                }
+            };
+            table.push(vec![
+                target.image.clone().cell().left().color(Color::BrightBlue),
+                target.build_target.as_ref().cell().left(),
+                status.left(),
+                duration.cell(),
+                details.cell().left(),
+            ]);
+        }
+
+        table
+            .into_table()
+            .with_headers(vec![
+                "Image".cell().bold(),
+                "Target".cell().bold(),
+                "Status".cell().bold(),
+                "Duration".cell().bold(),
+                "Details".cell().bold(),
+            ])
+            .print();
+
+        if failed > 0 {
+            return err!("{} out of {} images failed to build", failed, targets.len());
+        }
+        Ok(())
+    }
+
+    async fn repo(&self, object: RepoObject, logger: &mut BoxedCollector) -> Result<()> {
+        let (dir, no_sign, target) = match object {
+            RepoObject::Rpm { dir, no_sign } => (dir, no_sign, recipe::BuildTarget::Rpm),
+            RepoObject::Deb { dir, no_sign } => (dir, no_sign, recipe::BuildTarget::Deb),
+        };
+        let dir = self.repo_dir(dir)?;
+        let gpg_keys = if no_sign {
+            GpgKeys::default()
+        } else {
+            load_gpg_keys(&self.config)?
+        };
+        let image = dir
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let gpg_key = gpg_keys.key_for(&image, target);
+        if gpg_key.is_none() && !no_sign {
+            warning!(logger => "no GPG key configured, repository metadata won't be signed");
+        }
+
+        let runtime = self.runtime.connect();
+        let registry_auth = &self.config.registry_auth;
+        match target {
+            recipe::BuildTarget::Deb => {
+                repo::create_deb_repo(&runtime, &dir, gpg_key, registry_auth, logger).await?
+            }
+            _ => repo::create_rpm_repo(&runtime, &dir, gpg_key, registry_auth, logger).await?,
+        }
+        info!(logger => "created {} repository in `{}`", target.as_ref(), dir.display());
+        Ok(())
+    }
+
+    /// Returns the directory of a repository. Directories that don't exist are looked up by the
+    /// image name or the path relative to the output directory among directories of packages.
+    fn repo_dir(&self, dir: PathBuf) -> Result<PathBuf> {
+        if dir.is_dir() {
+            return Ok(dir);
+        }
+        if dir.is_relative() {
+            let name = dir.to_string_lossy();
+            let mut found: Vec<_> = self
+                .output_image_dirs(None)?
+                .into_iter()
+                .filter(|(path, image)| *path == name || image.image() == name)
+                .collect();
+            match found.len() {
+                0 => {}
+                1 => return Ok(found.remove(0).1.path().to_path_buf()),
+                _ => {
+                    return err!(
+                        "`{}` matches multiple directories of packages: {}",
+                        name,
+                        found
+                            .iter()
+                            .map(|(path, _)| path.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                }
+            }
+        }
+        err!("repository directory `{}` doesn't exist", dir.display())
+    }
+
+    fn inspect(
+        &self,
+        name: &str,
+        target: &str,
+        image: Option<String>,
+        version: Option<String>,
+        logger: &mut BoxedCollector,
+    ) -> Result<()> {
+        let recipe = self
+            .recipes
+            .load(name)
+            .context(format!("failed to load recipe `{}`", name))?;
+        let target = recipe::BuildTarget::try_from(target)?;
+        let image = image.unwrap_or_else(|| Image::simple(target).name.to_string());
+        let version = match version {
+            Some(version) if !recipe.metadata.version.has_version(&version) => {
+                return err!("recipe `{}` has no version `{}`", name, version);
+            }
+            Some(version) => version,
+            None => recipe
+                .metadata
+                .version
+                .versions()
+                .first()
+                .cloned()
+                .context("recipe has no versions")?,
+        };
+
+        let manifest = render_manifest(&recipe, target, &image, &version, logger)?;
+
+        println!("{}", manifest);
+        Ok(())
+    }
+
+    fn validate(&self, mut names: Vec<String>) -> Result<()> {
+        if names.is_empty() {
+            names = self.recipes.list()?;
+        }
+        names.sort_unstable();
+
+        let mut table = vec![];
+        let mut invalid = 0;
+        for name in &names {
+            let problems = match self.recipes.load(name) {
+                Ok(recipe) => recipe.validate(),
+                Err(e) => vec![format!("{:#}", e)],
+            };
+            if problems.is_empty() {
+                table.push(vec![
+                    name.cell().left().color(Color::BrightBlue),
+                    "OK".cell().left().color(Color::BrightGreen),
+                    "".cell(),
+                ]);
+                continue;
+            }
+
+            invalid += 1;
+            for problem in problems {
+                table.push(vec![
+                    name.cell().left().color(Color::BrightBlue),
+                    "ERROR".cell().left().color(Color::BrightRed),
+                    problem.cell().left(),
+                ]);
+            }
+        }
+
+        table
+            .into_table()
+            .with_headers(vec![
+                "Recipe".cell().bold(),
+                "Status".cell().bold(),
+                "Problem".cell().bold(),
+            ])
+            .print();
+
+        if invalid > 0 {
+            return err!("{} out of {} recipes are invalid", invalid, names.len());
+        }
+        Ok(())
+    }
+
     async fn save_images_state(&self, logger: &mut BoxedCollector) {
         info!(logger => "saving images state");
         let state = self.images_state.read().await;
@@ -1,9 +1,18 @@
+use crate::hooks::PostBuildHook;
 use crate::Result;
+use pkger_core::build::container::ContainerRemoval;
+use pkger_core::build::package::permissions::ArtifactPermissions;
+use pkger_core::build::OutputLayout;
+use pkger_core::checksum::Checksum;
+use pkger_core::limits::ResourceLimits;
 use pkger_core::recipe::{deserialize_images, BuildTarget, ImageTarget};
+use pkger_core::registry::RegistryAuth;
+use pkger_core::runtime::retry::{RetryPolicy, DEFAULT_INITIAL_BACKOFF, DEFAULT_MAX_ATTEMPTS};
 use pkger_core::ssh::SshConfig;
-use pkger_core::ErrContext;
+use pkger_core::{err, ErrContext};
 
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -13,12 +22,29 @@ pub struct Configuration {
     pub output_dir: PathBuf,
     pub images_dir: Option<PathBuf>,
     pub log_dir: Option<PathBuf>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "default")]
+    /// Write the output of every build job to a file of its own in `log_dir` while printing a
+    /// summary of the jobs to the terminal.
+    pub job_logs: bool,
     pub runtime_uri: Option<String>,
     pub gpg_key: Option<PathBuf>,
     pub gpg_name: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    /// GPG keys assigned to image names or build targets, override `gpg_key` for those builds.
+    pub gpg_keys: BTreeMap<String, GpgKeyConfig>,
     pub ssh: Option<SshConfig>,
     #[serde(deserialize_with = "deserialize_images")]
     pub images: Vec<ImageTarget>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    /// Sets of images keyed by the name of a profile. The images of a profile selected with
+    /// `--profile` replace `images`.
+    pub profiles: BTreeMap<String, Profile>,
+    #[serde(skip)]
+    /// Name of the profile whose images are used.
+    pub active_profile: Option<String>,
     #[serde(skip_serializing)]
     #[serde(skip_deserializing)]
     pub path: PathBuf,
@@ -26,6 +52,107 @@ pub struct Configuration {
     #[serde(default)]
     #[serde(skip_serializing_if = "default")]
     pub no_color: bool,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "default")]
+    /// Keep the build container running after a failed build so that it can be inspected.
+    pub keep_containers: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// When to remove build containers, `always`, `on-success` or `never`. Takes precedence over
+    /// `keep_containers`.
+    pub container_removal: Option<ContainerRemoval>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "default")]
+    /// Reuse build directories of previous builds of unchanged recipes.
+    pub build_cache: bool,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "default")]
+    /// Bump the release of packages to follow the last built release of the same version and
+    /// the highest release of the same version already present in the output directory.
+    pub auto_release: bool,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Commands run on the host after every successfully built package.
+    pub post_build: Vec<PostBuildHook>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "default")]
+    /// Package a recipe in every format targeted on an image from a single build.
+    pub share_builds: bool,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "default")]
+    /// Skip the remaining jobs of a recipe when one of its images fails to build.
+    pub fail_fast_image: bool,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "default")]
+    /// Skip versions of recipes that were already successfully built for the same image and target.
+    pub new_versions_only: bool,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "default")]
+    /// Skip the `check` steps of recipes.
+    pub no_check: bool,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "default")]
+    /// Install built packages in the build container to check that their dependencies resolve.
+    pub verify_install: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Maximum time in seconds that building an image can take.
+    pub image_build_timeout: Option<u64>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "default")]
+    /// Allow recipes to run build containers in privileged mode or with additional capabilities.
+    pub allow_privileged: bool,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Algorithms of checksum files written next to every built package, one of `md5`, `sha1`,
+    /// `sha256`, `sha512` or `blake2`.
+    pub checksums: Vec<Checksum>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "ArtifactPermissions::is_empty")]
+    /// File mode and SELinux context set on built packages.
+    pub artifact_permissions: ArtifactPermissions,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "default")]
+    /// Subdirectories of `output_dir` in which packages are saved, `per-image` or `per-recipe`.
+    pub output_layout: OutputLayout,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "default")]
+    /// Run build containers with a read-only root filesystem, only the build directories and
+    /// `writable_paths` are writable.
+    pub read_only_rootfs: bool,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Additional paths with a writable tmpfs in build containers with a read-only root filesystem.
+    pub writable_paths: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Default resource limits of build containers.
+    pub limits: Option<ResourceLimits>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Default network of build containers.
+    pub network: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// DNS servers of build containers.
+    pub dns: Vec<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Additional `host:ip` entries of `/etc/hosts` in build containers.
+    pub extra_hosts: Vec<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Credentials used to pull base images from private registries.
+    pub registry_auth: Vec<RegistryAuth>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Directory in which base recipes referenced by `from` are looked up.
+    pub recipe_root: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Default platform of images like `linux/amd64`.
+    pub platform: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Content appended after the `FROM` instruction of every generated simple image Dockerfile.
+    pub simple_image_extra_dockerfile: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Maximum number of attempts of calls to the container runtime that fail with transient
+    /// network errors.
+    pub runtime_max_attempts: Option<u32>,
 }
 
 fn default<T: Default + PartialEq>(t: &T) -> bool {
@@ -43,6 +170,43 @@ impl Configuration {
             })
     }
 
+    /// Returns the container removal policy, `keep_containers` is the same as `on-success`.
+    pub fn container_removal(&self) -> ContainerRemoval {
+        match self.container_removal {
+            Some(policy) => policy,
+            None if self.keep_containers => ContainerRemoval::OnSuccess,
+            None => ContainerRemoval::Always,
+        }
+    }
+
+    /// Returns the policy of retrying calls to the container runtime, 3 attempts by default.
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::new(
+            self.runtime_max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS),
+            DEFAULT_INITIAL_BACKOFF,
+        )
+    }
+
+    /// Replaces the images with the ones of the profile `name`.
+    pub fn apply_profile(&mut self, name: &str) -> Result<()> {
+        match self.profiles.get(name) {
+            Some(profile) => {
+                self.images = profile.images.clone();
+                self.active_profile = Some(name.to_string());
+                Ok(())
+            }
+            None => err!(
+                "profile `{}` not found in configuration, available profiles: {}",
+                name,
+                self.profiles
+                    .keys()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
     pub fn save(&self) -> Result<()> {
         fs::write(
             &self.path,
@@ -53,6 +217,21 @@ impl Configuration {
     }
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Profile {
+    #[serde(deserialize_with = "deserialize_images")]
+    /// Images used instead of `images` when the profile is active.
+    pub images: Vec<ImageTarget>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GpgKeyConfig {
+    /// Path to the private key
+    pub key: PathBuf,
+    /// Must be the same as the `Name` field on the key
+    pub name: String,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CustomImagesDefinition {
     pub rpm: Option<String>,
@@ -73,3 +252,56 @@ impl CustomImagesDefinition {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_images_of_active_profile() {
+        let mut config: Configuration = serde_yaml::from_str(
+            r#"
+recipes_dir: /recipes
+output_dir: /output
+images:
+  - name: rocky
+    target: rpm
+  - name: debian
+    target: deb
+profiles:
+  ci:
+    images:
+      - name: rocky-ci
+        target: rpm
+      - name: alpine-ci
+        target: apk
+  local:
+    images:
+      - name: debian
+        target: deb
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.active_profile, None);
+        let names = |config: &Configuration| {
+            config
+                .images
+                .iter()
+                .map(|image| image.image.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&config), vec!["rocky", "debian"]);
+
+        config.apply_profile("ci").unwrap();
+        assert_eq!(config.active_profile.as_deref(), Some("ci"));
+        assert_eq!(names(&config), vec!["rocky-ci", "alpine-ci"]);
+        assert_eq!(config.images[1].build_target, BuildTarget::Apk);
+
+        let err = config.apply_profile("staging").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "profile `staging` not found in configuration, available profiles: ci, local"
+        );
+        assert_eq!(config.active_profile.as_deref(), Some("ci"));
+    }
+}
@@ -0,0 +1,336 @@
+use crate::app;
+use crate::config::Configuration;
+use crate::opts::Opts;
+use crate::table::{IntoCell, IntoTable};
+use pkger_core::build::output;
+use pkger_core::log::BoxedCollector;
+use pkger_core::recipe;
+use pkger_core::{ErrContext, Error, Result};
+
+use colored::Color;
+use std::env;
+use std::path::{Path, PathBuf};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Warning,
+    Failed,
+    Skipped,
+}
+
+impl Status {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Status::Ok => "OK",
+            Status::Warning => "WARNING",
+            Status::Failed => "FAILED",
+            Status::Skipped => "SKIPPED",
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            Status::Ok => Color::BrightGreen,
+            Status::Warning => Color::BrightYellow,
+            Status::Failed => Color::BrightRed,
+            Status::Skipped => Color::White,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: Status,
+    pub details: String,
+}
+
+/// Results of all checks of the environment.
+#[derive(Debug, Default)]
+pub struct Report {
+    checks: Vec<CheckResult>,
+}
+
+impl Report {
+    fn add(&mut self, name: &'static str, status: Status, details: impl Into<String>) {
+        self.checks.push(CheckResult {
+            name,
+            status,
+            details: details.into(),
+        });
+    }
+
+    /// Adds a check that fails with the error of `result`.
+    fn add_result(&mut self, name: &'static str, result: Result<String>) {
+        match result {
+            Ok(details) => self.add(name, Status::Ok, details),
+            Err(e) => self.add(name, Status::Failed, format!("{:#}", e)),
+        }
+    }
+
+    pub fn checks(&self) -> &[CheckResult] {
+        &self.checks
+    }
+
+    pub fn failed(&self) -> usize {
+        self.checks
+            .iter()
+            .filter(|check| check.status == Status::Failed)
+            .count()
+    }
+
+    pub fn print(&self) {
+        self.checks
+            .iter()
+            .map(|check| {
+                vec![
+                    check.name.cell().left().color(Color::BrightBlue),
+                    check
+                        .status
+                        .as_str()
+                        .cell()
+                        .left()
+                        .color(check.status.color()),
+                    check.details.as_str().cell().left(),
+                ]
+            })
+            .collect::<Vec<_>>()
+            .into_table()
+            .with_headers(vec![
+                "Check".cell().bold(),
+                "Status".cell().bold(),
+                "Details".cell().bold(),
+            ])
+            .print();
+    }
+}
+
+/// Runs all checks of the environment and prints the report. Every check runs even if previous
+/// checks failed, checks that depend on a valid configuration are skipped without it.
+pub async fn run(opts: &Opts, config_path: &str, logger: &mut BoxedCollector) -> Result<()> {
+    let config = Configuration::load(config_path)
+        .with_context(|| format!("failed to load configuration file `{}`", config_path));
+    let runtime = match &config {
+        Ok(config) => Some(
+            app::init_runtime(opts, config, logger)
+                .await
+                .map(|_| "connected to the container runtime".to_string()),
+        ),
+        Err(_) => None,
+    };
+    let recipe_root = opts
+        .recipe_root
+        .clone()
+        .or_else(|| config.as_ref().ok().and_then(|c| c.recipe_root.clone()));
+
+    let report = report(config.as_ref(), runtime, recipe_root.as_deref(), logger);
+    report.print();
+
+    if report.failed() > 0 {
+        return err!(
+            "{} out of {} checks failed",
+            report.failed(),
+            report.checks().len()
+        );
+    }
+    Ok(())
+}
+
+/// Aggregates results of all checks. `runtime` is the result of connecting to the container
+/// runtime, `None` if it couldn't be attempted.
+pub fn report(
+    config: std::result::Result<&Configuration, &Error>,
+    runtime: Option<Result<String>>,
+    recipe_root: Option<&Path>,
+    logger: &mut BoxedCollector,
+) -> Report {
+    let mut report = Report::default();
+
+    let config = match config {
+        Ok(config) => {
+            report.add(
+                "configuration",
+                Status::Ok,
+                format!("loaded `{}`", config.path.display()),
+            );
+            Some(config)
+        }
+        Err(e) => {
+            report.add("configuration", Status::Failed, format!("{:#}", e));
+            None
+        }
+    };
+
+    match runtime {
+        Some(result) => report.add_result("runtime", result),
+        None => report.add("runtime", Status::Skipped, "configuration is invalid"),
+    }
+
+    match config {
+        Some(config) => {
+            report.add_result("recipes directory", check_dir(&config.recipes_dir));
+            match &config.images_dir {
+                Some(dir) => report.add_result("images directory", check_dir(dir)),
+                None => report.add(
+                    "images directory",
+                    Status::Warning,
+                    "not configured, only simple images are available",
+                ),
+            }
+            match check_dir(&output::output_root(&config.output_dir)) {
+                Ok(details) => report.add("output directory", Status::Ok, details),
+                Err(e) => report.add(
+                    "output directory",
+                    Status::Warning,
+                    format!("{:#}, it will be created by the first build", e),
+                ),
+            }
+            report.add_result("recipes", check_recipes(config, recipe_root, logger));
+            report.add_result("gpg keys", check_gpg_keys(config));
+        }
+        None => {
+            for name in [
+                "recipes directory",
+                "images directory",
+                "output directory",
+                "recipes",
+                "gpg keys",
+            ] {
+                report.add(name, Status::Skipped, "configuration is invalid");
+            }
+        }
+    }
+
+    match check_editor() {
+        Ok(details) => report.add("editor", Status::Ok, details),
+        Err(e) => report.add(
+            "editor",
+            Status::Warning,
+            format!("{:#}, `pkger edit` won't work", e),
+        ),
+    }
+
+    report
+}
+
+fn check_dir(dir: &Path) -> Result<String> {
+    if !dir.is_dir() {
+        return err!("directory `{}` doesn't exist", dir.display());
+    }
+    Ok(format!("`{}` exists", dir.display()))
+}
+
+fn check_recipes(
+    config: &Configuration,
+    recipe_root: Option<&Path>,
+    logger: &mut BoxedCollector,
+) -> Result<String> {
+    let mut recipes =
+        recipe::Loader::new(&config.recipes_dir).context("failed to initialize recipe loader")?;
+    if let Some(recipe_root) = recipe_root {
+        recipes = recipes.base_root(recipe_root);
+    }
+    let count = recipes.list()?.len();
+    app::check_recipes(&recipes, &config.images, vec![], false, logger)?;
+    Ok(format!("{} recipes loaded", count))
+}
+
+fn check_gpg_keys(config: &Configuration) -> Result<String> {
+    let mut keys = vec![];
+    if let Some(key) = &config.gpg_key {
+        if config.gpg_name.is_none() {
+            return err!("missing `gpg_name` field from configuration");
+        }
+        keys.push(key);
+    }
+    keys.extend(config.gpg_keys.values().map(|key| &key.key));
+
+    if keys.is_empty() {
+        return Ok("no keys configured, packages won't be signed".to_string());
+    }
+    for key in &keys {
+        if !key.is_file() {
+            return err!("gpg key `{}` doesn't exist", key.display());
+        }
+    }
+    Ok(format!("{} keys found", keys.len()))
+}
+
+fn check_editor() -> Result<String> {
+    let editor = env::var("EDITOR").context("$EDITOR is not set")?;
+    let path = find_executable(&editor)
+        .with_context(|| format!("editor `{}` was not found in $PATH", editor))?;
+    Ok(format!("`{}`", path.display()))
+}
+
+/// Returns the path of the `program` if it's a path or can be found in one of directories from
+/// `$PATH`.
+fn find_executable(program: &str) -> Option<PathBuf> {
+    let program = Path::new(program);
+    if program.components().count() > 1 {
+        return program.is_file().then(|| program.to_path_buf());
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pkger_core::log;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn reports_results_of_all_checks() {
+        let mut logger = log::Config::stdout().as_collector().unwrap();
+        let dir = TempDir::new("pkger-doctor").unwrap();
+        let recipes_dir = dir.path().join("recipes");
+        fs::create_dir(&recipes_dir).unwrap();
+
+        let config: Configuration = serde_yaml::from_str(&format!(
+            "recipes_dir: {}\noutput_dir: {}\ngpg_key: {}\ngpg_name: test\nimages: []",
+            recipes_dir.display(),
+            dir.path().join("output").display(),
+            dir.path().join("key.gpg").display(),
+        ))
+        .unwrap();
+
+        let result = report(
+            Ok(&config),
+            Some(Err(Error::msg("connection refused"))),
+            None,
+            &mut logger,
+        );
+        let results: Vec<_> = result
+            .checks()
+            .iter()
+            .map(|check| (check.name, check.status))
+            .collect();
+        assert_eq!(
+            &results[..7],
+            &[
+                ("configuration", Status::Ok),
+                ("runtime", Status::Failed),
+                ("recipes directory", Status::Ok),
+                ("images directory", Status::Warning),
+                ("output directory", Status::Warning),
+                ("recipes", Status::Ok),
+                ("gpg keys", Status::Failed),
+            ]
+        );
+        assert_eq!(results[7].0, "editor");
+        assert_eq!(result.failed(), 2);
+        assert!(result.checks()[1].details.contains("connection refused"));
+
+        let error = Error::msg("failed to read configuration file");
+        let result = report(Err(&error), None, None, &mut logger);
+        assert_eq!(result.checks().len(), 8);
+        assert_eq!(result.failed(), 1);
+        assert!(result.checks()[1..7]
+            .iter()
+            .all(|check| check.status == Status::Skipped));
+    }
+}
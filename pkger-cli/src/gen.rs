@@ -61,6 +61,9 @@ pub fn recipe(opts: Box<GenRecipeOpts>, logger: &mut BoxedCollector) -> RecipeRe
         enhances: vec_as_deps!(opts.enchances),
 
         postinst_script: None,
+        files: None,
+        installed_size: None,
+        unknown: Default::default(),
     };
 
     let rpm = RpmRep {
@@ -74,6 +77,9 @@ pub fn recipe(opts: Box<GenRecipeOpts>, logger: &mut BoxedCollector) -> RecipeRe
         preun_script: None,
         postun_script: None,
         config_noreplace: opts.config_noreplace,
+        files: None,
+        native_prep: None,
+        unknown: Default::default(),
     };
 
     let pkg = PkgRep {
@@ -81,6 +87,8 @@ pub fn recipe(opts: Box<GenRecipeOpts>, logger: &mut BoxedCollector) -> RecipeRe
         backup: opts.backup_files.unwrap_or_default(),
         replaces: vec_as_deps!(opts.replaces),
         optdepends: opts.optdepends.unwrap_or_default(),
+        noextract: vec![],
+        unknown: Default::default(),
     };
 
     let metadata = MetadataRep {
@@ -101,26 +109,44 @@ pub fn recipe(opts: Box<GenRecipeOpts>, logger: &mut BoxedCollector) -> RecipeRe
         exclude: opts.exclude,
         group: opts.group,
         release: opts.release,
+        auto_release: None,
         epoch: opts.epoch,
+        limits: None,
+        privileged: None,
+        cap_add: None,
+        dns: None,
+        extra_hosts: None,
+        latest_link: None,
+        check_arch: None,
+        compression: None,
 
         build_depends: vec_as_deps!(opts.build_depends),
         depends: vec_as_deps!(opts.depends),
         conflicts: vec_as_deps!(opts.conflicts),
         provides: vec_as_deps!(opts.provides),
+        obsoletes: vec_as_deps!(opts.obsoletes),
+        breaks: YamlValue::Null,
         patches: vec_as_deps!(opts.patches),
+        build_repos: YamlValue::Null,
 
         deb: Some(deb),
         rpm: Some(rpm),
         pkg: Some(pkg),
         apk: None,
+        unknown: Default::default(),
     };
 
     RecipeRep {
         from: None,
         metadata: Some(metadata),
         env: if env.is_empty() { None } else { Some(env) },
+        env_file: None,
+        changelog_file: None,
+        post_fetch: None,
         configure: None,
         build: Default::default(),
         install: None,
+        check: None,
+        unknown: Default::default(),
     }
 }
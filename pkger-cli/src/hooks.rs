@@ -0,0 +1,107 @@
+use pkger_core::log::{debug, info, warning, BoxedCollector};
+use pkger_core::template;
+use pkger_core::{err, ErrContext, Result};
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::process::Command;
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+/// A command run on the host after a package was successfully built.
+pub struct PostBuildHook {
+    /// Command run with `sh -c`. Can reference `${artifact}`, `${recipe}`, `${version}`,
+    /// `${target}` and `${image}`.
+    pub cmd: String,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    /// Whether a failure of the hook fails the build.
+    pub fatal: bool,
+}
+
+/// Runs the `hooks` in order with the variables of a built package. Failures of hooks that are
+/// not fatal are only logged.
+pub fn run_post_build(
+    hooks: &[PostBuildHook],
+    vars: &HashMap<String, String>,
+    logger: &mut BoxedCollector,
+) -> Result<()> {
+    for hook in hooks {
+        let cmd = template::render(&hook.cmd, vars);
+        info!(logger => "running post build hook `{}`", cmd);
+        let result = Command::new("sh")
+            .arg("-c")
+            .arg(&cmd)
+            .output()
+            .with_context(|| format!("failed to run post build hook `{}`", cmd))
+            .and_then(|output| {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                if !stdout.trim().is_empty() {
+                    debug!(logger => "{}", stdout.trim_end());
+                }
+                if output.status.success() {
+                    Ok(())
+                } else {
+                    err!(
+                        "post build hook `{}` failed with {}: {}",
+                        cmd,
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    )
+                }
+            });
+
+        match result {
+            Err(e) if hook.fatal => return Err(e),
+            Err(e) => warning!(logger => "{:?}", e),
+            Ok(_) => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pkger_core::log;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn runs_post_build_hooks() {
+        let dir = TempDir::new("pkger-hooks").unwrap();
+        let out = dir.path().join("hook.out");
+        let mut logger = log::Config::stdout().as_collector().unwrap();
+        let vars: HashMap<String, String> = [
+            ("artifact", "/tmp/out/rocky/pkger-0.11.0-0.x86_64.rpm"),
+            ("recipe", "pkger"),
+            ("version", "0.11.0"),
+            ("target", "rpm"),
+            ("image", "rocky"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        let hook = |cmd: String, fatal| PostBuildHook { cmd, fatal };
+        let write = hook(
+            format!(
+                "echo ${{recipe}} ${{version}} ${{target}} ${{image}} ${{artifact}} > {}",
+                out.display()
+            ),
+            true,
+        );
+        let failing = hook("exit 1".to_string(), false);
+
+        run_post_build(&[failing.clone(), write], &vars, &mut logger).unwrap();
+        assert_eq!(
+            fs::read_to_string(&out).unwrap(),
+            "pkger 0.11.0 rpm rocky /tmp/out/rocky/pkger-0.11.0-0.x86_64.rpm\n"
+        );
+
+        let fatal = PostBuildHook {
+            fatal: true,
+            ..failing
+        };
+        assert!(run_post_build(&[fatal], &vars, &mut logger).is_err());
+    }
+}
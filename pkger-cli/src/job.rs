@@ -1,24 +1,46 @@
-use pkger_core::build::{self, Context};
+use pkger_core::build::{self, image::ImageBuildFailed, Context};
 use pkger_core::log::BoxedCollector;
 use pkger_core::runtime;
 
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::time::{Duration, Instant};
 
+/// Stage in which a job failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailureKind {
+    /// Building the image of the job failed.
+    ImageBuild,
+    /// The build itself failed after the image was built.
+    Build,
+    /// The job wasn't started because the image of another job of the same recipe failed.
+    Skipped,
+    /// The job was cancelled by a ctrl-c signal.
+    Cancelled,
+}
+
 pub enum JobResult {
     Success {
         id: String,
         duration: Duration,
         output: String,
+        /// Paths of the built packages.
+        artifacts: Vec<PathBuf>,
+        /// Log file of the job when every job logs to a file of its own.
+        log_file: Option<PathBuf>,
     },
     Failure {
         id: String,
         duration: Duration,
         reason: String,
+        kind: FailureKind,
+        log_file: Option<PathBuf>,
     },
 }
 
 impl JobResult {
-    pub fn success<I, O>(id: I, duration: Duration, output: O) -> Self
+    pub fn success<I, O>(id: I, duration: Duration, output: O, artifacts: Vec<PathBuf>) -> Self
     where
         I: Into<String>,
         O: Into<String>,
@@ -27,10 +49,12 @@ impl JobResult {
             id: id.into(),
             duration,
             output: output.into(),
+            artifacts,
+            log_file: None,
         }
     }
 
-    pub fn failure<I, E>(id: I, duration: Duration, err: E) -> Self
+    pub fn failure<I, E>(id: I, duration: Duration, err: E, kind: FailureKind) -> Self
     where
         I: Into<String>,
         E: Into<String>,
@@ -39,6 +63,122 @@ impl JobResult {
             id: id.into(),
             duration,
             reason: err.into(),
+            kind,
+            log_file: None,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        match self {
+            Self::Success { id, .. } | Self::Failure { id, .. } => id,
+        }
+    }
+
+    pub fn with_log_file(mut self, path: Option<PathBuf>) -> Self {
+        match &mut self {
+            Self::Success { log_file, .. } | Self::Failure { log_file, .. } => *log_file = path,
+        }
+        self
+    }
+
+    /// Returns a suffix like `, log: /var/log/pkger/job.log` referencing the log file of the job
+    /// for the messages about the result.
+    pub fn log_file_note(&self) -> String {
+        match self {
+            Self::Success { log_file, .. } | Self::Failure { log_file, .. } => log_file
+                .as_ref()
+                .map(|path| format!(", log: {}", path.display()))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Tracks images of recipes that failed to build so that the remaining jobs of those recipes can
+/// be skipped when failing fast is enabled.
+pub struct ImageFailures {
+    fail_fast: bool,
+    /// Recipe names mapped to the first image that failed to build.
+    failed: HashMap<String, String>,
+}
+
+impl ImageFailures {
+    pub fn new(fail_fast: bool) -> Self {
+        Self {
+            fail_fast,
+            failed: HashMap::new(),
+        }
+    }
+
+    /// Records the `result` of a job that built the `recipe` on the `image`.
+    pub fn record(&mut self, recipe: &str, image: &str, result: &JobResult) {
+        if let JobResult::Failure {
+            kind: FailureKind::ImageBuild,
+            ..
+        } = result
+        {
+            self.failed
+                .entry(recipe.to_string())
+                .or_insert_with(|| image.to_string());
+        }
+    }
+
+    /// Returns the image that failed to build if the remaining jobs of the `recipe` should be
+    /// skipped.
+    pub fn failed_image(&self, recipe: &str) -> Option<&str> {
+        if self.fail_fast {
+            self.failed.get(recipe).map(String::as_str)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns a summary of the `results` counting image build failures separately from failures of
+/// the builds.
+pub fn summary(results: &[JobResult]) -> String {
+    let count = |kind: FailureKind| {
+        results
+            .iter()
+            .filter(|res| matches!(res, JobResult::Failure { kind: k, .. } if *k == kind))
+            .count()
+    };
+    let succeeded = results
+        .iter()
+        .filter(|res| matches!(res, JobResult::Success { .. }))
+        .count();
+
+    format!(
+        "jobs: {} succeeded, {} failed to build image, {} failed to build, {} skipped, {} cancelled",
+        succeeded,
+        count(FailureKind::ImageBuild),
+        count(FailureKind::Build),
+        count(FailureKind::Skipped),
+        count(FailureKind::Cancelled),
+    )
+}
+
+/// Compact summary of the results printed with `--summary-json`.
+#[derive(Debug, Serialize)]
+pub struct JsonSummary<'r> {
+    pub succeeded: usize,
+    pub failed: usize,
+    /// Ids of the jobs that didn't succeed, including the skipped and cancelled ones.
+    pub failed_ids: Vec<&'r str>,
+}
+
+impl<'r> JsonSummary<'r> {
+    pub fn new(results: &'r [JobResult]) -> Self {
+        let failed_ids: Vec<_> = results
+            .iter()
+            .filter_map(|res| match res {
+                JobResult::Failure { id, .. } => Some(id.as_str()),
+                JobResult::Success { .. } => None,
+            })
+            .collect();
+        Self {
+            succeeded: results.len() - failed_ids.len(),
+            failed: failed_ids.len(),
+            failed_ids,
         }
     }
 }
@@ -48,12 +188,21 @@ pub enum JobCtx {
 }
 
 impl JobCtx {
-    pub async fn run(self, mut logger: BoxedCollector) -> JobResult {
+    pub async fn run(self, logger: BoxedCollector, log_file: Option<PathBuf>) -> JobResult {
+        self.run_job(logger).await.with_log_file(log_file)
+    }
+
+    async fn run_job(self, mut logger: BoxedCollector) -> JobResult {
         let start = Instant::now();
         match self {
             JobCtx::Build(mut ctx) => match build::run(&mut ctx, &mut logger).await {
                 Err(e) => {
                     let duration = start.elapsed();
+                    let kind = if e.is::<ImageBuildFailed>() {
+                        FailureKind::ImageBuild
+                    } else {
+                        FailureKind::Build
+                    };
                     let reason = if ctx.is_docker() {
                         match e.downcast::<runtime::docker_api::Error>() {
                             Ok(err) => match err {
@@ -71,14 +220,74 @@ impl JobCtx {
                             Err(e) => format!("{:?}", e),
                         }
                     };
-                    JobResult::failure(ctx.id(), duration, reason)
+                    JobResult::failure(ctx.id(), duration, reason, kind)
                 }
-                Ok(output) => JobResult::success(
+                Ok(artifacts) => JobResult::success(
                     ctx.id(),
                     start.elapsed(),
-                    output.to_string_lossy().to_string(),
+                    artifacts
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    artifacts
+                        .into_iter()
+                        .map(|artifact| artifact.path)
+                        .collect(),
                 ),
             },
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_build_failure_does_not_abort_other_images() {
+        let failure =
+            |id, kind| JobResult::failure(id, Duration::ZERO, "failed to build image", kind);
+        let image_failure = failure("pkger-rocky", FailureKind::ImageBuild);
+        let build_failure = failure("other-rocky", FailureKind::Build);
+        let success = JobResult::success("pkger-debian", Duration::ZERO, "", vec![]);
+
+        let mut failures = ImageFailures::new(false);
+        failures.record("pkger", "rocky", &image_failure);
+        assert_eq!(failures.failed_image("pkger"), None);
+
+        let mut failures = ImageFailures::new(true);
+        failures.record("pkger", "debian", &success);
+        failures.record("other", "rocky", &build_failure);
+        assert_eq!(failures.failed_image("pkger"), None);
+        assert_eq!(failures.failed_image("other"), None);
+        failures.record("pkger", "rocky", &image_failure);
+        assert_eq!(failures.failed_image("pkger"), Some("rocky"));
+        assert_eq!(failures.failed_image("other"), None);
+
+        let skipped = failure("pkger-fedora", FailureKind::Skipped);
+        assert_eq!(
+            summary(&[image_failure, build_failure, success, skipped]),
+            "jobs: 1 succeeded, 1 failed to build image, 1 failed to build, 1 skipped, 0 cancelled"
+        );
+    }
+
+    #[test]
+    fn summarizes_results_as_json() {
+        let results = [
+            JobResult::success("pkger-debian", Duration::ZERO, "", vec![]),
+            JobResult::failure("pkger-rocky", Duration::ZERO, "failed", FailureKind::Build),
+            JobResult::success("pkger-ubuntu", Duration::ZERO, "", vec![]),
+            JobResult::failure("pkger-fedora", Duration::ZERO, "", FailureKind::Skipped),
+        ];
+
+        assert_eq!(
+            serde_json::to_value(JsonSummary::new(&results)).unwrap(),
+            serde_json::json!({
+                "succeeded": 2,
+                "failed": 2,
+                "failed_ids": ["pkger-rocky", "pkger-fedora"],
+            })
+        );
+    }
+}
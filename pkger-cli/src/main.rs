@@ -14,9 +14,12 @@ use pkger_core::{ErrContext, Error, Result};
 mod app;
 mod completions;
 mod config;
+mod doctor;
 mod gen;
+mod hooks;
 mod job;
 mod metadata;
+mod notification;
 mod opts;
 mod table;
 
@@ -71,14 +74,44 @@ async fn main() -> Result<()> {
             output_dir,
             images_dir: Some(images_dir),
             log_dir: None,
+            job_logs: false,
             runtime_uri: opts.runtime_uri,
             gpg_key: init_opts.gpg_key,
             gpg_name: init_opts.gpg_name,
+            gpg_keys: Default::default(),
             ssh: None,
             images: vec![],
+            profiles: Default::default(),
+            active_profile: None,
             path: config_path,
             custom_simple_images: None,
             no_color: false,
+            keep_containers: false,
+            container_removal: None,
+            build_cache: false,
+            auto_release: false,
+            share_builds: false,
+            fail_fast_image: false,
+            post_build: vec![],
+            new_versions_only: false,
+            no_check: false,
+            verify_install: false,
+            image_build_timeout: None,
+            allow_privileged: false,
+            checksums: vec![],
+            artifact_permissions: Default::default(),
+            output_layout: Default::default(),
+            read_only_rootfs: false,
+            writable_paths: vec![],
+            limits: None,
+            network: None,
+            dns: vec![],
+            extra_hosts: vec![],
+            registry_auth: vec![],
+            recipe_root: None,
+            platform: None,
+            simple_image_extra_dockerfile: None,
+            runtime_max_attempts: None,
         };
 
         if cfg.path.exists() {
@@ -114,20 +147,91 @@ async fn main() -> Result<()> {
                 .to_string(),
             None => DEFAULT_CONFIG_FILE.to_string(),
         });
+    if let opts::Command::Doctor = opts.command {
+        // the configuration is one of the checks so it can't be required here
+        let mut logger = match log::Config::stdout()
+            .no_color(opts.no_color)
+            .as_collector()
+            .context("failed to initialize global output collector")
+        {
+            Ok(logger) => logger,
+            Err(e) => exit!("execution failed, reason: {:?}", e),
+        };
+        if opts.quiet {
+            logger.set_level(log::Level::Warn);
+        }
+        if let Err(e) = doctor::run(&opts, &config_path, &mut logger).await {
+            exit!("execution failed, reason: {:?}", e);
+        }
+        return Ok(());
+    }
+
     let result = Configuration::load(&config_path).context("failed to load configuration file");
     if let Err(e) = &result {
         exit!("execution failed, reason: {:?}", e);
     }
-    let config = result.unwrap();
+    let mut config = result.unwrap();
+    if let Some(profile) = &opts.profile {
+        if let Err(e) = config.apply_profile(profile) {
+            exit!("execution failed, reason: {:?}", e);
+        }
+    }
 
-    let mut logger_config = if let Some(p) = &opts.log_dir {
+    // with logs per job the output of pkger itself is the summary printed to the terminal
+    let job_logs = config.job_logs
+        || matches!(
+            &opts.command,
+            opts::Command::Build(opts::BuildOpts { job_logs: true, .. })
+        );
+    // the JSON summary has to be the only output on stdout so logs are written to stderr
+    let summary_json = matches!(
+        &opts.command,
+        opts::Command::Build(opts::BuildOpts {
+            summary_json: true,
+            ..
+        })
+    );
+    let terminal = || {
+        if summary_json {
+            log::Config::stderr()
+        } else {
+            log::Config::stdout()
+        }
+    };
+    if summary_json {
+        if let Ok(mut log) = log::GLOBAL_OUTPUT_COLLECTOR.try_write() {
+            *log = Box::new(log::Logger::stderr(None));
+        }
+    }
+    let mut logger_config = if job_logs {
+        terminal()
+    } else if let Some(p) = &opts.log_dir {
         log::Config::file(p.join(format!("pkger-{}.log", timestamp)))
     } else if let Some(p) = &config.log_dir {
         log::Config::file(p.join(format!("pkger-{}.log", timestamp)))
     } else {
-        log::Config::stdout()
+        terminal()
     };
 
+    if let opts::Command::Build(opts::BuildOpts {
+        json_logs_to: Some(path),
+        ..
+    }) = &opts.command
+    {
+        logger_config = logger_config.json_file(path);
+    }
+
+    if opts.log_format == log::Format::Json {
+        logger_config = logger_config.format(log::Format::Json);
+        if let Ok(mut log) = log::GLOBAL_OUTPUT_COLLECTOR.try_write() {
+            *log = if summary_json {
+                Box::new(log::JsonLogger::new(std::io::stderr(), None))
+            } else {
+                Box::new(log::JsonLogger::new(std::io::stdout(), None))
+            };
+        }
+    }
+
     let disable_color = opts.no_color || config.no_color;
     if disable_color {
         logger_config = logger_config.no_color(true);
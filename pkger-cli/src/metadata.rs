@@ -1,10 +1,12 @@
+use pkger_core::build::package::link;
 use pkger_core::recipe::{BuildArch, BuildTarget};
 use pkger_core::{ErrContext, Result};
 
 use lazy_static::lazy_static;
 use regex::Regex;
 use std::convert::TryFrom;
-use std::fs::{DirEntry, Metadata};
+use std::fs::{self, DirEntry, Metadata};
+use std::path::Path;
 use std::time::SystemTime;
 
 lazy_static! {
@@ -15,10 +17,6 @@ lazy_static! {
     static ref APK_RE: Regex = Regex::new(r"([\w_.+@-]+?)-(\d+[.]\d+[.]\d+)-r(\d+)").unwrap();
 }
 
-pub const SUPPORTED_EXTENSIONS: &[&str] = &[
-    "deb", "src.deb", "rpm", "src.rpm", "srpm", "pkg", "apk", "gzip", "tar.gz", "tgz",
-];
-
 #[cfg(unix)]
 fn size(md: &Metadata) -> u64 {
     use std::os::unix::fs::MetadataExt;
@@ -73,14 +71,13 @@ impl PackageMetadata {
     }
 
     pub fn try_from_dir_entry(e: &DirEntry) -> Result<Self> {
-        let path = e.path();
-        let extension = path.extension().context("expected file extension")?;
-        let package_type = BuildTarget::try_from(extension.to_string_lossy().as_ref())?;
-        let path = path
-            .file_stem()
-            .context("expected a file name")?
-            .to_string_lossy();
-        let path = path.as_ref();
+        let file_name = e.file_name().to_string_lossy().to_string();
+        let package_type =
+            link::package_target(&file_name).context("unknown package file extension")?;
+        let path = file_name
+            .strip_suffix(link::package_extension(&file_name))
+            .and_then(|stem| stem.strip_suffix('.'))
+            .context("expected a file name")?;
 
         let (created, size) = e
             .metadata()
@@ -163,9 +160,35 @@ impl PackageMetadata {
     }
 }
 
+/// Returns metadata of all packages in `dir` whose names match one of the known schemes.
+pub fn read_packages(dir: &Path) -> Vec<PackageMetadata> {
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| PackageMetadata::try_from_dir_entry(&entry).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Returns the highest release of existing `packages` of `name` in `version` or `None` if there
+/// are no such packages. Packages with non-numeric releases are ignored.
+pub fn latest_release<'p>(
+    packages: impl IntoIterator<Item = &'p PackageMetadata>,
+    name: &str,
+    version: &str,
+) -> Option<u64> {
+    packages
+        .into_iter()
+        .filter(|package| package.name == name && package.version == version)
+        .filter_map(|package| package.release.as_ref()?.parse::<u64>().ok())
+        .max()
+}
+
 #[cfg(test)]
 mod tests {
-    use super::PackageMetadata;
+    use super::{latest_release, PackageMetadata};
     use pkger_core::recipe::{BuildArch, BuildTarget};
     use std::time::SystemTime;
 
@@ -242,4 +265,28 @@ mod tests {
             PackageMetadata::try_from_str(path, BuildTarget::Pkg, None, None).unwrap(),
         );
     }
+
+    #[test]
+    fn finds_latest_release() {
+        let packages: Vec<_> = [
+            "pkger-0.11.0-0.x86_64",
+            "pkger-0.11.0-3.x86_64",
+            "pkger-0.11.0-1.x86_64",
+            "pkger-0.10.0-7.x86_64",
+            "pkger-tools-0.11.0-9.x86_64",
+        ]
+        .iter()
+        .map(|path| PackageMetadata::try_from_str(path, BuildTarget::Rpm, None, None).unwrap())
+        .collect();
+
+        assert_eq!(latest_release(&packages, "pkger", "0.11.0"), Some(3));
+        assert_eq!(latest_release(&packages, "pkger", "0.10.0"), Some(7));
+        assert_eq!(latest_release(&packages, "pkger", "0.12.0"), None);
+
+        let mut non_numeric =
+            PackageMetadata::try_from_str("pkger-0.12.0-1.x86_64", BuildTarget::Rpm, None, None)
+                .unwrap();
+        non_numeric.release = Some("1rc".to_string());
+        assert_eq!(latest_release([&non_numeric], "pkger", "0.12.0"), None);
+    }
 }
@@ -0,0 +1,220 @@
+use crate::job::{summary, FailureKind, JobResult};
+use pkger_core::log::{debug, warning, BoxedCollector};
+use pkger_core::{err, ErrContext, Error, Result};
+
+use serde::Serialize;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Time after which a webhook request is abandoned so that an unresponsive server doesn't hold
+/// up the end of the build.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where to send a notification once all jobs of a build finished.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Notifier {
+    /// A notification shown on the desktop of the current user.
+    Desktop,
+    /// A URL that receives a `POST` request with a JSON summary of the results.
+    Webhook(String),
+}
+
+impl FromStr for Notifier {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "desktop" {
+            Ok(Self::Desktop)
+        } else if s.starts_with("http://") || s.starts_with("https://") {
+            Ok(Self::Webhook(s.to_string()))
+        } else {
+            err!(
+                "invalid notification target `{}`, expected `desktop` or an http(s) URL",
+                s
+            )
+        }
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+/// Body of the request sent to webhooks.
+pub struct Payload {
+    pub summary: String,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub jobs: Vec<JobPayload>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct JobPayload {
+    pub id: String,
+    /// One of `success`, `image-build-failure`, `failure`, `skipped` or `cancelled`.
+    pub status: &'static str,
+    pub duration_secs: f64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl Payload {
+    pub fn new(results: &[JobResult]) -> Self {
+        let jobs = results
+            .iter()
+            .map(|result| match result {
+                JobResult::Success {
+                    id,
+                    duration,
+                    artifacts,
+                    ..
+                } => JobPayload {
+                    id: id.clone(),
+                    status: "success",
+                    duration_secs: duration.as_secs_f64(),
+                    artifacts: artifacts.clone(),
+                    reason: None,
+                },
+                JobResult::Failure {
+                    id,
+                    duration,
+                    reason,
+                    kind,
+                    ..
+                } => JobPayload {
+                    id: id.clone(),
+                    status: match kind {
+                        FailureKind::ImageBuild => "image-build-failure",
+                        FailureKind::Build => "failure",
+                        FailureKind::Skipped => "skipped",
+                        FailureKind::Cancelled => "cancelled",
+                    },
+                    duration_secs: duration.as_secs_f64(),
+                    artifacts: vec![],
+                    reason: Some(reason.clone()),
+                },
+            })
+            .collect::<Vec<_>>();
+        let succeeded = jobs.iter().filter(|job| job.status == "success").count();
+
+        Self {
+            summary: summary(results),
+            succeeded,
+            failed: jobs.len() - succeeded,
+            jobs,
+        }
+    }
+
+    fn title(&self) -> String {
+        if self.failed == 0 {
+            format!("pkger: {} jobs succeeded", self.succeeded)
+        } else {
+            format!("pkger: {} of {} jobs failed", self.failed, self.jobs.len())
+        }
+    }
+}
+
+/// Sends the `payload` to the `notifier`. It blocks until the notification is sent so it has to
+/// run on a blocking thread.
+fn send(notifier: &Notifier, payload: &Payload) -> Result<()> {
+    match notifier {
+        Notifier::Desktop => notify_rust::Notification::new()
+            .summary(&payload.title())
+            .body(&payload.summary)
+            .show()
+            .map(|_| ())
+            .context("failed to show desktop notification"),
+        Notifier::Webhook(url) => {
+            let body = serde_json::to_string(payload).context("failed to serialize results")?;
+            ureq::AgentBuilder::new()
+                .timeout(WEBHOOK_TIMEOUT)
+                .build()
+                .post(url)
+                .set("Content-Type", "application/json")
+                .send_string(&body)
+                .map(|_| ())
+                .with_context(|| format!("failed to post results to `{}`", url))
+        }
+    }
+}
+
+/// Sends the `results` to all `notifiers`. Failures are only logged so that they don't affect the
+/// result of the build.
+pub async fn notify(notifiers: &[Notifier], results: &[JobResult], logger: &mut BoxedCollector) {
+    if notifiers.is_empty() {
+        return;
+    }
+    let payload = Arc::new(Payload::new(results));
+    for notifier in notifiers {
+        debug!(logger => "sending notification, notifier = {:?}", notifier);
+        let (notifier, payload) = (notifier.clone(), payload.clone());
+        let sent = tokio::task::spawn_blocking(move || send(&notifier, &payload))
+            .await
+            .context("failed to send notification")
+            .and_then(|sent| sent);
+        if let Err(e) = sent {
+            warning!(logger => "{:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn builds_webhook_payload_from_results() {
+        let results = vec![
+            JobResult::success(
+                "pkger-rocky",
+                Duration::from_millis(1500),
+                "done",
+                vec![PathBuf::from("/out/rocky/pkger-0.11.0-0.x86_64.rpm")],
+            ),
+            JobResult::failure(
+                "pkger-debian",
+                Duration::from_secs(2),
+                "failed to build image",
+                FailureKind::ImageBuild,
+            ),
+        ];
+
+        let payload = Payload::new(&results);
+        assert_eq!(payload.succeeded, 1);
+        assert_eq!(payload.failed, 1);
+        assert_eq!(payload.title(), "pkger: 1 of 2 jobs failed");
+        assert_eq!(
+            serde_json::to_value(&payload).unwrap(),
+            serde_json::json!({
+                "summary": "jobs: 1 succeeded, 1 failed to build image, 0 failed to build, 0 skipped, 0 cancelled",
+                "succeeded": 1,
+                "failed": 1,
+                "jobs": [
+                    {
+                        "id": "pkger-rocky",
+                        "status": "success",
+                        "duration_secs": 1.5,
+                        "artifacts": ["/out/rocky/pkger-0.11.0-0.x86_64.rpm"],
+                    },
+                    {
+                        "id": "pkger-debian",
+                        "status": "image-build-failure",
+                        "duration_secs": 2.0,
+                        "reason": "failed to build image",
+                    },
+                ],
+            })
+        );
+
+        assert_eq!("desktop".parse::<Notifier>().unwrap(), Notifier::Desktop);
+        assert_eq!(
+            "https://hooks.example.com/pkger"
+                .parse::<Notifier>()
+                .unwrap(),
+            Notifier::Webhook("https://hooks.example.com/pkger".to_string())
+        );
+        assert!("email".parse::<Notifier>().is_err());
+    }
+}
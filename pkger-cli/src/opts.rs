@@ -1,6 +1,12 @@
 use crate::completions::Shell;
+use crate::notification::Notifier;
+use crate::Error;
 use clap::Parser;
+use pkger_core::build::container::ContainerRemoval;
+use pkger_core::log;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
 pub const APP_NAME: &str = "pkger";
 
@@ -36,11 +42,26 @@ pub struct Opts {
     /// URL to container runtime daemon listening on a unix or tcp socket. An example could be
     /// `unix:///var/run/docker.sock` or a tcp uri `tcp://127.0.0.1:81`. By default, on a unix host
     /// pkger will try to connect to a unix socket at locations like
-    /// `/run/user/1000/podman/podman.sock` or `/run/docker.sock`.
+    /// `$XDG_RUNTIME_DIR/podman/podman.sock`, `/run/user/1000/podman/podman.sock` or
+    /// `/run/docker.sock`.
     pub runtime_uri: Option<String>,
 
     #[arg(long)]
     pub no_color: bool,
+
+    #[arg(long, default_value = "text")]
+    /// Format of the output, either `text` or `json`. With `json` every log record is printed as
+    /// a JSON object on a line of its own.
+    pub log_format: log::Format,
+
+    #[arg(long)]
+    /// Directory in which base recipes referenced by `from` are looked up. Defaults to
+    /// `recipes_dir`.
+    pub recipe_root: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Use the images of this profile from `profiles` in the configuration instead of `images`.
+    pub profile: Option<String>,
 }
 
 impl Opts {
@@ -66,10 +87,19 @@ pub enum Command {
         #[arg(short, long)]
         /// Should the output be more verbose and include fields like version, arch...
         verbose: bool,
+        #[arg(short, long, default_value = "table")]
+        /// Output format, either `table` or `json`. JSON output always includes all fields.
+        format: ListFormat,
     },
     #[command(alias = "cc")]
     /// Deletes the cache files with image state.
     CleanCache,
+    /// Removes build artifacts.
+    Clean {
+        #[command(subcommand)]
+        /// An object to clean like `output`.
+        object: CleanObject,
+    },
     #[command(alias = "e")]
     /// Edit a recipe or an image.
     Edit {
@@ -105,12 +135,44 @@ pub enum Command {
     Init(InitOpts),
     /// Prints completions for the specified shell
     PrintCompletions(CompletionsOpts),
+    /// Generate package repository metadata from built packages.
+    Repo {
+        #[command(subcommand)]
+        /// A type of the repository like `rpm` or `deb`.
+        object: RepoObject,
+    },
+    /// Diagnose the environment by checking the configuration, the connection to the container
+    /// runtime, directories, recipes and GPG keys. All checks are run even if some of them fail.
+    Doctor,
     /// Run various checks to verify health of the setup
     Check {
         #[command(subcommand)]
         /// An object to check
         object: CheckObject,
     },
+    /// Print the rendered package manifest of a recipe like an RPM spec or a PKGBUILD without
+    /// building it. Sources and files of the package are replaced with placeholders.
+    Inspect {
+        /// Recipe to inspect.
+        recipe: String,
+        #[arg(short, long)]
+        /// Target of the manifest, one of `rpm`, `deb`, `pkg` or `apk`.
+        target: String,
+        #[arg(short, long)]
+        /// Image for which the dependencies are resolved. Defaults to the simple image of the
+        /// target.
+        image: Option<String>,
+        #[arg(long)]
+        /// Version of the recipe to render. Defaults to the first version of the recipe.
+        version: Option<String>,
+    },
+    /// Validate recipes without building them. Fails if any recipe can't be loaded or has
+    /// problems like warnings or empty build steps.
+    Validate {
+        /// Recipes to validate. If empty all recipes in the `recipes_dir` directory will be
+        /// validated.
+        recipes: Vec<String>,
+    },
 }
 
 #[derive(Debug, Parser)]
@@ -140,6 +202,25 @@ pub enum CheckObject {
     #[command(aliases = &["conn", "con"])]
     /// Verify the connection to the container runtime daemon.
     Connection,
+    #[command(aliases = &["recipe", "rcp"])]
+    /// Load recipes and report any errors or warnings.
+    Recipes {
+        /// Recipes to check. If empty all recipes in the `recipes_dir` directory will be checked.
+        names: Vec<String>,
+        #[arg(long)]
+        /// Treat recipe warnings as errors.
+        fail_on_warnings: bool,
+    },
+    #[command(aliases = &["image", "img"])]
+    /// Build images defined in the configuration without running any recipe and report which
+    /// of them fail to build.
+    Images {
+        /// Images to check. If empty all images defined in the configuration will be checked.
+        names: Vec<String>,
+    },
+    #[command(aliases = &["inherit", "from"])]
+    /// Verify that the `from` references of all recipes resolve and don't form cycles.
+    Inheritance,
 }
 
 #[derive(Debug, Parser)]
@@ -157,7 +238,11 @@ pub enum ListObject {
     #[command(aliases = &["image", "img"])]
     Images,
     #[command(aliases = &["recipe", "rcp"])]
-    Recipes,
+    Recipes {
+        #[arg(long)]
+        /// Only list recipes that fail to load along with their errors.
+        errors_only: bool,
+    },
     #[command(aliases = &["package", "pkg"])]
     Packages {
         #[arg(short, long)]
@@ -166,6 +251,73 @@ pub enum ListObject {
     },
 }
 
+#[derive(Debug, Parser)]
+pub enum CleanObject {
+    #[command(alias = "out")]
+    /// Remove packages from the output directory.
+    Output {
+        #[arg(long)]
+        /// Only remove packages created before this long ago, like `30d`, `12h` or `90m`.
+        older_than: Option<Age>,
+        #[arg(short, long)]
+        /// Only remove packages of these images. Can be passed multiple times.
+        image: Vec<String>,
+        #[arg(short, long)]
+        /// Remove the packages without asking for confirmation.
+        yes: bool,
+    },
+}
+
+/// Age of a file parsed from a number with a unit like `30d`, one of `s`, `m`, `h`, `d` or `w`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Age(pub Duration);
+
+impl FromStr for Age {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let unit_start = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (value, unit) = s.split_at(unit_start);
+        let value: u64 = value
+            .parse()
+            .map_err(|_| Error::msg(format!("invalid age `{}`, expected a value like `30d`", s)))?;
+        let secs = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            "w" => 7 * 24 * 60 * 60,
+            _ => {
+                return Err(Error::msg(format!(
+                    "invalid unit `{}` of age `{}`, expected one of `s`, `m`, `h`, `d` or `w`",
+                    unit, s
+                )))
+            }
+        };
+        value
+            .checked_mul(secs)
+            .map(|secs| Age(Duration::from_secs(secs)))
+            .ok_or_else(|| Error::msg(format!("age `{}` is too large", s)))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListFormat {
+    Table,
+    Json,
+}
+
+impl FromStr for ListFormat {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "table" => Ok(ListFormat::Table),
+            "json" => Ok(ListFormat::Json),
+            _ => Err(Error::msg(format!("invalid format `{}`", s))),
+        }
+    }
+}
+
 #[derive(Debug, Parser)]
 pub enum CopyObject {
     #[command(alias = "rcp")]
@@ -197,6 +349,31 @@ pub enum NewObject {
     },
 }
 
+#[derive(Debug, Parser)]
+pub enum RepoObject {
+    /// Generate YUM/DNF repository metadata with `createrepo_c`. The `repodata` directory is
+    /// saved in the repository directory and `repomd.xml` is signed if a GPG key is configured.
+    Rpm {
+        /// Directory with RPM packages. Can also be the name of an image directory in
+        /// `output_dir`.
+        dir: PathBuf,
+        #[arg(long)]
+        /// Don't sign the repository metadata.
+        no_sign: bool,
+    },
+    /// Generate a flat APT repository with `dpkg-scanpackages` and `apt-ftparchive`. The
+    /// `Packages`, `Packages.gz` and `Release` files are saved in the repository directory and
+    /// `Release` is signed if a GPG key is configured.
+    Deb {
+        /// Directory with Debian packages. Can also be the name of an image directory in
+        /// `output_dir`.
+        dir: PathBuf,
+        #[arg(long)]
+        /// Don't sign the repository index.
+        no_sign: bool,
+    },
+}
+
 #[derive(Debug, Parser)]
 pub enum RemoveObject {
     #[command(aliases = &["recipe", "rcp"])]
@@ -213,7 +390,7 @@ pub enum RemoveObject {
     },
 }
 
-#[derive(Debug, Parser)]
+#[derive(Clone, Debug, Parser)]
 pub struct BuildOpts {
     /// Recipes to build. If empty all recipes in the `recipes_dir` directory will be built.
     pub recipes: Vec<String>,
@@ -240,6 +417,124 @@ pub struct BuildOpts {
     #[arg(short, long)]
     /// Override output directory specified in the configuration
     pub output_dir: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Forward the SSH agent of the host so that git sources and build steps can authenticate
+    /// with its keys, same as `ssh.forward_agent` in the configuration. Every build container
+    /// gets access to all keys loaded in the agent, only use this with trusted recipes.
+    pub forward_ssh_agent: bool,
+
+    #[arg(long)]
+    /// Run build containers with a read-only root filesystem, same as `read_only_rootfs` in the
+    /// configuration.
+    pub read_only_rootfs: bool,
+
+    #[arg(long)]
+    /// Send a notification when all jobs finished, `desktop` shows a desktop notification and an
+    /// http(s) URL receives a `POST` request with a JSON summary of the results. Can be repeated.
+    pub notify: Vec<Notifier>,
+
+    #[arg(long)]
+    /// Treat recipe warnings like a non-SPDX license or an overlong summary as errors.
+    pub fail_on_warnings: bool,
+
+    #[arg(long)]
+    /// Don't remove the build container when a build fails so that it can be inspected.
+    pub keep_on_failure: bool,
+
+    #[arg(long, value_name = "POLICY")]
+    /// When to remove build containers, one of `always`, `on-success` or `never`. `never` keeps
+    /// all containers until they are removed manually.
+    pub container_removal: Option<ContainerRemoval>,
+
+    #[arg(long)]
+    /// Save the build directory after the build phase and reuse it on the next build of the same
+    /// recipe and image as long as the recipe doesn't change.
+    pub build_cache: bool,
+
+    #[arg(long)]
+    /// Increment the release of a package if the same version was already built or a package
+    /// with the same version already exists in the output directory.
+    pub auto_release: bool,
+
+    #[arg(long)]
+    /// Use this release for all built packages instead of the release of the recipes. Takes
+    /// precedence over `--auto-release` and `auto_release` of recipes.
+    pub release: Option<String>,
+
+    #[arg(long, value_delimiter = ',')]
+    /// Build every recipe once for each of these architectures like `x86_64,aarch64`, overriding
+    /// the `arch` of the recipes. The images are built for the matching platforms, so building
+    /// for a foreign architecture needs emulation like `qemu-user-static` on the host.
+    pub arch: Vec<String>,
+
+    #[arg(long)]
+    /// Remove packages and checksum files of previous builds of the built recipes from their
+    /// output directories before packaging. Packages of other recipes are never removed.
+    pub clean_output: bool,
+
+    #[arg(long, requires = "clean_output")]
+    /// Only print the files that `--clean-output` would remove.
+    pub dry_run: bool,
+
+    #[arg(long)]
+    /// Build a recipe once per image and version and package it in every format targeted on that
+    /// image instead of running a separate build for each format.
+    pub share_builds: bool,
+
+    #[arg(long)]
+    /// Skip the remaining jobs of a recipe when one of its images fails to build. By default the
+    /// recipe is still built on the other images.
+    pub fail_fast_image: bool,
+
+    #[arg(long)]
+    /// Save packages in `<output_dir>/<recipe>/<image>` instead of `<output_dir>/<image>`.
+    pub artifact_dir_per_recipe: bool,
+
+    #[arg(long)]
+    /// Only build versions of recipes that weren't successfully built before for the same image
+    /// and target.
+    pub new_versions_only: bool,
+
+    #[arg(long)]
+    /// Don't run the `check` steps of recipes.
+    pub no_check: bool,
+
+    #[arg(long)]
+    /// Install the built packages in the build container with the package manager of the image
+    /// to check that they install and their dependencies resolve.
+    pub verify_install: bool,
+
+    #[arg(long, value_name = "SECONDS")]
+    /// Fail the build if building its image takes longer than this many seconds. Doesn't limit
+    /// the rest of the build.
+    pub image_build_timeout: Option<u64>,
+
+    #[arg(long)]
+    /// Load environment variables from a dotenv file. The variables override the ones defined in
+    /// recipes.
+    pub env_from_file: Option<PathBuf>,
+    #[arg(long)]
+    /// After the build keep watching directories of the built recipes and rebuild a recipe
+    /// whenever any of its files like `recipe.yml` or patches change. Stop with ctrl-c.
+    pub watch: bool,
+    #[arg(long)]
+    /// Print the environment that would be set in the container of each build without building
+    /// anything. Values of variables that look like secrets are masked.
+    pub print_env: bool,
+    #[arg(long)]
+    /// Additionally write all log records as JSON lines appended to this file. The regular output
+    /// is not affected.
+    pub json_logs_to: Option<PathBuf>,
+    #[arg(long)]
+    /// Write the output of every job to a file of its own in the log directory, named after the
+    /// recipe, version, image, target and start time of the job. A summary of the jobs is still
+    /// printed to the terminal.
+    pub job_logs: bool,
+    #[arg(long)]
+    /// Print a JSON summary of the jobs with the number of succeeded and failed jobs and the ids
+    /// of the failed ones to stdout after the build. Logs are written to stderr instead.
+    pub summary_json: bool,
 }
 
 #[derive(Debug, Parser)]
@@ -296,6 +591,8 @@ pub struct GenRecipeOpts {
     pub conflicts: Option<Vec<String>>,
     #[arg(long, action = clap::ArgAction::Append, num_args = 0..)]
     pub provides: Option<Vec<String>>,
+    #[arg(long, action = clap::ArgAction::Append, num_args = 0..)]
+    pub obsoletes: Option<Vec<String>>,
 
     #[arg(long, action = clap::ArgAction::Append, num_args = 0..)]
     pub patches: Option<Vec<String>>,
@@ -376,3 +673,20 @@ pub struct CompletionsOpts {
     /// powershell, zsh
     pub shell: Shell,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_age() {
+        assert_eq!(
+            "30d".parse::<Age>().unwrap().0,
+            Duration::from_secs(30 * 24 * 60 * 60)
+        );
+        assert_eq!("15m".parse::<Age>().unwrap().0, Duration::from_secs(900));
+        assert!("30".parse::<Age>().is_err());
+        assert!("d".parse::<Age>().is_err());
+        assert!("18446744073709551615w".parse::<Age>().is_err());
+    }
+}
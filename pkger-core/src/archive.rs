@@ -8,52 +8,67 @@ use crate::{ErrContext, Result};
 
 use flate2::write::GzEncoder;
 use flate2::Compression;
+use futures::{Stream, StreamExt};
 use std::fs::File;
-use std::io;
-use std::io::prelude::*;
+use std::io::{self, Write};
 use std::path::Path;
+use tokio_util::io::StreamReader;
 
-/// Unpacks a given tar archive to the path specified by `output_dir`
-pub fn unpack_tarball<T: io::Read, P: AsRef<Path>>(
-    archive: &mut tar::Archive<T>,
+/// Unpacks a tar archive read from a `stream` of chunks, like the one returned when copying
+/// files out of a container, to the path specified by `output_dir`. The next chunk is only
+/// fetched once the previous one was consumed so that the whole archive is never held in memory.
+pub async fn unpack_stream<S, P>(
+    stream: S,
     output_dir: P,
     logger: &mut BoxedCollector,
-) -> Result<()> {
+) -> Result<()>
+where
+    S: Stream<Item = Result<Vec<u8>>> + Unpin,
+    P: AsRef<Path>,
+{
     let output_dir = output_dir.as_ref();
     debug!(logger => "unpacking archive to {}", output_dir.display());
 
-    for entry in archive.entries()? {
+    let reader = StreamReader::new(stream.map(|chunk| {
+        chunk
+            .map(io::Cursor::new)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }));
+    let mut archive = tokio_tar::Archive::new(reader);
+    let mut entries = archive.entries()?;
+    while let Some(entry) = entries.next().await {
         let mut entry = entry?;
-        if let tar::EntryType::Regular = entry.header().entry_type() {
+        if let tokio_tar::EntryType::Regular = entry.header().entry_type() {
             let path = entry.header().path()?.to_path_buf();
             trace!(logger => "unpacking {}", path.display());
             let name = path.file_name().unwrap_or_default();
 
-            entry.unpack(output_dir.join(name))?;
+            entry.unpack(output_dir.join(name)).await?;
         }
     }
 
     Ok(())
 }
 
-/// Save the given tar archive as gzip encoded tar to path specified by `output_dir` with the
-/// filename set to `name`.
-pub fn save_tar_gz<T: io::Read>(
-    archive: tar::Archive<T>,
+/// Saves the tar archive read from a `stream` of chunks as gzip encoded tar to path specified by
+/// `output_dir` with the filename set to `name`.
+pub async fn save_tar_gz<S>(
+    mut stream: S,
     name: &str,
     output_dir: &Path,
     logger: &mut BoxedCollector,
-) -> Result<()> {
+) -> Result<()>
+where
+    S: Stream<Item = Result<Vec<u8>>> + Unpin,
+{
     let path = output_dir.join(name);
     debug!(logger => "creating a gzipped tar archive, name: {}, path: {}", name, output_dir.display());
 
     let f = File::create(path.as_path())?;
     let mut e = GzEncoder::new(f, Compression::default());
-    let mut archive = archive.into_inner();
-    let mut bytes = Vec::new();
-    archive.read_to_end(&mut bytes)?;
-
-    e.write_all(&bytes)?;
+    while let Some(chunk) = stream.next().await {
+        e.write_all(&chunk?)?;
+    }
 
     e.finish()?;
 
@@ -86,3 +101,38 @@ where
 
     archive.into_inner().context("failed to create tar archive")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn unpacks_archive_from_stream() {
+        let mut logger: BoxedCollector = Box::new(crate::log::Logger::stdout(None));
+        // copying a single file out of a container returns an archive with a single entry
+        let package = vec![7u8; 1500];
+        let archive = create_tarball(
+            [(Path::new("pkger-0.11.0-0.x86_64.rpm"), &package[..])].into_iter(),
+            &mut logger,
+        )
+        .unwrap();
+
+        // chunks split the headers at arbitrary offsets, empty chunks don't end the stream and
+        // the chunk after the end of the archive is never fetched
+        let mut chunks = archive
+            .chunks(333)
+            .map(|c| Ok(c.to_vec()))
+            .collect::<Vec<_>>();
+        chunks.insert(1, Ok(vec![]));
+        chunks.push(Err(crate::anyhow!("read past the end of the archive")));
+
+        let dir = tempdir::TempDir::new("pkger-unpack-stream").unwrap();
+        unpack_stream(futures::stream::iter(chunks), dir.path(), &mut logger)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read(dir.path().join("pkger-0.11.0-0.x86_64.rpm")).unwrap(),
+            package
+        );
+    }
+}
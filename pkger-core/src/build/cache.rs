@@ -0,0 +1,238 @@
+use crate::build::container::{self, Context};
+use crate::hash::Fnv64;
+use crate::image::ImageState;
+use crate::log::{info, trace, BoxedCollector};
+use crate::recipe::BuildTarget;
+use crate::runtime::container::ExecOpts;
+use crate::{ErrContext, Result};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub static DEFAULT_CACHE_DIR: &str = ".pkger.builds";
+
+/// Host cache of build directories. An entry holds the build directory of a container after the
+/// build phase so that the next build of the same recipe on the same image can skip fetching the
+/// sources and running the configure and build scripts.
+#[derive(Clone, Debug)]
+pub struct BuildCache {
+    dir: PathBuf,
+}
+
+/// Everything that invalidates a cached build directory when it changes.
+pub struct CacheKey<'key> {
+    pub recipe: &'key str,
+    pub version: &'key str,
+    pub image: &'key str,
+    pub target: BuildTarget,
+    /// Id of the image the build ran on, changes when dependencies are installed again.
+    pub image_id: &'key str,
+    /// Directory of the recipe with the recipe file, local sources and patches.
+    pub recipe_dir: &'key Path,
+    /// Environment of the build container as sorted `KEY=VALUE` entries, it includes variables
+    /// from `env_file` of the recipe and from `--env-from-file`.
+    pub env: Vec<String>,
+    /// Remote sources, scripts and other parts of the recipe that affect the build.
+    pub extra: Vec<String>,
+}
+
+impl CacheKey<'_> {
+    pub fn hash(&self) -> Result<String> {
+        let mut hasher = Fnv64::default();
+        hasher.write_field(self.recipe);
+        hasher.write_field(self.version);
+        hasher.write_field(self.image);
+        hasher.write_field(self.target.as_ref());
+        hasher.write_field(self.image_id);
+        for var in &self.env {
+            hasher.write_field(var);
+        }
+        for extra in &self.extra {
+            hasher.write_field(extra);
+        }
+        if self.recipe_dir.is_dir() {
+            hasher
+                .write_dir(self.recipe_dir)
+                .context("failed to hash recipe files")?;
+        }
+        Ok(hasher.finish_hex())
+    }
+}
+
+impl BuildCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Returns the path of the archive for the given key.
+    pub fn entry(&self, key: &CacheKey) -> Result<PathBuf> {
+        Ok(self.dir.join(key.recipe).join(format!(
+            "{}-{}-{}.tar",
+            key.image,
+            key.version,
+            key.hash()?
+        )))
+    }
+
+    /// Returns the archive of the build directory if there is one saved for the given key.
+    pub fn find(&self, key: &CacheKey) -> Result<Option<PathBuf>> {
+        let entry = self.entry(key)?;
+        Ok(if entry.is_file() { Some(entry) } else { None })
+    }
+
+    /// Saves the archive of a build directory and removes older entries of the same recipe, image
+    /// and version as they can never be restored again.
+    pub fn save(&self, key: &CacheKey, archive: &[u8]) -> Result<PathBuf> {
+        let entry = self.entry(key)?;
+        let parent = entry.parent().unwrap_or(&self.dir);
+        fs::create_dir_all(parent).context("failed to create build cache directory")?;
+
+        let prefix = format!("{}-{}-", key.image, key.version);
+        for old in fs::read_dir(parent)? {
+            let old = old?.path();
+            let is_stale = old
+                .file_name()
+                .and_then(|name| {
+                    let name = name.to_string_lossy();
+                    name.strip_prefix(&prefix)
+                        .and_then(|rest| rest.strip_suffix(".tar"))
+                        .map(|hash| hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit()))
+                })
+                .unwrap_or_default();
+            if is_stale && old != entry {
+                fs::remove_file(&old).context("failed to remove stale build cache entry")?;
+            }
+        }
+
+        let tmp = entry.with_extension("tar.tmp");
+        fs::write(&tmp, archive).context("failed to write build cache entry")?;
+        fs::rename(&tmp, &entry).context("failed to write build cache entry")?;
+        Ok(entry)
+    }
+}
+
+/// Returns the cache key of the build in `ctx` running on the image from `image_state`.
+pub fn key<'ctx>(ctx: &'ctx Context<'_>, image_state: &'ctx ImageState) -> CacheKey<'ctx> {
+    let recipe = &ctx.build.recipe;
+    let mut extra: Vec<_> = recipe
+        .metadata
+        .source
+        .iter()
+        .map(|source| source.url().to_string())
+        .collect();
+    if let Some(git) = &recipe.metadata.git {
+        extra.push(format!("{:?}", git));
+    }
+    extra.push(format!("{:?}", recipe.post_fetch_script));
+    extra.push(format!("{:?}", recipe.configure_script));
+    extra.push(format!("{:?}", recipe.build_script));
+
+    // the build and output directories are named after the time of the build
+    let mut env = container::environment(ctx.build, Some(&image_state.os));
+    env.remove("PKGER_BLD_DIR");
+    env.remove("PKGER_OUT_DIR");
+
+    CacheKey {
+        recipe: &recipe.metadata.name,
+        version: &ctx.build.build_version,
+        image: ctx.build.target.image(),
+        target: *ctx.build.target.build_target(),
+        image_id: &image_state.id,
+        recipe_dir: &recipe.recipe_dir,
+        env: env.sorted_kv_vec(),
+        extra,
+    }
+}
+
+/// Saves the build directory of the container in `cache`.
+pub async fn save(
+    ctx: &Context<'_>,
+    cache: &BuildCache,
+    key: &CacheKey<'_>,
+    logger: &mut BoxedCollector,
+) -> Result<()> {
+    info!(logger => "saving build directory to the build cache");
+    let archive = ctx
+        .container
+        .copy_from(&ctx.build.container_bld_dir, logger)
+        .await
+        .context("failed to copy build directory from container")?;
+    let entry = cache.save(key, &archive)?;
+    trace!(logger => "saved build cache entry {}", entry.display());
+    Ok(())
+}
+
+/// Restores the build directory of the container from the cached `entry`.
+pub async fn restore(ctx: &Context<'_>, entry: &Path, logger: &mut BoxedCollector) -> Result<()> {
+    info!(logger => "restoring build directory from the build cache, entry: {}", entry.display());
+    let archive = fs::read(entry).context("failed to read build cache entry")?;
+    let archive_path = ctx
+        .container
+        .upload_archive(
+            archive,
+            &ctx.build.container_tmp_dir,
+            "build-cache.tar",
+            logger,
+        )
+        .await?;
+
+    // the archive contains the build directory of the cached build which has a different name
+    ctx.checked_exec(
+        &ExecOpts::default().cmd(&format!(
+            "tar -xf {0} --strip-components=1 -C {1} && rm -f {0}",
+            archive_path.display(),
+            ctx.build.container_bld_dir.display()
+        )),
+        logger,
+    )
+    .await
+    .map(|_| ())
+    .context("failed to extract build cache entry")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn restores_only_matching_builds() {
+        let dir = TempDir::new("pkger-build-cache").unwrap();
+        let recipe_dir = dir.path().join("recipe");
+        fs::create_dir(&recipe_dir).unwrap();
+        fs::write(recipe_dir.join("recipe.yml"), "metadata: {}").unwrap();
+
+        let cache = BuildCache::new(dir.path().join("cache"));
+        let key = |image_id| CacheKey {
+            env: vec!["CFLAGS=-O2".into()],
+            recipe: "test",
+            version: "1.0.0",
+            image: "rocky",
+            target: BuildTarget::Rpm,
+            image_id,
+            recipe_dir: &recipe_dir,
+            extra: vec!["https://example.com/test-1.0.0.tar.gz".into()],
+        };
+
+        assert_eq!(key("id").hash().unwrap(), key("id").hash().unwrap());
+        assert_ne!(key("id").hash().unwrap(), key("other").hash().unwrap());
+        let mut other_env = key("id");
+        other_env.env = vec!["CFLAGS=-O0".into()];
+        assert_ne!(key("id").hash().unwrap(), other_env.hash().unwrap());
+        assert!(cache.find(&key("id")).unwrap().is_none());
+
+        let entry = cache.save(&key("id"), b"archive").unwrap();
+        assert_eq!(cache.find(&key("id")).unwrap(), Some(entry.clone()));
+        assert!(cache.find(&key("other")).unwrap().is_none());
+
+        // changing the recipe invalidates the entry
+        fs::write(recipe_dir.join("recipe.yml"), "metadata: { name: test }").unwrap();
+        assert!(cache.find(&key("id")).unwrap().is_none());
+
+        // saving a new entry removes the stale one
+        let new_entry = cache.save(&key("id"), b"archive").unwrap();
+        assert_ne!(entry, new_entry);
+        assert!(!entry.exists());
+        assert!(new_entry.exists());
+    }
+}
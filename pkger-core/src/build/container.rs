@@ -1,16 +1,133 @@
 use crate::build;
 use crate::image::ImageState;
-use crate::log::{debug, info, trace, BoxedCollector};
+use crate::log::{debug, info, trace, warning, BoxedCollector};
 use crate::runtime::container::{fix_name, Container, CreateOpts, ExecOpts, Output};
-use crate::runtime::{DockerContainer, PodmanContainer, RuntimeConnector};
+use crate::runtime::{DockerContainer, PodmanContainer, RetryingContainer, RuntimeConnector};
 use crate::ssh;
 use crate::{err, ErrContext, Error, Result};
 
-use crate::recipe::Env;
+use crate::recipe::{BuildTarget, Env, Os};
+use serde::{Deserialize, Serialize};
 use std::path::Path;
+use std::str::FromStr;
 
 pub static SESSION_LABEL_KEY: &str = "pkger.session";
 
+/// Decides whether the build container is removed after the build finishes.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContainerRemoval {
+    #[default]
+    Always,
+    OnSuccess,
+    Never,
+}
+
+impl ContainerRemoval {
+    /// Returns `true` if the container of a build that ended with `success` should be removed.
+    pub fn should_remove(&self, success: bool) -> bool {
+        match self {
+            ContainerRemoval::Always => true,
+            ContainerRemoval::OnSuccess => success,
+            ContainerRemoval::Never => false,
+        }
+    }
+}
+
+impl AsRef<str> for ContainerRemoval {
+    fn as_ref(&self) -> &str {
+        match self {
+            ContainerRemoval::Always => "always",
+            ContainerRemoval::OnSuccess => "on-success",
+            ContainerRemoval::Never => "never",
+        }
+    }
+}
+
+impl FromStr for ContainerRemoval {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match &s.to_lowercase()[..] {
+            "always" => Ok(ContainerRemoval::Always),
+            "on-success" => Ok(ContainerRemoval::OnSuccess),
+            "never" => Ok(ContainerRemoval::Never),
+            _ => err!(
+                "invalid container removal policy `{}`, expected one of `always`, `on-success`, `never`",
+                s
+            ),
+        }
+    }
+}
+
+const SSH_AGENT_CONTAINER_PATH: &str = "/ssh-agent";
+
+/// Returns the environment of the build container consisting of the recipe variables and the
+/// variables injected by pkger. Variables describing the OS are only set when the `os` is known.
+pub fn environment(ctx: &build::Context, os: Option<&Os>) -> Env {
+    let mut injected = Env::new();
+    injected.insert("PKGER_BLD_DIR", ctx.container_bld_dir.to_string_lossy());
+    injected.insert("PKGER_OUT_DIR", ctx.container_out_dir.to_string_lossy());
+    if let Some(os) = os {
+        injected.insert("PKGER_OS", os.name());
+        injected.insert("PKGER_OS_VERSION", os.version());
+    }
+    if ctx.has_read_only_rootfs() {
+        injected.insert("HOME", build::READ_ONLY_HOME_DIR);
+    }
+    injected.insert("RECIPE", &ctx.recipe.metadata.name);
+    injected.insert("RECIPE_VERSION", &ctx.build_version);
+    injected.insert("RECIPE_RELEASE", ctx.recipe.metadata.release());
+
+    if let Some(ssh) = &ctx.ssh {
+        if ssh.forward_agent {
+            injected.insert(ssh::SOCK_ENV, SSH_AGENT_CONTAINER_PATH);
+        }
+
+        if ssh.disable_key_verification {
+            injected.insert("GIT_SSH_COMMAND", "ssh -o StrictHostKeyChecking=no");
+        }
+    }
+
+    if let Some(proxy) = ctx.target.image_proxy() {
+        for (key, value) in proxy.env() {
+            injected.insert(key, value);
+        }
+    }
+
+    ctx.recipe.env.with_injected(injected)
+}
+
+/// Returns the paths that have a tmpfs mounted in a container with a read-only root filesystem.
+/// Besides the build directories `/tmp` and the scratch home directory used by the packaging tools
+/// are writable.
+fn read_only_tmpfs(ctx: &build::Context, writable_paths: &[String]) -> Vec<String> {
+    let mut tmpfs = vec![
+        ctx.container_bld_dir.to_string_lossy().to_string(),
+        ctx.container_out_dir.to_string_lossy().to_string(),
+        ctx.container_tmp_dir.to_string_lossy().to_string(),
+        "/tmp".to_string(),
+        ctx.container_home_dir().to_string_lossy().to_string(),
+    ];
+    for path in writable_paths {
+        if !tmpfs.contains(path) {
+            tmpfs.push(path.clone());
+        }
+    }
+    tmpfs
+}
+
+/// Returns the entries from configuration followed by the ones of the recipe that aren't already
+/// in the configuration.
+fn merge_entries(config: &[String], recipe: &[String]) -> Vec<String> {
+    let mut entries = config.to_vec();
+    for entry in recipe {
+        if !entries.contains(entry) {
+            entries.push(entry.clone());
+        }
+    }
+    entries
+}
+
 // https://github.com/rust-lang/rust-clippy/issues/7271
 #[allow(clippy::needless_lifetimes)]
 /// Creates and starts a container from the given ImageState
@@ -28,33 +145,20 @@ pub async fn spawn<'ctx>(
 
     let mut volumes = Vec::new();
 
-    let mut env = ctx.recipe.env.clone();
-    env.insert("PKGER_BLD_DIR", ctx.container_bld_dir.to_string_lossy());
-    env.insert("PKGER_OUT_DIR", ctx.container_out_dir.to_string_lossy());
-    env.insert("PKGER_OS", image_state.os.name());
-    env.insert("PKGER_OS_VERSION", image_state.os.version());
-    env.insert("RECIPE", &ctx.recipe.metadata.name);
-    env.insert("RECIPE_VERSION", &ctx.build_version);
-    env.insert("RECIPE_RELEASE", ctx.recipe.metadata.release());
-
     if let Some(ssh) = &ctx.ssh {
         if ssh.forward_agent {
-            const CONTAINER_PATH: &str = "/ssh-agent";
             let host_path = ssh::auth_sock()?;
-            volumes.push(format!("{}:{}", host_path, CONTAINER_PATH));
-            env.insert(ssh::SOCK_ENV, CONTAINER_PATH);
-        }
-
-        if ssh.disable_key_verification {
-            env.insert("GIT_SSH_COMMAND", "ssh -o StrictHostKeyChecking=no");
+            volumes.push(format!("{}:{}", host_path, SSH_AGENT_CONTAINER_PATH));
         }
     }
 
+    let env = environment(ctx, Some(&image_state.os));
+
     trace!("{:?}", env);
 
     let session_label = ctx.session_id.to_string();
 
-    let opts = CreateOpts::new(&image_state.id)
+    let mut opts = CreateOpts::new(&image_state.id)
         .name(&fix_name(&ctx.id))
         .cmd(["sleep infinity"])
         .entrypoint(["/bin/sh", "-c"])
@@ -63,9 +167,76 @@ pub async fn spawn<'ctx>(
         .env(env.clone())
         .working_dir(ctx.container_bld_dir.to_string_lossy());
 
+    let limits = ctx
+        .recipe
+        .metadata
+        .limits
+        .clone()
+        .unwrap_or_default()
+        .or(&ctx.limits);
+    if let Some(memory) = limits.memory_bytes()? {
+        opts = opts.memory(memory);
+    }
+    if let Some(nano_cpus) = limits.nano_cpus() {
+        opts = opts.nano_cpus(nano_cpus);
+    }
+    if let Some(network) = ctx.target.image_network().or(ctx.network.as_deref()) {
+        debug!(logger => "using network `{}`", network);
+        opts = opts.network(network);
+    }
+    let metadata = &ctx.recipe.metadata;
+    if metadata.privileged || !metadata.cap_add.is_empty() {
+        if !ctx.allow_privileged {
+            return err!(
+                "recipe `{}` requires a privileged container or additional capabilities, set `allow_privileged: true` in configuration to allow it",
+                metadata.name
+            );
+        }
+        if metadata.privileged {
+            warning!(logger => "running build container in privileged mode");
+        }
+        if !metadata.cap_add.is_empty() {
+            warning!(logger => "adding capabilities to build container: {}", metadata.cap_add.join(", "));
+        }
+        opts = opts
+            .privileged(metadata.privileged)
+            .cap_add(&metadata.cap_add);
+    }
+    let dns = merge_entries(&ctx.dns, &metadata.dns);
+    if !dns.is_empty() {
+        debug!(logger => "using dns servers: {}", dns.join(", "));
+        opts = opts.dns(dns);
+    }
+    let extra_hosts = merge_entries(&ctx.extra_hosts, &metadata.extra_hosts);
+    if !extra_hosts.is_empty() {
+        debug!(logger => "adding hosts: {}", extra_hosts.join(", "));
+        opts = opts.extra_hosts(extra_hosts);
+    }
+    if let Some(writable_paths) = &ctx.read_only_rootfs {
+        if ctx.has_read_only_rootfs() {
+            let tmpfs = read_only_tmpfs(ctx, writable_paths);
+            debug!(logger => "using read-only root filesystem, writable paths: {}", tmpfs.join(", "));
+            opts = opts.read_only_rootfs(true).tmpfs(tmpfs);
+        } else {
+            warning!(logger => "PKG and APK packages are built by a user created during the build, using a writable root filesystem");
+        }
+    }
+
     let mut ctx = Context::new(ctx, opts);
     ctx.set_env(env);
-    ctx.container.spawn(&ctx.opts, logger).await?;
+    ctx.os = Some(image_state.os.clone());
+    if let Err(e) = ctx.container.spawn(&ctx.opts, logger).await {
+        if limits.is_empty() {
+            return Err(e);
+        }
+        // rootless runtimes without cgroup delegation can't apply resource limits
+        warning!(logger => "failed to spawn container with resource limits, the runtime might not support them, spawning it without limits, reason: {:?}", e);
+        if let Err(e) = ctx.container.remove(logger).await {
+            trace!(logger => "failed to remove container, reason: {:?}", e);
+        }
+        ctx.opts = ctx.opts.clone().without_limits();
+        ctx.container.spawn(&ctx.opts, logger).await?;
+    }
     Ok(ctx)
 }
 
@@ -74,18 +245,32 @@ pub struct Context<'job> {
     pub opts: CreateOpts,
     pub build: &'job build::Context,
     pub vars: Env,
+    /// Format of the package built from the container, all package targets of the build are
+    /// packaged from the same container one after another.
+    pub package_target: BuildTarget,
+    /// Operating system of the image of the container, steps can be limited to some of them.
+    pub os: Option<Os>,
 }
 
 impl<'job> Context<'job> {
     pub fn new(build: &'job build::Context, opts: CreateOpts) -> Context<'_> {
         Context {
-            container: match &build.runtime {
-                RuntimeConnector::Docker(docker) => Box::new(DockerContainer::new(docker.clone())),
-                RuntimeConnector::Podman(podman) => Box::new(PodmanContainer::new(podman.clone())),
-            },
+            container: Box::new(RetryingContainer::new(
+                match &build.runtime {
+                    RuntimeConnector::Docker(docker) => {
+                        Box::new(DockerContainer::new(docker.clone()))
+                    }
+                    RuntimeConnector::Podman(podman) => {
+                        Box::new(PodmanContainer::new(podman.clone()))
+                    }
+                },
+                build.retry,
+            )),
             opts,
             build,
             vars: Env::new(),
+            package_target: *build.target().build_target(),
+            os: None,
         }
     }
 
@@ -156,3 +341,36 @@ impl<'job> Context<'job> {
         .map(|_| ())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decides_container_removal() {
+        use ContainerRemoval::*;
+        for (policy, on_success, on_failure) in [
+            (Always, true, true),
+            (OnSuccess, true, false),
+            (Never, false, false),
+        ] {
+            assert_eq!(policy.should_remove(true), on_success, "{:?}", policy);
+            assert_eq!(policy.should_remove(false), on_failure, "{:?}", policy);
+            assert_eq!(policy.as_ref().parse::<ContainerRemoval>().unwrap(), policy);
+        }
+        assert_eq!(ContainerRemoval::default(), Always);
+        assert!("sometimes".parse::<ContainerRemoval>().is_err());
+    }
+
+    #[test]
+    fn merges_dns_entries_of_config_and_recipe() {
+        let config = vec!["10.0.0.53".to_string(), "10.0.0.54".to_string()];
+        let recipe = vec!["10.0.0.54".to_string(), "1.1.1.1".to_string()];
+        assert_eq!(
+            merge_entries(&config, &recipe),
+            vec!["10.0.0.53", "10.0.0.54", "1.1.1.1"]
+        );
+        assert_eq!(merge_entries(&[], &recipe), recipe);
+        assert!(merge_entries(&[], &[]).is_empty());
+    }
+}
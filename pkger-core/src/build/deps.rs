@@ -1,7 +1,61 @@
-use crate::image::Image;
-use crate::recipe::{BuildTarget, Dependencies, Recipe};
+use crate::recipe::{BuildArch, BuildTarget, Dependencies, PackageManager, Recipe};
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// Recipe, image, target and architecture that build dependencies are resolved for.
+type ResolveKey = (String, String, BuildTarget, BuildArch);
+
+/// Keys of the `build_depends` of recipes that apply to an image and target. Clones share the
+/// memoized keys so that one instance can be passed to every build of a run, where the
+/// dependencies of a recipe don't change and each combination is resolved only once.
+#[derive(Clone, Debug, Default)]
+pub struct ResolvedDeps {
+    keys: Arc<Mutex<HashMap<ResolveKey, Arc<Vec<String>>>>>,
+}
+
+impl ResolvedDeps {
+    /// Returns the default build dependencies of the `build_target` along with the
+    /// `build_depends` of the `recipe` that apply to the `image`.
+    pub fn build_depends<'r>(
+        &self,
+        recipe: &'r Recipe,
+        build_target: BuildTarget,
+        image: &str,
+        enable_gpg: bool,
+        package_manager: &PackageManager,
+    ) -> HashSet<&'r str> {
+        let mut deps_out = default(&build_target, recipe, enable_gpg, package_manager);
+        if let Some(deps) = &recipe.metadata.build_depends {
+            deps_out.extend(deps.names_of_keys(&self.keys(recipe, image, build_target)));
+        }
+        deps_out
+    }
+
+    /// Returns the keys of the `build_depends` of the `recipe` that apply to the `image` and
+    /// `build_target`, resolving them only if they weren't resolved yet.
+    fn keys(&self, recipe: &Recipe, image: &str, build_target: BuildTarget) -> Arc<Vec<String>> {
+        let key = (
+            recipe.metadata.name.clone(),
+            image.to_string(),
+            build_target,
+            recipe.metadata.arch.clone(),
+        );
+        let mut keys = self.keys.lock().unwrap_or_else(|e| e.into_inner());
+        keys.entry(key)
+            .or_insert_with(|| {
+                Arc::new(
+                    recipe
+                        .metadata
+                        .build_depends
+                        .as_ref()
+                        .map(|deps| deps.matching_keys(image, build_target, &recipe.metadata.arch))
+                        .unwrap_or_default(),
+                )
+            })
+            .clone()
+    }
+}
 
 pub fn recipe_and_default<'ctx>(
     deps: Option<&'ctx Dependencies>,
@@ -9,9 +63,10 @@ pub fn recipe_and_default<'ctx>(
     build_target: BuildTarget,
     state_image: &str,
     enable_gpg: bool,
+    package_manager: &PackageManager,
 ) -> HashSet<&'ctx str> {
-    let mut deps_out = default(&build_target, recipe_, enable_gpg);
-    let recipe = recipe(deps, build_target, state_image);
+    let mut deps_out = default(&build_target, recipe_, enable_gpg, package_manager);
+    let recipe = recipe(deps, build_target, state_image, &recipe_.metadata.arch);
     deps_out.extend(recipe);
     deps_out
 }
@@ -20,17 +75,21 @@ pub fn recipe<'ctx>(
     deps: Option<&'ctx Dependencies>,
     build_target: BuildTarget,
     state_image: &str,
+    arch: &BuildArch,
 ) -> HashSet<&'ctx str> {
     let mut deps_out = HashSet::new();
     if let Some(deps) = &deps {
-        deps_out.extend(deps.resolve_names(state_image));
-        let simple = Image::simple(build_target).name;
-        deps_out.extend(deps.resolve_names(simple));
+        deps_out.extend(deps.resolve_names(state_image, build_target, arch));
     }
     deps_out
 }
 
-fn default(target: &BuildTarget, recipe: &Recipe, enable_gpg: bool) -> HashSet<&'static str> {
+fn default(
+    target: &BuildTarget,
+    recipe: &Recipe,
+    enable_gpg: bool,
+    package_manager: &PackageManager,
+) -> HashSet<&'static str> {
     let mut deps = HashSet::new();
     deps.insert("tar");
     match target {
@@ -52,10 +111,14 @@ fn default(target: &BuildTarget, recipe: &Recipe, enable_gpg: bool) -> HashSet<&
             }
         }
         BuildTarget::Gzip => {
-            deps.insert("gzip");
+            deps.insert(recipe.metadata.compression.dependency(package_manager));
         }
         BuildTarget::Pkg => {
             deps.insert("base-devel");
+
+            if enable_gpg {
+                deps.insert("gnupg");
+            }
         }
         BuildTarget::Apk => {
             deps.insert("alpine-sdk");
@@ -68,10 +131,10 @@ fn default(target: &BuildTarget, recipe: &Recipe, enable_gpg: bool) -> HashSet<&
     let mut is_zip = false;
 
     for src in &recipe.metadata.source {
-        if src.starts_with("http") {
+        if src.is_http() {
             is_http = true;
         }
-        if src.ends_with(".zip") {
+        if src.url().ends_with(".zip") {
             is_zip = true;
         }
     }
@@ -88,3 +151,68 @@ fn default(target: &BuildTarget, recipe: &Recipe, enable_gpg: bool) -> HashSet<&
 
     deps
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recipe::RecipeRep;
+    use std::path::PathBuf;
+
+    #[test]
+    fn shares_resolved_deps_between_clones() {
+        let recipe = r#"
+metadata:
+  name: test-deps
+  version: "1.0.0"
+  description: test
+  license: MIT
+  build_depends:
+    all: [gcc]
+    rocky: [openssl-devel]
+    arm64: [arm-tools]
+build:
+  steps: []"#;
+        let rep = RecipeRep::from_yaml_bytes(recipe.as_bytes()).unwrap();
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+
+        let resolved = ResolvedDeps::default();
+        let cloned = resolved.clone();
+        let first = resolved.keys(&recipe, "rocky", BuildTarget::Rpm);
+        // every build gets its own clone of the recipe and of the memo
+        let second = cloned.keys(&recipe.clone(), "rocky", BuildTarget::Rpm);
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(!Arc::ptr_eq(
+            &first,
+            &cloned.keys(&recipe, "debian", BuildTarget::Rpm)
+        ));
+        assert_eq!(resolved.keys.lock().unwrap().len(), 2);
+
+        // builds of an architecture matrix share the image but not the dependencies
+        let mut arm = recipe.clone();
+        arm.metadata.arch = BuildArch::Arm64;
+        assert!(!first.contains(&"arm64".to_string()));
+        assert!(cloned
+            .keys(&arm, "rocky", BuildTarget::Rpm)
+            .contains(&"arm64".to_string()));
+
+        let deps = cloned.build_depends(
+            &recipe,
+            BuildTarget::Rpm,
+            "rocky",
+            false,
+            &PackageManager::Dnf,
+        );
+        assert!(deps.contains("gcc"));
+        assert!(deps.contains("openssl-devel"));
+        assert!(deps.contains("rpm-build"));
+        assert!(!cloned
+            .build_depends(
+                &recipe,
+                BuildTarget::Rpm,
+                "debian",
+                false,
+                &PackageManager::Dnf
+            )
+            .contains("openssl-devel"));
+    }
+}
@@ -1,34 +1,151 @@
 use crate::build::{container, Context};
-use crate::image::{ImageState, ImagesState};
+use crate::image::{Image, ImageState, ImagesState};
 use crate::log::{debug, info, trace, warning, BoxedCollector};
-use crate::recipe::RecipeTarget;
-use crate::runtime::RuntimeConnector;
-use crate::{err, Error, Result};
+use crate::recipe::{ImageTarget, RecipeTarget};
+use crate::registry::{self, RegistryAuth};
+use crate::runtime::retry::retry;
+use crate::runtime::{RetryPolicy, RuntimeConnector};
+use crate::{err, ErrContext, Error, Result};
 use docker_api::models::ImageBuildChunk;
 use docker_api::opts::ImageBuildOpts;
 
 use async_rwlock::RwLock;
 use futures::StreamExt;
 use std::collections::HashSet;
+use std::fmt;
 use std::fs;
+use std::future::Future;
 use std::path::Path;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tempdir::TempDir;
 
 pub static CACHED: &str = "cached";
 pub static LATEST: &str = "latest";
 
+#[derive(Debug)]
+/// Error of an image build that didn't finish within the image build timeout.
+pub struct ImageBuildTimeout {
+    image: String,
+    timeout: Duration,
+}
+
+impl fmt::Display for ImageBuildTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "building image `{}` timed out after {:?}, the base image might be hanging on pull",
+            self.image, self.timeout
+        )
+    }
+}
+
+impl std::error::Error for ImageBuildTimeout {}
+
+#[derive(Debug)]
+/// Context of errors that happened while building the image of a build as opposed to errors of
+/// the build steps.
+pub struct ImageBuildFailed;
+
+impl fmt::Display for ImageBuildFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to build image")
+    }
+}
+
+/// Awaits the build of the `image` failing with [ImageBuildTimeout](ImageBuildTimeout) if it
+/// doesn't finish within `timeout`.
+pub async fn with_timeout<T>(
+    timeout: Duration,
+    image: &str,
+    build: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match tokio::time::timeout(timeout, build).await {
+        Ok(result) => result,
+        Err(_) => Err(Error::new(ImageBuildTimeout {
+            image: image.to_string(),
+            timeout,
+        })),
+    }
+}
+
+/// Builds the image of the build context. If the image build timeout is set and the build takes
+/// longer, an image tagged by the unfinished build is removed.
 pub async fn build(ctx: &mut Context, logger: &mut BoxedCollector) -> Result<ImageState> {
-    info!(logger => "building image '{}'", ctx.target.image());
+    let timeout = match ctx.image_build_timeout {
+        Some(timeout) => timeout,
+        None => return build_image(ctx, logger).await,
+    };
+    let image = format!("{}:{}", ctx.target.runtime_image(), LATEST);
+    let previous = image_id(&ctx.runtime, &image).await;
+
+    let result = with_timeout(timeout, &image, build_image(ctx, logger)).await;
+    if let Err(e) = &result {
+        if e.is::<ImageBuildTimeout>() {
+            warning!(logger => "{}", e);
+            let current = image_id(&ctx.runtime, &image).await;
+            if let Some(id) = current.filter(|id| previous.as_ref() != Some(id)) {
+                info!(logger => "removing partially built image '{}'", image);
+                if let Err(e) = remove_image(&ctx.runtime, &id).await {
+                    warning!(logger => "failed to remove image '{}', reason: {:?}", image, e);
+                }
+            }
+        }
+    }
+    result
+}
 
-    let deps = ctx.build_depends();
+/// Returns the id of the `image` if it exists.
+async fn image_id(runtime: &RuntimeConnector, image: &str) -> Option<String> {
+    match runtime {
+        RuntimeConnector::Docker(docker) => docker.images().get(image).inspect().await.ok()?.id,
+        RuntimeConnector::Podman(podman) => podman.images().get(image).inspect().await.ok()?.id,
+    }
+}
+
+async fn remove_image(runtime: &RuntimeConnector, id: &str) -> Result<()> {
+    match runtime {
+        RuntimeConnector::Docker(docker) => {
+            docker.images().get(id).delete().await?;
+        }
+        RuntimeConnector::Podman(podman) => {
+            podman.images().get(id).remove().await?;
+        }
+    }
+    Ok(())
+}
+
+async fn build_image(ctx: &mut Context, logger: &mut BoxedCollector) -> Result<ImageState> {
+    info!(logger => "building image '{}'", ctx.target.runtime_image());
+
+    if let Some(platform) = ctx.platform() {
+        if !ctx.recipe.metadata.arch.matches_platform(platform) {
+            warning!(logger => "recipe architecture `{}` doesn't match the image platform `{}`", ctx.recipe.metadata.arch, platform);
+        }
+    }
+
+    let os = ctx.image_os().await;
+    let deps = ctx.build_depends(os.as_ref());
     trace!(logger => "resolved dependencies: {:?}", deps);
 
+    let hash = if let Some(reference) = ctx.target.image_reference() {
+        // pulled images have no files, a change of the reference invalidates the cache
+        Some(reference.to_string())
+    } else {
+        match ctx.image.content_hash() {
+            Ok(hash) => Some(hash),
+            Err(e) => {
+                warning!(logger => "failed to hash image files, reason: {:?}", e);
+                None
+            }
+        }
+    };
+    trace!(logger => "image hash: {:?}", hash);
+
     let state = find_cached_state(
         &ctx.image.path,
         &ctx.target,
         &ctx.image_state,
-        ctx.simple,
+        hash.as_deref(),
         logger,
     )
     .await;
@@ -39,8 +156,11 @@ pub async fn build(ctx: &mut Context, logger: &mut BoxedCollector) -> Result<Ima
             .iter()
             .map(|s| s.as_str())
             .collect::<HashSet<_>>();
+        let repos = ctx.build_repos();
         if deps != state_deps {
             info!(logger => "dependencies changed, old: {:?}, new: {:?}", state_deps, deps);
+        } else if state.tag == CACHED && state.repos != repos {
+            info!(logger => "repositories changed, old: {:?}, new: {:?}", state.repos, repos);
         } else {
             trace!(logger => "dependencies unchanged");
 
@@ -53,14 +173,61 @@ pub async fn build(ctx: &mut Context, logger: &mut BoxedCollector) -> Result<Ima
         }
     }
 
+    if let Some(reference) = ctx.target.image_reference() {
+        let reference = reference.to_string();
+        return pull_reference(ctx, &reference, hash.as_deref(), logger).await;
+    }
+
     debug!(logger => "building from scratch");
 
-    match &ctx.runtime {
+    pull_base_images(
+        &ctx.runtime,
+        &ctx.image.path,
+        &ctx.registry_auth,
+        ctx.retry,
+        logger,
+    )
+    .await?;
+
+    let tag = format!("{}:{}", ctx.target.runtime_image(), LATEST);
+    let id = build_dockerfile(&ctx.runtime, &ctx.image.path, &tag, ctx.platform(), logger).await?;
+
+    let state = ImageState::new(
+        &id,
+        &ctx.target,
+        LATEST,
+        &SystemTime::now(),
+        &ctx.runtime,
+        &Default::default(),
+        ctx.simple,
+        hash.as_deref(),
+        logger,
+    )
+    .await?;
+
+    trace!(logger => "updating image state {}", state.id);
+    let mut image_state = ctx.image_state.write().await;
+    (*image_state).update(ctx.target.clone(), state.clone());
+
+    Ok(state)
+}
+
+/// Builds the Dockerfile in `path` tagging the image with `tag` and returns the id of the image.
+async fn build_dockerfile(
+    runtime: &RuntimeConnector,
+    path: &Path,
+    tag: &str,
+    platform: Option<&str>,
+    logger: &mut BoxedCollector,
+) -> Result<String> {
+    match runtime {
         RuntimeConnector::Docker(docker) => {
             let images = docker.images();
-            let opts = ImageBuildOpts::builder(&ctx.image.path)
-                .tag(&format!("{}:{}", &ctx.target.image(), LATEST))
-                .build();
+            let mut opts = ImageBuildOpts::builder(path).tag(tag);
+            if let Some(platform) = platform {
+                opts = opts.platform(platform);
+            }
+            let opts = opts.build();
 
             let mut stream = images.build(&opts);
 
@@ -77,23 +244,7 @@ pub async fn build(ctx: &mut Context, logger: &mut BoxedCollector) -> Result<Ima
                         info!(logger => "{}", stream);
                     }
                     ImageBuildChunk::Digest { aux } => {
-                        let state = ImageState::new(
-                            &aux.id,
-                            &ctx.target,
-                            LATEST,
-                            &SystemTime::now(),
-                            &ctx.runtime,
-                            &Default::default(),
-                            ctx.simple,
-                            logger,
-                        )
-                        .await?;
-
-                        trace!(logger => "updating image state {}", state.id);
-                        let mut image_state = ctx.image_state.write().await;
-                        (*image_state).update(ctx.target.clone(), state.clone());
-
-                        return Ok(state);
+                        return Ok(aux.id);
                     }
                     _ => {}
                 }
@@ -102,9 +253,11 @@ pub async fn build(ctx: &mut Context, logger: &mut BoxedCollector) -> Result<Ima
         RuntimeConnector::Podman(podman) => {
             use podman_api::opts::ImageBuildOpts;
 
-            let opts = ImageBuildOpts::builder(ctx.image.path.to_string_lossy())
-                .tag(format!("{}:{}", ctx.target.image(), LATEST))
-                .build();
+            let mut opts = ImageBuildOpts::builder(path.to_string_lossy()).tag(tag);
+            if let Some(platform) = platform {
+                opts = opts.platform(platform);
+            }
+            let opts = opts.build();
 
             let images = podman.images();
 
@@ -117,38 +270,223 @@ pub async fn build(ctx: &mut Context, logger: &mut BoxedCollector) -> Result<Ima
                 info!(logger => "{}", last.as_ref().unwrap().stream);
             }
 
-            let id = if let Some(last) = last {
-                last.stream.trim_end().to_owned()
+            return if let Some(last) = last {
+                Ok(last.stream.trim_end().to_owned())
             } else {
-                return err!("expected stream response for image build");
+                err!("expected stream response for image build")
             };
-            let state = ImageState::new(
-                &id,
-                &ctx.target,
-                LATEST,
-                &SystemTime::now(),
-                &ctx.runtime,
-                &Default::default(),
-                ctx.simple,
-                logger,
-            )
-            .await?;
+        }
+    };
 
-            trace!(logger => "updating image state {}", state.id);
-            let mut image_state = ctx.image_state.write().await;
-            (*image_state).update(ctx.target.clone(), state.clone());
+    err!("stream ended before image id was received")
+}
+
+/// Builds or pulls the image of the `target` without a recipe to verify that it works, the image
+/// is tagged just like in a regular build. Returns the id of the image.
+pub async fn check(
+    runtime: &RuntimeConnector,
+    image: &Image,
+    target: &ImageTarget,
+    registry_auth: &[RegistryAuth],
+    platform: Option<&str>,
+    retry_policy: RetryPolicy,
+    logger: &mut BoxedCollector,
+) -> Result<String> {
+    let platform = target.platform.as_deref().or(platform);
+    if let Some(reference) = &target.reference {
+        info!(logger => "pulling image '{}'", reference);
+        let auth = registry::find_auth(registry_auth, registry::registry_of(reference));
+        retry!(
+            retry_policy,
+            "pulling image",
+            logger,
+            pull_image(runtime, reference, auth.as_ref(), logger)
+        )?;
+        return image_id(runtime, reference)
+            .await
+            .ok_or_else(|| Error::msg("pulled image has no id"));
+    }
+
+    info!(logger => "building image '{}'", image.name);
+    pull_base_images(runtime, &image.path, registry_auth, retry_policy, logger).await?;
+    let tag = format!("{}:{}", image.name, LATEST);
+    build_dockerfile(runtime, &image.path, &tag, platform, logger).await
+}
 
-            return Ok(state);
+/// Pulls the prebuilt image `reference` and tags it as the latest version of the image target
+/// so that it can be used like a built image.
+async fn pull_reference(
+    ctx: &Context,
+    reference: &str,
+    hash: Option<&str>,
+    logger: &mut BoxedCollector,
+) -> Result<ImageState> {
+    info!(logger => "pulling image '{}'", reference);
+
+    let auth = registry::find_auth(&ctx.registry_auth, registry::registry_of(reference));
+    retry!(
+        ctx.retry,
+        "pulling image",
+        logger,
+        pull_image(&ctx.runtime, reference, auth.as_ref(), logger)
+    )?;
+
+    let id = match &ctx.runtime {
+        RuntimeConnector::Docker(docker) => {
+            use docker_api::opts::TagOpts;
+
+            let image = docker.images().get(reference);
+            image
+                .tag(
+                    &TagOpts::builder()
+                        .repo(ctx.target.runtime_image())
+                        .tag(LATEST)
+                        .build(),
+                )
+                .await
+                .context("failed to tag pulled image")?;
+            image
+                .inspect()
+                .await
+                .context("failed to inspect pulled image")?
+                .id
+        }
+        RuntimeConnector::Podman(podman) => {
+            use podman_api::opts::ImageTagOpts;
+
+            let image = podman.images().get(reference);
+            image
+                .tag(
+                    &ImageTagOpts::builder()
+                        .repo(ctx.target.runtime_image())
+                        .tag(LATEST)
+                        .build(),
+                )
+                .await
+                .context("failed to tag pulled image")?;
+            image
+                .inspect()
+                .await
+                .context("failed to inspect pulled image")?
+                .id
         }
     };
+    let id = id.ok_or_else(|| Error::msg("pulled image has no id"))?;
 
-    err!("stream ended before image id was received")
+    let state = ImageState::new(
+        &id,
+        &ctx.target,
+        LATEST,
+        &SystemTime::now(),
+        &ctx.runtime,
+        &Default::default(),
+        ctx.simple,
+        hash,
+        logger,
+    )
+    .await?;
+
+    trace!(logger => "updating image state {}", state.id);
+    let mut image_state = ctx.image_state.write().await;
+    (*image_state).update(ctx.target.clone(), state.clone());
+
+    Ok(state)
+}
+
+/// Pulls base images of the Dockerfile that have registry credentials configured. Images without
+/// credentials are left for the runtime to pull during the build.
+async fn pull_base_images(
+    runtime: &RuntimeConnector,
+    image_path: &Path,
+    registry_auth: &[RegistryAuth],
+    retry_policy: RetryPolicy,
+    logger: &mut BoxedCollector,
+) -> Result<()> {
+    let dockerfile = match fs::read_to_string(image_path.join("Dockerfile")) {
+        Ok(dockerfile) => dockerfile,
+        Err(e) => {
+            trace!(logger => "failed to read Dockerfile, skipping pulling base images, reason: {:?}", e);
+            return Ok(());
+        }
+    };
+
+    for image in registry::base_images(&dockerfile) {
+        let registry = registry::registry_of(&image);
+        let auth = match registry::find_auth(registry_auth, registry) {
+            Some(auth) => auth,
+            None => continue,
+        };
+        info!(logger => "pulling base image '{}' from registry '{}'", image, registry);
+
+        retry!(
+            retry_policy,
+            "pulling base image",
+            logger,
+            pull_image(runtime, &image, Some(&auth), logger)
+        )
+        .context("failed to pull base image")?;
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn pull_image(
+    runtime: &RuntimeConnector,
+    image: &str,
+    auth: Option<&RegistryAuth>,
+    logger: &mut BoxedCollector,
+) -> Result<()> {
+    match runtime {
+        RuntimeConnector::Docker(docker) => {
+            use docker_api::opts::PullOpts;
+
+            let mut opts = PullOpts::builder().image(image);
+            if let Some(auth) = auth {
+                opts = opts.auth(auth.as_docker());
+            }
+            let opts = opts.build();
+            let images = docker.images();
+            let mut stream = images.pull(&opts);
+            while let Some(chunk) = stream.next().await {
+                match chunk.context("failed to pull image")? {
+                    ImageBuildChunk::Error {
+                        error,
+                        error_detail: _,
+                    } => {
+                        return err!("failed to pull image '{}': {}", image, error);
+                    }
+                    chunk => trace!(logger => "{:?}", chunk),
+                }
+            }
+        }
+        RuntimeConnector::Podman(podman) => {
+            use podman_api::opts::PullOpts;
+
+            let mut opts = PullOpts::builder().reference(image);
+            if let Some(auth) = auth {
+                opts = opts.auth(auth.as_podman());
+            }
+            let opts = opts.build();
+            let images = podman.images();
+            let mut stream = images.pull(&opts);
+            while let Some(report) = stream.next().await {
+                let report = report.context("failed to pull image")?;
+                if let Some(error) = report.error {
+                    return err!("failed to pull image '{}': {}", image, error);
+                }
+                trace!(logger => "{:?}", report.stream);
+            }
+        }
+    }
+
+    Ok(())
 }
 
 pub async fn create_cache(
     ctx: &container::Context<'_>,
     state: &ImageState,
     deps: &HashSet<&str>,
+    repos: &[&str],
     logger: &mut BoxedCollector,
 ) -> Result<ImageState> {
     info!(logger => "caching image '{}'", state.image);
@@ -164,17 +502,22 @@ pub async fn create_cache(
     }
 
     let deps_joined = deps.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    let repos_run = repos
+        .iter()
+        .map(|cmd| format!("RUN {}\n", cmd))
+        .collect::<String>();
 
     #[rustfmt::skip]
             let dockerfile = format!(
 r#"FROM {}
 ENV DEBIAN_FRONTEND noninteractive
 {}
-RUN {} {} && \
+{}RUN {} {} && \
     {} {} {}
 "#,
                 tag,
                 if pkg_mngr.should_clean_cache() { format!("RUN {} {}", pkg_mngr_name, pkg_mngr.clean_cache().join(" "))} else { String::new() },
+                repos_run,
                 pkg_mngr_name, pkg_mngr.update_repos_args().join(" "),
                 pkg_mngr_name, pkg_mngr.install_args().join(" "), deps_joined.join(" ")
             );
@@ -197,9 +540,12 @@ RUN {} {} && \
     match &ctx.build.runtime {
         RuntimeConnector::Docker(docker) => {
             let images = docker.images();
-            let opts = ImageBuildOpts::builder(temp_path)
-                .tag(format!("{}:{}", state.image, CACHED))
-                .build();
+            let mut opts =
+                ImageBuildOpts::builder(temp_path).tag(format!("{}:{}", state.image, CACHED));
+            if let Some(platform) = ctx.build.platform() {
+                opts = opts.platform(platform);
+            }
+            let opts = opts.build();
 
             let mut stream = images.build(&opts);
 
@@ -224,9 +570,11 @@ RUN {} {} && \
                             &ctx.build.runtime,
                             deps,
                             ctx.build.simple,
+                            state.hash.as_deref(),
                             logger,
                         )
                         .await
+                        .map(|state| state.with_repos(repos))
                     }
                     _ => {}
                 }
@@ -237,9 +585,12 @@ RUN {} {} && \
         RuntimeConnector::Podman(podman) => {
             use podman_api::opts::ImageBuildOpts;
 
-            let opts = ImageBuildOpts::builder(temp_path.to_string_lossy())
-                .tag(format!("{}:{}", state.image, CACHED))
-                .build();
+            let mut opts = ImageBuildOpts::builder(temp_path.to_string_lossy())
+                .tag(format!("{}:{}", state.image, CACHED));
+            if let Some(platform) = ctx.build.platform() {
+                opts = opts.platform(platform);
+            }
+            let opts = opts.build();
 
             let images = podman.images();
 
@@ -260,14 +611,16 @@ RUN {} {} && \
             let state = ImageState::new(
                 &id,
                 &ctx.build.target,
-                LATEST,
+                CACHED,
                 &SystemTime::now(),
                 &ctx.build.runtime,
-                &Default::default(),
+                deps,
                 ctx.build.simple,
+                state.hash.as_deref(),
                 logger,
             )
-            .await?;
+            .await?
+            .with_repos(repos);
 
             trace!(logger => "updating image state {}", state.id);
             let mut image_state = ctx.build.image_state.write().await;
@@ -278,13 +631,14 @@ RUN {} {} && \
     }
 }
 
-/// Checks whether any of the files located at the path of this Image changed since last build.
-/// If shouldn't be rebuilt returns previous `ImageState`.
+/// Checks whether any of the files located at the path of this Image changed since last build
+/// by comparing the `hash` of the image files with the hash saved in the state. If the image
+/// shouldn't be rebuilt returns previous `ImageState`.
 pub async fn find_cached_state(
     image: &Path,
     target: &RecipeTarget,
     state: &RwLock<ImagesState>,
-    simple: bool,
+    hash: Option<&str>,
     logger: &mut BoxedCollector,
 ) -> Option<ImageState> {
     info!(logger => "finding cache for image {}", image.display());
@@ -294,35 +648,9 @@ pub async fn find_cached_state(
     trace!("checking if image should be rebuilt");
     let states = state.read().await;
     if let Some(state) = states.images.get(target) {
-        if simple {
-            return Some(state.to_owned());
-        }
-        if let Ok(entries) = fs::read_dir(image) {
-            for file in entries {
-                if let Err(e) = file {
-                    warning!(logger => "error while loading file, reason: {:?}", e);
-                    continue;
-                }
-                let file = file.unwrap();
-                let path = file.path();
-                trace!(logger => "checking '{}'", path.display());
-                let metadata = fs::metadata(path.as_path());
-                if let Err(e) = metadata {
-                    warning!(logger => "failed to read metadata, reason: {:?}", e);
-                    continue;
-                }
-                let metadata = metadata.unwrap();
-                let mod_time = metadata.modified();
-                if let Err(e) = &mod_time {
-                    warning!(logger => "failed to check modification time, reason: {:?}", e);
-                    continue;
-                }
-                let mod_time = mod_time.unwrap();
-                if mod_time > state.timestamp {
-                    trace!(logger => "found modified file - not returning cache, mod time: {:?}, image mod time: {:?}", mod_time, state.timestamp);
-                    return None;
-                }
-            }
+        if hash.is_none() || state.hash.as_deref() != hash {
+            trace!(logger => "image files changed - not returning cache, hash: {:?}, cached hash: {:?}", hash, state.hash);
+            return None;
         }
         let state = state.to_owned();
         trace!(logger => "found cached state: {:?}", state);
@@ -331,3 +659,34 @@ pub async fn find_cached_state(
 
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn fails_image_build_exceeding_timeout() {
+        let timeout = Duration::from_millis(10);
+        let err = with_timeout(
+            timeout,
+            "rocky:latest",
+            futures::future::pending::<Result<()>>(),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.is::<ImageBuildTimeout>());
+        assert!(err
+            .to_string()
+            .starts_with("building image `rocky:latest` timed out after 10ms"));
+
+        let result = with_timeout(timeout, "rocky:latest", async { Ok(1) }).await;
+        assert_eq!(result.unwrap(), 1);
+
+        let err = with_timeout(timeout, "rocky:latest", async {
+            err!("failed to pull base image")
+        })
+        .await
+        .unwrap_err();
+        assert!(!err.is::<ImageBuildTimeout>());
+    }
+}
@@ -1,29 +1,52 @@
 #[macro_use]
 pub mod container;
+pub mod cache;
 pub mod deps;
 pub mod image;
+pub mod output;
 pub mod package;
 pub mod patches;
+pub mod releases;
 pub mod remote;
 pub mod scripts;
-
+pub mod verify;
+pub mod versions;
+
+use crate::build::cache::BuildCache;
+use crate::build::container::ContainerRemoval;
+use crate::build::package::checksums;
+use crate::build::package::clean::{self, CleanOutput};
+use crate::build::package::permissions::ArtifactPermissions;
+use crate::checksum::Checksum;
 use crate::gpg::GpgKey;
 use crate::image::{Image, ImageState, ImagesState};
+use crate::limits::ResourceLimits;
 use crate::log::{debug, info, trace, warning, write_out, BoxedCollector};
-use crate::proxy::ProxyConfig;
-use crate::recipe::{ImageTarget, Recipe, RecipeTarget};
-use crate::runtime::container::ExecOpts;
-use crate::runtime::RuntimeConnector;
+use crate::proxy::{ProxyConfig, ProxyOverride};
+use crate::recipe::{
+    BuildArch, BuildTarget, ImageTarget, Os, PackageManager, Recipe, RecipeTarget,
+};
+use crate::registry::RegistryAuth;
+use crate::runtime::container::{fix_name, ExecOpts};
+use crate::runtime::{RetryPolicy, RuntimeConnector};
 use crate::ssh::SshConfig;
-use crate::{ErrContext, Result};
+use crate::{err, ErrContext, Result};
+
+pub use output::{output_dir, OutputLayout};
+
+/// Home directory of builds in the container.
+const DEFAULT_HOME_DIR: &str = "/root";
+/// Scratch home directory of builds with a read-only root filesystem, mounted as a tmpfs.
+pub(crate) const READ_ONLY_HOME_DIR: &str = "/tmp/pkger-home";
 
 use async_rwlock::RwLock;
 use std::collections::HashSet;
+use std::fmt;
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 use uuid::Uuid;
 
 /// Groups all data and functionality necessary to create an artifact
@@ -45,24 +68,123 @@ pub struct Context {
     ssh: Option<SshConfig>,
     proxy: ProxyConfig,
     build_version: String,
+    container_removal: ContainerRemoval,
+    limits: ResourceLimits,
+    network: Option<String>,
+    registry_auth: Vec<RegistryAuth>,
+    platform: Option<String>,
+    build_cache: Option<BuildCache>,
+    allow_privileged: bool,
+    checksums: Vec<Checksum>,
+    image_build_timeout: Option<Duration>,
+    artifact_permissions: ArtifactPermissions,
+    output_layout: OutputLayout,
+    /// Additional writable paths of a build container with a read-only root filesystem, `None`
+    /// if the root filesystem is writable.
+    read_only_rootfs: Option<Vec<String>>,
+    /// Additional targets on the same image packaged from the same build.
+    package_targets: Vec<ImageTarget>,
+    retry: RetryPolicy,
+    /// DNS servers of build containers.
+    dns: Vec<String>,
+    /// Additional `host:ip` entries of `/etc/hosts` in build containers.
+    extra_hosts: Vec<String>,
+    /// Removal of artifacts of previous builds requested with `--clean-output`.
+    clean_output: Option<CleanOutput>,
+    /// Build dependencies resolved by the builds of the run.
+    resolved_deps: deps::ResolvedDeps,
+    /// Install the built packages in the build container to check that their dependencies
+    /// resolve.
+    verify_install: bool,
+}
+
+/// Settings shared by the build contexts of a run.
+#[derive(Clone)]
+pub struct ContextOpts {
+    pub session_id: Uuid,
+    pub runtime: RuntimeConnector,
+    pub out_dir: PathBuf,
+    pub recipe_dir: PathBuf,
+    pub image_state: Arc<RwLock<ImagesState>>,
+    pub ssh: Option<SshConfig>,
+    pub proxy: ProxyConfig,
+    pub container_removal: ContainerRemoval,
+    pub limits: ResourceLimits,
+    pub network: Option<String>,
+    pub registry_auth: Vec<RegistryAuth>,
+    pub platform: Option<String>,
+    pub build_cache: Option<BuildCache>,
+    pub allow_privileged: bool,
+    pub checksums: Vec<Checksum>,
+    pub image_build_timeout: Option<Duration>,
+    pub artifact_permissions: ArtifactPermissions,
+    pub output_layout: OutputLayout,
+    /// Additional writable paths of a build container with a read-only root filesystem, `None`
+    /// if the root filesystem is writable.
+    pub read_only_rootfs: Option<Vec<String>>,
+    pub retry: RetryPolicy,
+    /// DNS servers of build containers.
+    pub dns: Vec<String>,
+    /// Additional `host:ip` entries of `/etc/hosts` in build containers.
+    pub extra_hosts: Vec<String>,
+    /// Removal of artifacts of previous builds requested with `--clean-output`.
+    pub clean_output: Option<CleanOutput>,
+    /// Build dependencies resolved by the builds of the run.
+    pub resolved_deps: deps::ResolvedDeps,
+    /// Install the built packages in the build container to check that their dependencies
+    /// resolve.
+    pub verify_install: bool,
+}
+
+impl ContextOpts {
+    /// Creates options of builds run with the `runtime` using the default values of all other
+    /// settings.
+    pub fn new(
+        session_id: Uuid,
+        runtime: RuntimeConnector,
+        out_dir: impl Into<PathBuf>,
+        recipe_dir: impl Into<PathBuf>,
+        image_state: Arc<RwLock<ImagesState>>,
+    ) -> Self {
+        Self {
+            session_id,
+            runtime,
+            out_dir: out_dir.into(),
+            recipe_dir: recipe_dir.into(),
+            image_state,
+            ssh: None,
+            proxy: ProxyConfig::default(),
+            container_removal: ContainerRemoval::default(),
+            limits: ResourceLimits::default(),
+            network: None,
+            registry_auth: vec![],
+            platform: None,
+            build_cache: None,
+            allow_privileged: false,
+            checksums: vec![],
+            image_build_timeout: None,
+            artifact_permissions: ArtifactPermissions::default(),
+            output_layout: OutputLayout::default(),
+            read_only_rootfs: None,
+            retry: RetryPolicy::default(),
+            dns: vec![],
+            extra_hosts: vec![],
+            clean_output: None,
+            resolved_deps: deps::ResolvedDeps::default(),
+            verify_install: false,
+        }
+    }
 }
 
 impl Context {
-    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        session_id: &Uuid,
         recipe: Recipe,
         image: Image,
-        connector: RuntimeConnector,
         target: ImageTarget,
-        out_dir: &Path,
-        recipe_dir: &Path,
-        image_state: Arc<RwLock<ImagesState>>,
-        simple: bool,
-        gpg_key: Option<GpgKey>,
-        ssh: Option<SshConfig>,
-        proxy: ProxyConfig,
         build_version: String,
+        gpg_key: Option<GpgKey>,
+        simple: bool,
+        opts: ContextOpts,
     ) -> Self {
         let timestamp = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
@@ -84,25 +206,48 @@ impl Context {
         trace!("creating new build context {}", id);
 
         let target = RecipeTarget::new(recipe.metadata.name.clone(), target);
+        let proxy = target
+            .image_proxy()
+            .map(ProxyOverride::to_config)
+            .unwrap_or(opts.proxy);
 
         Context {
             id,
-            session_id: *session_id,
+            session_id: opts.session_id,
             recipe,
             image,
-            runtime: connector,
+            runtime: opts.runtime,
             container_bld_dir,
             container_out_dir,
             container_tmp_dir,
-            out_dir: out_dir.to_path_buf(),
-            recipe_dir: recipe_dir.to_path_buf(),
+            out_dir: opts.out_dir,
+            recipe_dir: opts.recipe_dir,
             target,
-            image_state,
+            image_state: opts.image_state,
             simple,
             gpg_key,
-            ssh,
+            ssh: opts.ssh,
             proxy,
             build_version,
+            container_removal: opts.container_removal,
+            limits: opts.limits,
+            network: opts.network,
+            registry_auth: opts.registry_auth,
+            platform: opts.platform,
+            build_cache: opts.build_cache,
+            allow_privileged: opts.allow_privileged,
+            checksums: opts.checksums,
+            image_build_timeout: opts.image_build_timeout,
+            artifact_permissions: opts.artifact_permissions,
+            output_layout: opts.output_layout,
+            read_only_rootfs: opts.read_only_rootfs,
+            package_targets: vec![],
+            retry: opts.retry,
+            dns: opts.dns,
+            extra_hosts: opts.extra_hosts,
+            clean_output: opts.clean_output,
+            resolved_deps: opts.resolved_deps,
+            verify_install: opts.verify_install,
         }
     }
 
@@ -118,32 +263,187 @@ impl Context {
         self.id.as_str()
     }
 
-    pub fn build_depends(&self) -> HashSet<&str> {
-        deps::recipe_and_default(
-            self.recipe.metadata.build_depends.as_ref(),
+    pub fn target(&self) -> &RecipeTarget {
+        &self.target
+    }
+
+    pub fn recipe(&self) -> &Recipe {
+        &self.recipe
+    }
+
+    pub fn build_version(&self) -> &str {
+        &self.build_version
+    }
+
+    /// Returns all targets packaged by this build, the first one is the target of the build.
+    pub fn targets(&self) -> Vec<RecipeTarget> {
+        let mut targets = vec![self.target.clone()];
+        targets.extend(
+            self.package_targets
+                .iter()
+                .map(|target| RecipeTarget::new(self.target.recipe().to_string(), target.clone())),
+        );
+        targets
+    }
+
+    /// Returns `true` if the build container runs with a read-only root filesystem. PKG and APK
+    /// packages are built by a user created during the build, so builds packaging them always use
+    /// a writable one.
+    pub fn has_read_only_rootfs(&self) -> bool {
+        self.read_only_rootfs.is_some()
+            && !self
+                .targets()
+                .iter()
+                .any(|target| matches!(target.build_target(), BuildTarget::Pkg | BuildTarget::Apk))
+    }
+
+    /// Returns the home directory of the build in the container where the packaging tools keep
+    /// their files. Builds with a read-only root filesystem use a scratch directory so that the
+    /// content of `/root` in the image stays visible.
+    pub fn container_home_dir(&self) -> &Path {
+        if self.has_read_only_rootfs() {
+            Path::new(READ_ONLY_HOME_DIR)
+        } else {
+            Path::new(DEFAULT_HOME_DIR)
+        }
+    }
+
+    /// Returns `true` if the `target` can be packaged from this build instead of running a build
+    /// of its own. That is the case when it only differs from the target of this build by the
+    /// package format and the recipe, version and signing key are the same. Recipes with steps
+    /// that only run on some targets before packaging always get a build per target.
+    pub fn can_share_build(
+        &self,
+        recipe: &Recipe,
+        target: &ImageTarget,
+        version: &str,
+        gpg_key: Option<&GpgKey>,
+    ) -> bool {
+        let mut shared = target.clone();
+        shared.build_target = *self.target.build_target();
+        &shared == self.target.image_target()
+            && self.build_version == version
+            && &self.recipe == recipe
+            && self.gpg_key.as_ref() == gpg_key
+            && !recipe.has_target_scoped_build_steps()
+            // RPM builds with native prep are patched by rpmbuild
+            && !recipe.rpm_native_prep(target.build_target)
+            && !recipe.rpm_native_prep(*self.target.build_target())
+            && !self
+                .targets()
+                .iter()
+                .any(|t| t.build_target() == &target.build_target)
+    }
+
+    /// Returns the architecture of a build that is one of several builds of the recipe for
+    /// different architectures, the names of packages that don't contain the architecture get it
+    /// appended.
+    pub fn matrix_arch(&self) -> Option<&BuildArch> {
+        self.target.image_target().arch.as_ref()
+    }
+
+    /// Adds a target that is packaged from this build, see
+    /// [can_share_build](Context::can_share_build).
+    pub fn add_package_target(&mut self, target: ImageTarget) {
+        self.package_targets.push(target);
+    }
+
+    /// Returns the operating system of the image if it is known without building the image,
+    /// either from the image target or from the state of a previously built image.
+    pub async fn image_os(&self) -> Option<Os> {
+        if let Some(os) = self.target.image_os() {
+            return Some(os.clone());
+        }
+        self.image_state
+            .read()
+            .await
+            .images
+            .get(&self.target)
+            .map(|state| state.os.clone())
+    }
+
+    /// Returns the platform of the image, the one of image target takes precedence over the
+    /// default one.
+    pub fn platform(&self) -> Option<&str> {
+        self.target
+            .image_platform()
+            .or_else(|| self.platform.as_deref())
+    }
+
+    /// Returns build dependencies of the build including tools needed by every package target.
+    /// Names of default dependencies are resolved for the package manager of the `os` if known.
+    pub fn build_depends(&self, os: Option<&Os>) -> HashSet<&str> {
+        let package_manager = os
+            .map(Os::package_manager)
+            .unwrap_or(PackageManager::Unknown);
+        let mut deps = self.resolved_deps.build_depends(
             &self.recipe,
             *self.target.build_target(),
             self.target.image(),
             self.gpg_key.is_some(),
-        )
+            &package_manager,
+        );
+        for target in &self.package_targets {
+            deps.extend(self.resolved_deps.build_depends(
+                &self.recipe,
+                target.build_target,
+                &target.image,
+                self.gpg_key.is_some(),
+                &package_manager,
+            ));
+        }
+        deps
+    }
+
+    /// Returns commands enabling additional repositories of the build including the ones of
+    /// every package target in order without duplicates.
+    pub fn build_repos(&self) -> Vec<&str> {
+        let mut repos: Vec<&str> = vec![];
+        if let Some(build_repos) = &self.recipe.metadata.build_repos {
+            let targets = std::iter::once((self.target.image(), *self.target.build_target()))
+                .chain(
+                    self.package_targets
+                        .iter()
+                        .map(|target| (target.image.as_str(), target.build_target)),
+                );
+            for (image, target) in targets {
+                for repo in build_repos.resolve_names(image, target) {
+                    if !repos.contains(&repo) {
+                        repos.push(repo);
+                    }
+                }
+            }
+        }
+        repos
     }
 
-    pub fn depends(&self) -> HashSet<&str> {
+    pub fn depends(&self, os: Option<&Os>) -> HashSet<&str> {
         deps::recipe_and_default(
             self.recipe.metadata.depends.as_ref(),
             &self.recipe,
             *self.target.build_target(),
             self.target.image(),
             self.gpg_key.is_some(),
+            &os.map(Os::package_manager)
+                .unwrap_or(PackageManager::Unknown),
         )
     }
 
-    async fn create_out_dir(
-        &mut self,
+    fn create_out_dir(
+        &self,
         logger: &mut BoxedCollector,
         image: &ImageState,
+        target: BuildTarget,
     ) -> Result<PathBuf> {
-        let out_dir = self.out_dir.join(&image.image);
+        let out_dir = output_dir(
+            &self.out_dir,
+            &self.recipe.metadata.name,
+            &image.image,
+            target,
+            &image.os,
+            &self.recipe.metadata.arch,
+            self.output_layout,
+        );
         debug!(logger => "creating output directory `{}`", out_dir.display());
 
         if out_dir.exists() {
@@ -158,26 +458,101 @@ impl Context {
     }
 }
 
-pub async fn run(ctx: &mut Context, logger: &mut BoxedCollector) -> Result<PathBuf> {
+/// Phases of a build that prepare the build directory, their results are saved in the build cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Fetch,
+    PostFetch,
+    Patch,
+    Configure,
+    Build,
+}
+
+impl AsRef<str> for Phase {
+    fn as_ref(&self) -> &str {
+        match self {
+            Phase::Fetch => "fetch",
+            Phase::PostFetch => "post_fetch",
+            Phase::Patch => "patch",
+            Phase::Configure => "configure",
+            Phase::Build => "build",
+        }
+    }
+}
+
+impl Phase {
+    /// Returns the phases of the `recipe` that run before the install script in the order they
+    /// run. The `post_fetch` script runs before patches so that patches can be applied to files
+    /// generated by it.
+    pub fn before_install(recipe: &Recipe) -> Vec<Phase> {
+        let mut phases = vec![Phase::Fetch];
+        if recipe.post_fetch_script.is_some() {
+            phases.push(Phase::PostFetch);
+        }
+        if recipe.metadata.patches.is_some() {
+            phases.push(Phase::Patch);
+        }
+        if recipe.configure_script.is_some() {
+            phases.push(Phase::Configure);
+        }
+        phases.push(Phase::Build);
+        phases
+    }
+}
+
+/// A package produced by a build along with digests of its checksum files.
+#[derive(Clone, Debug)]
+pub struct Artifact {
+    pub path: PathBuf,
+    pub digests: Vec<(Checksum, String)>,
+}
+
+impl fmt::Display for Artifact {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        for (algorithm, digest) in &self.digests {
+            write!(f, " ({}: {})", algorithm.as_ref(), digest)?;
+        }
+        Ok(())
+    }
+}
+
+pub async fn run(ctx: &mut Context, logger: &mut BoxedCollector) -> Result<Vec<Artifact>> {
     info!(logger => "starting build, id = {}, recipe = {}, image = {}, target = {}", ctx.id, ctx.recipe.metadata.name, ctx.target.image(), ctx.target.build_target().as_ref());
+    // checked before entering the scopes of the build so that failing doesn't leave them open
+    let problems = ctx
+        .targets()
+        .iter()
+        .flat_map(|target| {
+            ctx.recipe.manifest_step_problems(
+                target.image(),
+                *target.build_target(),
+                &ctx.build_version,
+            )
+        })
+        .collect::<Vec<_>>();
+    if !problems.is_empty() {
+        return err!("{}", problems.join("\n"));
+    }
     logger.append_scope(ctx.recipe.metadata.name.clone());
     logger.append_scope(ctx.build_version.clone());
     logger.append_scope(ctx.target.image().to_string());
     let image_state = image::build(ctx, logger)
         .await
-        .context("failed to build image")?;
-
-    let out_dir = ctx.create_out_dir(logger, &image_state).await?;
+        .context(image::ImageBuildFailed)?;
 
     let mut container_ctx = container::spawn(ctx, &image_state, logger).await?;
 
     let image_state = if image_state.tag != image::CACHED {
         trace!(logger => "image tag is not {}, caching", image::CACHED);
 
-        let deps = ctx.build_depends();
+        let deps = ctx.build_depends(Some(&image_state.os));
         trace!(logger => "dependencies: {:?}", deps);
+        let repos = ctx.build_repos();
+        trace!(logger => "repositories: {:?}", repos);
 
-        let new_state = image::create_cache(&container_ctx, &image_state, &deps, logger).await?;
+        let new_state =
+            image::create_cache(&container_ctx, &image_state, &deps, &repos, logger).await?;
 
         info!(logger => "successfully cached image, id = {}, image = {}", &new_state.id, &new_state.image);
 
@@ -193,6 +568,37 @@ pub async fn run(ctx: &mut Context, logger: &mut BoxedCollector) -> Result<PathB
         image_state
     };
 
+    let result = build_in_container(&mut container_ctx, &image_state, logger).await;
+
+    if !ctx.container_removal.should_remove(result.is_ok()) {
+        let runtime = if ctx.is_podman() { "podman" } else { "docker" };
+        let name = fix_name(&ctx.id);
+        if result.is_err() {
+            warning!(logger => "build failed, keeping container `{}` for inspection", name);
+        } else {
+            warning!(logger => "keeping container `{}`, remove it with `{} rm -f {}`", name, runtime, name);
+        }
+        warning!(logger => "to inspect it run `{} exec -it {} sh`", runtime, name);
+    } else if let Err(e) = container_ctx.container.remove(logger).await {
+        if result.is_ok() {
+            return Err(e);
+        }
+        warning!(logger => "failed to remove container, reason: {:?}", e);
+    }
+
+    logger.pop_scope();
+    logger.pop_scope();
+    logger.pop_scope();
+
+    result
+}
+
+async fn build_in_container(
+    container_ctx: &mut container::Context<'_>,
+    image_state: &ImageState,
+    logger: &mut BoxedCollector,
+) -> Result<Vec<Artifact>> {
+    let ctx = container_ctx.build;
     let dirs = vec![
         &ctx.container_out_dir,
         &ctx.container_bld_dir,
@@ -201,28 +607,105 @@ pub async fn run(ctx: &mut Context, logger: &mut BoxedCollector) -> Result<PathB
 
     container_ctx.create_dirs(&dirs[..], logger).await?;
 
-    remote::fetch_source(&container_ctx, logger).await?;
+    let cache_key = cache::key(container_ctx, image_state);
+    let cached = match &ctx.build_cache {
+        Some(build_cache) => build_cache.find(&cache_key)?,
+        None => None,
+    };
 
-    if let Some(patches) = &ctx.recipe.metadata.patches {
-        let patches = patches::collect(&container_ctx, patches, logger).await?;
-        patches::apply(&container_ctx, patches, logger).await?;
+    if let Some(entry) = cached {
+        cache::restore(container_ctx, &entry, logger).await?;
     } else {
-        debug!(logger => "no patches to apply");
+        for phase in Phase::before_install(&ctx.recipe) {
+            debug!(logger => "running {} phase", phase.as_ref());
+            match phase {
+                Phase::Fetch => remote::fetch_source(container_ctx, logger).await?,
+                Phase::PostFetch => scripts::run_post_fetch(container_ctx, logger).await?,
+                Phase::Patch => {
+                    if let Some(patches) = &ctx.recipe.metadata.patches {
+                        let patches = patches::collect(container_ctx, patches, logger).await?;
+                        if ctx.recipe.rpm_native_prep(*ctx.target.build_target()) {
+                            patches::apply_with_rpm(container_ctx, patches, logger).await?;
+                        } else {
+                            patches::apply(container_ctx, patches, logger).await?;
+                        }
+                    }
+                }
+                Phase::Configure => scripts::run_configure(container_ctx, logger).await?,
+                Phase::Build => scripts::run_build(container_ctx, logger).await?,
+            }
+        }
+
+        if let Some(build_cache) = &ctx.build_cache {
+            if let Err(e) = cache::save(container_ctx, build_cache, &cache_key, logger).await {
+                warning!(logger => "failed to save build directory to the build cache, reason: {:?}", e);
+            }
+        }
     }
 
-    scripts::run(&container_ctx, logger).await?;
+    scripts::run_install(container_ctx, logger).await?;
 
-    exclude_paths(&container_ctx, logger).await?;
+    exclude_paths(container_ctx, logger).await?;
 
-    let package = package::build(&container_ctx, &image_state, out_dir.as_path(), logger).await?;
+    if ctx.recipe.metadata.check_arch {
+        package::elf::check_arch(container_ctx, logger).await;
+    }
 
-    container_ctx.container.remove(logger).await?;
+    let mut artifacts = vec![package_artifact(container_ctx, image_state, logger).await?];
+    let mut built_targets = vec![container_ctx.package_target];
+    for target in &ctx.package_targets {
+        info!(logger => "packaging the same build for target {}", target.build_target.as_ref());
+        container_ctx.package_target = target.build_target;
+        artifacts.push(package_artifact(container_ctx, image_state, logger).await?);
+        built_targets.push(target.build_target);
+    }
 
-    logger.pop_scope();
-    logger.pop_scope();
-    logger.pop_scope();
+    if ctx.verify_install {
+        let packages = built_targets
+            .into_iter()
+            .zip(artifacts.iter().map(|artifact| artifact.path.as_path()))
+            .collect::<Vec<_>>();
+        verify::install_packages(container_ctx, &image_state.os, &packages, logger).await?;
+    }
+
+    Ok(artifacts)
+}
+
+/// Creates a package in the format of the current package target of the container from the
+/// installed files.
+async fn package_artifact(
+    container_ctx: &container::Context<'_>,
+    image_state: &ImageState,
+    logger: &mut BoxedCollector,
+) -> Result<Artifact> {
+    let ctx = container_ctx.build;
+    scripts::run_check(container_ctx, logger).await?;
+    let out_dir = ctx.create_out_dir(logger, image_state, container_ctx.package_target)?;
+    if let Some(clean_output) = &ctx.clean_output {
+        clean::remove_stale_artifacts(
+            &out_dir,
+            &ctx.recipe.metadata.name,
+            container_ctx.package_target,
+            clean_output,
+            logger,
+        )
+        .context("failed to clean the output directory")?;
+    }
+    let package = package::build(container_ctx, image_state, &out_dir, logger).await?;
+
+    ctx.artifact_permissions
+        .apply(&package, logger)
+        .context("failed to set permissions of the package")?;
+
+    package::link::link_latest(container_ctx, &package, logger)?;
 
-    Ok(package)
+    let digests = checksums::write_sidecars(&package, &ctx.checksums, logger)
+        .context("failed to write checksum files")?;
+
+    Ok(Artifact {
+        path: package,
+        digests,
+    })
 }
 
 pub async fn exclude_paths(
@@ -231,29 +714,301 @@ pub async fn exclude_paths(
 ) -> Result<()> {
     info!(logger => "excluding paths");
     if let Some(exclude) = &ctx.build.recipe.metadata.exclude {
-        let exclude_paths = exclude
-            .iter()
-            .filter(|p| {
-                let p = PathBuf::from(p);
-                if p.is_absolute() {
-                    warning!(logger => "absolute paths are not allowed in excludes - '{}'", p.display());
-                    false
-                } else {
-                    true
-                }
-            })
-            .map(ToOwned::to_owned)
-            .collect::<Vec<_>>();
-        info!(logger => "exclude_dirs = {:?}", exclude_paths);
+        if let Some(cmd) = exclude_cmd(exclude, logger) {
+            ctx.checked_exec(
+                &ExecOpts::default()
+                    .cmd(&cmd)
+                    .working_dir(&ctx.build.container_out_dir),
+                logger,
+            )
+            .await?;
+        }
+    }
 
-        ctx.checked_exec(
-            &ExecOpts::default()
-                .cmd(&format!("rm -rvf {}", exclude_paths.join(" ")))
-                .working_dir(&ctx.build.container_out_dir),
-            logger,
+    Ok(())
+}
+
+/// Builds the command removing `exclude` entries relative to the output directory. Literal paths
+/// are removed with `rm`, entries with glob characters like `*.a` or `usr/lib/debug/*` are
+/// matched with `find`. Entries without a `/` match files at any depth.
+fn exclude_cmd(exclude: &[String], logger: &mut BoxedCollector) -> Option<String> {
+    let mut paths = vec![];
+    let mut patterns = vec![];
+    for entry in exclude {
+        if Path::new(entry).is_absolute() {
+            warning!(logger => "absolute paths are not allowed in excludes - '{}'", entry);
+            continue;
+        }
+        if !entry.contains(['*', '?', '[']) {
+            paths.push(shell_quote(entry));
+            continue;
+        }
+
+        let pattern = entry.trim_start_matches("**/").trim_end_matches('/');
+        if pattern.contains('/') {
+            let pattern = format!("./{}", pattern.trim_start_matches("./"));
+            patterns.push(format!("-path {}", shell_quote(&pattern)));
+        } else {
+            patterns.push(format!("-name {}", shell_quote(pattern)));
+        }
+    }
+    info!(logger => "exclude_paths = {:?}, exclude_patterns = {:?}", paths, patterns);
+
+    let mut cmds = vec![];
+    if !paths.is_empty() {
+        cmds.push(format!("rm -rvf {}", paths.join(" ")));
+    }
+    if !patterns.is_empty() {
+        cmds.push(format!(
+            "find . -mindepth 1 \\( {} \\) -prune -exec rm -rvf {{}} +",
+            patterns.join(" -o ")
+        ));
+    }
+
+    if cmds.is_empty() {
+        None
+    } else {
+        Some(cmds.join(" && "))
+    }
+}
+
+/// Quotes `arg` as a single shell word that is never expanded, like `'it'\''s'`.
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recipe::RecipeRep;
+    use tempdir::TempDir;
+
+    #[test]
+    fn runs_post_fetch_before_patches() {
+        let recipe = |extra: &str| {
+            let recipe = format!(
+                r#"
+metadata:
+  name: test-phases
+  version: "1.0.0"
+  description: a recipe with a post_fetch script
+  license: MIT
+{}
+build:
+  steps:
+    - cmd: make"#,
+                extra
+            );
+            let rep = RecipeRep::from_yaml_bytes(recipe.as_bytes()).unwrap();
+            Recipe::new(rep, PathBuf::new()).unwrap()
+        };
+
+        assert_eq!(
+            Phase::before_install(&recipe("")),
+            vec![Phase::Fetch, Phase::Build]
+        );
+        assert_eq!(
+            Phase::before_install(&recipe(
+                "  patches: [fix.patch]\npost_fetch:\n  steps:\n    - cmd: autoreconf -fi"
+            )),
+            vec![Phase::Fetch, Phase::PostFetch, Phase::Patch, Phase::Build]
+        );
+        assert_eq!(
+            Phase::before_install(&recipe("  patches: [fix.patch]")),
+            vec![Phase::Fetch, Phase::Patch, Phase::Build]
+        );
+        assert_eq!(
+            Phase::before_install(&recipe(
+                "  patches: [fix.patch]\nconfigure:\n  steps:\n    - cmd: ./configure"
+            )),
+            vec![Phase::Fetch, Phase::Patch, Phase::Configure, Phase::Build]
+        );
+    }
+
+    #[test]
+    fn shares_build_between_package_targets() {
+        let rep = RecipeRep::from_yaml_bytes(
+            br#"
+metadata:
+  name: test-shared
+  version: "1.0.0"
+  description: a recipe packaged in multiple formats
+  license: MIT
+  build_depends:
+    rocky: [gcc]
+build:
+  steps:
+    - cmd: make"#,
         )
-        .await?;
+        .unwrap();
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        let rpm = ImageTarget::new("rocky", BuildTarget::Rpm, None);
+        let gzip = ImageTarget::new("rocky", BuildTarget::Gzip, None);
+        let deb = ImageTarget::new("debian", BuildTarget::Deb, None);
+
+        let docker = crate::runtime::docker_api::Docker::new("unix:///var/run/docker.sock");
+        let opts = ContextOpts::new(
+            Uuid::new_v4(),
+            RuntimeConnector::Docker(docker.unwrap()),
+            "out",
+            "recipes",
+            Arc::new(RwLock::new(ImagesState::default())),
+        );
+        let mut ctx = Context::new(
+            recipe.clone(),
+            Image::new("rocky".to_string(), PathBuf::from("rocky")),
+            rpm.clone(),
+            "1.0.0".to_string(),
+            None,
+            false,
+            opts,
+        );
+
+        assert!(ctx.can_share_build(&recipe, &gzip, "1.0.0", None));
+        assert!(!ctx.can_share_build(&recipe, &gzip, "2.0.0", None));
+        assert!(!ctx.can_share_build(&recipe, &rpm, "1.0.0", None));
+        assert!(!ctx.can_share_build(&recipe, &deb, "1.0.0", None));
+        let mut other = recipe.clone();
+        other.metadata.release = Some("2".to_string());
+        assert!(!ctx.can_share_build(&other, &gzip, "1.0.0", None));
+        let mut scoped = recipe.clone();
+        scoped.build_script.steps[0].rpm = Some(true);
+        assert!(!ctx.can_share_build(&scoped, &gzip, "1.0.0", None));
+
+        assert!(!ctx.build_depends(None).contains("gzip"));
+        ctx.add_package_target(gzip.clone());
+        assert!(!ctx.can_share_build(&recipe, &gzip, "1.0.0", None));
+
+        let deps = ctx.build_depends(None);
+        assert!(deps.contains("rpm-build"));
+        assert!(deps.contains("gzip"));
+        assert!(deps.contains("gcc"));
+        assert_eq!(
+            ctx.targets(),
+            vec![
+                RecipeTarget::new("test-shared".to_string(), rpm),
+                RecipeTarget::new("test-shared".to_string(), gzip),
+            ]
+        );
     }
 
-    Ok(())
+    #[test]
+    fn uses_scratch_home_with_read_only_rootfs() {
+        let rep = RecipeRep::from_yaml_bytes(
+            br#"
+metadata:
+  name: test-read-only
+  version: "1.0.0"
+  description: a recipe built with a read-only root filesystem
+  license: MIT
+build:
+  steps:
+    - cmd: make"#,
+        )
+        .unwrap();
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        let docker = crate::runtime::docker_api::Docker::new("unix:///var/run/docker.sock");
+        let mut opts = ContextOpts::new(
+            Uuid::new_v4(),
+            RuntimeConnector::Docker(docker.unwrap()),
+            "out",
+            "recipes",
+            Arc::new(RwLock::new(ImagesState::default())),
+        );
+        let ctx = |target, opts: &ContextOpts| {
+            Context::new(
+                recipe.clone(),
+                Image::new("rocky".to_string(), PathBuf::from("rocky")),
+                ImageTarget::new("rocky", target, None),
+                "1.0.0".to_string(),
+                None,
+                false,
+                opts.clone(),
+            )
+        };
+
+        let writable = ctx(BuildTarget::Rpm, &opts);
+        assert!(!writable.has_read_only_rootfs());
+        assert_eq!(writable.container_home_dir(), Path::new("/root"));
+        assert!(!container::environment(&writable, None)
+            .inner()
+            .contains_key("HOME"));
+
+        opts.read_only_rootfs = Some(vec![]);
+        let read_only = ctx(BuildTarget::Rpm, &opts);
+        assert!(read_only.has_read_only_rootfs());
+        assert_eq!(
+            read_only.container_home_dir(),
+            Path::new(READ_ONLY_HOME_DIR)
+        );
+        assert_eq!(
+            container::environment(&read_only, None)
+                .inner()
+                .get("HOME")
+                .map(String::as_str),
+            Some(READ_ONLY_HOME_DIR)
+        );
+
+        let pkg = ctx(BuildTarget::Pkg, &opts);
+        assert!(!pkg.has_read_only_rootfs());
+        assert_eq!(pkg.container_home_dir(), Path::new("/root"));
+    }
+
+    #[test]
+    fn excludes_glob_patterns() {
+        let mut logger: BoxedCollector = Box::new(crate::log::Logger::stdout(None));
+        let exclude = |entries: &[&str], logger: &mut BoxedCollector| {
+            let entries: Vec<_> = entries.iter().map(|e| e.to_string()).collect();
+            exclude_cmd(&entries, logger)
+        };
+
+        assert_eq!(exclude(&["/usr/share"], &mut logger), None);
+        assert_eq!(
+            exclude(&["share", "/usr/share", "*.a"], &mut logger).unwrap(),
+            "rm -rvf 'share' && find . -mindepth 1 \\( -name '*.a' \\) -prune -exec rm -rvf {} +"
+        );
+        assert_eq!(
+            exclude(&["it's; rm -rf /", "usr/lib/*'$(id)'.a"], &mut logger).unwrap(),
+            "rm -rvf 'it'\\''s; rm -rf /' && find . -mindepth 1 \\( -path './usr/lib/*'\\''$(id)'\\''.a' \\) -prune -exec rm -rvf {} +"
+        );
+
+        let dir = TempDir::new("pkger-exclude").unwrap();
+        for file in [
+            "usr/lib/libpkger.a",
+            "usr/lib/libpkger.so",
+            "usr/lib/libpkger.la",
+            "usr/lib/debug/pkger.debug",
+            "usr/share/doc/README",
+            "libtop.a",
+        ] {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let cmd = exclude(
+            &["usr/share", "*.a", "**/*.la", "usr/lib/debug/*"],
+            &mut logger,
+        )
+        .unwrap();
+        let status = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(&cmd)
+            .current_dir(dir.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        assert!(dir.path().join("usr/lib/libpkger.so").exists());
+        assert!(dir.path().join("usr/lib/debug").exists());
+        for removed in [
+            "usr/lib/libpkger.a",
+            "usr/lib/libpkger.la",
+            "usr/lib/debug/pkger.debug",
+            "usr/share",
+            "libtop.a",
+        ] {
+            assert!(!dir.path().join(removed).exists(), "{} exists", removed);
+        }
+    }
 }
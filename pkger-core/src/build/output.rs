@@ -0,0 +1,398 @@
+//! Layout of packages in the output directory. Directories of packages are resolved from the
+//! configured `output_dir` template and the `OutputLayout` when building and discovered the same
+//! way when listing, cleaning or creating repositories.
+use crate::recipe::{BuildArch, BuildTarget, Os};
+use crate::template;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How packages are organized in subdirectories of the output directory.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputLayout {
+    /// `<output_dir>/<image>`
+    #[default]
+    PerImage,
+    /// `<output_dir>/<recipe>/<image>`
+    PerRecipe,
+}
+
+/// Returns the directory in which packages of the `recipe` built on the `image` for the `target`
+/// are saved. The `output_dir` can reference `${target}`, `${distro}`, `${arch}`, `${recipe}`
+/// and `${image}`. Subdirectories of the `layout` are only added for the parts that
+/// `output_dir` doesn't already reference, the recipe directory is never added below the image.
+pub fn output_dir(
+    output_dir: &Path,
+    recipe: &str,
+    image: &str,
+    target: BuildTarget,
+    os: &Os,
+    arch: &BuildArch,
+    layout: OutputLayout,
+) -> PathBuf {
+    let mut vars = HashMap::new();
+    vars.insert(TARGET.to_string(), target.as_ref());
+    vars.insert(DISTRO.to_string(), os.name());
+    vars.insert(ARCH.to_string(), arch.as_ref());
+    vars.insert(RECIPE.to_string(), recipe);
+    vars.insert(IMAGE.to_string(), image);
+    PathBuf::from(template::render(layout_template(output_dir, layout), &vars))
+}
+
+/// Returns the `output_dir` with the subdirectories of the `layout` it doesn't reference
+/// appended, every directory of packages is a rendered form of it.
+pub fn layout_template(output_dir: &Path, layout: OutputLayout) -> String {
+    let mut template = output_dir
+        .to_string_lossy()
+        .trim_end_matches('/')
+        .to_string();
+    if !references(&template, IMAGE) {
+        if layout == OutputLayout::PerRecipe && !references(&template, RECIPE) {
+            template.push_str("/${recipe}");
+        }
+        template.push_str("/${image}");
+    }
+    template
+}
+
+/// Returns the part of the `output_dir` before the first path component referencing a variable.
+pub fn output_root(output_dir: &Path) -> PathBuf {
+    output_dir
+        .components()
+        .take_while(|component| {
+            let component = component.as_os_str().to_string_lossy();
+            VARS.iter().all(|var| !references(&component, var))
+        })
+        .collect()
+}
+
+/// A directory of packages found in the output directory along with the values of the variables
+/// of the layout its path was rendered from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputDir {
+    path: PathBuf,
+    vars: HashMap<String, String>,
+}
+
+impl OutputDir {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the image the packages in the directory were built on.
+    pub fn image(&self) -> &str {
+        self.var(IMAGE).unwrap_or_default()
+    }
+
+    /// Returns the recipe of the packages if the layout has a directory per recipe.
+    pub fn recipe(&self) -> Option<&str> {
+        self.var(RECIPE)
+    }
+
+    fn var(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(String::as_str)
+    }
+
+    /// Whether packages of the `recipe` built on the `image` for the `target` are saved in this
+    /// directory. Variables the layout doesn't reference match anything.
+    pub fn holds(&self, recipe: &str, image: &str, target: BuildTarget) -> bool {
+        self.image() == image
+            && self.recipe().map(|r| r == recipe).unwrap_or(true)
+            && self
+                .var(TARGET)
+                .map(|t| t == target.as_ref())
+                .unwrap_or(true)
+    }
+}
+
+/// Returns the existing directories of packages in the `output_dir` organized by the `layout`,
+/// sorted by path. Directories that don't match the layout are skipped.
+pub fn find_output_dirs(output_dir: &Path, layout: OutputLayout) -> Vec<OutputDir> {
+    let template = layout_template(output_dir, layout);
+    let mut found = vec![OutputDir {
+        path: PathBuf::new(),
+        vars: HashMap::new(),
+    }];
+
+    for component in Path::new(&template).components() {
+        let parts = pattern(&component.as_os_str().to_string_lossy());
+        if parts.iter().all(|part| matches!(part, Part::Text(_))) {
+            found
+                .iter_mut()
+                .for_each(|dir| dir.path.push(component.as_os_str()));
+            continue;
+        }
+
+        let mut next = vec![];
+        for dir in found {
+            let parent = if dir.path.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir.path.as_path()
+            };
+            let entries = match fs::read_dir(parent) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                if !entry.path().is_dir() {
+                    continue;
+                }
+                let mut vars = dir.vars.clone();
+                if match_parts(&parts, &entry.file_name().to_string_lossy(), &mut vars) {
+                    next.push(OutputDir {
+                        path: dir.path.join(entry.file_name()),
+                        vars,
+                    });
+                }
+            }
+        }
+        found = next;
+    }
+
+    found.retain(|dir| dir.path.is_dir());
+    found.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+    found
+}
+
+const TARGET: &str = "target";
+const DISTRO: &str = "distro";
+const ARCH: &str = "arch";
+const RECIPE: &str = "recipe";
+const IMAGE: &str = "image";
+const VARS: [&str; 5] = [TARGET, DISTRO, ARCH, RECIPE, IMAGE];
+
+/// Separates the variables from the text when a template is rendered by `pattern`.
+const MARKER: char = '\0';
+
+fn references(template: &str, var: &str) -> bool {
+    let mut vars = HashMap::new();
+    vars.insert(var.to_string(), MARKER.to_string());
+    template::render(template, &vars).contains(MARKER)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Var(&'static str),
+}
+
+/// Splits the path `component` of a layout template into text and the variables it references.
+fn pattern(component: &str) -> Vec<Part> {
+    let vars: HashMap<String, String> = VARS
+        .iter()
+        .enumerate()
+        .map(|(i, var)| (var.to_string(), format!("{0}{1}{0}", MARKER, i)))
+        .collect();
+    template::render(component, &vars)
+        .split(MARKER)
+        .enumerate()
+        .filter_map(|(i, part)| {
+            if i % 2 == 0 {
+                (!part.is_empty()).then(|| Part::Text(part.to_string()))
+            } else {
+                part.parse::<usize>().ok().map(|var| Part::Var(VARS[var]))
+            }
+        })
+        .collect()
+}
+
+/// Matches the directory `name` against the `parts` of a layout template saving the values of
+/// variables in `vars`. A variable referenced more than once has to have the same value and
+/// `${target}` has to be a known target.
+fn match_parts(parts: &[Part], name: &str, vars: &mut HashMap<String, String>) -> bool {
+    match parts.split_first() {
+        None => name.is_empty(),
+        Some((Part::Text(text), rest)) => name
+            .strip_prefix(text.as_str())
+            .map(|name| match_parts(rest, name, vars))
+            .unwrap_or_default(),
+        Some((Part::Var(var), rest)) => {
+            for end in (1..=name.len()).filter(|end| name.is_char_boundary(*end)) {
+                let value = &name[..end];
+                if vars.get(*var).map(|v| v != value).unwrap_or_default()
+                    || (*var == TARGET && BuildTarget::try_from(value).is_err())
+                {
+                    continue;
+                }
+                let mut matched = vars.clone();
+                matched.insert(var.to_string(), value.to_string());
+                if match_parts(rest, &name[end..], &mut matched) {
+                    *vars = matched;
+                    return true;
+                }
+            }
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn resolves_templated_output_dir() {
+        let rocky = Os::new("rocky", Some("9"));
+        let debian = Os::new("debian", Some("12"));
+        let arch = BuildArch::x86_64;
+        let out_dir = |template: &str, image: &str, target, os: &Os| {
+            output_dir(
+                Path::new(template),
+                "pkger",
+                image,
+                target,
+                os,
+                &arch,
+                OutputLayout::PerImage,
+            )
+        };
+
+        assert_eq!(
+            out_dir("/out", "rocky", BuildTarget::Rpm, &rocky),
+            PathBuf::from("/out/rocky")
+        );
+        assert_eq!(
+            out_dir(
+                "/out/${target}/${distro}-${arch}",
+                "rocky",
+                BuildTarget::Rpm,
+                &rocky
+            ),
+            PathBuf::from("/out/rpm/rocky-x86_64/rocky")
+        );
+        assert_eq!(
+            out_dir(
+                "/out/${target}/${distro}-${arch}",
+                "debian",
+                BuildTarget::Deb,
+                &debian
+            ),
+            PathBuf::from("/out/deb/debian-x86_64/debian")
+        );
+        assert_eq!(
+            out_dir(
+                "/repos/${target}/${image}",
+                "debian",
+                BuildTarget::Deb,
+                &debian
+            ),
+            PathBuf::from("/repos/deb/debian")
+        );
+    }
+
+    #[test]
+    fn resolves_per_recipe_output_dir() {
+        let rocky = Os::new("rocky", Some("9"));
+        let out_dir = |template: &str| {
+            output_dir(
+                Path::new(template),
+                "pkger",
+                "rocky",
+                BuildTarget::Rpm,
+                &rocky,
+                &BuildArch::x86_64,
+                OutputLayout::PerRecipe,
+            )
+        };
+
+        assert_eq!(out_dir("/out"), PathBuf::from("/out/pkger/rocky"));
+        assert_eq!(
+            out_dir("/out/${target}"),
+            PathBuf::from("/out/rpm/pkger/rocky")
+        );
+        assert_eq!(
+            out_dir("/out/${recipe}-${target}"),
+            PathBuf::from("/out/pkger-rpm/rocky")
+        );
+        assert_eq!(
+            out_dir("/out/${image}/${recipe}"),
+            PathBuf::from("/out/rocky/pkger")
+        );
+        assert_eq!(out_dir("/out/${image}"), PathBuf::from("/out/rocky"));
+    }
+
+    #[test]
+    fn finds_output_dirs_of_layout() {
+        let dir = TempDir::new("pkger-output").unwrap();
+        let root = dir.path();
+        for path in [
+            "rpm/rocky-x86_64/rocky",
+            "deb/debian-x86_64/debian",
+            "deb/debian-x86_64/ubuntu",
+            "unknown/debian-x86_64/debian",
+            "rpm/notes",
+        ] {
+            fs::create_dir_all(root.join(path)).unwrap();
+        }
+        fs::write(root.join("rpm/rocky-x86_64/file"), "").unwrap();
+
+        let template = root.join("${target}/${distro}-${arch}");
+        let found = find_output_dirs(&template, OutputLayout::PerImage);
+        let paths: Vec<_> = found.iter().map(|dir| dir.path().to_path_buf()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                root.join("deb/debian-x86_64/debian"),
+                root.join("deb/debian-x86_64/ubuntu"),
+                root.join("rpm/rocky-x86_64/rocky"),
+            ]
+        );
+        assert_eq!(found[2].image(), "rocky");
+        assert!(found[2].holds("pkger", "rocky", BuildTarget::Rpm));
+        assert!(!found[2].holds("pkger", "rocky", BuildTarget::Gzip));
+        assert_eq!(output_root(&template), root);
+
+        let dir = TempDir::new("pkger-output-recipe").unwrap();
+        let root = dir.path();
+        for path in ["pkger/rocky", "pkger/debian", "other-pkger/rocky"] {
+            fs::create_dir_all(root.join(path)).unwrap();
+        }
+        let found = find_output_dirs(root, OutputLayout::PerRecipe);
+        assert_eq!(found.len(), 3);
+        assert!(found
+            .iter()
+            .all(|dir| dir.recipe().is_some() && dir.path().starts_with(root)));
+        let held: Vec<_> = found
+            .iter()
+            .filter(|dir| dir.holds("pkger", "rocky", BuildTarget::Rpm))
+            .map(|dir| dir.path().to_path_buf())
+            .collect();
+        assert_eq!(held, vec![root.join("pkger/rocky")]);
+
+        let found = find_output_dirs(root, OutputLayout::PerImage);
+        assert_eq!(
+            found.iter().map(OutputDir::image).collect::<Vec<_>>(),
+            vec!["other-pkger", "pkger"]
+        );
+    }
+
+    #[test]
+    fn matches_variables_of_directory_names() {
+        let mut vars = HashMap::new();
+        assert!(match_parts(
+            &pattern("${recipe}-${target}"),
+            "foo-bar-rpm",
+            &mut vars
+        ));
+        assert_eq!(vars.get(RECIPE).unwrap(), "foo-bar");
+        assert_eq!(vars.get(TARGET).unwrap(), "rpm");
+
+        let mut vars = HashMap::new();
+        assert!(!match_parts(
+            &pattern("${image}-${image}"),
+            "a-b",
+            &mut vars
+        ));
+        assert!(match_parts(&pattern("${image}-${image}"), "a-a", &mut vars));
+        assert!(!match_parts(
+            &pattern("out-${image}"),
+            "pkg-rocky",
+            &mut vars
+        ));
+    }
+}
@@ -6,6 +6,7 @@ use crate::runtime::container::ExecOpts;
 use crate::{ErrContext, Result};
 
 use async_trait::async_trait;
+use std::fs;
 use std::path::{Path, PathBuf};
 
 pub struct Apk;
@@ -25,7 +26,7 @@ impl Package for Apk {
     /// Creates a final APK package and saves it to `output_dir`
     async fn build(
         ctx: &Context<'_>,
-        image_state: &ImageState,
+        _: &ImageState,
         output_dir: &Path,
         logger: &mut BoxedCollector,
     ) -> Result<PathBuf> {
@@ -68,15 +69,17 @@ impl Package for Apk {
         let sources = vec![source_tar_name];
         static BUILD_USER: &str = "builduser";
 
-        let apkbuild = ctx
-            .build
-            .recipe
+        // the configure steps already ran in the build container, so they're left out of
+        // `prepare()` of the APKBUILD used to build the package
+        let mut recipe = ctx.build.recipe.clone();
+        recipe.configure_script = None;
+        let apkbuild = recipe
             .as_apkbuild(
-                &image_state.image,
+                ctx.build.target().image(),
                 &sources,
                 &bld_dir,
                 &ctx.build.build_version,
-                *ctx.build.target.build_target(),
+                ctx.package_target,
                 logger,
             )
             .render()
@@ -227,7 +230,18 @@ impl Package for Apk {
         ctx.container
             .download_files(&apk_path, output_dir, logger)
             .await
-            .map(|_| output_dir.join(apk))
-            .context("failed to download finished package")
+            .context("failed to download finished package")?;
+
+        let package = output_dir.join(apk);
+        match ctx.build.matrix_arch() {
+            // abuild doesn't put the architecture in the name, only in the directory
+            Some(arch) => {
+                let renamed = output_dir.join(format!("{}-{}.apk", package_name, arch.apk_name()));
+                fs::rename(&package, &renamed)
+                    .context("failed to add architecture to the package name")?;
+                Ok(renamed)
+            }
+            None => Ok(package),
+        }
     }
 }
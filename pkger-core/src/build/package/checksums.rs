@@ -0,0 +1,74 @@
+use crate::checksum::{self, Checksum};
+use crate::log::{info, BoxedCollector};
+use crate::{ErrContext, Result};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Returns the path of the sidecar file with the `checksum` of `package`.
+pub fn sidecar_path(checksum: Checksum, package: &Path) -> PathBuf {
+    let mut path = package.as_os_str().to_owned();
+    path.push(".");
+    path.push(checksum.as_ref());
+    PathBuf::from(path)
+}
+
+/// Writes a sidecar file like `<package>.sha256` for every algorithm in the format of
+/// `sha256sum` so that it can be verified with `sha256sum -c` or the command of the algorithm.
+/// Returns the digests.
+pub fn write_sidecars(
+    package: &Path,
+    algorithms: &[Checksum],
+    logger: &mut BoxedCollector,
+) -> Result<Vec<(Checksum, String)>> {
+    let file_name = package
+        .file_name()
+        .context("package path has no file name")?
+        .to_string_lossy();
+
+    let mut digests = vec![];
+    for algorithm in algorithms {
+        let digest = checksum::compute(package, *algorithm)?;
+        let sidecar = sidecar_path(*algorithm, package);
+        info!(logger => "writing {} checksum to `{}`", algorithm.as_ref(), sidecar.display());
+        fs::write(&sidecar, format!("{}  {}\n", digest, file_name))
+            .with_context(|| format!("failed to write `{}`", sidecar.display()))?;
+        digests.push((*algorithm, digest));
+    }
+    Ok(digests)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn writes_checksum_sidecars() {
+        let dir = TempDir::new("pkger-checksums").unwrap();
+        let package = dir.path().join("pkger-0.11.0.tar.gz");
+        fs::write(&package, "abc").unwrap();
+        let mut logger: BoxedCollector = Box::new(crate::log::Logger::stdout(None));
+
+        let digests =
+            write_sidecars(&package, &[Checksum::Sha256, Checksum::Sha512], &mut logger).unwrap();
+
+        let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let sha512 = "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f";
+        assert_eq!(
+            digests,
+            vec![
+                (Checksum::Sha256, sha256.to_string()),
+                (Checksum::Sha512, sha512.to_string()),
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("pkger-0.11.0.tar.gz.sha256")).unwrap(),
+            format!("{}  pkger-0.11.0.tar.gz\n", sha256)
+        );
+        assert!(dir.path().join("pkger-0.11.0.tar.gz.sha512").exists());
+        assert!(write_sidecars(&package, &[], &mut logger)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,206 @@
+use crate::build::package::link::{package_extension, target_extensions};
+use crate::checksum::Checksum;
+use crate::log::{info, trace, BoxedCollector};
+use crate::recipe::BuildTarget;
+use crate::{ErrContext, Result};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Settings of removing artifacts of previous builds of a recipe before packaging it again.
+#[derive(Clone, Debug)]
+pub struct CleanOutput {
+    /// Names of all known recipes, files of recipes with a longer matching name like `foo-bar`
+    /// are never removed when building `foo`.
+    recipes: Vec<String>,
+    /// Files modified after this time were created by the current session and are kept.
+    since: SystemTime,
+    dry_run: bool,
+}
+
+impl CleanOutput {
+    pub fn new(recipes: Vec<String>, since: SystemTime, dry_run: bool) -> Self {
+        Self {
+            recipes,
+            since,
+            dry_run,
+        }
+    }
+}
+
+/// Returns the file name of the package without the extension of a checksum or signature
+/// sidecar file.
+fn strip_sidecar(file_name: &str) -> &str {
+    Checksum::ALL
+        .iter()
+        .map(Checksum::as_ref)
+        .chain(std::iter::once("sig"))
+        .find_map(|extension| {
+            file_name
+                .strip_suffix(extension)
+                .and_then(|rest| rest.strip_suffix('.'))
+        })
+        .unwrap_or(file_name)
+}
+
+/// Selects the file names out of `file_names` that are packages of the `recipe` for the `target`
+/// or their checksum and signature files. A file belongs to the recipe with the longest name that is followed
+/// by a `-` at the start of the file name out of `recipes` and the `recipe` itself.
+pub fn select_artifacts<'f>(
+    file_names: &[&'f str],
+    recipe: &str,
+    target: BuildTarget,
+    recipes: &[String],
+) -> Vec<&'f str> {
+    let extensions = target_extensions(target);
+    let is_owned_by = |name: &str, owner: &str| {
+        name.strip_prefix(owner)
+            .map(|rest| rest.starts_with('-'))
+            .unwrap_or_default()
+    };
+
+    file_names
+        .iter()
+        .copied()
+        .filter(|file_name| {
+            let package = strip_sidecar(file_name);
+            if !extensions.contains(&package_extension(package)) {
+                return false;
+            }
+            let owner = recipes
+                .iter()
+                .map(String::as_str)
+                .chain(std::iter::once(recipe))
+                .filter(|owner| is_owned_by(package, owner))
+                .max_by_key(|owner| owner.len());
+            owner == Some(recipe)
+        })
+        .collect()
+}
+
+/// Removes packages of previous builds of the `recipe` for the `target` from `out_dir`. Links and
+/// files written by the current session are kept. With a dry run the files are only logged.
+/// Returns paths of the selected files.
+pub fn remove_stale_artifacts(
+    out_dir: &Path,
+    recipe: &str,
+    target: BuildTarget,
+    clean: &CleanOutput,
+    logger: &mut BoxedCollector,
+) -> Result<Vec<PathBuf>> {
+    let mut file_names = vec![];
+    for entry in fs::read_dir(out_dir)
+        .with_context(|| format!("failed to read directory `{}`", out_dir.display()))?
+    {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        if metadata
+            .modified()
+            .map(|m| m >= clean.since)
+            .unwrap_or(true)
+        {
+            trace!(logger => "keeping `{}` written by this session", entry.path().display());
+            continue;
+        }
+        file_names.push(entry.file_name().to_string_lossy().to_string());
+    }
+
+    let file_names: Vec<&str> = file_names.iter().map(String::as_str).collect();
+    let mut removed = vec![];
+    for file_name in select_artifacts(&file_names, recipe, target, &clean.recipes) {
+        let path = out_dir.join(file_name);
+        if clean.dry_run {
+            info!(logger => "would remove `{}`", path.display());
+        } else {
+            info!(logger => "removing `{}`", path.display());
+            fs::remove_file(&path)
+                .with_context(|| format!("failed to remove `{}`", path.display()))?;
+        }
+        removed.push(path);
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_only_artifacts_of_the_recipe() {
+        let recipes = vec![
+            "foo".to_string(),
+            "foo-bar".to_string(),
+            "foobar".to_string(),
+        ];
+        let files = [
+            "foo-1.0.0-0.x86_64.rpm",
+            "foo-1.0.0-0.x86_64.rpm.sha256",
+            "foo-0.9.0-1.x86_64.rpm.sha512",
+            "foo-1.0.0-0.amd64.deb",
+            "foo-1.0.0-0.x86_64.rpm.sig",
+            "foo-bar-2.0.0-0.x86_64.rpm",
+            "foo-bar-2.0.0-0.x86_64.rpm.sha256",
+            "foobar-1.0.0-0.x86_64.rpm",
+            "foo.rpm",
+            "notes.txt",
+        ];
+
+        assert_eq!(
+            select_artifacts(&files, "foo", BuildTarget::Rpm, &recipes),
+            vec![
+                "foo-1.0.0-0.x86_64.rpm",
+                "foo-1.0.0-0.x86_64.rpm.sha256",
+                "foo-0.9.0-1.x86_64.rpm.sha512",
+                "foo-1.0.0-0.x86_64.rpm.sig",
+            ]
+        );
+        assert_eq!(
+            select_artifacts(&files, "foo-bar", BuildTarget::Rpm, &recipes),
+            vec![
+                "foo-bar-2.0.0-0.x86_64.rpm",
+                "foo-bar-2.0.0-0.x86_64.rpm.sha256",
+            ]
+        );
+        assert_eq!(
+            select_artifacts(&files, "foo", BuildTarget::Deb, &recipes),
+            vec!["foo-1.0.0-0.amd64.deb"]
+        );
+        assert_eq!(
+            select_artifacts(
+                &[
+                    "foo-1.0.0.tar.gz",
+                    "foo-1.0.0-x86_64.tar.zst",
+                    "foo-1.0.0.tar"
+                ],
+                "foo",
+                BuildTarget::Gzip,
+                &recipes
+            ),
+            vec!["foo-1.0.0.tar.gz", "foo-1.0.0-x86_64.tar.zst"]
+        );
+        assert_eq!(
+            select_artifacts(
+                &[
+                    "foo-1.0.0-0-x86_64.pkg.tar.zst",
+                    "foo-1.0.0-0-x86_64.pkg.tar.zst.sig",
+                    "foo-0.9.0-0-x86_64.pkg.tar.xz",
+                    "foo-bar-1.0.0-0-x86_64.pkg.tar.zst",
+                    "foo-1.0.0.tar.zst",
+                ],
+                "foo",
+                BuildTarget::Pkg,
+                &recipes
+            ),
+            vec![
+                "foo-1.0.0-0-x86_64.pkg.tar.zst",
+                "foo-1.0.0-0-x86_64.pkg.tar.zst.sig",
+                "foo-0.9.0-0-x86_64.pkg.tar.xz",
+            ]
+        );
+    }
+}
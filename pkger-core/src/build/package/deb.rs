@@ -1,12 +1,16 @@
+use crate::archive::flate2::{write::GzEncoder, Compression};
 use crate::build::container::Context;
+use crate::build::package::files::{Inspection, InstalledFiles};
 use crate::build::package::sign::{import_gpg_key, upload_gpg_key};
 use crate::build::package::{Manifest, Package};
 use crate::image::ImageState;
 use crate::log::{debug, info, trace, BoxedCollector};
+use crate::recipe::{files_entry_path, BuildTarget, Changelog, AUTO_FILES};
 use crate::runtime::container::ExecOpts;
-use crate::{ErrContext, Result};
+use crate::{err, ErrContext, Result};
 
 use async_trait::async_trait;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 
 pub struct Deb;
@@ -35,7 +39,7 @@ impl Package for Deb {
 
         info!(logger => "building DEB package {}", package_name);
 
-        let debbld_dir = PathBuf::from("/root/debbuild");
+        let debbld_dir = ctx.build.container_home_dir().join("debbuild");
         let tmp_dir = debbld_dir.join("tmp");
         let base_dir = debbld_dir.join(&package_name);
         let deb_dir = base_dir.join("DEBIAN");
@@ -45,27 +49,30 @@ impl Package for Deb {
             .await
             .context("failed to create dirs")?;
 
-        let size_out = ctx
-            .checked_exec(
-                &ExecOpts::default()
-                    .cmd("du -s .")
-                    .working_dir(&ctx.build.container_out_dir),
-                logger,
-            )
-            .await
-            .context("failed to check size of package files")?
-            .stdout
-            .join("");
-        let size = size_out.split_ascii_whitespace().next();
+        let explicit_files = explicit_files(ctx);
+        let installed_size = ctx
+            .build
+            .recipe
+            .metadata
+            .deb
+            .as_ref()
+            .and_then(|deb| deb.installed_size);
+        let size = match installed_size {
+            Some(size) => {
+                debug!(logger => "using installed size from recipe: {} KiB", size);
+                Some(size.to_string())
+            }
+            None => installed_size_kib(ctx, explicit_files.as_deref(), logger).await?,
+        };
 
         let control = ctx
             .build
             .recipe
             .as_deb_control(
-                &image_state.image,
-                size,
+                ctx.build.target().image(),
+                size.as_deref(),
                 &ctx.build.build_version,
-                *ctx.build.target.build_target(),
+                ctx.package_target,
                 logger,
             )
             .render()
@@ -112,15 +119,24 @@ impl Package for Deb {
             .context("failed to upload control file to container")?;
 
         trace!(logger => "copy source files to build dir");
+        let copy_cmd = match &explicit_files {
+            Some(files) => {
+                debug!(logger => "using explicit files list: {:?}", files);
+                format!("cp -av --parents {} {}", files, base_dir.display())
+            }
+            None => format!("cp -rv . {}", base_dir.display()),
+        };
         ctx.checked_exec(
             &ExecOpts::default()
-                .cmd(&format!("cp -rv . {}", base_dir.display()))
+                .cmd(&copy_cmd)
                 .working_dir(&ctx.build.container_out_dir),
             logger,
         )
         .await
         .context("failed to copy source files to build directory")?;
 
+        upload_changelog(ctx, &base_dir, logger).await?;
+
         let dpkg_deb_opts = if image_state.os.version().parse::<u8>().unwrap_or_default() < 10 {
             "--build"
         } else {
@@ -151,6 +167,118 @@ impl Package for Deb {
     }
 }
 
+/// Calculates the size of the package files in kilobytes with `du` or from the downloaded
+/// archive of the output directory if `du` is not available in the image.
+async fn installed_size_kib(
+    ctx: &Context<'_>,
+    explicit_files: Option<&str>,
+    logger: &mut BoxedCollector,
+) -> Result<Option<String>> {
+    let size_cmd = match explicit_files {
+        Some(files) => format!("du -ksc {} | tail -n 1", files),
+        None => "du -ks .".to_string(),
+    };
+    let size_out = match Inspection::detect(ctx, "du", logger).await {
+        Inspection::Tool => ctx
+            .checked_exec(
+                &ExecOpts::default()
+                    .cmd(&size_cmd)
+                    .working_dir(&ctx.build.container_out_dir),
+                logger,
+            )
+            .await
+            .context("failed to check size of package files")?
+            .stdout
+            .join(""),
+        Inspection::Archive => InstalledFiles::download(ctx, logger)
+            .await
+            .context("failed to check size of package files")?
+            .size_kib()
+            .to_string(),
+    };
+    let size = size_out
+        .split_ascii_whitespace()
+        .next()
+        .map(ToString::to_string);
+    debug!(logger => "calculated installed size: {:?} KiB", size);
+    Ok(size)
+}
+
+/// Returns the quoted paths, relative to the output directory, of the explicit files list of the
+/// recipe. Lists with an `auto` entry include every file so `None` is returned for them as well.
+fn explicit_files(ctx: &Context<'_>) -> Option<String> {
+    let files = ctx.build.recipe.explicit_files(BuildTarget::Deb)?;
+    deb_files(files)
+}
+
+/// Returns the quoted paths of the files list entries without RPM directives. `%ghost` entries
+/// don't exist in the output directory and are left out.
+fn deb_files(files: &[String]) -> Option<String> {
+    if files.iter().any(|file| file.trim() == AUTO_FILES) {
+        return None;
+    }
+    Some(
+        files
+            .iter()
+            .filter(|file| !file.trim_start().starts_with("%ghost"))
+            .map(|file| {
+                format!(
+                    "'./{}'",
+                    files_entry_path(file)
+                        .trim_start_matches('/')
+                        .replace('\'', r"'\''")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// Uploads the changelog of the recipe to `usr/share/doc/<name>/changelog.Debian.gz` in the
+/// package directory if the recipe has a `changelog_file`.
+async fn upload_changelog(
+    ctx: &Context<'_>,
+    base_dir: &Path,
+    logger: &mut BoxedCollector,
+) -> Result<()> {
+    let changelog = match &ctx.build.recipe.changelog {
+        Some(changelog) => changelog,
+        None => return Ok(()),
+    };
+    let metadata = &ctx.build.recipe.metadata;
+    let name = metadata.name.replace('_', "-");
+    let changelog = changelog.deb_changelog(
+        &name,
+        metadata.maintainer.as_deref(),
+        &ctx.build.build_version,
+        metadata.release(),
+        Changelog::today(),
+    );
+    debug!(logger => "{}", changelog);
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder
+        .write_all(changelog.as_bytes())
+        .context("failed to compress changelog")?;
+    let compressed = encoder.finish().context("failed to compress changelog")?;
+
+    let doc_dir = base_dir.join("usr/share/doc").join(&name);
+    ctx.create_dirs(&[doc_dir.as_path()], logger)
+        .await
+        .context("failed to create doc dir")?;
+    ctx.container
+        .upload_files(
+            vec![(
+                PathBuf::from("./changelog.Debian.gz").as_path(),
+                compressed.as_slice(),
+            )],
+            &doc_dir,
+            logger,
+        )
+        .await
+        .context("failed to upload changelog to container")
+}
+
 pub async fn sign_package(
     ctx: &Context<'_>,
     package: &Path,
@@ -163,11 +291,20 @@ pub async fn sign_package(
         return Ok(());
     };
 
-    let key_file = upload_gpg_key(ctx, gpg_key, &ctx.build.container_tmp_dir, logger)
+    trace!(logger => "check if dpkg-sig is installed");
+    if ctx
+        .checked_exec(&ExecOpts::default().cmd("command -v dpkg-sig"), logger)
+        .await
+        .is_err()
+    {
+        return err!("failed to sign package, `dpkg-sig` is not installed in the image. Install it in the image or disable signing with `--no-sign`");
+    }
+
+    let uploaded_key = upload_gpg_key(ctx, gpg_key, &ctx.build.container_tmp_dir, logger)
         .await
         .context("failed to upload gpg key to container")?;
 
-    import_gpg_key(ctx, gpg_key, &key_file, logger)
+    import_gpg_key(ctx, &uploaded_key, logger)
         .await
         .context("failed to import gpg key")?;
 
@@ -195,9 +332,9 @@ pub async fn sign_package(
     trace!(logger => "add signature");
     ctx.checked_exec(
         &ExecOpts::default().cmd(&format!(
-            r#"dpkg-sig -k {} -g "--pinentry-mode=loopback --passphrase {}" --sign {} {}"#,
+            r#"dpkg-sig -k {} -g "--pinentry-mode=loopback --passphrase-file {}" --sign {} {}"#,
             key_id,
-            gpg_key.pass(),
+            uploaded_key.passphrase.display(),
             gpg_key.name().to_lowercase(),
             package.display()
         )),
@@ -206,3 +343,25 @@ pub async fn sign_package(
     .await
     .map(|_| ())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_rpm_directives_of_files() {
+        let files = vec![
+            "%attr(0755, root, root) /usr/bin/foo".to_string(),
+            "%config(noreplace) /etc/foo/foo.conf".to_string(),
+            "%ghost /var/log/foo.log".to_string(),
+            "/usr/share/foo's".to_string(),
+        ];
+        assert_eq!(
+            deb_files(&files).unwrap(),
+            r"'./usr/bin/foo' './etc/foo/foo.conf' './usr/share/foo'\''s'"
+        );
+
+        let files = vec!["/usr/bin/foo".to_string(), AUTO_FILES.to_string()];
+        assert_eq!(deb_files(&files), None);
+    }
+}
@@ -0,0 +1,155 @@
+use crate::build::container::Context;
+use crate::log::{debug, info, warning, BoxedCollector};
+use crate::recipe::BuildArch;
+use crate::runtime::container::ExecOpts;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+/// Offset of `EI_DATA` which tells whether the file is little or big endian.
+const EI_DATA: usize = 5;
+/// Offset of `e_machine` with the architecture of the file.
+const E_MACHINE: usize = 18;
+const HEADER_LEN: usize = 20;
+
+/// Prints the first bytes of every ELF file followed by its path, one file per line.
+const FIND_ELF_CMD: &str = r#"find . -type f | while IFS= read -r f; do if [ "$(head -c 4 "$f" | tail -c 3)" = ELF ]; then echo "$(od -An -tx1 -N20 "$f" | tr -d '\n') $f"; fi; done"#;
+
+/// Returns the machine type from the header of an ELF file or `None` if it's not an ELF file.
+pub fn elf_machine(header: &[u8]) -> Option<u16> {
+    if header.len() < HEADER_LEN || !header.starts_with(ELF_MAGIC) {
+        return None;
+    }
+    let bytes = [header[E_MACHINE], header[E_MACHINE + 1]];
+    match header[EI_DATA] {
+        1 => Some(u16::from_le_bytes(bytes)),
+        2 => Some(u16::from_be_bytes(bytes)),
+        _ => None,
+    }
+}
+
+/// Returns the architecture of an ELF machine type if it's a known one.
+pub fn machine_arch(machine: u16) -> Option<BuildArch> {
+    Some(match machine {
+        3 => BuildArch::x86,
+        21 => BuildArch::Other("ppc64".into()),
+        22 => BuildArch::Other("s390x".into()),
+        40 => BuildArch::Arm,
+        62 => BuildArch::x86_64,
+        183 => BuildArch::Arm64,
+        243 => BuildArch::Other("riscv64".into()),
+        _ => return None,
+    })
+}
+
+fn is_compatible(declared: &BuildArch, found: &BuildArch) -> bool {
+    use BuildArch::*;
+    match (declared, found) {
+        (All, _) => true,
+        (Arm | Armv6h | Armv7h, Arm) => true,
+        (declared, found) => declared == found,
+    }
+}
+
+/// Parses the output of `FIND_ELF_CMD` and returns paths of the files with their architecture
+/// that don't match the `declared` one.
+pub fn find_mismatches(declared: &BuildArch, output: &str) -> Vec<(String, BuildArch)> {
+    let mut mismatches = vec![];
+    for line in output.lines() {
+        let mut parts = line.split_whitespace();
+        let header: Vec<u8> = parts
+            .by_ref()
+            .take(HEADER_LEN)
+            .filter_map(|byte| u8::from_str_radix(byte, 16).ok())
+            .collect();
+        let path = parts.collect::<Vec<_>>().join(" ");
+        let path = path.trim_start_matches("./").to_string();
+
+        if let Some(found) = elf_machine(&header).and_then(machine_arch) {
+            if !is_compatible(declared, &found) {
+                mismatches.push((path, found));
+            }
+        }
+    }
+    mismatches
+}
+
+/// Warns about ELF files in the staged output whose architecture doesn't match the architecture
+/// declared in the recipe. Packages without an architecture are not checked.
+pub async fn check_arch(ctx: &Context<'_>, logger: &mut BoxedCollector) {
+    let declared = &ctx.build.recipe.metadata.arch;
+    if *declared == BuildArch::All {
+        debug!(logger => "package has no architecture, skipping the architecture check");
+        return;
+    }
+    info!(logger => "checking architecture of binaries");
+
+    let output = match ctx
+        .checked_exec(
+            &ExecOpts::default()
+                .cmd(FIND_ELF_CMD)
+                .working_dir(&ctx.build.container_out_dir),
+            logger,
+        )
+        .await
+    {
+        Ok(output) => output.stdout.join(""),
+        Err(e) => {
+            warning!(logger => "failed to check architecture of binaries, reason: {:?}", e);
+            return;
+        }
+    };
+
+    for (path, found) in find_mismatches(declared, &output) {
+        warning!(logger => "`{}` is built for `{}` but the package architecture is `{}`", path, found, declared);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(data: u8, machine: [u8; 2]) -> String {
+        let mut header = vec![0x7f, b'E', b'L', b'F', 2, data, 1, 0];
+        header.resize(E_MACHINE, 0);
+        header.extend(machine);
+        header.iter().map(|byte| format!(" {:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn detects_arch_mismatch() {
+        let x86_64 = header(1, [62, 0]);
+        let aarch64 = header(1, [183, 0]);
+        let armhf = header(1, [40, 0]);
+        let s390x = header(2, [0, 22]);
+        let unknown = header(1, [0xff, 0xff]);
+        let output = format!(
+            "{} ./usr/bin/foo\n{} ./usr/lib/libfoo bar.so\n{} ./usr/bin/arm\n{} ./usr/bin/s390x\n{} ./usr/bin/unknown\n",
+            x86_64, aarch64, armhf, s390x, unknown
+        );
+
+        assert_eq!(
+            find_mismatches(&BuildArch::x86_64, &output),
+            vec![
+                ("usr/lib/libfoo bar.so".to_string(), BuildArch::Arm64),
+                ("usr/bin/arm".to_string(), BuildArch::Arm),
+                (
+                    "usr/bin/s390x".to_string(),
+                    BuildArch::Other("s390x".into())
+                ),
+            ]
+        );
+        assert_eq!(
+            find_mismatches(&BuildArch::Armv7h, &output),
+            vec![
+                ("usr/bin/foo".to_string(), BuildArch::x86_64),
+                ("usr/lib/libfoo bar.so".to_string(), BuildArch::Arm64),
+                (
+                    "usr/bin/s390x".to_string(),
+                    BuildArch::Other("s390x".into())
+                ),
+            ]
+        );
+        assert!(find_mismatches(&BuildArch::All, &output).is_empty());
+        assert!(find_mismatches(&BuildArch::x86_64, "").is_empty());
+        assert_eq!(elf_machine(b"#!/bin/sh\necho not an elf file"), None);
+    }
+}
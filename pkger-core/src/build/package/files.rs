@@ -0,0 +1,148 @@
+use crate::build::container::Context;
+use crate::log::{debug, warning, BoxedCollector};
+use crate::runtime::container::ExecOpts;
+use crate::{ErrContext, Result};
+
+use std::path::{Component, Path};
+
+/// How files installed in the output directory of the container are inspected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Inspection {
+    /// Run a tool like `du` or `find` in the container.
+    Tool,
+    /// Copy the output directory out of the container as an archive and inspect it on the host.
+    Archive,
+}
+
+impl Inspection {
+    /// Decides how to inspect the installed files depending on whether the `tool` is available
+    /// in the container.
+    pub fn choose(tool: &str, available: bool, logger: &mut BoxedCollector) -> Self {
+        if available {
+            Inspection::Tool
+        } else {
+            warning!(logger => "`{}` is not available in the image, inspecting the package files on the host instead", tool);
+            Inspection::Archive
+        }
+    }
+
+    /// Checks whether the `tool` is available in the container and decides how to inspect the
+    /// installed files.
+    pub async fn detect(ctx: &Context<'_>, tool: &str, logger: &mut BoxedCollector) -> Self {
+        debug!(logger => "checking if `{}` is available", tool);
+        let available = ctx
+            .container
+            .exec(
+                &ExecOpts::default().cmd(&format!("command -v {}", tool)),
+                logger,
+            )
+            .await
+            .map(|out| out.exit_code == 0)
+            .unwrap_or(false);
+        Self::choose(tool, available, logger)
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+/// Regular files and symlinks installed in the output directory.
+pub struct InstalledFiles {
+    /// Absolute paths of the files in the package.
+    pub paths: Vec<String>,
+    /// Total size of the regular files in bytes.
+    pub size: u64,
+}
+
+impl InstalledFiles {
+    /// Reads the installed files from a tar `archive` of the output directory. The first
+    /// component of every path is the output directory itself and is stripped.
+    pub fn from_archive(archive: &[u8]) -> Result<Self> {
+        let mut files = InstalledFiles::default();
+        let mut archive = tar::Archive::new(archive);
+        for entry in archive.entries().context("failed to read archive")? {
+            let entry = entry.context("failed to read archive entry")?;
+            let header = entry.header();
+            let entry_type = header.entry_type();
+            if !entry_type.is_file() && !entry_type.is_symlink() {
+                continue;
+            }
+            let path = entry.path().context("invalid path in archive")?;
+            let path = strip_first_component(&path);
+            if path.is_empty() {
+                continue;
+            }
+            if entry_type.is_file() {
+                files.size += header.size().unwrap_or_default();
+            }
+            files.paths.push(format!("/{}", path));
+        }
+        Ok(files)
+    }
+
+    /// Copies the output directory out of the container and reads the installed files.
+    pub async fn download(ctx: &Context<'_>, logger: &mut BoxedCollector) -> Result<Self> {
+        let archive = ctx
+            .container
+            .copy_from(&ctx.build.container_out_dir, logger)
+            .await
+            .context("failed to copy package files from container")?;
+        Self::from_archive(&archive)
+    }
+
+    /// Returns the size in KiB rounded up like `du -s` does.
+    pub fn size_kib(&self) -> u64 {
+        self.size.div_ceil(1024)
+    }
+}
+
+fn strip_first_component(path: &Path) -> String {
+    path.components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .skip(1)
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_to_archive_when_tool_is_missing() {
+        let mut logger: BoxedCollector = Box::new(crate::log::Logger::stdout(None));
+        assert_eq!(
+            Inspection::choose("du", true, &mut logger),
+            Inspection::Tool
+        );
+        assert_eq!(
+            Inspection::choose("find", false, &mut logger),
+            Inspection::Archive
+        );
+
+        let mut builder = tar::Builder::new(vec![]);
+        let mut dir = tar::Header::new_gnu();
+        dir.set_entry_type(tar::EntryType::Directory);
+        dir.set_size(0);
+        builder
+            .append_data(&mut dir, "pkger-out-1/usr/bin/", std::io::empty())
+            .unwrap();
+        let mut file = tar::Header::new_gnu();
+        file.set_size(2049);
+        builder
+            .append_data(&mut file, "pkger-out-1/usr/bin/pkger", &[0u8; 2049][..])
+            .unwrap();
+        let mut link = tar::Header::new_gnu();
+        link.set_entry_type(tar::EntryType::Symlink);
+        link.set_size(0);
+        link.set_link_name("pkger").unwrap();
+        builder
+            .append_data(&mut link, "pkger-out-1/usr/bin/pk", std::io::empty())
+            .unwrap();
+        let archive = builder.into_inner().unwrap();
+
+        let files = InstalledFiles::from_archive(&archive).unwrap();
+        assert_eq!(files.paths, vec!["/usr/bin/pkger", "/usr/bin/pk"]);
+        assert_eq!(files.size, 2049);
+        assert_eq!(files.size_kib(), 3);
+    }
+}
@@ -1,8 +1,9 @@
-use crate::archive::{save_tar_gz, tar};
+use crate::archive::save_tar_gz;
 use crate::build::container::Context;
 use crate::build::package::Package;
 use crate::image::ImageState;
-use crate::log::{info, BoxedCollector};
+use crate::log::{debug, info, BoxedCollector};
+use crate::runtime::container::ExecOpts;
 use crate::{ErrContext, Result};
 
 use async_trait::async_trait;
@@ -13,33 +14,72 @@ pub struct Gzip;
 #[async_trait]
 impl Package for Gzip {
     fn name(ctx: &Context<'_>, extension: bool) -> String {
-        format!(
-            "{}-{}.{}",
-            &ctx.build.recipe.metadata.name,
-            &ctx.build.build_version,
-            if extension { ".tar.gz" } else { "" },
-        )
+        let mut name = format!(
+            "{}-{}",
+            &ctx.build.recipe.metadata.name, &ctx.build.build_version,
+        );
+        if let Some(arch) = ctx.build.matrix_arch() {
+            name.push('-');
+            name.push_str(arch.as_ref());
+        }
+        if extension {
+            format!(
+                "{}.{}",
+                name,
+                ctx.build.recipe.metadata.compression.extension()
+            )
+        } else {
+            name
+        }
     }
 
-    /// Creates a final GZIP package and saves it to `output_dir` returning the path of the final
-    /// archive as String.
+    /// Creates a final tar archive compressed with the compression of the recipe and saves it to
+    /// `output_dir` returning the path of the final archive as String.
     async fn build(
         ctx: &Context<'_>,
         _: &ImageState,
         output_dir: &Path,
         logger: &mut BoxedCollector,
     ) -> Result<PathBuf> {
+        let compression = ctx.build.recipe.metadata.compression;
         let archive_name = Self::name(ctx, true);
-        info!(logger => "building GZIP package {}" ,archive_name);
-        let package = ctx
-            .container
-            .copy_from(&ctx.build.container_out_dir, logger)
-            .await?;
+        info!(logger => "building {} package {}", compression.extension(), archive_name);
+
+        let compress = match compression.command() {
+            Some(compress) => compress,
+            None => {
+                debug!(logger => "copying package files from container {}", ctx.container.id());
+                let package = ctx.container.copy_from_stream(&ctx.build.container_out_dir);
 
-        let archive = tar::Archive::new(&package[..]);
+                return save_tar_gz(package, &archive_name, output_dir, logger)
+                    .await
+                    .context("failed to save package as tar.gz")
+                    .map(|_| output_dir.join(archive_name));
+            }
+        };
+
+        let tar_path = ctx
+            .build
+            .container_tmp_dir
+            .join(format!("{}.tar", Self::name(ctx, false)));
+        ctx.checked_exec(
+            &ExecOpts::default()
+                .cmd(&format!(
+                    "tar -cf {0} . && {1} {0}",
+                    tar_path.display(),
+                    compress
+                ))
+                .working_dir(&ctx.build.container_out_dir),
+            logger,
+        )
+        .await
+        .with_context(|| format!("failed to compress package as {}", compression.extension()))?;
 
-        save_tar_gz(archive, &archive_name, output_dir, logger)
-            .context("failed to save package as tar.gz")
+        let archive_path = ctx.build.container_tmp_dir.join(&archive_name);
+        ctx.container
+            .download_files(&archive_path, output_dir, logger)
+            .await
             .map(|_| output_dir.join(archive_name))
+            .context("failed to copy archive to output directory")
     }
 }
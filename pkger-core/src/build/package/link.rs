@@ -0,0 +1,189 @@
+use crate::build::container::Context;
+use crate::log::{info, BoxedCollector};
+use crate::recipe::BuildTarget;
+use crate::template;
+use crate::{err, ErrContext, Result};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Extensions made of multiple parts that `Path::extension` would cut short.
+const COMPOUND_EXTENSIONS: &[&str] = &["pkg.tar.zst", "pkg.tar.xz", "tar.gz", "tar.xz", "tar.zst"];
+
+/// Returns the extension of the package file name like `rpm` or `tar.gz`.
+pub fn package_extension(file_name: &str) -> &str {
+    COMPOUND_EXTENSIONS
+        .iter()
+        .find(|ext| {
+            file_name
+                .strip_suffix(*ext)
+                .map(|rest| rest.ends_with('.'))
+                .unwrap_or_default()
+        })
+        .copied()
+        .or_else(|| {
+            Path::new(file_name)
+                .extension()
+                .and_then(|ext| ext.to_str())
+        })
+        .unwrap_or_default()
+}
+
+/// Returns extensions of packages of the `target` as returned by `package_extension`.
+pub fn target_extensions(target: BuildTarget) -> &'static [&'static str] {
+    match target {
+        BuildTarget::Rpm => &["rpm"],
+        BuildTarget::Deb => &["deb"],
+        BuildTarget::Pkg => &["pkg.tar.zst", "pkg.tar.xz"],
+        BuildTarget::Apk => &["apk"],
+        BuildTarget::Gzip => &["tar.gz", "tar.xz", "tar.zst"],
+    }
+}
+
+/// Returns the target of the package with the `file_name` or `None` if it's not a package.
+pub fn package_target(file_name: &str) -> Option<BuildTarget> {
+    let extension = package_extension(file_name);
+    [
+        BuildTarget::Rpm,
+        BuildTarget::Deb,
+        BuildTarget::Pkg,
+        BuildTarget::Apk,
+        BuildTarget::Gzip,
+    ]
+    .into_iter()
+    .find(|target| target_extensions(*target).contains(&extension))
+}
+
+/// Renders the `template` of the link name with `vars` and the `PKGER_PACKAGE_EXT` variable
+/// and returns the path of the link next to `package`.
+pub fn link_path(
+    template: &str,
+    package: &Path,
+    vars: &HashMap<String, String>,
+) -> Result<PathBuf> {
+    let file_name = package
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let mut vars = vars.clone();
+    vars.insert(
+        "PKGER_PACKAGE_EXT".to_string(),
+        package_extension(&file_name).to_string(),
+    );
+
+    let name = template::render(template, &vars);
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return err!("invalid link name `{}`, it must be a plain file name", name);
+    }
+    if name == file_name {
+        return err!("link name `{}` is the same as the package name", name);
+    }
+
+    Ok(package.with_file_name(name))
+}
+
+/// Points the symlink at `link` to `package` replacing an existing link atomically. The target of
+/// the link is relative so that the output directory can be moved.
+pub fn update_link(link: &Path, package: &Path) -> Result<()> {
+    let target = package
+        .file_name()
+        .context("package path has no file name")?;
+    if link.exists() && !link.is_symlink() {
+        return err!(
+            "`{}` already exists and is not a symlink, refusing to replace it",
+            link.display()
+        );
+    }
+
+    let tmp = link.with_file_name(format!(
+        ".{}.tmp",
+        link.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    let _ = fs::remove_file(&tmp);
+    symlink(Path::new(target), &tmp).context("failed to create symlink")?;
+    fs::rename(&tmp, link).context("failed to replace symlink")
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+fn symlink(_: &Path, _: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "symlinks are only supported on unix",
+    ))
+}
+
+/// Creates or updates the link declared by `latest_link` in the recipe metadata.
+pub fn link_latest(ctx: &Context<'_>, package: &Path, logger: &mut BoxedCollector) -> Result<()> {
+    if let Some(template) = &ctx.build.recipe.metadata.latest_link {
+        let link = link_path(template, package, ctx.vars.inner())?;
+        info!(logger => "linking `{}` to `{}`", link.display(), package.display());
+        update_link(&link, package)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn links_latest_package() {
+        assert_eq!(package_extension("pkger-0.11.0-0.x86_64.rpm"), "rpm");
+        assert_eq!(package_extension("pkger-0.11.0.tar.gz"), "tar.gz");
+        assert_eq!(package_extension("pkger-0.11.0.tar.zst"), "tar.zst");
+        assert_eq!(
+            package_extension("pkger-0.11.0-0-x86_64.pkg.tar.zst"),
+            "pkg.tar.zst"
+        );
+        assert_eq!(package_extension("pkger"), "");
+        assert_eq!(
+            package_target("pkger-0.11.0-0-x86_64.pkg.tar.zst"),
+            Some(BuildTarget::Pkg)
+        );
+        assert_eq!(
+            package_target("pkger-0.11.0.tar.xz"),
+            Some(BuildTarget::Gzip)
+        );
+        assert_eq!(package_target("pkger-0.11.0-0.x86_64.rpm.sig"), None);
+        assert_eq!(package_target("pkger-0.11.0.tar"), None);
+
+        let dir = TempDir::new("pkger-latest-link").unwrap();
+        let old = dir.path().join("pkger-0.10.0-0.x86_64.rpm");
+        let new = dir.path().join("pkger-0.11.0-0.x86_64.rpm");
+        fs::write(&old, "old").unwrap();
+        fs::write(&new, "new").unwrap();
+
+        let mut vars = HashMap::new();
+        vars.insert("RECIPE".to_string(), "pkger".to_string());
+        let template = "${RECIPE}-latest.${PKGER_PACKAGE_EXT}";
+        let link = link_path(template, &new, &vars).unwrap();
+        assert_eq!(link, dir.path().join("pkger-latest.rpm"));
+        assert!(link_path("../${RECIPE}", &new, &vars).is_err());
+        assert!(link_path("pkger-0.11.0-0.x86_64.rpm", &new, &vars).is_err());
+
+        update_link(&link, &old).unwrap();
+        assert_eq!(
+            fs::read_link(&link).unwrap(),
+            PathBuf::from("pkger-0.10.0-0.x86_64.rpm")
+        );
+
+        update_link(&link, &new).unwrap();
+        assert_eq!(
+            fs::read_link(&link).unwrap(),
+            PathBuf::from("pkger-0.11.0-0.x86_64.rpm")
+        );
+        assert_eq!(fs::read_to_string(&link).unwrap(), "new");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+
+        fs::remove_file(&link).unwrap();
+        fs::write(&link, "regular file").unwrap();
+        assert!(update_link(&link, &new).is_err());
+    }
+}
@@ -7,11 +7,18 @@ use crate::Result;
 use pkgspec_core::Manifest;
 
 pub mod apk;
+pub mod checksums;
+pub mod clean;
 pub mod deb;
+pub mod elf;
+pub mod files;
 pub mod gzip;
+pub mod link;
+pub mod permissions;
 pub mod pkg;
 pub mod rpm;
 mod sign;
+pub mod tools;
 
 use async_trait::async_trait;
 use std::path::{Path, PathBuf};
@@ -33,7 +40,8 @@ pub async fn build(
     output_dir: &Path,
     output: &mut BoxedCollector,
 ) -> Result<PathBuf> {
-    match ctx.build.target.build_target() {
+    tools::preflight(ctx, output).await?;
+    match ctx.package_target {
         BuildTarget::Gzip => gzip::Gzip::build(ctx, image_state, output_dir, output).await,
         BuildTarget::Rpm => rpm::Rpm::build(ctx, image_state, output_dir, output).await,
         BuildTarget::Deb => deb::Deb::build(ctx, image_state, output_dir, output).await,
@@ -0,0 +1,142 @@
+use crate::log::{debug, info, warning, BoxedCollector};
+use crate::{err, ErrContext, Result};
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// File mode and SELinux context applied to built packages after they are copied out of the
+/// container.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ArtifactPermissions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Octal file mode like `0644`.
+    pub mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// SELinux context set with `chcon` like `system_u:object_r:httpd_sys_content_t:s0`.
+    pub selinux_context: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    /// Restore the default SELinux context of the package location with `restorecon`. Ignored
+    /// when `selinux_context` is set.
+    pub restorecon: bool,
+}
+
+impl ArtifactPermissions {
+    pub fn is_empty(&self) -> bool {
+        self.mode.is_none() && self.selinux_context.is_none() && !self.restorecon
+    }
+
+    /// Applies the file mode and SELinux context to the package at `path`. If the SELinux tools
+    /// are not installed the context is skipped with a warning.
+    pub fn apply(&self, path: &Path, logger: &mut BoxedCollector) -> Result<()> {
+        if let Some(mode) = &self.mode {
+            let mode = parse_mode(mode)?;
+            info!(logger => "setting mode of `{}` to {:o}", path.display(), mode);
+            set_mode(path, mode)
+                .with_context(|| format!("failed to set mode of `{}`", path.display()))?;
+        }
+
+        if let Some(context) = &self.selinux_context {
+            info!(logger => "setting SELinux context of `{}` to `{}`", path.display(), context);
+            run_selinux_tool("chcon", &[context.as_str()], path, logger)?;
+        } else if self.restorecon {
+            info!(logger => "restoring SELinux context of `{}`", path.display());
+            run_selinux_tool("restorecon", &[], path, logger)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses an octal file mode like `0644` or `755`.
+pub fn parse_mode(mode: &str) -> Result<u32> {
+    match u32::from_str_radix(mode, 8) {
+        Ok(parsed) if parsed <= 0o7777 => Ok(parsed),
+        _ => err!(
+            "invalid file mode `{}`, expected an octal number like `0644`",
+            mode
+        ),
+    }
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_: &Path, _: u32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "file modes are only supported on unix",
+    ))
+}
+
+/// Runs `program` with `args` followed by `path`. A missing program is skipped with a warning.
+fn run_selinux_tool(
+    program: &str,
+    args: &[&str],
+    path: &Path,
+    logger: &mut BoxedCollector,
+) -> Result<()> {
+    debug!(logger => "running `{}` on `{}`", program, path.display());
+    match Command::new(program).args(args).arg(path).output() {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => err!(
+            "`{}` failed on `{}`: {}",
+            program,
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            warning!(logger => "`{}` not found, skipping setting the SELinux context of `{}`", program, path.display());
+            Ok(())
+        }
+        Err(e) => Err(e).with_context(|| format!("failed to run `{}`", program)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn sets_artifact_mode() {
+        assert_eq!(parse_mode("0644").unwrap(), 0o644);
+        assert_eq!(parse_mode("755").unwrap(), 0o755);
+        assert!(parse_mode("0999").is_err());
+        assert!(parse_mode("rw-r--r--").is_err());
+        assert!(parse_mode("17777").is_err());
+
+        let dir = TempDir::new("pkger-artifact-mode").unwrap();
+        let package = dir.path().join("pkger-0.11.0-0.x86_64.rpm");
+        fs::write(&package, "package").unwrap();
+        let mut logger: BoxedCollector = Box::new(crate::log::Logger::stdout(None));
+
+        let permissions = ArtifactPermissions {
+            mode: Some("0640".to_string()),
+            ..Default::default()
+        };
+        assert!(!permissions.is_empty());
+        permissions.apply(&package, &mut logger).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&package).unwrap().permissions().mode();
+            assert_eq!(mode & 0o7777, 0o640);
+        }
+
+        assert!(run_selinux_tool("pkger-missing-restorecon", &[], &package, &mut logger).is_ok());
+        assert!(ArtifactPermissions {
+            mode: Some("invalid".to_string()),
+            ..Default::default()
+        }
+        .apply(&package, &mut logger)
+        .is_err());
+    }
+}
@@ -1,5 +1,7 @@
 use crate::build::container::Context;
+use crate::build::package::sign::{import_gpg_key, upload_gpg_key};
 use crate::build::package::{Manifest, Package};
+use crate::checksum::Checksum;
 use crate::image::ImageState;
 use crate::log::{debug, info, trace, BoxedCollector};
 use crate::runtime::container::ExecOpts;
@@ -8,6 +10,9 @@ use crate::{ErrContext, Result};
 use async_trait::async_trait;
 use std::path::{Path, PathBuf};
 
+/// Algorithm of the checksums of sources in generated PKGBUILDs.
+pub const SOURCE_CHECKSUM: Checksum = Checksum::Sha256;
+
 pub struct Pkg;
 
 #[async_trait]
@@ -26,7 +31,7 @@ impl Package for Pkg {
     /// Creates a final PKG package and saves it to `output_dir`
     async fn build(
         ctx: &Context<'_>,
-        image_state: &ImageState,
+        _: &ImageState,
         output_dir: &Path,
         logger: &mut BoxedCollector,
     ) -> Result<PathBuf> {
@@ -57,6 +62,35 @@ impl Package for Pkg {
         .await
         .context("failed to copy source files to temp directory")?;
 
+        let mut sources = vec![source_tar_path.to_string_lossy().to_string()];
+
+        // files that are not extracted are separate sources next to the PKGBUILD
+        let noextract = ctx
+            .build
+            .recipe
+            .metadata
+            .pkg
+            .as_ref()
+            .map(|pkg| pkg.noextract.as_slice())
+            .unwrap_or_default();
+        let bld_dir_path = bld_dir.to_string_lossy();
+        for entry in noextract {
+            let entry = entry.trim_start_matches('/');
+            trace!(logger => "move {} to a source that is not extracted", entry);
+            ctx.checked_exec(
+                &ExecOpts::default()
+                    .cmd(r#"mv -- "$1" "$2""#)
+                    .args([entry, bld_dir_path.as_ref()])
+                    .working_dir(src_dir.as_path()),
+                logger,
+            )
+            .await
+            .with_context(|| format!("file `{}` listed in `noextract` doesn't exist", entry))?;
+            if let Some(name) = Path::new(entry).file_name() {
+                sources.push(bld_dir.join(name).to_string_lossy().to_string());
+            }
+        }
+
         trace!(logger => "prepare archived source files");
         ctx.checked_exec(
             &ExecOpts::default()
@@ -66,33 +100,39 @@ impl Package for Pkg {
         )
         .await?;
 
-        trace!(logger => "calculate source MD5 checksum");
-        let sum = ctx
-            .checked_exec(
-                &ExecOpts::default().cmd(&format!("md5sum {}", source_tar_path.display())),
-                logger,
-            )
-            .await
-            .map(|out| out.stdout.join(""))?;
-        let sum = sum
-            .split_ascii_whitespace()
-            .next()
-            .map(|s| s.to_string())
-            .context("failed to calculate MD5 checksum of source")?;
-
-        let sources = vec![source_tar_path.to_string_lossy().to_string()];
-        let checksums = vec![sum];
+        trace!(logger => "calculate source {} checksums", SOURCE_CHECKSUM.as_ref());
+        let mut checksums = vec![];
+        for source in &sources {
+            let sum = ctx
+                .checked_exec(
+                    &ExecOpts::default()
+                        .cmd(&format!(r#"{} "$1""#, SOURCE_CHECKSUM.command()))
+                        .args([source.as_str()]),
+                    logger,
+                )
+                .await
+                .map(|out| out.stdout.join(""))?;
+            let sum = sum
+                .split_ascii_whitespace()
+                .next()
+                .map(|s| s.to_string())
+                .context("failed to calculate checksum of source")?;
+            checksums.push(sum);
+        }
         static BUILD_USER: &str = "builduser";
 
-        let pkgbuild = ctx
-            .build
-            .recipe
+        // the configure steps already ran in the build container, so they're left out of
+        // `prepare()` of the PKGBUILD used to build the package
+        let mut recipe = ctx.build.recipe.clone();
+        recipe.configure_script = None;
+        let pkgbuild = recipe
             .as_pkgbuild(
-                &image_state.image,
+                ctx.build.target().image(),
                 &sources,
+                SOURCE_CHECKSUM,
                 &checksums,
                 &ctx.build.build_version,
-                *ctx.build.target.build_target(),
+                ctx.package_target,
                 logger,
             )
             .render()
@@ -146,6 +186,13 @@ impl Package for Pkg {
         let pkg = format!("{}.pkg.tar.zst", package_name);
         let pkg_path = bld_dir.join(&pkg);
 
+        if let Some(signature) = sign_package(ctx, &pkg_path, logger).await? {
+            ctx.container
+                .download_files(&signature, output_dir, logger)
+                .await
+                .context("failed to download package signature")?;
+        }
+
         ctx.container
             .download_files(&pkg_path, output_dir, logger)
             .await
@@ -153,3 +200,48 @@ impl Package for Pkg {
             .context("failed to download finished package")
     }
 }
+
+/// Creates a detached signature of the `package` if a GPG key is configured and returns the path
+/// of the signature in the container.
+pub async fn sign_package(
+    ctx: &Context<'_>,
+    package: &Path,
+    logger: &mut BoxedCollector,
+) -> Result<Option<PathBuf>> {
+    let gpg_key = if let Some(key) = &ctx.build.gpg_key {
+        key
+    } else {
+        return Ok(None);
+    };
+    info!(logger => "signing package {}", package.display());
+
+    let uploaded_key = upload_gpg_key(ctx, gpg_key, &ctx.build.container_tmp_dir, logger)
+        .await
+        .context("failed to upload gpg key to container")?;
+
+    import_gpg_key(ctx, &uploaded_key, logger)
+        .await
+        .context("failed to import gpg key")?;
+
+    let signature = PathBuf::from(format!("{}.sig", package.display()));
+
+    trace!(logger => "create detached signature");
+    let passphrase = uploaded_key.passphrase.to_string_lossy();
+    let signature_path = signature.to_string_lossy();
+    let package_path = package.to_string_lossy();
+    ctx.checked_exec(
+        &ExecOpts::default()
+            .cmd(r#"gpg --batch --yes --pinentry-mode=loopback --passphrase-file "$1" -u "$2" --output "$3" --detach-sign "$4""#)
+            .args([
+                passphrase.as_ref(),
+                gpg_key.name(),
+                signature_path.as_ref(),
+                package_path.as_ref(),
+            ]),
+        logger,
+    )
+    .await
+    .context("failed to sign package")?;
+
+    Ok(Some(signature))
+}
@@ -1,4 +1,5 @@
 use crate::build::container::Context;
+use crate::build::package::files::{Inspection, InstalledFiles};
 use crate::build::package::sign::{import_gpg_key, upload_gpg_key};
 use crate::build::package::{Manifest, Package};
 use crate::image::ImageState;
@@ -28,7 +29,7 @@ impl Package for Rpm {
     /// Creates a final RPM package and saves it to `output_dir`
     async fn build(
         ctx: &Context<'_>,
-        image_state: &ImageState,
+        _: &ImageState,
         output_dir: &Path,
         logger: &mut BoxedCollector,
     ) -> Result<PathBuf> {
@@ -39,7 +40,7 @@ impl Package for Rpm {
 
         info!(logger => "building RPM package {}", package_name);
 
-        let base_path = PathBuf::from("/root/rpmbuild");
+        let base_path = ctx.build.container_home_dir().join("rpmbuild");
         let specs = base_path.join("SPECS");
         let sources = base_path.join("SOURCES");
         let rpms = base_path.join("RPMS");
@@ -84,32 +85,37 @@ impl Package for Rpm {
         .await?;
 
         trace!(logger => "find source file paths");
-        let files = ctx
-            .checked_exec(
-                &ExecOpts::default()
-                    .cmd(r#"find . -type f -o -type l -name "*""#)
-                    .working_dir(&ctx.build.container_out_dir),
-                logger,
-            )
-            .await
-            .map(|out| {
-                out.stdout
-                    .join("")
-                    .split('\n')
-                    .filter(|s| !s.is_empty())
-                    .map(|s| s.trim_start_matches('.').to_string())
-                    .collect::<Vec<_>>()
-            })
-            .context("failed to find source files")?;
+        let files = match Inspection::detect(ctx, "find", logger).await {
+            Inspection::Tool => ctx
+                .checked_exec(
+                    &ExecOpts::default()
+                        .cmd(r#"find . -type f -o -type l -name "*""#)
+                        .working_dir(&ctx.build.container_out_dir),
+                    logger,
+                )
+                .await
+                .map(|out| {
+                    out.stdout
+                        .join("")
+                        .split('\n')
+                        .filter(|s| !s.is_empty())
+                        .map(|s| s.trim_start_matches('.').to_string())
+                        .collect::<Vec<_>>()
+                }),
+            Inspection::Archive => InstalledFiles::download(ctx, logger)
+                .await
+                .map(|files| files.paths),
+        }
+        .context("failed to find source files")?;
         trace!(logger => "source files: {:?}", files);
 
         let spec = recipe
             .as_rpm_spec(
                 &[source_tar],
                 &files[..],
-                &image_state.image,
+                ctx.build.target().image(),
                 &ctx.build.build_version,
-                *ctx.build.target.build_target(),
+                ctx.package_target,
                 logger,
             )
             .render()
@@ -188,30 +194,31 @@ pub async fn sign_package(
         return Ok(());
     };
 
-    let key_file = upload_gpg_key(ctx, gpg_key, &ctx.build.container_tmp_dir, logger)
+    let uploaded_key = upload_gpg_key(ctx, gpg_key, &ctx.build.container_tmp_dir, logger)
         .await
         .context("failed to upload gpg key to container")?;
 
-    import_gpg_key(ctx, gpg_key, &key_file, logger)
+    import_gpg_key(ctx, &uploaded_key, logger)
         .await
         .context("failed to import gpg key")?;
 
     let macros = format!(
         r##"
 %_signature gpg
-%_gpg_path /root/.gnupg
+%_gpg_path {}/.gnupg
 %_gpg_name {}
 %_gpgbin /usr/bin/gpg2
-%__gpg_sign_cmd %{{__gpg}} gpg --batch --verbose --pinentry-mode=loopback --passphrase {} -u "%{{_gpg_name}}" -sbo %{{__signature_filename}} --digest-algo sha256 %{{__plaintext_filename}}'
+%__gpg_sign_cmd %{{__gpg}} gpg --batch --verbose --pinentry-mode=loopback --passphrase-file {} -u "%{{_gpg_name}}" -sbo %{{__signature_filename}} --digest-algo sha256 %{{__plaintext_filename}}'
 "##,
+        ctx.build.container_home_dir().display(),
         gpg_key.name(),
-        gpg_key.pass()
+        uploaded_key.passphrase.display()
     );
 
     ctx.container
         .upload_files(
             vec![(PathBuf::from("./.rpmmacros").as_path(), macros.as_bytes())],
-            PathBuf::from("/root/").as_path(),
+            ctx.build.container_home_dir(),
             logger,
         )
         .await
@@ -221,8 +228,8 @@ pub async fn sign_package(
     ctx.checked_exec(
         &ExecOpts::default()
             .cmd(&format!(
-                r#"gpg --pinentry-mode=loopback --passphrase {} --export -a '{}' > public.key"#,
-                gpg_key.pass(),
+                r#"gpg --pinentry-mode=loopback --passphrase-file {} --export -a '{}' > public.key"#,
+                uploaded_key.passphrase.display(),
                 gpg_key.name()
             ))
             .working_dir(&ctx.build.container_tmp_dir),
@@ -9,42 +9,58 @@ use std::{
     path::{Path, PathBuf},
 };
 
-/// Uploads the `gpg_key` to `destination` in the container and returns the
-/// full path of the key in the container.
+const GPG_KEY_FILE: &str = "GPG-SIGN-KEY";
+const GPG_PASSPHRASE_FILE: &str = "GPG-PASSPHRASE";
+
+/// Paths of the files of a GPG key uploaded to the container.
+pub(crate) struct UploadedGpgKey {
+    pub key: PathBuf,
+    /// File with the passphrase of the key, passed to gpg with `--passphrase-file` so that the
+    /// passphrase is never a part of a command.
+    pub passphrase: PathBuf,
+}
+
+/// Uploads the `gpg_key` and its passphrase to `destination` in the container and returns the
+/// full paths of the files in the container.
 pub(crate) async fn upload_gpg_key(
     ctx: &Context<'_>,
     gpg_key: &GpgKey,
     destination: &Path,
     logger: &mut BoxedCollector,
-) -> Result<PathBuf> {
+) -> Result<UploadedGpgKey> {
     info!(logger => "uploading GPG key to '{}'", destination.display());
     let key = fs::read(gpg_key.path()).context("failed reading the gpg key")?;
 
     ctx.container
         .upload_files(
-            vec![(PathBuf::from("./GPG-SIGN-KEY").as_path(), key.as_slice())],
+            vec![
+                (Path::new(GPG_KEY_FILE), key.as_slice()),
+                (Path::new(GPG_PASSPHRASE_FILE), gpg_key.pass().as_bytes()),
+            ],
             destination,
             logger,
         )
         .await
-        .map(|_| destination.join("GPG-SIGN-KEY"))
+        .map(|_| UploadedGpgKey {
+            key: destination.join(GPG_KEY_FILE),
+            passphrase: destination.join(GPG_PASSPHRASE_FILE),
+        })
         .context("failed to upload gpg key")
 }
 
-/// Imports the gpg key located at `path` to the database in the container.
+/// Imports the uploaded `gpg_key` to the database in the container.
 pub(crate) async fn import_gpg_key(
     ctx: &Context<'_>,
-    gpg_key: &GpgKey,
-    path: &Path,
+    gpg_key: &UploadedGpgKey,
     logger: &mut BoxedCollector,
 ) -> Result<()> {
-    info!(logger => "importing GPG key from '{}'", path.display());
+    info!(logger => "importing GPG key from '{}'", gpg_key.key.display());
+    let passphrase = gpg_key.passphrase.to_string_lossy();
+    let key = gpg_key.key.to_string_lossy();
     ctx.checked_exec(
-        &ExecOpts::new().cmd(&format!(
-            r#"gpg --pinentry-mode=loopback --passphrase {} --import {}"#,
-            gpg_key.pass(),
-            path.display(),
-        )),
+        &ExecOpts::new()
+            .cmd(r#"gpg --batch --pinentry-mode=loopback --passphrase-file "$1" --import "$2""#)
+            .args([passphrase.as_ref(), key.as_ref()]),
         logger,
     )
     .await
@@ -0,0 +1,141 @@
+use crate::build::container::Context;
+use crate::log::{debug, BoxedCollector};
+use crate::recipe::{BuildTarget, Compression, Os, PackageManager};
+use crate::runtime::container::ExecOpts;
+use crate::{err, ErrContext, Result};
+
+/// A command needed to build a package along with the package that provides it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequiredTool {
+    pub command: &'static str,
+    pub package: &'static str,
+}
+
+const fn tool(command: &'static str, package: &'static str) -> RequiredTool {
+    RequiredTool { command, package }
+}
+
+/// Returns the commands that have to be available in the container to build a package of the
+/// `target` along with the names of the packages of the `package_manager` that provide them.
+pub fn required_tools(
+    target: BuildTarget,
+    compression: Compression,
+    package_manager: &PackageManager,
+) -> Vec<RequiredTool> {
+    let mut tools = vec![tool("tar", "tar")];
+    match target {
+        BuildTarget::Rpm => tools.push(tool("rpmbuild", "rpm-build")),
+        BuildTarget::Deb => tools.push(tool("dpkg-deb", "dpkg")),
+        BuildTarget::Pkg => tools.push(tool("makepkg", "base-devel")),
+        BuildTarget::Apk => tools.push(tool("abuild", "alpine-sdk")),
+        BuildTarget::Gzip => {
+            if let Some(cmd) = compression.command() {
+                let command = cmd.split_whitespace().next().unwrap_or_default();
+                tools.push(tool(command, compression.dependency(package_manager)));
+            }
+        }
+    }
+    tools
+}
+
+/// Returns an error naming the `tools` that are `missing` and the packages that provide them.
+pub fn check_tools(target: BuildTarget, tools: &[RequiredTool], missing: &[&str]) -> Result<()> {
+    let missing = tools
+        .iter()
+        .filter(|tool| missing.contains(&tool.command))
+        .map(|tool| format!("`{}` (provided by `{}`)", tool.command, tool.package))
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        err!(
+            "the image is missing tools required to build {} packages: {}. Install them in the image or don't skip default dependencies",
+            target.as_ref().to_uppercase(),
+            missing.join(", ")
+        )
+    }
+}
+
+/// Verifies that the tools needed to build the package target of the build are available in the
+/// container before building the package.
+pub async fn preflight(ctx: &Context<'_>, logger: &mut BoxedCollector) -> Result<()> {
+    let package_manager = ctx
+        .os
+        .as_ref()
+        .map(Os::package_manager)
+        .unwrap_or(PackageManager::Unknown);
+    let tools = required_tools(
+        ctx.package_target,
+        ctx.build.recipe.metadata.compression,
+        &package_manager,
+    );
+    let commands = tools
+        .iter()
+        .map(|tool| tool.command)
+        .collect::<Vec<_>>()
+        .join(" ");
+    debug!(logger => "checking if required tools are available: {}", commands);
+
+    let out = ctx
+        .checked_exec(
+            &ExecOpts::default().cmd(&format!(
+                "for cmd in {}; do command -v $cmd >/dev/null 2>&1 || echo $cmd; done",
+                commands
+            )),
+            logger,
+        )
+        .await
+        .context("failed to check required tools")?;
+    let stdout = out.stdout.join("");
+    let missing = stdout.split_whitespace().collect::<Vec<_>>();
+
+    check_tools(ctx.package_target, &tools, &missing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_missing_tools() {
+        let tools = required_tools(BuildTarget::Rpm, Compression::Gzip, &PackageManager::Dnf);
+        assert_eq!(
+            tools,
+            vec![tool("tar", "tar"), tool("rpmbuild", "rpm-build")]
+        );
+        assert!(check_tools(BuildTarget::Rpm, &tools, &[]).is_ok());
+
+        let err = check_tools(BuildTarget::Rpm, &tools, &["rpmbuild"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the image is missing tools required to build RPM packages: `rpmbuild` (provided by `rpm-build`). Install them in the image or don't skip default dependencies"
+        );
+
+        let tools = required_tools(BuildTarget::Deb, Compression::Gzip, &PackageManager::Apt);
+        let err = check_tools(BuildTarget::Deb, &tools, &["tar", "dpkg-deb"]).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("`tar` (provided by `tar`), `dpkg-deb` (provided by `dpkg`)"));
+
+        assert_eq!(
+            required_tools(BuildTarget::Gzip, Compression::Zstd, &PackageManager::Apt),
+            vec![tool("tar", "tar"), tool("zstd", "zstd")]
+        );
+        assert_eq!(
+            required_tools(BuildTarget::Gzip, Compression::Xz, &PackageManager::Apt),
+            vec![tool("tar", "tar"), tool("xz", "xz-utils")]
+        );
+        assert_eq!(
+            required_tools(BuildTarget::Gzip, Compression::Xz, &PackageManager::Dnf),
+            vec![tool("tar", "tar"), tool("xz", "xz")]
+        );
+        assert_eq!(
+            required_tools(
+                BuildTarget::Gzip,
+                Compression::Gzip,
+                &PackageManager::Unknown
+            ),
+            vec![tool("tar", "tar")]
+        );
+    }
+}
@@ -1,9 +1,10 @@
 use crate::build::{container, remote};
 use crate::log::{debug, info, trace, BoxedCollector};
-use crate::recipe::{Patch, Patches};
+use crate::recipe::{Patch, Patches, RECIPES_DIR_PREFIX};
 use crate::runtime::container::ExecOpts;
-use crate::Result;
+use crate::{ErrContext, Result};
 
+use pkgspec_core::Manifest;
 use std::path::PathBuf;
 
 pub async fn apply(
@@ -14,11 +15,9 @@ pub async fn apply(
     info!(logger => "applying patches");
     trace!(logger => "{:?}", patches);
     for (patch, location) in patches {
-        if let Some(images) = patch.images() {
-            if !images.is_empty() && !images.contains(&ctx.build.image.name) {
-                debug!(logger => "skipping patch {:?}", patch);
-                continue;
-            }
+        if !patch.applies_to(&ctx.build.image.name) {
+            debug!(logger => "skipping patch {:?}", patch);
+            continue;
         }
         debug!(logger => "applying patch: {:?}", patch);
         ctx.checked_exec(
@@ -37,6 +36,94 @@ pub async fn apply(
     Ok(())
 }
 
+/// Applies the patches with `rpmbuild -bp` instead of `patch` for recipes with
+/// `rpm.native_prep`. The fetched sources are archived as the source of a spec whose `%prep`
+/// unpacks them with `%autosetup`, applies the patches and copies the result back to the build
+/// directory.
+pub async fn apply_with_rpm(
+    ctx: &container::Context<'_>,
+    patches: Vec<(Patch, PathBuf)>,
+    logger: &mut BoxedCollector,
+) -> Result<()> {
+    info!(logger => "applying patches with rpmbuild");
+    trace!(logger => "{:?}", patches);
+    let recipe = &ctx.build.recipe;
+    let bld_dir = &ctx.build.container_bld_dir;
+    let base_path = ctx.build.container_home_dir().join("rpmbuild");
+    let specs = base_path.join("SPECS");
+    let sources = base_path.join("SOURCES");
+    ctx.create_dirs(&[specs.as_path(), sources.as_path()], logger)
+        .await
+        .context("failed to create directories")?;
+
+    for (patch, location) in patches
+        .iter()
+        .filter(|(patch, _)| patch.applies_to(&ctx.build.image.name))
+    {
+        ctx.checked_exec(
+            &ExecOpts::default().cmd(&format!(
+                "cp {} {}",
+                location.display(),
+                sources.join(patch.file_name()).display()
+            )),
+            logger,
+        )
+        .await
+        .context("failed to copy patch to sources directory")?;
+    }
+
+    let source_tar = format!(
+        "{}-{}-sources.tar.gz",
+        recipe.metadata.name, ctx.build.build_version
+    );
+    trace!(logger => "archive fetched sources");
+    ctx.checked_exec(
+        &ExecOpts::default()
+            .cmd(&format!(
+                "tar -zcf {} . && find . -mindepth 1 -delete",
+                sources.join(&source_tar).display()
+            ))
+            .working_dir(bld_dir),
+        logger,
+    )
+    .await
+    .context("failed to archive fetched sources")?;
+
+    let spec = recipe
+        .as_rpm_prep_spec(
+            &source_tar,
+            &ctx.build.image.name,
+            &ctx.build.build_version,
+            bld_dir,
+        )
+        .render()
+        .context("rendering prep spec failed")?;
+    debug!(logger => "{}", spec);
+    let spec_file = format!("{}-prep.spec", recipe.metadata.name);
+    ctx.container
+        .upload_files(
+            vec![(
+                PathBuf::from(["./", &spec_file].join("")).as_path(),
+                spec.as_bytes(),
+            )],
+            &specs,
+            logger,
+        )
+        .await
+        .context("failed to upload prep spec to container")?;
+
+    ctx.checked_exec(
+        &ExecOpts::default().cmd(&format!(
+            "rpmbuild -bp --nodeps {}",
+            specs.join(spec_file).display()
+        )),
+        logger,
+    )
+    .await
+    .context("failed to apply patches with rpmbuild")
+    .map(|_| ())
+}
+
 pub async fn collect(
     ctx: &container::Context<'_>,
     patches: &Patches,
@@ -61,6 +148,17 @@ pub async fn collect(
             continue;
         }
 
+        if src.starts_with(RECIPES_DIR_PREFIX) {
+            let patch_p = ctx.build.recipe.resolve_path(src)?;
+            trace!(logger => "using patch from recipes dir '{}'", patch_p.display());
+            out.push((
+                patch.clone(),
+                patch_dir.join(patch_p.file_name().unwrap_or_default()),
+            ));
+            to_copy.push(patch_p);
+            continue;
+        }
+
         let patch_p = PathBuf::from(src);
         if patch_p.is_absolute() {
             trace!(logger => "found absolute path '{}'", patch_p.display());
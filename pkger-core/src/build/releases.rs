@@ -0,0 +1,182 @@
+use crate::log::{debug, trace};
+use crate::{ErrContext, Result};
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub static DEFAULT_RELEASES_FILE: &str = ".pkger.releases";
+
+#[derive(Debug, Deserialize, Serialize)]
+/// Record of the last release of each version of recipes built with an incremented release.
+pub struct Releases {
+    /// Last release of each version keyed by the name of the recipe.
+    recipes: HashMap<String, HashMap<String, u64>>,
+    /// Path to a file containing the record
+    path: PathBuf,
+    #[serde(skip_serializing)]
+    #[serde(default)]
+    has_changed: bool,
+}
+
+impl Default for Releases {
+    fn default() -> Self {
+        Releases::new(DEFAULT_RELEASES_FILE)
+    }
+}
+
+impl Releases {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            recipes: HashMap::new(),
+            path: path.into(),
+            has_changed: false,
+        }
+    }
+
+    /// Tries to initialize the record from the given path, if the path doesn't exist creates
+    /// a new empty record.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            debug!("releases file doesn't exist");
+            return Ok(Releases::new(path));
+        }
+        debug!("loading releases");
+        let contents = fs::read(path).context("failed to read releases file")?;
+        let mut releases: Releases =
+            serde_cbor::from_slice(&contents).context("failed to deserialize releases")?;
+        releases.path = path.to_path_buf();
+        Ok(releases)
+    }
+
+    /// Returns the release of the next build of the `version` of the `recipe`. It follows the
+    /// last recorded release and `built`, the highest release of already built packages. A higher
+    /// `current` release of the recipe is kept and without any previous release `current` is
+    /// used. Returns an error if `current` is not a number.
+    pub fn next_release(
+        &self,
+        recipe: &str,
+        version: &str,
+        current: &str,
+        built: Option<u64>,
+    ) -> Result<String> {
+        let current = current.parse::<u64>().with_context(|| {
+            format!(
+                "release `{}` of recipe `{}` is not a number and can't be incremented",
+                current, recipe
+            )
+        })?;
+        let last = self
+            .recipes
+            .get(recipe)
+            .and_then(|versions| versions.get(version))
+            .copied();
+        let release = match last.into_iter().chain(built).max() {
+            Some(last) => (last + 1).max(current),
+            None => current,
+        };
+        Ok(release.to_string())
+    }
+
+    /// Records the `release` of a successful build of the `version` of the `recipe`. Lower
+    /// releases than the recorded one are ignored.
+    pub fn record(&mut self, recipe: &str, version: &str, release: &str) {
+        let release = match release.parse::<u64>() {
+            Ok(release) => release,
+            Err(_) => return,
+        };
+        let versions = self.recipes.entry(recipe.to_string()).or_default();
+        if versions.get(version).map_or(true, |last| *last < release) {
+            versions.insert(version.to_string(), release);
+            self.has_changed = true;
+        }
+    }
+
+    /// Saves the record to the filesystem.
+    pub fn save(&self) -> Result<()> {
+        trace!("saving releases");
+        serde_cbor::to_vec(&self)
+            .context("failed to serialize releases")
+            .and_then(|d| fs::write(&self.path, d).context("failed to save releases file"))
+    }
+
+    /// Returns true if the record was updated.
+    pub fn has_changed(&self) -> bool {
+        self.has_changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn increments_release_per_version() {
+        let dir = TempDir::new("pkger-releases").unwrap();
+        let path = dir.path().join(DEFAULT_RELEASES_FILE);
+
+        let mut releases = Releases::load(&path).unwrap();
+        assert_eq!(
+            releases.next_release("pkger", "0.11.0", "1", None).unwrap(),
+            "1"
+        );
+        // the release is only recorded after a successful build
+        assert!(!releases.has_changed());
+        assert_eq!(
+            releases.next_release("pkger", "0.11.0", "1", None).unwrap(),
+            "1"
+        );
+        releases.record("pkger", "0.11.0", "1");
+        releases.record("pkger", "0.12.0", "0");
+        assert!(releases.has_changed());
+        releases.save().unwrap();
+
+        let mut releases = Releases::load(&path).unwrap();
+        assert!(!releases.has_changed());
+        assert_eq!(
+            releases.next_release("pkger", "0.11.0", "1", None).unwrap(),
+            "2"
+        );
+        assert_eq!(
+            releases.next_release("pkger", "0.12.0", "5", None).unwrap(),
+            "5"
+        );
+        // packages already built with a higher release are followed
+        assert_eq!(
+            releases
+                .next_release("pkger", "0.11.0", "1", Some(6))
+                .unwrap(),
+            "7"
+        );
+        assert_eq!(
+            releases
+                .next_release("other", "0.11.0", "0", Some(2))
+                .unwrap(),
+            "3"
+        );
+        releases.record("pkger", "0.11.0", "2");
+        releases.record("pkger", "0.11.0", "1");
+        releases.save().unwrap();
+
+        let releases = Releases::load(&path).unwrap();
+        assert_eq!(
+            releases.next_release("pkger", "0.11.0", "1", None).unwrap(),
+            "3"
+        );
+    }
+
+    #[test]
+    fn rejects_non_numeric_release() {
+        let releases = Releases::default();
+        assert_eq!(
+            releases
+                .next_release("pkger", "0.11.0", "1.el8", Some(1))
+                .unwrap_err()
+                .to_string(),
+            "release `1.el8` of recipe `pkger` is not a number and can't be incremented"
+        );
+    }
+}
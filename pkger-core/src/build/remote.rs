@@ -1,12 +1,15 @@
 use crate::build::container::Context;
-use crate::log::{info, trace, BoxedCollector};
-use crate::proxy::ShouldProxyResult;
-use crate::recipe::GitSource;
+use crate::checksum::{self, Checksum};
+use crate::log::{info, trace, warning, BoxedCollector};
+use crate::proxy::{ProxyConfig, ShouldProxyResult};
+use crate::recipe::{GitSource, Source};
 use crate::runtime::container::ExecOpts;
+use crate::ssh::SshConfig;
 use crate::template;
 use crate::{unix_timestamp, ErrContext, Result};
 
-use std::path::{Path, PathBuf};
+use async_trait::async_trait;
+use std::path::Path;
 
 pub async fn fetch_git_source(
     ctx: &Context<'_>,
@@ -19,33 +22,35 @@ pub async fn fetch_git_source(
         .context("failed to initialize temporary directory for git repo")?;
     let url = template::render(repo.url(), ctx.vars.inner());
 
+    let tag = repo.tag_for_version(&ctx.build.build_version);
+
     tokio::task::block_in_place(|| {
         let mut repo_builder = git2::build::RepoBuilder::new();
 
-        let mut proxy_opts = git2::ProxyOptions::new();
-
-        match ctx.build.proxy.should_proxy(&url) {
-            ShouldProxyResult::Http => {
-                if let Some(url) = ctx.build.proxy.http_proxy() {
-                    proxy_opts.url(&url.to_string());
-                }
-            }
-            ShouldProxyResult::Https => {
-                if let Some(url) = ctx.build.proxy.https_proxy() {
-                    proxy_opts.url(&url.to_string());
-                }
-            }
-            _ => {}
-        }
-
         let mut opts = git2::FetchOptions::new();
-        opts.proxy_options(proxy_opts);
+        opts.proxy_options(proxy_options(&ctx.build.proxy, &url));
+        if let Some(callbacks) = ssh_agent_callbacks(ctx.build.ssh.as_ref()) {
+            opts.remote_callbacks(callbacks);
+        }
+        if tag.is_some() {
+            opts.download_tags(git2::AutotagOption::All);
+        }
 
         repo_builder.branch(repo.branch());
         repo_builder.fetch_options(opts);
-        repo_builder
+        let cloned = repo_builder
             .clone(&url, tmp.path())
-            .context("failed to clone git repository")
+            .context("failed to clone git repository")?;
+
+        match &tag {
+            // versions declared in the recipe may not have a matching tag
+            Some(tag) if cloned.find_reference(&format!("refs/tags/{}", tag)).is_ok() => {
+                info!(logger => "checking out tag {}", tag);
+                checkout_tag(&cloned, tag)
+                    .with_context(|| format!("failed to checkout tag `{}`", tag))
+            }
+            _ => Ok(()),
+        }
     })?;
 
     let tar_file = vec![];
@@ -63,6 +68,95 @@ pub async fn fetch_git_source(
         .context("failed to upload git repo")
 }
 
+fn proxy_options<'a>(proxy: &ProxyConfig, url: &str) -> git2::ProxyOptions<'a> {
+    let mut proxy_opts = git2::ProxyOptions::new();
+
+    match proxy.should_proxy(url) {
+        ShouldProxyResult::Http => {
+            if let Some(url) = proxy.http_proxy() {
+                proxy_opts.url(&url.to_string());
+            }
+        }
+        ShouldProxyResult::Https => {
+            if let Some(url) = proxy.https_proxy() {
+                proxy_opts.url(&url.to_string());
+            }
+        }
+        _ => {}
+    }
+
+    proxy_opts
+}
+
+/// Returns callbacks authenticating with the SSH agent of the host if agent forwarding is enabled,
+/// so that repositories with urls like `git@github.com:user/repo.git` can be cloned.
+fn ssh_agent_callbacks<'a>(ssh: Option<&SshConfig>) -> Option<git2::RemoteCallbacks<'a>> {
+    if !ssh.map(|ssh| ssh.forward_agent).unwrap_or_default() {
+        return None;
+    }
+
+    let mut callbacks = git2::RemoteCallbacks::new();
+    let mut tried_agent = false;
+    callbacks.credentials(move |_, username, allowed| {
+        // libgit2 calls this again when authentication fails, only try the agent once
+        if tried_agent || !allowed.contains(git2::CredentialType::SSH_KEY) {
+            return Err(git2::Error::from_str(
+                "failed to authenticate with the ssh agent",
+            ));
+        }
+        tried_agent = true;
+        git2::Cred::ssh_key_from_agent(username.unwrap_or("git"))
+    });
+    Some(callbacks)
+}
+
+fn checkout_tag(repo: &git2::Repository, tag: &str) -> Result<()> {
+    let commit = repo
+        .revparse_single(&format!("refs/tags/{}", tag))?
+        .peel_to_commit()?;
+    repo.checkout_tree(
+        commit.as_object(),
+        Some(git2::build::CheckoutBuilder::new().force()),
+    )?;
+    repo.set_head_detached(commit.id())?;
+    Ok(())
+}
+
+/// Lists names of all tags of the remote git repository at `url` without cloning it.
+pub fn list_remote_tags(
+    url: &str,
+    proxy: &ProxyConfig,
+    ssh: Option<&SshConfig>,
+) -> Result<Vec<String>> {
+    let mut remote = git2::Remote::create_detached(url).context("failed to create git remote")?;
+    let connection = remote
+        .connect_auth(
+            git2::Direction::Fetch,
+            ssh_agent_callbacks(ssh),
+            Some(proxy_options(proxy, url)),
+        )
+        .context("failed to connect to git remote")?;
+
+    Ok(connection
+        .list()
+        .context("failed to list references of git remote")?
+        .iter()
+        .filter_map(|head| head.name().strip_prefix("refs/tags/"))
+        // skip peeled annotated tags
+        .filter(|tag| !tag.ends_with("^{}"))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Number of attempts at downloading an http source. Every attempt after the first one continues
+/// the download from the partially downloaded file.
+const HTTP_FETCH_ATTEMPTS: usize = 3;
+/// Exit code of curl when the server doesn't support byte ranges.
+const CURL_RANGE_ERROR: u64 = 33;
+
+/// Downloads `source` to `dest` in the build container. A failed attempt leaves the partial file
+/// in `dest` and the next attempt continues from it, there is no cache of downloads on the host.
+/// The checksum of the complete file is verified by the caller.
 pub async fn fetch_http_source(
     ctx: &Context<'_>,
     source: &str,
@@ -71,14 +165,173 @@ pub async fn fetch_http_source(
 ) -> Result<()> {
     info!(logger => "fetching http source to {}, url = {}", dest.display(), source);
 
+    let file = source_file_name(source);
+
+    for attempt in 1..=HTTP_FETCH_ATTEMPTS {
+        let out = ctx
+            .container
+            .exec(
+                &ExecOpts::default()
+                    .cmd(http_fetch_cmd(attempt > 1))
+                    .args([file.as_str(), source])
+                    .working_dir(dest),
+                logger,
+            )
+            .await?;
+
+        match out.exit_code {
+            0 => return Ok(()),
+            CURL_RANGE_ERROR => {
+                warning!(logger => "server doesn't support resuming downloads, fetching '{}' from scratch", source);
+                return ctx
+                    .checked_exec(
+                        &ExecOpts::default()
+                            .cmd(&format!(r#"rm -f -- "$1" && {}"#, http_fetch_cmd(false)))
+                            .args([file.as_str(), source])
+                            .working_dir(dest),
+                        logger,
+                    )
+                    .await
+                    .map(|_| ());
+            }
+            code => {
+                warning!(logger => "attempt {} of {} at fetching '{}' failed with exit code {}\n{}", attempt, HTTP_FETCH_ATTEMPTS, source, code, out.stderr.join("\n"));
+            }
+        }
+    }
+
+    Err(anyhow!(
+        "failed to fetch http source '{}' after {} attempts",
+        source,
+        HTTP_FETCH_ATTEMPTS
+    ))
+}
+
+/// Returns the command that downloads the url passed as `$2` to the file passed as `$1`. If
+/// `resume` is true the download continues from the size of the already existing file with a
+/// HTTP Range request.
+fn http_fetch_cmd(resume: bool) -> &'static str {
+    if resume {
+        r#"curl -fL -C - -o "$1" "$2""#
+    } else {
+        r#"curl -fL -o "$1" "$2""#
+    }
+}
+
+/// Returns the name of the file a source is saved as, that is the last segment of the url path.
+fn source_file_name(source: &str) -> String {
+    let path = source.split(&['?', '#'][..]).next().unwrap_or_default();
+    match path.trim_end_matches('/').rsplit_once('/') {
+        Some((scheme, name))
+            if !name.is_empty() && !scheme.ends_with(':') && !scheme.ends_with(":/") =>
+        {
+            name.to_string()
+        }
+        _ => "source".to_string(),
+    }
+}
+
+/// Verifies in the container that the `checksum` digest of the `file` in `dir` is `digest`.
+async fn verify_checksum(
+    ctx: &Context<'_>,
+    file: &str,
+    checksum: Checksum,
+    digest: &str,
+    dir: &Path,
+    logger: &mut BoxedCollector,
+) -> Result<()> {
+    info!(logger => "verifying checksum of {}", file);
+    let cmd = format!(
+        r#"printf '%s  %s\n' "$1" "$2" | {} -c -"#,
+        checksum.command()
+    );
     ctx.checked_exec(
         &ExecOpts::default()
-            .cmd(&format!("curl -LO {}", source))
-            .working_dir(dest),
+            .cmd(&cmd)
+            .args([digest, file])
+            .working_dir(dir),
         logger,
     )
     .await
     .map(|_| ())
+    .with_context(|| {
+        format!(
+            "checksum of '{}' doesn't match, expected {} {}",
+            file,
+            checksum.as_ref(),
+            digest
+        )
+    })
+}
+
+/// Fetches a single url of a source.
+#[async_trait]
+trait FetchUrl {
+    async fn fetch(&self, url: &str, logger: &mut BoxedCollector) -> Result<()>;
+}
+
+/// Downloads http sources to `dest` verifying the checksum of the downloaded file if there is one.
+struct HttpFetch<'a, 'ctx> {
+    ctx: &'a Context<'ctx>,
+    dest: &'a Path,
+    sha256: Option<&'a str>,
+}
+
+#[async_trait]
+impl FetchUrl for HttpFetch<'_, '_> {
+    async fn fetch(&self, url: &str, logger: &mut BoxedCollector) -> Result<()> {
+        let file = source_file_name(url);
+        let mut result = fetch_http_source(self.ctx, url, self.dest, logger).await;
+        if let (Ok(()), Some(sha256)) = (&result, self.sha256) {
+            result =
+                verify_checksum(self.ctx, &file, Checksum::Sha256, sha256, self.dest, logger).await;
+        }
+        if result.is_err() {
+            // don't leave a partial or corrupted file behind for the next mirror
+            let _ = self
+                .ctx
+                .container
+                .exec(
+                    &ExecOpts::default()
+                        .cmd(r#"rm -f -- "$1""#)
+                        .args([file.as_str()])
+                        .working_dir(self.dest),
+                    logger,
+                )
+                .await;
+        }
+        result
+    }
+}
+
+/// Fetches the `source` from its url or, if that fails, from its mirrors in order until one of
+/// them succeeds. If all of them fail the error lists the reason of every failure.
+async fn fetch_with_mirrors(
+    fetcher: &(impl FetchUrl + Sync),
+    source: &Source,
+    logger: &mut BoxedCollector,
+) -> Result<()> {
+    if source.mirrors().is_empty() {
+        return fetcher.fetch(source.url(), logger).await;
+    }
+
+    let mut failures = vec![];
+    for url in source.urls() {
+        match fetcher.fetch(url, logger).await {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                warning!(logger => "failed to fetch source from '{}', reason: {:#}", url, e);
+                failures.push(format!("  {}: {:#}", url, e));
+            }
+        }
+    }
+
+    Err(anyhow!(
+        "failed to fetch source '{}' from any of its {} urls:\n{}",
+        source.url(),
+        failures.len(),
+        failures.join("\n")
+    ))
 }
 
 pub async fn fetch_fs_source(
@@ -126,25 +379,22 @@ pub async fn fetch_source(ctx: &Context<'_>, logger: &mut BoxedCollector) -> Res
         fetch_git_source(ctx, repo, logger).await?;
     } else if !ctx.build.recipe.metadata.source.is_empty() {
         for source in &ctx.build.recipe.metadata.source {
-            if source.starts_with("http") {
-                fetch_http_source(ctx, source, &ctx.build.container_tmp_dir, logger).await?;
-            } else {
-                let p = PathBuf::from(source);
-                let source = if p.is_absolute() {
-                    p
-                } else {
-                    ctx.build
-                        .recipe_dir
-                        .join(&ctx.build.recipe.metadata.name)
-                        .join(template::render(source, ctx.vars.inner()))
-                };
-                fetch_fs_source(
+            if source.is_http() {
+                let fetcher = HttpFetch {
                     ctx,
-                    &[source.as_path()],
-                    &ctx.build.container_tmp_dir,
-                    logger,
-                )
-                .await?;
+                    dest: &ctx.build.container_tmp_dir,
+                    sha256: source.sha256(),
+                };
+                fetch_with_mirrors(&fetcher, source, logger).await?;
+            } else {
+                let url = template::render(source.url(), ctx.vars.inner());
+                let path = ctx.build.recipe.resolve_path(&url)?;
+                if let Some(sha256) = source.sha256() {
+                    info!(logger => "verifying checksum of {}", path.display());
+                    checksum::verify(&path, Checksum::Sha256, sha256)?;
+                }
+                fetch_fs_source(ctx, &[path.as_path()], &ctx.build.container_tmp_dir, logger)
+                    .await?;
             }
         }
         ctx.checked_exec(
@@ -175,3 +425,97 @@ pub async fn fetch_source(ctx: &Context<'_>, logger: &mut BoxedCollector) -> Res
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_resumed_fetch_cmd() {
+        let source = "https://example.com/releases/pkger-0.11.0.tar.gz?download=1";
+        let file = source_file_name(source);
+        assert_eq!(file, "pkger-0.11.0.tar.gz");
+
+        // the url and the file are passed as arguments so quotes in them are not interpreted
+        assert_eq!(http_fetch_cmd(false), r#"curl -fL -o "$1" "$2""#);
+        assert_eq!(http_fetch_cmd(true), r#"curl -fL -C - -o "$1" "$2""#);
+        assert_eq!(
+            source_file_name("https://example.com/a'b.tar.gz"),
+            "a'b.tar.gz"
+        );
+    }
+
+    #[test]
+    fn finds_source_file_name() {
+        assert_eq!(source_file_name("https://example.com/a/b.zip"), "b.zip");
+        assert_eq!(
+            source_file_name("https://example.com/a/b.zip#frag"),
+            "b.zip"
+        );
+        assert_eq!(source_file_name("https://example.com/"), "source");
+        assert_eq!(source_file_name("https://example.com"), "source");
+    }
+
+    struct FakeFetch {
+        available: Vec<&'static str>,
+        tried: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl FetchUrl for FakeFetch {
+        async fn fetch(&self, url: &str, _: &mut BoxedCollector) -> Result<()> {
+            self.tried.lock().unwrap().push(url.to_string());
+            if self.available.contains(&url) {
+                Ok(())
+            } else {
+                Err(anyhow!("the requested URL returned error: 404"))
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_mirrors() {
+        let mut logger: BoxedCollector = Box::new(crate::log::Logger::stdout(None));
+        let source: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+url: https://example.com/pkger.tar.gz
+mirrors:
+  - https://mirror1.example.com/pkger.tar.gz
+  - https://mirror2.example.com/pkger.tar.gz
+  - https://mirror3.example.com/pkger.tar.gz"#,
+        )
+        .unwrap();
+        let source = Source::try_from(source).unwrap();
+
+        let fetcher = FakeFetch {
+            available: vec!["https://mirror2.example.com/pkger.tar.gz"],
+            tried: Default::default(),
+        };
+        fetch_with_mirrors(&fetcher, &source, &mut logger)
+            .await
+            .unwrap();
+        assert_eq!(
+            *fetcher.tried.lock().unwrap(),
+            vec![
+                "https://example.com/pkger.tar.gz",
+                "https://mirror1.example.com/pkger.tar.gz",
+                "https://mirror2.example.com/pkger.tar.gz",
+            ]
+        );
+
+        let fetcher = FakeFetch {
+            available: vec![],
+            tried: Default::default(),
+        };
+        let err = fetch_with_mirrors(&fetcher, &source, &mut logger)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with(
+            "failed to fetch source 'https://example.com/pkger.tar.gz' from any of its 4 urls:"
+        ));
+        for url in source.urls() {
+            assert!(err.contains(&format!("  {}: the requested URL returned error: 404", url)));
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use crate::build::container::Context;
 use crate::log::{debug, info, trace, BoxedCollector};
+use crate::recipe::BuildTarget;
 use crate::runtime::container::ExecOpts;
 use crate::template;
 use crate::{Error, Result};
@@ -12,57 +13,94 @@ macro_rules! run_script {
         trace!($logger => "{:?}", $script);
         info!($logger => concat!("executing ", $phase, " scripts"));
         let mut opts = ExecOpts::default();
-        let mut _dir;
 
-        if let Some(dir) = &$script.working_dir {
-            _dir = PathBuf::from(template::render(dir.to_string_lossy(), $ctx.vars.inner()));
-            trace!($logger => "Working directory: {}", _dir.display());
-            opts = opts.working_dir(&_dir);
+        let script_dir = if let Some(dir) = &$script.working_dir {
+            let dir = PathBuf::from(template::render(dir.to_string_lossy(), $ctx.vars.inner()));
+            trace!($logger => "Working directory: {}", dir.display());
+            dir
         } else {
             trace!($logger => "Working directory: {} (Default)", $dir.display());
-            opts = opts.working_dir($dir);
-        }
+            $dir.to_path_buf()
+        };
+        opts = opts.working_dir(&script_dir);
 
         if let Some(shell) = &$script.shell {
             trace!($logger => "Shell: {}", shell);
             opts = opts.shell(shell.as_str());
         }
 
-        for cmd in &$script.steps {
+        for cmd in $script.steps_for_image($ctx.build.target.image()) {
             debug!($logger => "Processing: {:?}", cmd);
-            if let Some(images) = &cmd.images {
-                trace!($logger => "only execute on {:?}", images);
-                if !images.contains(&$ctx.build.target.image().to_owned()) {
-                    trace!($logger => "'{}' not found in images", $ctx.build.target.image());
-                    if !cmd.has_target_specified() {
-                        debug!($logger => "skipping command, excluded by image filter");
-                        continue;
-                    }
-                }
-            }
-
-            let target = $ctx.build.target.build_target();
-            if !cmd.should_run_on_target(target) {
-                trace!($logger => "skipping command, shouldn't run on target {:?}", target);
+            let image = $ctx.build.target.image();
+            let target = &$ctx.package_target;
+            if !cmd.applies_to(image, target, &$ctx.build.build_version) {
+                debug!($logger => "skipping command, doesn't apply to image {}, target {:?} and version {}", image, target, $ctx.build.build_version);
                 continue;
             }
 
-            if !cmd.should_run_on_version(&$ctx.build.build_version) {
-                trace!($logger => "skipping command, shouldn't run on version {}", $ctx.build.build_version);
-                continue;
+            if let Some(os) = &$ctx.os {
+                if !cmd.should_run_on_os(os) {
+                    trace!($logger => "skipping command, shouldn't run on os {} {}", os.name(), os.version());
+                    continue;
+                }
             }
 
             info!($logger => "running command {:?}", cmd);
-            $ctx.checked_exec(&opts.clone().cmd(&cmd.cmd), $logger)
-                .await?;
+            let step_dir = cmd.working_dir.as_ref().map(|_| {
+                cmd.run_dir(&script_dir, |dir| template::render(dir, $ctx.vars.inner()))
+            });
+            let mut cmd_opts = opts.clone().cmd(&cmd.cmd);
+            if let Some(dir) = &step_dir {
+                trace!($logger => "Step working directory: {}", dir.display());
+                cmd_opts = cmd_opts.working_dir(dir);
+            }
+            if let Some(user) = &cmd.user {
+                trace!($logger => "User: {}", user);
+                cmd_opts = cmd_opts.user(user);
+            }
+            if let Some(privileged) = cmd.privileged {
+                trace!($logger => "Privileged: {}", privileged);
+                cmd_opts = cmd_opts.privileged(privileged);
+            }
+            if cmd.has_expectations() {
+                let out = $ctx.container.exec(&cmd_opts, $logger).await?;
+                if let Err(e) = cmd.check_output(out.exit_code, &out.stdout.join("")) {
+                    if out.stderr.is_empty() {
+                        return Err(e);
+                    }
+                    return Err(e.context(out.stderr.join("\n")));
+                }
+            } else {
+                $ctx.checked_exec(&cmd_opts, $logger)
+                    .await?;
+            }
         }
 
         Ok::<_, Error>(())
     }};
 }
 
-pub async fn run(ctx: &Context<'_>, logger: &mut BoxedCollector) -> Result<()> {
-    info!(logger => "executing scripts");
+/// Runs the script executed right after fetching the sources.
+pub async fn run_post_fetch(ctx: &Context<'_>, logger: &mut BoxedCollector) -> Result<()> {
+    if let Some(post_fetch_script) = &ctx.build.recipe.post_fetch_script {
+        run_script!(
+            "post_fetch",
+            post_fetch_script,
+            &ctx.build.container_bld_dir,
+            ctx,
+            logger
+        )?;
+    } else {
+        info!(logger => "no post_fetch steps to run");
+    }
+
+    Ok(())
+}
+
+/// Runs the configure script in the build container before the build script. In PKGBUILDs and
+/// APKBUILDs the steps are rendered as `prepare()`, but pkger runs them here for every target so
+/// that they precede the build.
+pub async fn run_configure(ctx: &Context<'_>, logger: &mut BoxedCollector) -> Result<()> {
     if let Some(config_script) = &ctx.build.recipe.configure_script {
         run_script!(
             "configure",
@@ -75,6 +113,11 @@ pub async fn run(ctx: &Context<'_>, logger: &mut BoxedCollector) -> Result<()> {
         info!(logger => "no configure steps to run");
     }
 
+    Ok(())
+}
+
+/// Runs the build script.
+pub async fn run_build(ctx: &Context<'_>, logger: &mut BoxedCollector) -> Result<()> {
     let build_script = &ctx.build.recipe.build_script;
     run_script!(
         "build",
@@ -82,8 +125,35 @@ pub async fn run(ctx: &Context<'_>, logger: &mut BoxedCollector) -> Result<()> {
         &ctx.build.container_bld_dir,
         ctx,
         logger
-    )?;
+    )
+}
+
+/// Runs the check script for package formats without a check phase of their own. RPM, PKG and
+/// APK packages run it while building the package instead.
+pub async fn run_check(ctx: &Context<'_>, logger: &mut BoxedCollector) -> Result<()> {
+    if matches!(
+        ctx.package_target,
+        BuildTarget::Rpm | BuildTarget::Pkg | BuildTarget::Apk
+    ) {
+        return Ok(());
+    }
+    if let Some(check_script) = &ctx.build.recipe.check_script {
+        run_script!(
+            "check",
+            check_script,
+            &ctx.build.container_bld_dir,
+            ctx,
+            logger
+        )?;
+    } else {
+        info!(logger => "no check steps to run");
+    }
+
+    Ok(())
+}
 
+/// Runs the install script.
+pub async fn run_install(ctx: &Context<'_>, logger: &mut BoxedCollector) -> Result<()> {
     if let Some(install_script) = &ctx.build.recipe.install_script {
         run_script!(
             "install",
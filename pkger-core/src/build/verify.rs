@@ -0,0 +1,71 @@
+use crate::build::container::Context;
+use crate::log::{info, trace, warning, BoxedCollector};
+use crate::recipe::{BuildTarget, Os};
+use crate::runtime::container::ExecOpts;
+use crate::{ErrContext, Result};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Installs the built `packages` in the build container with the package manager of the image so
+/// that their runtime dependencies are resolved from the configured repositories. Packages of
+/// the same build are installed in a single transaction, so dependencies between them are
+/// satisfied locally. Packages the package manager of `os` can't install are skipped.
+pub async fn install_packages(
+    ctx: &Context<'_>,
+    os: &Os,
+    packages: &[(BuildTarget, &Path)],
+    logger: &mut BoxedCollector,
+) -> Result<()> {
+    let package_manager = os.package_manager();
+    let installable = packages
+        .iter()
+        .filter(|(target, _)| package_manager.build_target() == Some(*target))
+        .map(|(_, package)| *package)
+        .collect::<Vec<_>>();
+    if installable.is_empty() {
+        warning!(logger => "none of the built packages can be installed with `{}`, skipping install verification", package_manager.as_ref());
+        return Ok(());
+    }
+    if ctx.build.has_read_only_rootfs() {
+        warning!(logger => "packages can't be installed in a container with a read-only root filesystem, skipping install verification");
+        return Ok(());
+    }
+
+    info!(logger => "verifying that built packages install");
+    let verify_dir = ctx.build.container_tmp_dir.join("verify");
+    ctx.create_dirs(&[verify_dir.as_path()], logger).await?;
+
+    let mut names = Vec::with_capacity(installable.len());
+    let mut contents = Vec::with_capacity(installable.len());
+    for package in installable {
+        let name = PathBuf::from(package.file_name().unwrap_or_default());
+        let content = fs::read(package)
+            .with_context(|| format!("failed to read package `{}`", package.display()))?;
+        names.push(name);
+        contents.push(content);
+    }
+    let files = names
+        .iter()
+        .map(PathBuf::as_path)
+        .zip(contents.iter().map(Vec::as_slice))
+        .collect::<Vec<_>>();
+    ctx.container
+        .upload_files(files, &verify_dir, logger)
+        .await
+        .context("failed to upload packages to container")?;
+
+    let paths = names
+        .iter()
+        .map(|name| verify_dir.join(name).to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    let cmd = match package_manager.install_local_cmd(&paths) {
+        Some(cmd) => cmd,
+        None => return Ok(()),
+    };
+    trace!(logger => "{}", cmd);
+    ctx.checked_exec(&ExecOpts::default().cmd(&cmd), logger)
+        .await
+        .context("failed to install built packages, their dependencies may not be available")
+        .map(|_| ())
+}
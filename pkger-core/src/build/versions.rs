@@ -0,0 +1,147 @@
+use crate::log::{debug, trace};
+use crate::recipe::{BuildTarget, RecipeTarget};
+use crate::{ErrContext, Result};
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub static DEFAULT_BUILT_VERSIONS_FILE: &str = ".pkger.built";
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+/// A version of a recipe built on an image for a target.
+pub struct BuiltVersion {
+    pub version: String,
+    pub image: String,
+    pub target: BuildTarget,
+}
+
+impl BuiltVersion {
+    pub fn new(target: &RecipeTarget, version: &str) -> Self {
+        Self {
+            version: version.to_string(),
+            image: target.runtime_image(),
+            target: *target.build_target(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+/// Record of versions of recipes that were successfully built.
+pub struct BuiltVersions {
+    /// Built versions of each recipe keyed by the name of the recipe.
+    recipes: HashMap<String, HashSet<BuiltVersion>>,
+    /// Path to a file containing the record
+    path: PathBuf,
+    #[serde(skip_serializing)]
+    #[serde(default)]
+    has_changed: bool,
+}
+
+impl Default for BuiltVersions {
+    fn default() -> Self {
+        BuiltVersions::new(DEFAULT_BUILT_VERSIONS_FILE)
+    }
+}
+
+impl BuiltVersions {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            recipes: HashMap::new(),
+            path: path.into(),
+            has_changed: false,
+        }
+    }
+
+    /// Tries to initialize the record from the given path, if the path doesn't exist creates
+    /// a new empty record.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            debug!("built versions file doesn't exist");
+            return Ok(BuiltVersions::new(path));
+        }
+        debug!("loading built versions");
+        let contents = fs::read(path).context("failed to read built versions file")?;
+        let mut built: BuiltVersions =
+            serde_cbor::from_slice(&contents).context("failed to deserialize built versions")?;
+        built.path = path.to_path_buf();
+        Ok(built)
+    }
+
+    /// Returns `true` if the `version` of the recipe was already built for the `target`.
+    pub fn is_built(&self, target: &RecipeTarget, version: &str) -> bool {
+        self.recipes
+            .get(target.recipe())
+            .map(|built| built.contains(&BuiltVersion::new(target, version)))
+            .unwrap_or_default()
+    }
+
+    /// Records a successful build of the `version` of the recipe for the `target`.
+    pub fn mark_built(&mut self, target: &RecipeTarget, version: &str) {
+        let inserted = self
+            .recipes
+            .entry(target.recipe().to_string())
+            .or_default()
+            .insert(BuiltVersion::new(target, version));
+        if inserted {
+            self.has_changed = true;
+        }
+    }
+
+    /// Saves the record to the filesystem.
+    pub fn save(&self) -> Result<()> {
+        trace!("saving built versions");
+        serde_cbor::to_vec(&self)
+            .context("failed to serialize built versions")
+            .and_then(|d| fs::write(&self.path, d).context("failed to save built versions file"))
+    }
+
+    /// Returns true if the record was updated.
+    pub fn has_changed(&self) -> bool {
+        self.has_changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recipe::ImageTarget;
+    use tempdir::TempDir;
+
+    #[test]
+    fn skips_built_versions() {
+        let dir = TempDir::new("pkger-built-versions").unwrap();
+        let path = dir.path().join(DEFAULT_BUILT_VERSIONS_FILE);
+        let rocky = RecipeTarget::new(
+            "pkger".to_string(),
+            ImageTarget::new("rocky", BuildTarget::Rpm, None),
+        );
+        let rocky_gzip = RecipeTarget::new(
+            "pkger".to_string(),
+            ImageTarget::new("rocky", BuildTarget::Gzip, None),
+        );
+        let other = RecipeTarget::new(
+            "other".to_string(),
+            ImageTarget::new("rocky", BuildTarget::Rpm, None),
+        );
+
+        let mut built = BuiltVersions::load(&path).unwrap();
+        assert!(!built.is_built(&rocky, "0.11.0"));
+
+        built.mark_built(&rocky, "0.11.0");
+        assert!(built.has_changed());
+        assert!(built.is_built(&rocky, "0.11.0"));
+        assert!(!built.is_built(&rocky, "0.12.0"));
+        assert!(!built.is_built(&rocky_gzip, "0.11.0"));
+        assert!(!built.is_built(&other, "0.11.0"));
+        built.save().unwrap();
+
+        let mut built = BuiltVersions::load(&path).unwrap();
+        assert!(!built.has_changed());
+        assert!(built.is_built(&rocky, "0.11.0"));
+        built.mark_built(&rocky, "0.11.0");
+        assert!(!built.has_changed());
+    }
+}
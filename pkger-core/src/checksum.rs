@@ -0,0 +1,167 @@
+//! Checksums of files like sources, packages and PKGBUILD sources. Every algorithm is available
+//! both on the host, where files are hashed while they are read, and in build containers through
+//! the matching coreutils command.
+use crate::{ErrContext, Result};
+
+use blake2::Blake2b512;
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Checksum {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+    /// BLAKE2b with a 512 bit digest, the one used by `b2sum` and `b2sums` of PKGBUILDs.
+    Blake2,
+}
+
+impl AsRef<str> for Checksum {
+    fn as_ref(&self) -> &str {
+        match self {
+            Checksum::Md5 => "md5",
+            Checksum::Sha1 => "sha1",
+            Checksum::Sha256 => "sha256",
+            Checksum::Sha512 => "sha512",
+            Checksum::Blake2 => "blake2",
+        }
+    }
+}
+
+impl Checksum {
+    pub const ALL: [Checksum; 5] = [
+        Checksum::Md5,
+        Checksum::Sha1,
+        Checksum::Sha256,
+        Checksum::Sha512,
+        Checksum::Blake2,
+    ];
+
+    /// Returns the command printing the checksum of files in the format accepted by its `-c`
+    /// option.
+    pub fn command(&self) -> &'static str {
+        match self {
+            Checksum::Md5 => "md5sum",
+            Checksum::Sha1 => "sha1sum",
+            Checksum::Sha256 => "sha256sum",
+            Checksum::Sha512 => "sha512sum",
+            Checksum::Blake2 => "b2sum",
+        }
+    }
+}
+
+/// Returns the hex encoded digest of all bytes read from `reader`. The input is hashed in chunks
+/// so it is never held in memory at once.
+pub fn compute_reader(mut reader: impl Read, checksum: Checksum) -> Result<String> {
+    fn digest<D: Digest + Write>(reader: &mut impl Read) -> io::Result<String> {
+        let mut hasher = D::new();
+        io::copy(reader, &mut hasher)?;
+        Ok(hex(&hasher.finalize()))
+    }
+
+    match checksum {
+        Checksum::Md5 => digest::<Md5>(&mut reader),
+        Checksum::Sha1 => digest::<Sha1>(&mut reader),
+        Checksum::Sha256 => digest::<Sha256>(&mut reader),
+        Checksum::Sha512 => digest::<Sha512>(&mut reader),
+        Checksum::Blake2 => digest::<Blake2b512>(&mut reader),
+    }
+    .with_context(|| format!("failed to compute {} checksum", checksum.as_ref()))
+}
+
+/// Returns the hex encoded digest of the file at `path`.
+pub fn compute(path: &Path, checksum: Checksum) -> Result<String> {
+    let file = File::open(path).with_context(|| format!("failed to open `{}`", path.display()))?;
+    compute_reader(file, checksum).with_context(|| format!("failed to read `{}`", path.display()))
+}
+
+/// Verifies that the digest of the file at `path` is `expected`, ignoring the case of the hex
+/// digits.
+pub fn verify(path: &Path, checksum: Checksum, expected: &str) -> Result<()> {
+    let digest = compute(path, checksum)?;
+    if digest.eq_ignore_ascii_case(expected.trim()) {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "checksum of `{}` doesn't match, expected {} {}, found {}",
+            path.display(),
+            checksum.as_ref(),
+            expected.trim(),
+            digest
+        ))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn computes_known_digests() {
+        let vectors = [
+            (
+                Checksum::Md5,
+                "d41d8cd98f00b204e9800998ecf8427e",
+                "900150983cd24fb0d6963f7d28e17f72",
+            ),
+            (
+                Checksum::Sha1,
+                "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+                "a9993e364706816aba3e25717850c26c9cd0d89d",
+            ),
+            (
+                Checksum::Sha256,
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                Checksum::Sha512,
+                "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e",
+                "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+            ),
+            (
+                Checksum::Blake2,
+                "786a02f742015903c6c6fd852552d272912f4740e15847618a86e217f71f5419d25e1031afee585313896444934eb04b903a685b1448b755d56f701afe9be2ce",
+                "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d17d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923",
+            ),
+        ];
+        for (checksum, empty, abc) in vectors {
+            assert_eq!(compute_reader(&b""[..], checksum).unwrap(), empty);
+            assert_eq!(compute_reader(&b"abc"[..], checksum).unwrap(), abc);
+        }
+    }
+
+    #[test]
+    fn computes_digest_of_file() {
+        let dir = TempDir::new("pkger-checksum").unwrap();
+        let path = dir.path().join("source.tar.gz");
+        // larger than the buffer of `io::copy` so that it is hashed in several chunks
+        let contents = vec![b'a'; 64 * 1024 + 1];
+        fs::write(&path, &contents).unwrap();
+
+        for checksum in Checksum::ALL {
+            assert_eq!(
+                compute(&path, checksum).unwrap(),
+                compute_reader(contents.as_slice(), checksum).unwrap()
+            );
+        }
+
+        let sha256 = compute(&path, Checksum::Sha256).unwrap();
+        assert!(verify(&path, Checksum::Sha256, &sha256.to_uppercase()).is_ok());
+        assert!(verify(&path, Checksum::Md5, &sha256).is_err());
+        assert!(compute(&dir.path().join("missing"), Checksum::Md5).is_err());
+    }
+}
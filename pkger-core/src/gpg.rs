@@ -1,8 +1,10 @@
+use crate::recipe::BuildTarget;
 use crate::{err, Error, Result};
 
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct GpgKey {
     path: PathBuf,
     name: String,
@@ -35,3 +37,62 @@ impl GpgKey {
         &self.path
     }
 }
+
+#[derive(Clone, Debug, Default)]
+/// GPG keys used to sign packages. Keys are assigned to image names or build targets like `rpm`,
+/// builds without a key of their own are signed with the default key.
+pub struct GpgKeys {
+    default: Option<GpgKey>,
+    keys: HashMap<String, GpgKey>,
+}
+
+impl GpgKeys {
+    pub fn new(default: Option<GpgKey>) -> Self {
+        Self {
+            default,
+            keys: HashMap::new(),
+        }
+    }
+
+    /// Assigns the `key` to an image or a build target.
+    pub fn add_key(&mut self, image_or_target: impl Into<String>, key: GpgKey) {
+        self.keys.insert(image_or_target.into(), key);
+    }
+
+    /// Returns the key used to sign packages built on `image` for the `target`. Keys assigned to
+    /// the image take precedence over keys assigned to the target.
+    pub fn key_for(&self, image: &str, target: BuildTarget) -> Option<&GpgKey> {
+        self.keys
+            .get(image)
+            .or_else(|| self.keys.get(target.as_ref()))
+            .or(self.default.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_key_for_build() {
+        let dir = tempdir::TempDir::new("pkger-gpg").unwrap();
+        let path = dir.path().join("key");
+        std::fs::write(&path, "").unwrap();
+        let key = |name: &str| GpgKey::new(&path, name, "pass").unwrap();
+
+        let mut keys = GpgKeys::new(Some(key("default")));
+        keys.add_key("rpm", key("rpm"));
+        keys.add_key("rocky", key("rocky"));
+
+        let name = |image, target| keys.key_for(image, target).map(GpgKey::name);
+        assert_eq!(name("rocky", BuildTarget::Rpm), Some("rocky"));
+        assert_eq!(name("fedora", BuildTarget::Rpm), Some("rpm"));
+        assert_eq!(name("debian", BuildTarget::Deb), Some("default"));
+        assert_eq!(
+            GpgKeys::default()
+                .key_for("debian", BuildTarget::Deb)
+                .map(GpgKey::name),
+            None
+        );
+    }
+}
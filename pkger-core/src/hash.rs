@@ -0,0 +1,93 @@
+//! Hashing used to detect changes of files between runs. The FNV-1a hash is used as it is
+//! stable across runs and versions of Rust unlike the hasher of the standard library.
+use crate::{ErrContext, Result};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+#[derive(Clone, Debug)]
+pub struct Fnv64(u64);
+
+impl Default for Fnv64 {
+    fn default() -> Self {
+        Self(FNV_OFFSET)
+    }
+}
+
+impl Fnv64 {
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    /// Writes `bytes` followed by a separator so that consecutive fields can't be confused.
+    pub fn write_field(&mut self, bytes: impl AsRef<[u8]>) {
+        self.write(bytes.as_ref());
+        self.write(&[0]);
+    }
+
+    /// Writes relative paths and contents of all files in `dir` and its subdirectories.
+    pub fn write_dir(&mut self, dir: &Path) -> Result<()> {
+        let mut files = vec![];
+        collect_files(dir, &mut files)?;
+        files.sort();
+
+        for file in files {
+            let relative = file.strip_prefix(dir).unwrap_or(&file);
+            self.write_field(relative.to_string_lossy().as_bytes());
+            let contents = fs::read(&file)
+                .with_context(|| format!("failed to read file `{}`", file.display()))?;
+            self.write_field(&contents);
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+
+    pub fn finish_hex(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)
+        .with_context(|| format!("failed to read directory `{}`", dir.display()))?
+    {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_fields() {
+        let mut hasher = Fnv64::default();
+        assert_eq!(hasher.finish(), FNV_OFFSET);
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+
+        let mut first = Fnv64::default();
+        first.write_field("ab");
+        first.write_field("c");
+        let mut second = Fnv64::default();
+        second.write_field("a");
+        second.write_field("bc");
+        assert_ne!(first.finish(), second.finish());
+    }
+}
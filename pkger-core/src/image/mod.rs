@@ -5,6 +5,7 @@ use anyhow::Context;
 pub use os::find;
 pub use state::{ImageState, ImagesState};
 
+use crate::hash::Fnv64;
 use crate::recipe::{BuildTarget, BuildTargetInfo, Os};
 use crate::{err, Error, Result};
 
@@ -51,10 +52,13 @@ impl Image {
         .into()
     }
 
+    /// Creates a simple image for the `target` in `images_dir`. The generated Dockerfile only
+    /// contains a `FROM` instruction followed by `extra_dockerfile` if provided.
     pub fn create_simple(
         images_dir: &Path,
         target: BuildTarget,
         custom_image: Option<&str>,
+        extra_dockerfile: Option<&str>,
     ) -> Result<Image> {
         let BuildTargetInfo { image, name, os: _ } = Self::simple(target);
         let image = custom_image.unwrap_or(image);
@@ -62,8 +66,12 @@ impl Image {
         let image_dir = images_dir.join(name);
         fs::create_dir_all(&image_dir)?;
 
-        let dockerfile = format!("FROM {}", image);
-        fs::write(image_dir.join("Dockerfile"), dockerfile.as_bytes())?;
+        let dockerfile = simple_dockerfile(image, extra_dockerfile);
+        let dockerfile_path = image_dir.join("Dockerfile");
+        // only write the Dockerfile when it changed so that the image state stays cached
+        if fs::read_to_string(&dockerfile_path).ok().as_deref() != Some(dockerfile.as_str()) {
+            fs::write(dockerfile_path, dockerfile.as_bytes())?;
+        }
 
         Image::try_from_path(image_dir)
     }
@@ -72,15 +80,11 @@ impl Image {
         images_dir: &Path,
         target: BuildTarget,
         custom_image: Option<&str>,
+        extra_dockerfile: Option<&str>,
     ) -> Result<(Image, Os)> {
-        let BuildTargetInfo { image: _, name, os } = Self::simple(target);
+        let BuildTargetInfo { os, .. } = Self::simple(target);
 
-        let image_dir = images_dir.join(name);
-        if image_dir.exists() {
-            return Image::try_from_path(image_dir).map(|i| (i, os));
-        }
-
-        Self::create_simple(images_dir, target, custom_image).map(|i| (i, os))
+        Self::create_simple(images_dir, target, custom_image, extra_dockerfile).map(|i| (i, os))
     }
 
     /// Loads an `FsImage` from the given `path`
@@ -100,4 +104,74 @@ impl Image {
         fs::read_to_string(self.path.join("Dockerfile"))
             .context("failed to read a Dockerfile of image")
     }
+
+    /// Returns a hash of the Dockerfile and all other files in the image directory. The hash
+    /// depends only on the relative paths and contents of the files so it is stable across runs.
+    pub fn content_hash(&self) -> Result<String> {
+        let mut hasher = Fnv64::default();
+        hasher
+            .write_dir(&self.path)
+            .context("failed to hash image files")?;
+        Ok(hasher.finish_hex())
+    }
+}
+
+fn simple_dockerfile(image: &str, extra_dockerfile: Option<&str>) -> String {
+    let mut dockerfile = format!("FROM {}", image);
+    if let Some(extra) = extra_dockerfile
+        .map(str::trim)
+        .filter(|extra| !extra.is_empty())
+    {
+        dockerfile.push('\n');
+        dockerfile.push_str(extra);
+        dockerfile.push('\n');
+    }
+    dockerfile
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn hashes_image_files() {
+        let dir = TempDir::new("pkger-image").unwrap();
+        fs::write(dir.path().join("Dockerfile"), "FROM debian:latest").unwrap();
+        fs::create_dir(dir.path().join("files")).unwrap();
+        fs::write(dir.path().join("files").join("config"), "a = 1").unwrap();
+
+        let image = Image::try_from_path(dir.path()).unwrap();
+        let hash = image.content_hash().unwrap();
+        assert_eq!(hash, image.content_hash().unwrap());
+
+        fs::write(dir.path().join("files").join("config"), "a = 2").unwrap();
+        let changed = image.content_hash().unwrap();
+        assert_ne!(hash, changed);
+
+        fs::write(dir.path().join("Dockerfile"), "FROM debian:buster").unwrap();
+        assert_ne!(changed, image.content_hash().unwrap());
+    }
+
+    #[test]
+    fn appends_extra_dockerfile_to_simple_image() {
+        let dir = TempDir::new("pkger-images").unwrap();
+
+        let image = Image::create_simple(dir.path(), BuildTarget::Deb, None, None).unwrap();
+        assert_eq!(image.load_dockerfile().unwrap(), "FROM debian:latest");
+
+        let extra =
+            "RUN echo 'deb http://mirror.example.com/debian stable main' > /etc/apt/sources.list\n";
+        let image = Image::create_simple(
+            dir.path(),
+            BuildTarget::Deb,
+            Some("debian:bookworm"),
+            Some(extra),
+        )
+        .unwrap();
+        assert_eq!(
+            image.load_dockerfile().unwrap(),
+            format!("FROM debian:bookworm\n{}", extra)
+        );
+    }
 }
@@ -25,6 +25,13 @@ pub struct ImageState {
     pub timestamp: SystemTime,
     pub deps: HashSet<String>,
     pub simple: bool,
+    #[serde(default)]
+    /// Hash of the files in the image directory at the time the image was built or the reference
+    /// of a pulled image
+    pub hash: Option<String>,
+    #[serde(default)]
+    /// Commands enabling additional repositories that were run in the cached image
+    pub repos: Vec<String>,
 }
 
 impl PartialEq for ImageState {
@@ -36,6 +43,8 @@ impl PartialEq for ImageState {
             && self.timestamp == other.timestamp
             && self.deps == other.deps
             && self.simple == other.simple
+            && self.hash == other.hash
+            && self.repos == other.repos
     }
 }
 
@@ -49,11 +58,12 @@ impl ImageState {
         runtime: &RuntimeConnector,
         deps: &HashSet<&str>,
         simple: bool,
+        hash: Option<&str>,
         logger: &mut BoxedCollector,
     ) -> Result<ImageState> {
         let name = format!(
             "{}-{}",
-            target.image(),
+            target.runtime_image(),
             timestamp
                 .duration_since(UNIX_EPOCH)
                 .unwrap_or_default()
@@ -69,15 +79,23 @@ impl ImageState {
 
         Ok(ImageState {
             id: id.to_string(),
-            image: target.image().to_string(),
+            image: target.runtime_image(),
             os,
             tag: tag.to_string(),
             timestamp: *timestamp,
             deps: deps.iter().map(|s| s.to_string()).collect(),
             simple,
+            hash: hash.map(str::to_string),
+            repos: vec![],
         })
     }
 
+    /// Sets the commands enabling additional repositories that were run in the image.
+    pub fn with_repos(mut self, repos: &[&str]) -> Self {
+        self.repos = repos.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
     /// Verifies if a given image exists in docker, on connection error returns false
     pub async fn exists(&self, runtime: &RuntimeConnector, logger: &mut BoxedCollector) -> bool {
         info!(logger => "checking if image '{}' exists", self.image);
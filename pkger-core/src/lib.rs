@@ -6,13 +6,18 @@ extern crate lazy_static;
 
 pub mod archive;
 pub mod build;
+pub mod checksum;
 pub mod gpg;
+pub mod hash;
 pub mod image;
+pub mod limits;
 #[macro_export]
 pub mod log;
 pub mod oneshot;
 pub mod proxy;
 pub mod recipe;
+pub mod registry;
+pub mod repo;
 pub mod runtime;
 pub mod ssh;
 pub mod template;
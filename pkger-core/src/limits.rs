@@ -0,0 +1,137 @@
+use crate::{Error, Result};
+
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// Resource limits applied to build containers.
+#[derive(Clone, Default, Deserialize, Debug, Serialize, PartialEq, Eq)]
+pub struct ResourceLimits {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Memory limit with an optional unit suffix like `512m` or `2g`. Plain numbers are bytes.
+    pub memory: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Number of CPUs the container can use, for example `1.5`.
+    pub cpus: Option<Cpus>,
+}
+
+/// A CPU limit stored in units of 10<sup>-9</sup> CPUs, written as a number of CPUs like `1.5`.
+#[derive(Clone, Copy, Deserialize, Debug, Serialize, PartialEq, Eq, Hash)]
+#[serde(try_from = "f64", into = "f64")]
+pub struct Cpus(u64);
+
+impl TryFrom<f64> for Cpus {
+    type Error = Error;
+
+    fn try_from(cpus: f64) -> Result<Self> {
+        let nano_cpus = cpus * 1_000_000_000.;
+        if !nano_cpus.is_finite() || nano_cpus < 0. || nano_cpus > u64::MAX as f64 {
+            return Err(Error::msg(format!("invalid cpu limit `{}`", cpus)));
+        }
+        Ok(Self(nano_cpus as u64))
+    }
+}
+
+impl From<Cpus> for f64 {
+    fn from(cpus: Cpus) -> Self {
+        cpus.0 as f64 / 1_000_000_000.
+    }
+}
+
+impl ResourceLimits {
+    /// Returns limits where each unset field of `self` is taken from `defaults`.
+    pub fn or(self, defaults: &ResourceLimits) -> ResourceLimits {
+        ResourceLimits {
+            memory: self.memory.or_else(|| defaults.memory.clone()),
+            cpus: self.cpus.or(defaults.cpus),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_none() && self.cpus.is_none()
+    }
+
+    /// Returns the memory limit in bytes.
+    pub fn memory_bytes(&self) -> Result<Option<u64>> {
+        self.memory.as_deref().map(parse_memory).transpose()
+    }
+
+    /// Returns the CPU limit in units of 10<sup>-9</sup> CPUs.
+    pub fn nano_cpus(&self) -> Option<u64> {
+        self.cpus
+            .map(|cpus| cpus.0)
+            .filter(|nano_cpus| *nano_cpus > 0)
+    }
+}
+
+fn parse_memory(memory: &str) -> Result<u64> {
+    let memory = memory.trim().to_ascii_lowercase();
+    let memory = memory.trim_end_matches('b');
+    let (number, multiplier) = match memory.chars().last() {
+        Some('k') => (&memory[..memory.len() - 1], 1 << 10),
+        Some('m') => (&memory[..memory.len() - 1], 1 << 20),
+        Some('g') => (&memory[..memory.len() - 1], 1 << 30),
+        _ => (memory, 1),
+    };
+
+    number
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| Error::msg(format!("invalid memory limit `{}`", memory)))?
+        .checked_mul(multiplier)
+        .ok_or_else(|| Error::msg(format!("memory limit `{}` is too large", memory)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_memory_limits() {
+        assert_eq!(parse_memory("1024").unwrap(), 1024);
+        assert_eq!(parse_memory("4k").unwrap(), 4096);
+        assert_eq!(parse_memory("512m").unwrap(), 512 * 1024 * 1024);
+        assert_eq!(parse_memory("2G").unwrap(), 2 * 1024 * 1024 * 1024);
+        assert_eq!(parse_memory("2gb").unwrap(), 2 * 1024 * 1024 * 1024);
+        assert!(parse_memory("lots").is_err());
+        assert!(parse_memory("").is_err());
+        assert_eq!(
+            parse_memory("18446744073709551615g")
+                .unwrap_err()
+                .to_string(),
+            "memory limit `18446744073709551615g` is too large"
+        );
+    }
+
+    #[test]
+    fn merges_limits_with_defaults() {
+        let defaults = ResourceLimits {
+            memory: Some("2g".into()),
+            cpus: Some(Cpus::try_from(2.).unwrap()),
+        };
+        let limits = ResourceLimits {
+            memory: None,
+            cpus: Some(Cpus::try_from(0.5).unwrap()),
+        }
+        .or(&defaults);
+
+        assert_eq!(limits.memory.as_deref(), Some("2g"));
+        assert_eq!(limits.nano_cpus(), Some(500_000_000));
+        assert!(ResourceLimits::default().is_empty());
+        assert_eq!(ResourceLimits::default().nano_cpus(), None);
+    }
+
+    #[test]
+    fn parses_cpu_limits() {
+        let limits: ResourceLimits = serde_yaml::from_str("cpus: 1.5").unwrap();
+        assert_eq!(limits.nano_cpus(), Some(1_500_000_000));
+        let serialized = serde_yaml::to_string(&limits).unwrap();
+        assert!(serialized.contains("cpus: 1.5"));
+
+        let limits: ResourceLimits = serde_yaml::from_str("cpus: 0").unwrap();
+        assert_eq!(limits.nano_cpus(), None);
+
+        assert!(serde_yaml::from_str::<ResourceLimits>("cpus: -1").is_err());
+        assert!(serde_yaml::from_str::<ResourceLimits>("cpus: .nan").is_err());
+        assert!(serde_yaml::from_str::<ResourceLimits>("cpus: 1e30").is_err());
+    }
+}
@@ -6,6 +6,7 @@ use std::fmt::Write as _;
 use std::fs::File;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::RwLock;
 
 pub use colored::control;
@@ -32,11 +33,38 @@ lazy_static! {
     });
 }
 
+/// Format of the records written by collectors created from a [`Config`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Human readable lines, colored unless disabled.
+    #[default]
+    Text,
+    /// One JSON object per record with the timestamp, level, scopes and message.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> crate::Result<Self> {
+        match &s.to_lowercase()[..] {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(crate::Error::msg(format!(
+                "invalid log format `{}`, expected `text` or `json`",
+                s
+            ))),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     location: OutputLocation,
     level: Level,
     no_color: bool,
+    format: Format,
+    json_file: Option<PathBuf>,
 }
 impl Config {
     pub fn file<P: AsRef<Path>>(path: P) -> Self {
@@ -44,6 +72,8 @@ impl Config {
             location: OutputLocation::File(path.as_ref().to_path_buf()),
             level: Level::default(),
             no_color: true,
+            format: Format::default(),
+            json_file: None,
         }
     }
 
@@ -52,6 +82,18 @@ impl Config {
             location: OutputLocation::Stdout,
             level: Level::default(),
             no_color: false,
+            format: Format::default(),
+            json_file: None,
+        }
+    }
+
+    pub fn stderr() -> Self {
+        Self {
+            location: OutputLocation::Stderr,
+            level: Level::default(),
+            no_color: false,
+            format: Format::default(),
+            json_file: None,
         }
     }
 
@@ -65,18 +107,53 @@ impl Config {
         self
     }
 
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Additionally writes all records as JSON lines appended to the file at `path`.
+    pub fn json_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.json_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
     pub fn as_collector(self) -> std::io::Result<BoxedCollector> {
-        match self.location {
-            OutputLocation::File(path) => {
+        let collector: BoxedCollector = match (self.location, self.format) {
+            (OutputLocation::File(path), Format::Json) => {
+                Box::new(JsonLogger::file(path, Some(self.level))?)
+            }
+            (OutputLocation::Stdout, Format::Json) => {
+                Box::new(JsonLogger::new(std::io::stdout(), Some(self.level)))
+            }
+            (OutputLocation::Stderr, Format::Json) => {
+                Box::new(JsonLogger::new(std::io::stderr(), Some(self.level)))
+            }
+            (OutputLocation::File(path), Format::Text) => {
                 let mut logger = Logger::file(path, Some(self.level))?;
                 logger.set_no_color(self.no_color);
-                Ok(Box::new(logger))
+                Box::new(logger)
             }
-            OutputLocation::Stdout => {
+            (OutputLocation::Stdout, Format::Text) => {
                 let mut logger = Logger::stdout(Some(self.level));
                 logger.set_no_color(self.no_color);
-                Ok(Box::new(logger))
+                Box::new(logger)
+            }
+            (OutputLocation::Stderr, Format::Text) => {
+                let mut logger = Logger::stderr(Some(self.level));
+                logger.set_no_color(self.no_color);
+                Box::new(logger)
             }
+        };
+
+        if let Some(path) = self.json_file {
+            let json = JsonLogger::file(path, Some(self.level))?;
+            Ok(Box::new(MultiCollector::new(vec![
+                collector,
+                Box::new(json),
+            ])))
+        } else {
+            Ok(collector)
         }
     }
 }
@@ -85,6 +162,7 @@ impl Config {
 pub enum OutputLocation {
     File(PathBuf),
     Stdout,
+    Stderr,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -126,6 +204,7 @@ impl AsRef<str> for Level {
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct Arguments<'args> {
     pub level: Option<Level>,
     pub args: fmt::Arguments<'args>,
@@ -188,6 +267,10 @@ impl<'l> Logger<'l> {
         Self::new(std::io::stdout(), level, false)
     }
 
+    pub fn stderr(level: Option<Level>) -> Self {
+        Self::new(std::io::stderr(), level, false)
+    }
+
     pub fn file(path: impl AsRef<Path>, level: Option<Level>) -> io::Result<Self> {
         Ok(Self::new(
             File::open(path.as_ref()).or_else(|_| File::create(path.as_ref()))?,
@@ -273,6 +356,237 @@ impl<'l> Colored for Logger<'l> {
 
 impl<'l> OutputCollector for Logger<'l> {}
 
+/// Writes records as JSON lines with the timestamp, level, scopes and message of each record.
+/// Colors never apply to this output.
+pub struct JsonLogger<'l> {
+    level: Level,
+    handle: Box<dyn std::io::Write + Send + Sync + 'l>,
+    scopes: VecDeque<String>,
+}
+
+impl<'l> JsonLogger<'l> {
+    pub fn new(handle: impl std::io::Write + Send + Sync + 'l, level: Option<Level>) -> Self {
+        Self {
+            level: level.unwrap_or_default(),
+            handle: Box::new(handle),
+            scopes: VecDeque::new(),
+        }
+    }
+
+    /// Appends records to the file at `path` creating it if it doesn't exist.
+    pub fn file(path: impl AsRef<Path>, level: Option<Level>) -> io::Result<Self> {
+        Ok(Self::new(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path.as_ref())?,
+            level,
+        ))
+    }
+}
+
+fn write_json_str(out: &mut String, s: &str) {
+    out.push('"');
+    let mut chars = s.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            // skip ANSI escape sequences like colors
+            '\x1b' if chars.peek() == Some(&'[') => {
+                for ch in chars.by_ref() {
+                    if ch.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ch if ch.is_control() => {
+                let _ = write!(out, "\\u{:04x}", ch as u32);
+            }
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+}
+
+impl<'l> Writer for JsonLogger<'l> {
+    fn write_out(&mut self, args: Arguments<'_>) -> std::io::Result<()> {
+        use chrono::prelude::*;
+
+        let level = match args.level {
+            Some(level) if level > self.level => return Ok(()),
+            Some(level) => level,
+            None => self.level,
+        };
+
+        let mut s = String::from("{\"timestamp\":");
+        write_json_str(
+            &mut s,
+            &Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        );
+        s.push_str(",\"level\":");
+        write_json_str(&mut s, level.as_ref());
+        s.push_str(",\"scopes\":[");
+        for (i, scope) in self.scopes.iter().enumerate() {
+            if i > 0 {
+                s.push(',');
+            }
+            write_json_str(&mut s, scope);
+        }
+        s.push_str("],\"message\":");
+        write_json_str(&mut s, &args.args.to_string());
+        s.push_str("}\n");
+
+        // a single write so that records of parallel builds appended to the same file don't mix
+        self.handle.write_all(s.as_bytes())
+    }
+}
+
+impl<'l> Leveled for JsonLogger<'l> {
+    fn set_level(&mut self, level: Level) {
+        self.level = level;
+    }
+}
+
+impl<'l> Scoped for JsonLogger<'l> {
+    fn append_scope(&mut self, scope: String) {
+        self.scopes.push_back(scope);
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop_back();
+    }
+}
+
+impl<'l> Colored for JsonLogger<'l> {
+    fn set_override(&mut self, _: bool) {}
+}
+
+impl<'l> OutputCollector for JsonLogger<'l> {}
+
+/// Passes every record to all of the inner collectors.
+pub struct MultiCollector {
+    collectors: Vec<BoxedCollector>,
+}
+
+impl MultiCollector {
+    pub fn new(collectors: Vec<BoxedCollector>) -> Self {
+        Self { collectors }
+    }
+}
+
+impl Writer for MultiCollector {
+    fn write_out(&mut self, args: Arguments<'_>) -> std::io::Result<()> {
+        let mut result = Ok(());
+        for collector in &mut self.collectors {
+            if let Err(e) = collector.write_out(args) {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Leveled for MultiCollector {
+    fn set_level(&mut self, level: Level) {
+        self.collectors
+            .iter_mut()
+            .for_each(|collector| collector.set_level(level));
+    }
+}
+
+impl Scoped for MultiCollector {
+    fn append_scope(&mut self, scope: String) {
+        self.collectors
+            .iter_mut()
+            .for_each(|collector| collector.append_scope(scope.clone()));
+    }
+
+    fn pop_scope(&mut self) {
+        self.collectors
+            .iter_mut()
+            .for_each(|collector| collector.pop_scope());
+    }
+}
+
+impl Colored for MultiCollector {
+    fn set_override(&mut self, should_color: bool) {
+        self.collectors
+            .iter_mut()
+            .for_each(|collector| collector.set_override(should_color));
+    }
+}
+
+impl OutputCollector for MultiCollector {}
+
+/// Collector of a single job forked from the main output. All records go to the collector of the
+/// job, like a log file of its own, while records up to `summary_level` are also passed to the
+/// `summary` collector scoped with the name of the job so that output of parallel jobs can be
+/// told apart.
+pub struct ForkedCollector {
+    job: BoxedCollector,
+    summary: BoxedCollector,
+    summary_level: Level,
+}
+
+impl ForkedCollector {
+    pub fn new(
+        scope: impl Into<String>,
+        job: BoxedCollector,
+        mut summary: BoxedCollector,
+        summary_level: Level,
+    ) -> Self {
+        summary.append_scope(scope.into());
+        summary.set_level(summary_level);
+        Self {
+            job,
+            summary,
+            summary_level,
+        }
+    }
+}
+
+impl Writer for ForkedCollector {
+    fn write_out(&mut self, args: Arguments<'_>) -> std::io::Result<()> {
+        let job = self.job.write_out(args);
+        let summary = self.summary.write_out(args);
+        job.and(summary)
+    }
+}
+
+impl Leveled for ForkedCollector {
+    fn set_level(&mut self, level: Level) {
+        self.job.set_level(level);
+        self.summary.set_level(level.min(self.summary_level));
+    }
+}
+
+impl Scoped for ForkedCollector {
+    fn append_scope(&mut self, scope: String) {
+        self.job.append_scope(scope.clone());
+        self.summary.append_scope(scope);
+    }
+
+    fn pop_scope(&mut self) {
+        self.job.pop_scope();
+        self.summary.pop_scope();
+    }
+}
+
+impl Colored for ForkedCollector {
+    fn set_override(&mut self, should_color: bool) {
+        self.job.set_override(should_color);
+        self.summary.set_override(should_color);
+    }
+}
+
+impl OutputCollector for ForkedCollector {}
+
 #[macro_export]
 macro_rules! write_out {
     (-> $dst:expr, $($arg:tt)*) =>
@@ -417,3 +731,118 @@ macro_rules! trace {
 }
 
 pub use {debug, error, info, trace, warning, write_out};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn writes_records_to_human_and_json_sinks() {
+        let human = Buffer::default();
+        let json = Buffer::default();
+        let mut logger: BoxedCollector = Box::new(MultiCollector::new(vec![
+            Box::new(Logger::new(human.clone(), None, true)),
+            Box::new(JsonLogger::new(json.clone(), None)),
+        ]));
+
+        logger.append_scope("test".into());
+        info!(logger => "building \"{}\"", "recipe");
+        logger.pop_scope();
+        warning!(logger => "{}", "colored".red());
+        debug!(logger => "hidden");
+        logger.set_level(Level::Debug);
+        debug!(logger => "line\nbreak");
+
+        let human = human.contents();
+        let lines: Vec<_> = human.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].contains("[test] building \"recipe\""));
+        assert!(lines[1].ends_with("colored"));
+        assert!(lines[2].ends_with("line"));
+
+        let json = json.contents();
+        let lines: Vec<_> = json.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with(r#"{"timestamp":""#));
+        assert!(lines[0]
+            .ends_with(r#""level":"info","scopes":["test"],"message":"building \"recipe\""}"#));
+        assert!(lines[1].ends_with(r#""level":"warn","scopes":[],"message":"colored"}"#));
+        assert!(lines[2].ends_with(r#""level":"debug","scopes":[],"message":"line\nbreak"}"#));
+    }
+
+    #[test]
+    fn forks_job_output_with_summary() {
+        let job = Buffer::default();
+        let summary = Buffer::default();
+        let mut logger: BoxedCollector = Box::new(ForkedCollector::new(
+            "pkger-rocky",
+            Box::new(Logger::new(job.clone(), None, true)),
+            Box::new(Logger::new(summary.clone(), None, true)),
+            Level::Info,
+        ));
+        logger.set_level(Level::Debug);
+
+        info!(logger => "building");
+        debug!(logger => "running step");
+        logger.set_level(Level::Warn);
+        info!(logger => "hidden");
+        error!(logger => "failed");
+
+        let job = job.contents();
+        let lines: Vec<_> = job.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with("] building"));
+        assert!(lines[1].ends_with("] running step"));
+        assert!(!job.contains("pkger-rocky"));
+
+        let summary = summary.contents();
+        let lines: Vec<_> = summary.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("[pkger-rocky] building"));
+        assert!(lines[1].ends_with("[pkger-rocky] failed"));
+    }
+
+    #[test]
+    fn parses_log_format() {
+        assert_eq!("text".parse::<Format>().unwrap(), Format::Text);
+        assert_eq!("JSON".parse::<Format>().unwrap(), Format::Json);
+        assert!("yaml".parse::<Format>().is_err());
+
+        let dir = tempdir::TempDir::new("pkger-log-format").unwrap();
+        let path = dir.path().join("pkger.log");
+        let mut logger = Config::file(&path)
+            .format(Format::Json)
+            .as_collector()
+            .unwrap();
+        logger.append_scope("pkger-rocky".into());
+        info!(logger => "building");
+        drop(logger);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with(r#"{"timestamp":""#));
+        assert!(contents.ends_with(
+            r#""level":"info","scopes":["pkger-rocky"],"message":"building"}
+"#
+        ));
+    }
+}
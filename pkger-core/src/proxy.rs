@@ -1,5 +1,6 @@
 use http::Uri;
 use ipnet::{Ipv4Net, Ipv6Net};
+use serde::{Deserialize, Serialize};
 use std::net::{self, IpAddr, ToSocketAddrs};
 use std::{env, str::FromStr};
 
@@ -66,22 +67,33 @@ impl ProxyConfig {
         let http_proxy = env::var(HTTP_PROXY_ENV)
             .ok()
             .or_else(|| env::var(HTTP_PROXY_ENV.to_ascii_uppercase()).ok());
-        let mut no_proxy = vec![];
-        for addr in env::var(NO_PROXY_ENV)
+        let no_proxy = env::var(NO_PROXY_ENV)
             .ok()
-            .or_else(|| env::var(NO_PROXY_ENV.to_ascii_uppercase()).ok())
-            .unwrap_or_default()
-            .split(',')
-        {
+            .or_else(|| env::var(NO_PROXY_ENV.to_ascii_uppercase()).ok());
+
+        Self::from_values(
+            https_proxy.as_deref(),
+            http_proxy.as_deref(),
+            no_proxy.as_deref(),
+        )
+    }
+
+    fn from_values(
+        https_proxy: Option<&str>,
+        http_proxy: Option<&str>,
+        no_proxy: Option<&str>,
+    ) -> Self {
+        let mut no_proxy_opts = vec![];
+        for addr in no_proxy.unwrap_or_default().split(',') {
             if let Ok(addr) = addr.parse::<NoProxyOption>() {
-                no_proxy.push(addr);
+                no_proxy_opts.push(addr);
             }
         }
 
         ProxyConfig {
             https_proxy: https_proxy.and_then(|addr| addr.parse().ok()),
             http_proxy: http_proxy.and_then(|addr| addr.parse().ok()),
-            no_proxy,
+            no_proxy: no_proxy_opts,
         }
     }
 
@@ -215,6 +227,56 @@ impl ProxyConfig {
     }
 }
 
+/// Proxy settings of a single image that replace the ones read from the environment for builds on
+/// that image. Unset fields mean that no proxy is used.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub struct ProxyOverride {
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http_proxy: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub https_proxy: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Comma separated list of addresses, networks and domains that are not proxied
+    pub no_proxy: Option<String>,
+}
+
+impl ProxyOverride {
+    /// Returns an error if one of the proxy urls is not a valid URI.
+    pub fn validate(&self) -> crate::Result<()> {
+        for url in [&self.http_proxy, &self.https_proxy].into_iter().flatten() {
+            if url.parse::<Uri>().is_err() {
+                return Err(anyhow!("invalid proxy url `{}`", url));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_config(&self) -> ProxyConfig {
+        ProxyConfig::from_values(
+            self.https_proxy.as_deref(),
+            self.http_proxy.as_deref(),
+            self.no_proxy.as_deref(),
+        )
+    }
+
+    /// Returns the variables to set in the build container, both in lowercase and uppercase as
+    /// tools disagree on which one they read.
+    pub fn env(&self) -> Vec<(String, &str)> {
+        [
+            (HTTP_PROXY_ENV, &self.http_proxy),
+            (HTTPS_PROXY_ENV, &self.https_proxy),
+            (NO_PROXY_ENV, &self.no_proxy),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| value.as_deref().map(|value| (key, value)))
+        .flat_map(|(key, value)| [(key.to_string(), value), (key.to_ascii_uppercase(), value)])
+        .collect()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -375,4 +437,49 @@ mod test {
         );
         cleanup!();
     }
+
+    #[test]
+    fn overrides_proxy_per_image() {
+        let proxy = ProxyOverride {
+            http_proxy: Some("http://proxy.internal:3128".into()),
+            https_proxy: None,
+            no_proxy: Some("10.0.0.0/8".into()),
+        };
+        assert!(proxy.validate().is_ok());
+
+        let config = proxy.to_config();
+        assert_eq!(
+            config.http_proxy(),
+            Some(&"http://proxy.internal:3128".parse().unwrap())
+        );
+        assert_eq!(config.https_proxy(), None);
+        assert_eq!(
+            ShouldProxyResult::Http,
+            config.should_proxy("http://192.168.0.1")
+        );
+        assert_eq!(
+            ShouldProxyResult::No,
+            config.should_proxy("http://10.1.2.3")
+        );
+        assert_eq!(
+            ShouldProxyResult::No,
+            config.should_proxy("https://192.168.0.1")
+        );
+
+        assert_eq!(
+            proxy.env(),
+            vec![
+                ("http_proxy".to_string(), "http://proxy.internal:3128"),
+                ("HTTP_PROXY".to_string(), "http://proxy.internal:3128"),
+                ("no_proxy".to_string(), "10.0.0.0/8"),
+                ("NO_PROXY".to_string(), "10.0.0.0/8"),
+            ]
+        );
+
+        let invalid = ProxyOverride {
+            https_proxy: Some("http://in valid".into()),
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
 }
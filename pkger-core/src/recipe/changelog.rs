@@ -0,0 +1,287 @@
+use crate::{err, ErrContext, Result};
+
+use chrono::{NaiveDate, Utc};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_AUTHOR: &str = "pkger <pkger@localhost>";
+
+/// A single release described in the changelog file of a recipe.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangelogEntry {
+    /// Version of the release, `None` means the version being built.
+    pub version: Option<String>,
+    pub date: Option<NaiveDate>,
+    pub changes: Vec<String>,
+}
+
+/// Changelog entries parsed from a `CHANGELOG.md` like file referenced by `changelog_file`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Changelog {
+    pub path: PathBuf,
+    pub entries: Vec<ChangelogEntry>,
+    /// Whether the file had version headers. Files without them are kept as a single entry.
+    pub structured: bool,
+}
+
+/// Returns the version from a heading like `[1.0.0] - 2023-01-01` or `v1.0.0`.
+fn heading_version(heading: &str) -> Option<String> {
+    heading
+        .split(|c: char| c.is_whitespace() || matches!(c, '[' | ']' | '(' | ')'))
+        .map(|word| word.strip_prefix('v').unwrap_or(word))
+        .find(|word| word.starts_with(|c: char| c.is_ascii_digit()) && !is_date(word))
+        .map(str::to_string)
+}
+
+fn is_date(word: &str) -> bool {
+    NaiveDate::parse_from_str(word, "%Y-%m-%d").is_ok()
+}
+
+/// Returns the first `YYYY-MM-DD` date in the heading.
+fn heading_date(heading: &str) -> Option<NaiveDate> {
+    heading
+        .split(|c: char| c.is_whitespace() || matches!(c, '[' | ']' | '(' | ')'))
+        .find_map(|word| NaiveDate::parse_from_str(word, "%Y-%m-%d").ok())
+}
+
+fn strip_bullet(line: &str) -> Option<&str> {
+    ["- ", "* ", "+ "]
+        .iter()
+        .find_map(|bullet| line.strip_prefix(bullet))
+}
+
+impl Changelog {
+    /// Loads and parses the changelog at `path`.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return err!("changelog file `{}` doesn't exist", path.display());
+        }
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read changelog file `{}`", path.display()))?;
+        Ok(Self::parse(path, &contents))
+    }
+
+    /// Parses markdown `contents` on a best-effort basis. Every heading with a version starts an
+    /// entry, list items below it are the changes. Headings without a version, like
+    /// `Unreleased`, end the current entry unless they are nested in it, like `### Added`.
+    /// Contents without any version headings are kept as a single entry of the built version.
+    pub fn parse(path: &Path, contents: &str) -> Self {
+        let mut entries: Vec<ChangelogEntry> = vec![];
+        // heading level of the entry that is currently being filled
+        let mut current: Option<usize> = None;
+
+        for line in contents.lines() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+
+            if trimmed.starts_with('#') {
+                let level = trimmed.chars().take_while(|c| *c == '#').count();
+                let heading = trimmed[level..].trim();
+                if let Some(version) = heading_version(heading) {
+                    entries.push(ChangelogEntry {
+                        version: Some(version),
+                        date: heading_date(heading),
+                        changes: vec![],
+                    });
+                    current = Some(level);
+                } else if !matches!(current, Some(entry_level) if level > entry_level) {
+                    current = None;
+                }
+                continue;
+            }
+
+            let entry = match (current, entries.last_mut()) {
+                (Some(_), Some(entry)) => entry,
+                _ => continue,
+            };
+            if let Some(change) = strip_bullet(trimmed) {
+                entry.changes.push(change.trim().to_string());
+            } else if let Some(last) = entry.changes.last_mut().filter(|_| line.starts_with(' ')) {
+                last.push(' ');
+                last.push_str(trimmed);
+            } else {
+                entry.changes.push(trimmed.to_string());
+            }
+        }
+
+        if entries.is_empty() {
+            let changes = contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(|line| strip_bullet(line).unwrap_or(line).to_string())
+                .collect();
+            return Self {
+                path: path.to_path_buf(),
+                entries: vec![ChangelogEntry {
+                    version: None,
+                    date: None,
+                    changes,
+                }],
+                structured: false,
+            };
+        }
+
+        Self {
+            path: path.to_path_buf(),
+            entries,
+            structured: true,
+        }
+    }
+
+    /// Renders the entries as RPM `%changelog` entries. Entries without a date use `today`.
+    pub fn rpm_entries(
+        &self,
+        author: Option<&str>,
+        version: &str,
+        release: &str,
+        today: NaiveDate,
+    ) -> Vec<String> {
+        let author = author.unwrap_or(DEFAULT_AUTHOR);
+        self.entries
+            .iter()
+            .map(|entry| {
+                let entry_version = entry.version.as_deref().unwrap_or(version);
+                let mut out = format!(
+                    "* {} {} - {}",
+                    entry.date.unwrap_or(today).format("%a %b %d %Y"),
+                    author,
+                    entry_version
+                );
+                if entry_version == version {
+                    let _ = write!(out, "-{}", release);
+                }
+                for change in &entry.changes {
+                    let _ = write!(out, "\n- {}", change.replace('%', "%%"));
+                }
+                out.push('\n');
+                out
+            })
+            .collect()
+    }
+
+    /// Renders the entries in the format of `debian/changelog`. Entries without a date use
+    /// `today`.
+    pub fn deb_changelog(
+        &self,
+        name: &str,
+        author: Option<&str>,
+        version: &str,
+        release: &str,
+        today: NaiveDate,
+    ) -> String {
+        let author = author.unwrap_or(DEFAULT_AUTHOR);
+        let mut out = String::new();
+        for entry in &self.entries {
+            let mut entry_version = entry.version.as_deref().unwrap_or(version).to_string();
+            if entry_version == version {
+                let _ = write!(entry_version, "-{}", release);
+            }
+            let _ = writeln!(
+                out,
+                "{} ({}) unstable; urgency=medium\n",
+                name, entry_version
+            );
+            for change in &entry.changes {
+                let _ = writeln!(out, "  * {}", change);
+            }
+            let date = entry
+                .date
+                .unwrap_or(today)
+                .format("%a, %d %b %Y 00:00:00 +0000");
+            let _ = writeln!(out, "\n -- {}  {}\n", author, date);
+        }
+        out
+    }
+
+    /// The current date used for changelog entries without a date.
+    pub fn today() -> NaiveDate {
+        Utc::now().date_naive()
+    }
+
+    /// Returns a warning if the file couldn't be split into entries by version headers.
+    pub fn warning(&self) -> Option<String> {
+        if self.structured {
+            None
+        } else {
+            Some(format!(
+                "changelog file `{}` has no version headers, it will be used as a single entry",
+                self.path.display()
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_markdown_changelog() {
+        let contents = r#"# Changelog
+
+## [Unreleased]
+- not released yet
+
+## [1.1.0] - 2023-05-01
+### Added
+- feature a that spans
+  multiple lines
+* feature b
+
+## v1.0.0
+- initial release
+"#;
+        let changelog = Changelog::parse(Path::new("CHANGELOG.md"), contents);
+        assert!(changelog.structured);
+        assert!(changelog.warning().is_none());
+        assert_eq!(
+            changelog.entries,
+            vec![
+                ChangelogEntry {
+                    version: Some("1.1.0".to_string()),
+                    date: NaiveDate::from_ymd_opt(2023, 5, 1),
+                    changes: vec![
+                        "feature a that spans multiple lines".to_string(),
+                        "feature b".to_string()
+                    ],
+                },
+                ChangelogEntry {
+                    version: Some("1.0.0".to_string()),
+                    date: None,
+                    changes: vec!["initial release".to_string()],
+                },
+            ]
+        );
+
+        let today = NaiveDate::from_ymd_opt(2023, 6, 2).unwrap();
+        assert_eq!(
+            changelog.rpm_entries(Some("vv9k <vv9k@example.com>"), "1.1.0", "1", today),
+            vec![
+                "* Mon May 01 2023 vv9k <vv9k@example.com> - 1.1.0-1\n- feature a that spans multiple lines\n- feature b\n".to_string(),
+                "* Fri Jun 02 2023 vv9k <vv9k@example.com> - 1.0.0\n- initial release\n".to_string(),
+            ]
+        );
+        let deb = changelog.deb_changelog("pkger", None, "1.1.0", "1", today);
+        assert!(deb.starts_with(
+            "pkger (1.1.0-1) unstable; urgency=medium\n\n  * feature a that spans multiple lines\n"
+        ));
+        assert!(deb.contains(" -- pkger <pkger@localhost>  Mon, 01 May 2023 00:00:00 +0000\n"));
+        assert!(deb.contains("pkger (1.0.0) unstable; urgency=medium\n"));
+
+        let changelog = Changelog::parse(Path::new("NEWS"), "Fixed things\n- and more\n");
+        assert!(!changelog.structured);
+        assert!(changelog.warning().is_some());
+        assert_eq!(
+            changelog.entries,
+            vec![ChangelogEntry {
+                version: None,
+                date: None,
+                changes: vec!["Fixed things".to_string(), "and more".to_string()],
+            }]
+        );
+    }
+}
@@ -1,6 +1,8 @@
-use crate::recipe::BuildTarget;
+use crate::recipe::{BuildTarget, Distro, Os};
+use crate::{err, Result};
 
 use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
 /// Wrapper type for steps parsed from a recipe. Can be either a simple string or a map specifying
@@ -12,6 +14,12 @@ use serde::{Deserialize, Serialize};
 /// { cmd = "echo 123", images = ["rocky", "debian"] }
 ///
 /// { cmd = "echo 321", rpm = true } # execute only when building rpm target
+///
+/// { cmd = "chown -R app:app /opt/app", user = "root" } # execute as root
+///
+/// { cmd = "make", working_dir = "src" } # execute in `src` of the working directory of the script
+///
+/// { cmd = "echo 7", os = "centos", os_version = "7" } # execute only on CentOS 7.x
 pub struct Command {
     pub cmd: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -28,6 +36,29 @@ pub struct Command {
     pub gzip: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub apk: Option<bool>,
+    /// User that runs the step instead of the default user of the container.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Run the step with extended privileges.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub privileged: Option<bool>,
+    /// Directory the step runs in, relative paths are relative to the working directory of the
+    /// script.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<PathBuf>,
+    /// Distribution of the image the step runs on, like `centos` or `debian`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+    /// Version of the operating system of the image the step runs on. Matches the version
+    /// exactly or as a prefix of dot separated parts, `8` matches `8` and `8.5` but not `80`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub os_version: Option<String>,
+    /// Text that the standard output of the step must contain, can span multiple lines.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expect_stdout_contains: Option<String>,
+    /// Exit code the step must exit with instead of 0.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expect_exit_code: Option<u64>,
 }
 
 impl From<&str> for Command {
@@ -41,13 +72,64 @@ impl From<&str> for Command {
             pkg: None,
             gzip: None,
             apk: None,
+            user: None,
+            privileged: None,
+            working_dir: None,
+            os: None,
+            os_version: None,
+            expect_stdout_contains: None,
+            expect_exit_code: None,
         }
     }
 }
 
 impl Command {
     pub fn has_target_specified(&self) -> bool {
-        self.rpm.is_some() || self.deb.is_some() || self.pkg.is_some() || self.gzip.is_some()
+        self.rpm.is_some()
+            || self.deb.is_some()
+            || self.pkg.is_some()
+            || self.gzip.is_some()
+            || self.apk.is_some()
+    }
+
+    /// Returns `true` if the step applies to the `image`, `target` and `version`. A step limited
+    /// to other images still applies if it specifies a target.
+    pub fn applies_to(&self, image: &str, target: &BuildTarget, version: &str) -> bool {
+        let on_image = match &self.images {
+            Some(images) => images.iter().any(|i| i == image) || self.has_target_specified(),
+            None => true,
+        };
+        on_image && self.should_run_on_target(target) && self.should_run_on_version(version)
+    }
+
+    /// Returns an error if the `os` of the step is not a known distribution. The step would
+    /// otherwise be skipped on every image.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(os) = &self.os {
+            if Distro::from(os.as_str()) == Distro::Unknown {
+                return err!("step `{}` has an unknown os `{}`", self.cmd, os);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the fields of the step that have no effect when it's rendered into a package
+    /// manifest, where it runs as a plain command of the package build.
+    pub fn manifest_unsupported_fields(&self) -> Vec<&'static str> {
+        [
+            ("user", self.user.is_some()),
+            ("privileged", self.privileged.is_some()),
+            ("os", self.os.is_some()),
+            ("os_version", self.os_version.is_some()),
+            (
+                "expect_stdout_contains",
+                self.expect_stdout_contains.is_some(),
+            ),
+            ("expect_exit_code", self.expect_exit_code.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(field, is_set)| is_set.then_some(field))
+        .collect()
     }
 
     pub fn should_run_on_target(&self, target: &BuildTarget) -> bool {
@@ -64,6 +146,73 @@ impl Command {
         .unwrap_or_default()
     }
 
+    /// Returns `true` if the step should run on an image with the operating system `os`. Steps
+    /// without `os` and `os_version` run on every operating system.
+    pub fn should_run_on_os(&self, os: &Os) -> bool {
+        if let Some(distro) = &self.os {
+            if Distro::from(distro.as_str()).as_ref() != os.name() {
+                return false;
+            }
+        }
+        match &self.os_version {
+            Some(version) => {
+                let version = version.trim();
+                os.version() == version
+                    || os
+                        .version()
+                        .strip_prefix(version)
+                        .map(|rest| rest.starts_with('.'))
+                        .unwrap_or_default()
+            }
+            None => true,
+        }
+    }
+
+    /// Returns `true` if the output of the step has to be checked against expectations other than
+    /// a zero exit code.
+    pub fn has_expectations(&self) -> bool {
+        self.expect_stdout_contains.is_some() || self.expect_exit_code.is_some()
+    }
+
+    /// Checks the `exit_code` and `stdout` of the step against its expectations. Without
+    /// `expect_exit_code` the step has to exit with 0. Line endings are normalized and trailing
+    /// newlines of the expected output are ignored so that YAML block scalars can be used.
+    pub fn check_output(&self, exit_code: u64, stdout: &str) -> Result<()> {
+        let expected_code = self.expect_exit_code.unwrap_or_default();
+        if exit_code != expected_code {
+            return err!(
+                "step `{}` exited with code {}, expected {}",
+                self.cmd,
+                exit_code,
+                expected_code
+            );
+        }
+        if let Some(expected) = &self.expect_stdout_contains {
+            let stdout = stdout.replace("\r\n", "\n");
+            let expected = expected.replace("\r\n", "\n");
+            let expected = expected.trim_end_matches('\n');
+            if !stdout.contains(expected) {
+                return err!(
+                    "output of step `{}` doesn't contain the expected text\nExpected:\n{}\nOutput:\n{}",
+                    self.cmd,
+                    expected,
+                    stdout
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the directory the step runs in. The `working_dir` of the step is passed through
+    /// `render` and a relative one is resolved against `script_dir`, the already rendered
+    /// working directory of the script.
+    pub fn run_dir(&self, script_dir: &Path, render: impl FnOnce(&str) -> String) -> PathBuf {
+        match &self.working_dir {
+            Some(dir) => script_dir.join(render(&dir.to_string_lossy())),
+            None => script_dir.to_path_buf(),
+        }
+    }
+
     pub fn should_run_on_version(&self, version: impl AsRef<str>) -> bool {
         match &self.versions {
             None => true,
@@ -99,4 +248,144 @@ mod tests {
         assert!(cmd.should_run_on_target(&BuildTarget::Deb));
         assert!(cmd.should_run_on_target(&BuildTarget::Apk));
     }
+
+    #[test]
+    fn resolves_working_dir_of_step() {
+        let script_dir = Path::new("/tmp/pkger/bld");
+        let render = |dir: &str| dir.replace("${RECIPE}", "pkger");
+        let cmd: Command = serde_yaml::from_str("cmd: pwd\nworking_dir: /tmp").unwrap();
+        assert_eq!(cmd.run_dir(script_dir, render), PathBuf::from("/tmp"));
+
+        let cmd: Command = serde_yaml::from_str("cmd: make\nworking_dir: src/lib").unwrap();
+        assert_eq!(
+            cmd.run_dir(script_dir, render),
+            PathBuf::from("/tmp/pkger/bld/src/lib")
+        );
+
+        let cmd: Command = serde_yaml::from_str("cmd: make\nworking_dir: ${RECIPE}/src").unwrap();
+        assert_eq!(
+            cmd.run_dir(script_dir, render),
+            PathBuf::from("/tmp/pkger/bld/pkger/src")
+        );
+
+        assert_eq!(
+            Command::from("make").run_dir(script_dir, render),
+            script_dir
+        );
+    }
+
+    #[test]
+    fn matches_os_and_version_prefix() {
+        let centos = |version| Os::new("centos", Some(version));
+        let mut cmd = Command::from("echo 123");
+        assert!(cmd.should_run_on_os(&centos("7")));
+        assert!(cmd.should_run_on_os(&Os::new("debian", None::<&str>)));
+
+        cmd.os = Some("centos".to_string());
+        assert!(cmd.should_run_on_os(&centos("7")));
+        assert!(!cmd.should_run_on_os(&Os::new("rocky", Some("8"))));
+
+        cmd.os_version = Some("8".to_string());
+        assert!(cmd.should_run_on_os(&centos("8")));
+        assert!(cmd.should_run_on_os(&centos("8.5")));
+        assert!(cmd.should_run_on_os(&centos("8.5.2111")));
+        assert!(!cmd.should_run_on_os(&centos("7")));
+        assert!(!cmd.should_run_on_os(&centos("80")));
+        assert!(!cmd.should_run_on_os(&Os::new("centos", None::<&str>)));
+
+        cmd.os_version = Some("8.5".to_string());
+        assert!(cmd.should_run_on_os(&centos("8.5")));
+        assert!(cmd.should_run_on_os(&centos("8.5.2111")));
+        assert!(!cmd.should_run_on_os(&centos("8.50")));
+        assert!(!cmd.should_run_on_os(&centos("8")));
+
+        // without `os` the version matches on any distribution
+        cmd.os = None;
+        assert!(cmd.should_run_on_os(&Os::new("rocky", Some("8.5"))));
+    }
+
+    #[test]
+    fn fails_step_with_unexpected_output() {
+        let cmd: Command = serde_yaml::from_str(
+            r#"
+cmd: ./run-tests
+expect_stdout_contains: |
+  test a ... ok
+  test b ... ok
+"#,
+        )
+        .unwrap();
+        assert!(cmd.has_expectations());
+
+        let stdout = "running 2 tests\r\ntest a ... ok\r\ntest b ... ok\r\n\ndone\n";
+        assert!(cmd.check_output(0, stdout).is_ok());
+
+        let err = cmd
+            .check_output(0, "running 2 tests\ntest a ... ok\ntest b ... FAILED\n")
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("output of step `./run-tests` doesn't contain the expected text"));
+        // lines have to follow each other
+        assert!(cmd
+            .check_output(0, "test a ... ok\ntest c ... ok\ntest b ... ok\n")
+            .is_err());
+        assert!(cmd.check_output(1, stdout).is_err());
+
+        let cmd: Command =
+            serde_yaml::from_str("cmd: test -f missing\nexpect_exit_code: 1").unwrap();
+        assert!(cmd.check_output(1, "").is_ok());
+        assert_eq!(
+            cmd.check_output(0, "").unwrap_err().to_string(),
+            "step `test -f missing` exited with code 0, expected 1"
+        );
+        assert!(!Command::from("make").has_expectations());
+    }
+
+    #[test]
+    fn applies_to_image_target_and_version() {
+        let mut cmd = Command::from("make");
+        assert!(cmd.applies_to("rocky", &BuildTarget::Rpm, "1.0.0"));
+
+        cmd.images = Some(vec!["debian".to_string()]);
+        assert!(!cmd.applies_to("rocky", &BuildTarget::Rpm, "1.0.0"));
+        assert!(cmd.applies_to("debian", &BuildTarget::Rpm, "1.0.0"));
+
+        // a step with a target applies to its target on every image
+        cmd.rpm = Some(true);
+        assert!(cmd.applies_to("rocky", &BuildTarget::Rpm, "1.0.0"));
+        assert!(!cmd.applies_to("rocky", &BuildTarget::Deb, "1.0.0"));
+
+        cmd.versions = Some(vec!["2.0.0".to_string()]);
+        assert!(!cmd.applies_to("rocky", &BuildTarget::Rpm, "1.0.0"));
+        assert!(cmd.applies_to("rocky", &BuildTarget::Rpm, "2.0.0"));
+    }
+
+    #[test]
+    fn validates_os() {
+        let mut cmd = Command::from("make");
+        assert!(cmd.validate().is_ok());
+        cmd.os = Some("Rocky".to_string());
+        assert!(cmd.validate().is_ok());
+        cmd.os = Some("debain".to_string());
+        assert_eq!(
+            cmd.validate().unwrap_err().to_string(),
+            "step `make` has an unknown os `debain`"
+        );
+    }
+
+    #[test]
+    fn reports_manifest_unsupported_fields() {
+        let mut cmd = Command::from("make check");
+        cmd.working_dir = Some(PathBuf::from("tests"));
+        assert!(cmd.manifest_unsupported_fields().is_empty());
+
+        cmd.user = Some("root".to_string());
+        cmd.os_version = Some("8".to_string());
+        cmd.expect_exit_code = Some(1);
+        assert_eq!(
+            cmd.manifest_unsupported_fields(),
+            vec!["user", "os_version", "expect_exit_code"]
+        );
+    }
 }
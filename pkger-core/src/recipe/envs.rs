@@ -1,5 +1,25 @@
+use crate::template;
+use crate::{err, ErrContext, Error, Result};
+
 use serde_yaml::Mapping;
 use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Printed instead of values of secret variables.
+pub const MASKED_VALUE: &str = "********";
+
+/// Parts of variable names that mark the variable as secret.
+const SECRET_KEY_PARTS: &[&str] = &[
+    "PASSWORD",
+    "PASSWD",
+    "SECRET",
+    "TOKEN",
+    "API_KEY",
+    "PRIVATE_KEY",
+    "ACCESS_KEY",
+    "CREDENTIAL",
+];
 
 #[derive(Clone, Default, Debug, PartialEq, Eq)]
 pub struct Env(HashMap<String, String>);
@@ -24,6 +44,17 @@ impl From<Option<Mapping>> for Env {
 }
 
 impl Env {
+    /// Loads variables from a file with `KEY=VALUE` lines in the same format as a dotenv file.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return err!("env file `{}` doesn't exist", path.display());
+        }
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read env file `{}`", path.display()))?;
+        Env::from_dotenv(&contents)
+            .with_context(|| format!("failed to parse env file `{}`", path.display()))
+    }
+
     pub fn new() -> Self {
         Self::default()
     }
@@ -54,6 +85,16 @@ impl Env {
             .collect()
     }
 
+    /// Returns `KEY=VALUE` entries sorted by key.
+    pub fn sorted_kv_vec(&self) -> Vec<String> {
+        let mut entries: Vec<_> = self.0.iter().collect();
+        entries.sort();
+        entries
+            .into_iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect()
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
         self.0.iter()
     }
@@ -61,6 +102,103 @@ impl Env {
     pub fn inner(&self) -> &HashMap<String, String> {
         &self.0
     }
+
+    /// Inserts all variables of `other` overriding the existing ones.
+    pub fn extend(&mut self, other: Env) {
+        self.0.extend(other.0)
+    }
+
+    /// Renders templates in values of the variables with the `injected` variables and then inserts
+    /// them, so that variables can reference the injected ones like `${PKGER_OUT_DIR}/bin`.
+    pub fn with_injected(&self, injected: Env) -> Env {
+        let mut env = Env(self
+            .0
+            .iter()
+            .map(|(k, v)| (k.clone(), template::render(v, &injected.0)))
+            .collect());
+        env.extend(injected);
+        env
+    }
+
+    /// Returns `true` if the name of the variable suggests it holds a password, a token or some
+    /// other secret.
+    pub fn is_secret(key: &str) -> bool {
+        let key = key.to_uppercase();
+        SECRET_KEY_PARTS.iter().any(|part| key.contains(part))
+    }
+
+    /// Returns a copy of the environment with values of secret variables masked.
+    pub fn masked(&self) -> Env {
+        Env(self
+            .0
+            .iter()
+            .map(|(k, v)| {
+                if Env::is_secret(k) {
+                    (k.clone(), MASKED_VALUE.to_string())
+                } else {
+                    (k.clone(), v.clone())
+                }
+            })
+            .collect())
+    }
+
+    /// Parses the contents of a dotenv file. Lines can be prefixed with `export` and values can
+    /// be quoted with single quotes taken literally or double quotes that support escapes.
+    pub fn from_dotenv(contents: &str) -> Result<Self> {
+        let mut env = Env::new();
+
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let line = line
+                .strip_prefix("export ")
+                .map(str::trim_start)
+                .unwrap_or(line);
+
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) if !key.trim().is_empty() => (key.trim(), value.trim()),
+                _ => return err!("invalid dotenv entry on line {}: `{}`", i + 1, line),
+            };
+
+            let value = if let Some(value) = value.strip_prefix('\'') {
+                match value.split_once('\'') {
+                    Some((value, _)) => value.to_string(),
+                    None => return err!("unterminated quote on line {}", i + 1),
+                }
+            } else if let Some(value) = value.strip_prefix('"') {
+                let mut unescaped = String::new();
+                let mut chars = value.chars();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => unescaped.push('\n'),
+                            Some('t') => unescaped.push('\t'),
+                            Some(ch) => unescaped.push(ch),
+                            None => return err!("unterminated quote on line {}", i + 1),
+                        },
+                        Some(ch) => unescaped.push(ch),
+                        None => return err!("unterminated quote on line {}", i + 1),
+                    }
+                }
+                unescaped
+            } else {
+                // unquoted values end at an inline comment
+                value
+                    .split_once(" #")
+                    .map(|(value, _)| value)
+                    .unwrap_or(value)
+                    .trim_end()
+                    .to_string()
+            };
+
+            env.insert(key, value);
+        }
+
+        Ok(env)
+    }
 }
 
 #[cfg(test)]
@@ -83,4 +221,77 @@ mod tests {
         env.remove("second");
         assert!(env.is_empty());
     }
+
+    #[test]
+    fn dotenv_overrides_recipe_env() {
+        let mut env = Env::new();
+        env.insert("RUST_LOG", "info");
+        env.insert("PREFIX", "/usr");
+
+        let dotenv = r#"
+# comment
+export RUST_LOG=trace # inline comment
+SINGLE='literal $HOME \n'
+DOUBLE="quoted \"value\"\n"
+EMPTY=
+"#;
+        env.extend(Env::from_dotenv(dotenv).unwrap());
+
+        let get = |key: &str| env.inner().get(key).map(String::as_str);
+        assert_eq!(get("RUST_LOG"), Some("trace"));
+        assert_eq!(get("PREFIX"), Some("/usr"));
+        assert_eq!(get("SINGLE"), Some("literal $HOME \\n"));
+        assert_eq!(get("DOUBLE"), Some("quoted \"value\"\n"));
+        assert_eq!(get("EMPTY"), Some(""));
+
+        assert!(Env::from_dotenv("NO_VALUE").is_err());
+        assert!(Env::from_dotenv("UNTERMINATED=\"value").is_err());
+    }
+
+    #[test]
+    fn loads_env_file() {
+        let dir = tempdir::TempDir::new("pkger-env-file").unwrap();
+        let path = dir.path().join("build.env");
+        fs::write(&path, "RUST_LOG=trace\nPREFIX=${PKGER_OUT_DIR}/usr\n").unwrap();
+
+        let mut env = Env::from_file(&path).unwrap();
+        let mut inline = Env::new();
+        inline.insert("RUST_LOG", "info");
+        env.extend(inline);
+
+        let get = |key: &str| env.inner().get(key).map(String::as_str);
+        assert_eq!(get("RUST_LOG"), Some("info"));
+        assert_eq!(get("PREFIX"), Some("${PKGER_OUT_DIR}/usr"));
+
+        let err = Env::from_file(&dir.path().join("missing.env")).unwrap_err();
+        assert!(err.to_string().contains("missing.env` doesn't exist"));
+    }
+
+    #[test]
+    fn prints_env_with_masked_secrets() {
+        let mut recipe_env = Env::new();
+        recipe_env.insert("PREFIX", "${PKGER_OUT_DIR}/usr");
+        recipe_env.insert("PATH", "$PATH:/opt/bin");
+        recipe_env.insert("GITHUB_TOKEN", "ghp_123");
+        recipe_env.insert("db_password", "hunter2");
+        recipe_env.insert("RECIPE", "overridden");
+
+        let mut injected = Env::new();
+        injected.insert("PKGER_OUT_DIR", "/tmp/out");
+        injected.insert("RECIPE", "pkger");
+
+        let printed = recipe_env.with_injected(injected).masked().sorted_kv_vec();
+        assert_eq!(
+            printed,
+            vec![
+                format!("GITHUB_TOKEN={}", MASKED_VALUE),
+                "PATH=$PATH:/opt/bin".to_string(),
+                "PKGER_OUT_DIR=/tmp/out".to_string(),
+                "PREFIX=/tmp/out/usr".to_string(),
+                "RECIPE=pkger".to_string(),
+                format!("db_password={}", MASKED_VALUE),
+            ]
+        );
+        assert!(!Env::is_secret("KEYBOARD_LAYOUT"));
+    }
 }
@@ -2,12 +2,45 @@ use crate::log::{debug, trace, warning, BoxedCollector};
 use crate::recipe::{Recipe, RecipeRep};
 use crate::{err, ErrContext, Error, Result};
 
+use std::collections::BTreeSet;
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
 
+/// A problem with the `from` references of recipes.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InheritanceProblem {
+    /// The `recipe` inherits from a `base` recipe that can't be loaded.
+    Dangling {
+        recipe: String,
+        base: String,
+        reason: String,
+    },
+    /// Recipes that inherit from each other, the first recipe is repeated at the end.
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for InheritanceProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Dangling {
+                recipe,
+                base,
+                reason,
+            } => write!(
+                f,
+                "recipe `{}` inherits from `{}` which can't be loaded: {}",
+                recipe, base, reason
+            ),
+            Self::Cycle(chain) => write!(f, "cyclic recipe inheritance `{}`", chain.join(" -> ")),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct Loader {
     path: PathBuf,
+    base_root: Option<PathBuf>,
 }
 
 impl Loader {
@@ -23,22 +56,46 @@ impl Loader {
 
         Ok(Loader {
             path: path.to_path_buf(),
+            base_root: None,
         })
     }
 
+    /// Sets the directory in which base recipes referenced by `from` are looked up. By default
+    /// it's the directory of this loader.
+    pub fn base_root<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.base_root = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    fn base_root_path(&self) -> &Path {
+        self.base_root.as_deref().unwrap_or(&self.path)
+    }
+
     pub fn load_rep(&self, recipe: &str) -> Result<RecipeRep> {
         let base_path = self.path.join(recipe);
         let mut path = base_path.join("recipe.yml");
         if !path.exists() {
             path = base_path.join("recipe.yaml");
         }
+        if base_path.is_dir() && !path.exists() {
+            return err!(
+                "recipe directory `{}` has no recipe.yml or recipe.yaml",
+                base_path.display()
+            );
+        }
         RecipeRep::load(path)
     }
 
     pub fn load(&self, recipe: &str) -> Result<Recipe> {
         let base_path = self.path.join(recipe);
-        self.load_rep(recipe)
-            .and_then(|rep| Recipe::new(rep, base_path))
+        self.load_rep(recipe).and_then(|rep| {
+            Recipe::with_base_root(
+                rep,
+                base_path,
+                self.path.clone(),
+                Some(self.base_root_path()),
+            )
+        })
     }
 
     pub fn list(&self) -> Result<Vec<String>> {
@@ -56,6 +113,68 @@ impl Loader {
             .context("failed to list recipes")
     }
 
+    /// Loads every recipe in the underlying directory and returns the names of the ones that
+    /// fail to load along with their errors, sorted by name.
+    pub fn load_errors(&self) -> Result<Vec<(String, Error)>> {
+        let mut names = self.list()?;
+        names.sort_unstable();
+        Ok(names
+            .into_iter()
+            .filter_map(|name| self.load(&name).err().map(|e| (name, e)))
+            .collect())
+    }
+
+    /// Follows the `from` chain of every recipe in the underlying directory and returns the
+    /// dangling references and cycles, each reported once and sorted. Recipes that fail to load
+    /// on their own are skipped.
+    pub fn inheritance_problems(&self) -> Result<Vec<InheritanceProblem>> {
+        let bases = Loader {
+            path: self.base_root_path().to_path_buf(),
+            base_root: None,
+        };
+        let mut problems = BTreeSet::new();
+
+        for name in self.list()? {
+            let mut from = match self.load_rep(&name) {
+                Ok(rep) => rep.from,
+                Err(_) => continue,
+            };
+            let mut chain = vec![name];
+            while let Some(base) = from {
+                if let Some(start) = chain.iter().position(|recipe| *recipe == base) {
+                    let mut cycle = chain.split_off(start);
+                    // rotate so that the same cycle found from different recipes is equal
+                    let min = cycle
+                        .iter()
+                        .enumerate()
+                        .min_by_key(|(_, recipe)| *recipe)
+                        .map(|(i, _)| i)
+                        .unwrap_or_default();
+                    cycle.rotate_left(min);
+                    cycle.push(cycle[0].clone());
+                    problems.insert(InheritanceProblem::Cycle(cycle));
+                    break;
+                }
+                match bases.load_rep(&base) {
+                    Ok(rep) => {
+                        from = rep.from;
+                        chain.push(base);
+                    }
+                    Err(e) => {
+                        problems.insert(InheritanceProblem::Dangling {
+                            recipe: chain.last().cloned().unwrap_or_default(),
+                            base,
+                            reason: format!("{:#}", e),
+                        });
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(problems.into_iter().collect())
+    }
+
     /// Loads all recipes in the underlying directory
     pub fn load_all(&self, logger: &mut BoxedCollector) -> Result<Vec<Recipe>> {
         let path = self.path.as_path();
@@ -69,7 +188,14 @@ impl Loader {
                 Ok(entry) => {
                     let filename = entry.file_name().to_string_lossy().to_string();
                     let path = entry.path();
-                    match RecipeRep::try_from(entry).map(|rep| Recipe::new(rep, path)) {
+                    match RecipeRep::try_from(entry).map(|rep| {
+                        Recipe::with_base_root(
+                            rep,
+                            path,
+                            self.path.clone(),
+                            Some(self.base_root_path()),
+                        )
+                    }) {
                         Ok(result) => {
                             let recipe = result?;
                             trace!(logger => "{:?}", recipe);
@@ -1,23 +1,34 @@
 mod arch;
+mod compression;
 mod deps;
 mod git;
 mod image;
 mod os;
 mod patches;
+mod relation;
+mod repos;
+mod source;
 mod target;
 
 pub use arch::BuildArch;
+pub use compression::Compression;
 pub use deps::Dependencies;
 pub use git::GitSource;
 pub use image::{deserialize_images, ImageTarget};
 pub use os::{Distro, Os, PackageManager};
 pub use patches::{Patch, Patches};
+pub use relation::{Relation, VersionOp};
+pub use repos::BuildRepos;
+pub use source::Source;
 pub use target::{BuildTarget, BuildTargetInfo};
 
+use crate::limits::ResourceLimits;
 use crate::{Error, Result};
 
 use serde::{Deserialize, Serialize};
-use serde_yaml::Value as YamlValue;
+use serde_yaml::{Mapping, Value as YamlValue};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 
 macro_rules! if_let_some_ty {
@@ -34,7 +45,7 @@ fn null() -> YamlValue {
     YamlValue::Null
 }
 
-#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[derive(Clone, Default, Deserialize, Serialize, Debug, PartialEq, Eq)]
 pub struct Versions(Vec<String>);
 
 impl Versions {
@@ -45,6 +56,15 @@ impl Versions {
     pub fn versions(&self) -> &[String] {
         &self.0
     }
+
+    /// Adds `versions` that are not already declared.
+    pub fn extend(&mut self, versions: impl IntoIterator<Item = String>) {
+        for version in versions {
+            if !self.has_version(&version) {
+                self.0.push(version);
+            }
+        }
+    }
 }
 
 impl TryFrom<YamlValue> for Versions {
@@ -99,7 +119,9 @@ pub struct MetadataRep {
     pub arch: Option<String>,
     #[serde(default = "null")]
     #[serde(skip_serializing_if = "YamlValue::is_null")]
-    /// http/https or file system source pointing to a tar.gz or tar.xz package
+    /// http/https or file system source pointing to a tar.gz or tar.xz package. Entries can also
+    /// be tables with the `url`, http `mirrors` tried in order if fetching from the url fails and
+    /// the expected `sha256` digest of the file.
     pub source: YamlValue,
     #[serde(default = "null")]
     #[serde(skip_serializing_if = "YamlValue::is_null")]
@@ -118,8 +140,35 @@ pub struct MetadataRep {
     /// between consecutive builds of the same version of a package
     pub release: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    /// Increment the release on every build of the same version, starting from `release`
+    pub auto_release: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     /// Used to force the package to be seen as newer than any previous version with a lower epoch
     pub epoch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Resource limits of the build container like `memory: 2g` or `cpus: 1.5`
+    pub limits: Option<ResourceLimits>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Whether to run the build container in privileged mode
+    pub privileged: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Kernel capabilities added to the build container like `SYS_ADMIN`
+    pub cap_add: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// DNS servers of the build container
+    pub dns: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Additional `host:ip` entries of `/etc/hosts` of the build container
+    pub extra_hosts: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Name of a symlink in the output directory pointing to the latest built package
+    pub latest_link: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Whether to warn about binaries built for a different architecture than `arch`
+    pub check_arch: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Compression of the archive built for the `gzip` target like `xz` or `zstd`
+    pub compression: Option<Compression>,
 
     #[serde(default = "null")]
     #[serde(skip_serializing_if = "YamlValue::is_null")]
@@ -133,6 +182,17 @@ pub struct MetadataRep {
     #[serde(default = "null")]
     #[serde(skip_serializing_if = "YamlValue::is_null")]
     pub provides: YamlValue,
+    #[serde(default = "null")]
+    #[serde(skip_serializing_if = "YamlValue::is_null")]
+    /// Packages replaced by this package. Maps to `Obsoletes` in RPM and to `Breaks` and
+    /// `Replaces` in DEB.
+    pub obsoletes: YamlValue,
+    #[serde(default = "null")]
+    #[serde(skip_serializing_if = "YamlValue::is_null")]
+    /// Packages, optionally with a version constraint like `foo < 2.0`, that stop working once
+    /// this package is installed. Maps to `Breaks` in DEB, to `Conflicts` in RPM and PKG and to
+    /// negative dependencies in APK.
+    pub breaks: YamlValue,
 
     #[serde(default = "null")]
     #[serde(skip_serializing_if = "YamlValue::is_null")]
@@ -140,6 +200,12 @@ pub struct MetadataRep {
     /// as dependencies.
     pub patches: YamlValue,
 
+    #[serde(default = "null")]
+    #[serde(skip_serializing_if = "YamlValue::is_null")]
+    /// Commands enabling additional package repositories run before installing build
+    /// dependencies. Can be specified only for certain images same as dependencies.
+    pub build_repos: YamlValue,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     // Only DEB
     pub deb: Option<DebRep>,
@@ -155,6 +221,103 @@ pub struct MetadataRep {
     #[serde(skip_serializing_if = "Option::is_none")]
     // Only APK
     pub apk: Option<ApkRep>,
+
+    #[serde(flatten)]
+    /// Fields that are not part of the metadata, only kept to warn about them.
+    pub unknown: BTreeMap<String, YamlValue>,
+}
+
+impl MetadataRep {
+    /// Returns warnings about fields of the metadata and of its target sections that pkger
+    /// doesn't know, like a DEB field in the `rpm` section.
+    pub fn field_warnings(&self) -> Vec<String> {
+        let mut warnings = vec![];
+
+        for (field, value) in &self.unknown {
+            let sections = sections_with_field(field, value);
+            if sections.is_empty() {
+                warnings.push(format!(
+                    "unknown field `{}` in `metadata`, it will be ignored",
+                    field
+                ));
+            } else {
+                warnings.push(format!(
+                    "field `{}` of `metadata` only applies to {}, it will be ignored",
+                    field,
+                    describe_sections(&sections)
+                ));
+            }
+        }
+
+        let sections = [
+            ("deb", self.deb.as_ref().map(|deb| &deb.unknown)),
+            ("rpm", self.rpm.as_ref().map(|rpm| &rpm.unknown)),
+            ("pkg", self.pkg.as_ref().map(|pkg| &pkg.unknown)),
+            ("apk", self.apk.as_ref().map(|apk| &apk.unknown)),
+        ];
+        for (section, unknown) in sections {
+            for (field, value) in unknown.into_iter().flatten() {
+                let other = sections_with_field(field, value);
+                if other.is_empty() {
+                    warnings.push(format!(
+                        "unknown field `{}` in the `{}` section, it will be ignored",
+                        field, section
+                    ));
+                } else {
+                    warnings.push(format!(
+                        "field `{}` of the `{}` section only applies to {}, it will be ignored",
+                        field,
+                        section,
+                        describe_sections(&other)
+                    ));
+                }
+            }
+        }
+
+        warnings
+    }
+}
+
+/// Returns the names of the target sections of the metadata that have a `field` accepting the
+/// `value`.
+fn sections_with_field(field: &str, value: &YamlValue) -> Vec<&'static str> {
+    let mut mapping = Mapping::new();
+    mapping.insert(YamlValue::String(field.to_string()), value.clone());
+    let rep = YamlValue::Mapping(mapping);
+
+    macro_rules! has_field {
+        ($ty:ty) => {
+            matches!(serde_yaml::from_value::<$ty>(rep.clone()), Ok(rep) if rep.unknown.is_empty())
+        };
+    }
+
+    let mut sections = vec![];
+    if has_field!(DebRep) {
+        sections.push("deb");
+    }
+    if has_field!(RpmRep) {
+        sections.push("rpm");
+    }
+    if has_field!(PkgRep) {
+        sections.push("pkg");
+    }
+    if has_field!(ApkRep) {
+        sections.push("apk");
+    }
+    sections
+}
+
+fn describe_sections(sections: &[&str]) -> String {
+    let names = sections
+        .iter()
+        .map(|section| format!("`{}`", section))
+        .collect::<Vec<_>>();
+    match names.split_last() {
+        Some((last, rest)) if !rest.is_empty() => {
+            format!("the {} and {} sections", rest.join(", "), last)
+        }
+        _ => format!("the {} section", names.join("")),
+    }
 }
 
 #[derive(Clone, Deserialize, Serialize, Debug)]
@@ -171,6 +334,13 @@ pub struct PkgRep {
     #[serde(default)]
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub optdepends: Vec<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub noextract: Vec<String>,
+
+    #[serde(flatten)]
+    /// Fields that are not part of the section, only kept to warn about them.
+    pub unknown: BTreeMap<String, YamlValue>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -183,6 +353,8 @@ pub struct PkgInfo {
     pub replaces: Option<Dependencies>,
     /// Optional dependencies needed for full functionality of the package
     pub optdepends: Vec<String>,
+    /// File names of sources that are not extracted into the package
+    pub noextract: Vec<String>,
 }
 
 impl TryFrom<PkgRep> for PkgInfo {
@@ -194,6 +366,7 @@ impl TryFrom<PkgRep> for PkgInfo {
             backup: rep.backup,
             replaces: Dependencies::try_from(rep.replaces).ok(),
             optdepends: rep.optdepends,
+            noextract: rep.noextract,
         })
     }
 }
@@ -228,6 +401,18 @@ pub struct DebRep {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub postinst_script: Option<String>,
+    /// Files of the package used instead of the ones found in the output directory. An `auto`
+    /// entry adds the found files that aren't covered by the listed ones.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<Vec<String>>,
+    /// Installed size of the package in kilobytes used instead of the size calculated from the
+    /// files of the package.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub installed_size: Option<u64>,
+
+    #[serde(flatten)]
+    /// Fields that are not part of the section, only kept to warn about them.
+    pub unknown: BTreeMap<String, YamlValue>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -244,6 +429,8 @@ pub struct DebInfo {
     pub enhances: Option<Dependencies>,
 
     pub postinst_script: Option<String>,
+    pub files: Option<Vec<String>>,
+    pub installed_size: Option<u64>,
 }
 
 impl TryFrom<DebRep> for DebInfo {
@@ -263,6 +450,8 @@ impl TryFrom<DebRep> for DebInfo {
             enhances: Dependencies::try_from(rep.enhances).ok(),
 
             postinst_script: rep.postinst_script,
+            files: rep.files,
+            installed_size: rep.installed_size,
         })
     }
 }
@@ -290,6 +479,18 @@ pub struct RpmRep {
     pub postun_script: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub config_noreplace: Option<String>,
+    /// Files of the package used instead of the ones found in the output directory. An `auto`
+    /// entry adds the found files that aren't covered by the listed ones.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<Vec<String>>,
+    /// Let RPM apply the patches of the recipe to the fetched sources with `%autosetup` in
+    /// `%prep` instead of patching them with `patch` in the build container.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub native_prep: Option<bool>,
+
+    #[serde(flatten)]
+    /// Fields that are not part of the section, only kept to warn about them.
+    pub unknown: BTreeMap<String, YamlValue>,
 }
 
 impl TryFrom<RpmRep> for RpmInfo {
@@ -307,6 +508,8 @@ impl TryFrom<RpmRep> for RpmInfo {
             preun_script: rep.preun_script,
             postun_script: rep.postun_script,
             config_noreplace: rep.config_noreplace,
+            files: rep.files,
+            native_prep: rep.native_prep.unwrap_or_default(),
         })
     }
 }
@@ -323,6 +526,8 @@ pub struct RpmInfo {
     pub preun_script: Option<String>,
     pub postun_script: Option<String>,
     pub config_noreplace: Option<String>,
+    pub files: Option<Vec<String>>,
+    pub native_prep: bool,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -340,7 +545,7 @@ pub struct Metadata {
     /// The URL of the web site for this package
     pub url: Option<String>,
     /// http/https or file system source pointing to a tar.gz or tar.xz package
-    pub source: Vec<String>,
+    pub source: Vec<Source>,
     /// Git repository as source
     pub git: Option<GitSource>,
     /// Whether default dependencies should be installed before the build
@@ -352,17 +557,46 @@ pub struct Metadata {
     /// The release number. This is usually a positive integer number that allows to differentiate
     /// between consecutive builds of the same version of a package
     pub release: Option<String>,
+    /// Whether to increment the release on every build of the same version like with
+    /// `--auto-release`. The last release is kept in a state file of the builder
+    pub auto_release: bool,
     /// Used to force the package to be seen as newer than any previous version with a lower epoch
     pub epoch: Option<String>,
+    /// Resource limits of the build container, overrides the limits from configuration
+    pub limits: Option<ResourceLimits>,
+    /// Whether to run the build container in privileged mode, requires `allow_privileged` in
+    /// configuration
+    pub privileged: bool,
+    /// Kernel capabilities added to the build container, requires `allow_privileged` in
+    /// configuration
+    pub cap_add: Vec<String>,
+    /// DNS servers of the build container, added to the ones from configuration
+    pub dns: Vec<String>,
+    /// Additional `host:ip` entries of `/etc/hosts` of the build container, added to the ones
+    /// from configuration
+    pub extra_hosts: Vec<String>,
+    /// Name of a symlink in the output directory that is updated to point to the package after
+    /// every successful build. Can contain variables like `${RECIPE}`
+    pub latest_link: Option<String>,
+    /// Whether to warn about ELF files in the package built for a different architecture than
+    /// `arch`
+    pub check_arch: bool,
+    /// Compression of the archive built for the `gzip` target, defaults to gzip
+    pub compression: Compression,
 
     pub build_depends: Option<Dependencies>,
 
     pub depends: Option<Dependencies>,
     pub conflicts: Option<Dependencies>,
     pub provides: Option<Dependencies>,
+    pub obsoletes: Option<Dependencies>,
+    pub breaks: Option<Dependencies>,
 
     pub patches: Option<Patches>,
 
+    /// Commands enabling additional package repositories in the cached image
+    pub build_repos: Option<BuildRepos>,
+
     pub deb: Option<DebInfo>,
 
     pub rpm: Option<RpmInfo>,
@@ -373,6 +607,17 @@ pub struct Metadata {
 }
 
 impl Metadata {
+    /// Returns the URL of the package web site. If the URL has no scheme `https://` is prepended.
+    pub fn url(&self) -> Option<Cow<'_, str>> {
+        self.url.as_deref().map(|url| {
+            if has_url_scheme(url) {
+                Cow::Borrowed(url)
+            } else {
+                Cow::Owned(format!("https://{}", url))
+            }
+        })
+    }
+
     /// Returns the release number of this package if one exists, otherwise returns "0"
     pub fn release(&self) -> &str {
         if let Some(release) = &self.release {
@@ -383,30 +628,52 @@ impl Metadata {
     }
 }
 
+/// Checks whether `url` starts with a scheme like `https://` or `git://`.
+pub(crate) fn has_url_scheme(url: &str) -> bool {
+    match url.split_once("://") {
+        Some((scheme, _)) => {
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        }
+        None => false,
+    }
+}
+
 impl TryFrom<MetadataRep> for Metadata {
     type Error = Error;
 
     fn try_from(rep: MetadataRep) -> Result<Self> {
-        let source = if rep.source.is_sequence() {
-            rep.source
-                .as_sequence()
-                .map(|s| {
-                    s.iter()
-                        .map(|v| v.as_str().unwrap_or_default().to_string())
-                        .collect()
-                })
-                .unwrap_or_default()
-        } else if rep.source.is_string() {
-            rep.source
-                .as_str()
-                .map(|s| vec![s.to_string()])
-                .unwrap_or_default()
-        } else {
-            vec![]
+        let source = match rep.source {
+            YamlValue::Sequence(sources) => sources
+                .into_iter()
+                .map(Source::try_from)
+                .collect::<Result<Vec<_>>>()?,
+            YamlValue::Null => vec![],
+            source => vec![Source::try_from(source)?],
+        };
+        let group = match rep.group {
+            Some(group) if group.contains('\n') => {
+                return Err(anyhow!(
+                    "invalid group `{}`, group must not contain newlines",
+                    group.escape_debug()
+                ));
+            }
+            Some(group) if group.trim().is_empty() => None,
+            group => group,
+        };
+        let git = GitSource::try_from(rep.git).ok();
+        let version = match rep.version {
+            // versions are derived from tags of the repository
+            YamlValue::Null if git.as_ref().and_then(GitSource::tags).is_some() => {
+                Versions::default()
+            }
+            version => Versions::try_from(version)?,
         };
         Ok(Self {
             name: rep.name.ok_or_else(|| Error::msg("expected recipe name"))?,
-            version: Versions::try_from(rep.version)?,
+            version,
             description: rep
                 .description
                 .ok_or_else(|| Error::msg("expected recipe description"))?,
@@ -423,20 +690,37 @@ impl TryFrom<MetadataRep> for Metadata {
             maintainer: rep.maintainer,
             url: rep.url,
             source,
-            git: GitSource::try_from(rep.git).ok(),
+            git,
             skip_default_deps: rep.skip_default_deps,
             exclude: rep.exclude,
-            group: rep.group,
+            group,
             release: rep.release,
+            auto_release: rep.auto_release.unwrap_or_default(),
             epoch: rep.epoch,
+            limits: rep.limits,
+            privileged: rep.privileged.unwrap_or_default(),
+            cap_add: rep.cap_add.unwrap_or_default(),
+            dns: rep.dns.unwrap_or_default(),
+            extra_hosts: rep.extra_hosts.unwrap_or_default(),
+            latest_link: rep.latest_link,
+            check_arch: rep.check_arch.unwrap_or_default(),
+            compression: rep.compression.unwrap_or_default(),
 
             build_depends: Dependencies::try_from(rep.build_depends).ok(),
             depends: Dependencies::try_from(rep.depends).ok(),
             conflicts: Dependencies::try_from(rep.conflicts).ok(),
             provides: Dependencies::try_from(rep.provides).ok(),
+            obsoletes: Dependencies::try_from(rep.obsoletes).ok(),
+            breaks: Dependencies::try_from(rep.breaks).ok(),
 
             patches: Patches::try_from(rep.patches).ok(),
 
+            build_repos: if rep.build_repos.is_null() {
+                None
+            } else {
+                Some(BuildRepos::try_from(rep.build_repos)?)
+            },
+
             deb: if_let_some_ty!(rep.deb, DebInfo),
             rpm: if_let_some_ty!(rep.rpm, RpmInfo),
             pkg: if_let_some_ty!(rep.pkg, PkgInfo),
@@ -460,6 +744,10 @@ pub struct ApkRep {
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub private_key: Option<std::path::PathBuf>,
+
+    #[serde(flatten)]
+    /// Fields that are not part of the section, only kept to warn about them.
+    pub unknown: BTreeMap<String, YamlValue>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -1,8 +1,9 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
 use std::fmt::Formatter;
 
 #[allow(non_camel_case_types)]
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum BuildArch {
     All,
     x86_64,
@@ -51,7 +52,58 @@ impl fmt::Display for BuildArch {
     }
 }
 
+impl Serialize for BuildArch {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_ref())
+    }
+}
+
+impl<'de> Deserialize<'de> for BuildArch {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(|arch| BuildArch::from(arch.as_str()))
+    }
+}
+
 impl BuildArch {
+    /// Returns the architecture of a container platform like `linux/amd64` or `linux/arm/v7`.
+    pub fn from_platform(platform: &str) -> Option<Self> {
+        let mut elems = platform.split('/').skip(1);
+        let arch = match (elems.next()?, elems.next()) {
+            ("amd64", _) => Self::x86_64,
+            ("386", _) => Self::x86,
+            ("arm64", _) => Self::Arm64,
+            ("arm", Some("v6")) => Self::Armv6h,
+            ("arm", Some("v7")) => Self::Armv7h,
+            ("arm", _) => Self::Arm,
+            (arch, _) => Self::Other(arch.to_string()),
+        };
+        Some(arch)
+    }
+
+    /// Checks whether a package of this architecture can be built on the given container
+    /// platform. Architecture independent packages can be built anywhere.
+    pub fn matches_platform(&self, platform: &str) -> bool {
+        match (self, Self::from_platform(platform)) {
+            (Self::All, _) | (_, None) => true,
+            (arch, Some(platform_arch)) => *arch == platform_arch,
+        }
+    }
+
+    /// Returns the container platform that builds packages of this architecture. Architecture
+    /// independent and unknown architectures have none.
+    pub fn platform(&self) -> Option<&'static str> {
+        use BuildArch::*;
+        match &self {
+            x86_64 => Some("linux/amd64"),
+            x86 => Some("linux/386"),
+            Arm => Some("linux/arm/v5"),
+            Armv6h => Some("linux/arm/v6"),
+            Armv7h => Some("linux/arm/v7"),
+            Arm64 => Some("linux/arm64"),
+            All | Other(_) => None,
+        }
+    }
+
     pub fn deb_name(&self) -> &str {
         use BuildArch::*;
         match &self {
@@ -108,3 +160,38 @@ impl BuildArch {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_platforms() {
+        assert_eq!(
+            BuildArch::from_platform("linux/amd64"),
+            Some(BuildArch::x86_64)
+        );
+        assert_eq!(
+            BuildArch::from_platform("linux/arm64/v8"),
+            Some(BuildArch::Arm64)
+        );
+        assert_eq!(
+            BuildArch::from_platform("linux/arm/v7"),
+            Some(BuildArch::Armv7h)
+        );
+        assert_eq!(BuildArch::from_platform("linux"), None);
+
+        assert!(BuildArch::All.matches_platform("linux/arm64"));
+        assert!(BuildArch::x86_64.matches_platform("linux/amd64"));
+        assert!(!BuildArch::x86_64.matches_platform("linux/arm64"));
+        assert!(BuildArch::from("aarch64").matches_platform("linux/arm64"));
+
+        for arch in ["x86_64", "x86", "arm", "armv6h", "armv7h", "aarch64"] {
+            let arch = BuildArch::from(arch);
+            let platform = arch.platform().unwrap();
+            assert!(arch.matches_platform(platform), "{}", platform);
+        }
+        assert_eq!(BuildArch::All.platform(), None);
+        assert_eq!(BuildArch::from("riscv64").platform(), None);
+    }
+}
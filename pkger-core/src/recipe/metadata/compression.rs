@@ -0,0 +1,77 @@
+use crate::recipe::PackageManager;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Default, Deserialize, Serialize, Debug, Eq, PartialEq, Hash)]
+#[serde(rename_all = "lowercase")]
+/// Compression of the tar archive built for the `gzip` target.
+pub enum Compression {
+    #[default]
+    Gzip,
+    Xz,
+    #[serde(alias = "zst")]
+    Zstd,
+}
+
+impl Compression {
+    /// Returns the extension of the compressed archive like `tar.gz`.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "tar.gz",
+            Compression::Xz => "tar.xz",
+            Compression::Zstd => "tar.zst",
+        }
+    }
+
+    /// Returns the command compressing a tar archive in place in the container, `None` for gzip
+    /// which is compressed on the host.
+    pub fn command(&self) -> Option<&'static str> {
+        match self {
+            Compression::Gzip => None,
+            Compression::Xz => Some("xz -f"),
+            Compression::Zstd => Some("zstd -q -f --rm"),
+        }
+    }
+
+    /// Returns the package that provides the compression tool when installed with the
+    /// `package_manager`.
+    pub fn dependency(&self, package_manager: &PackageManager) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Xz if matches!(package_manager, PackageManager::Apt) => "xz-utils",
+            Compression::Xz => "xz",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_compression() {
+        let parse = |s: &str| serde_yaml::from_str::<Compression>(s).unwrap();
+        assert_eq!(parse("gzip"), Compression::Gzip);
+        assert_eq!(parse("xz"), Compression::Xz);
+        assert_eq!(parse("zstd"), Compression::Zstd);
+        assert_eq!(parse("zst"), Compression::Zstd);
+        assert!(serde_yaml::from_str::<Compression>("bzip2").is_err());
+
+        assert_eq!(Compression::default().extension(), "tar.gz");
+        assert_eq!(Compression::Xz.extension(), "tar.xz");
+        assert_eq!(Compression::Zstd.extension(), "tar.zst");
+    }
+
+    #[test]
+    fn resolves_dependency_of_package_manager() {
+        assert_eq!(Compression::Xz.dependency(&PackageManager::Apt), "xz-utils");
+        assert_eq!(Compression::Xz.dependency(&PackageManager::Dnf), "xz");
+        assert_eq!(Compression::Xz.dependency(&PackageManager::Apk), "xz");
+        assert_eq!(Compression::Zstd.dependency(&PackageManager::Apt), "zstd");
+        assert_eq!(
+            Compression::Gzip.dependency(&PackageManager::Pacman),
+            "gzip"
+        );
+    }
+}
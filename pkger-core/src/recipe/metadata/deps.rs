@@ -1,3 +1,5 @@
+use crate::image::Image;
+use crate::recipe::{BuildArch, BuildTarget};
 use crate::Result;
 
 use anyhow::Context;
@@ -104,18 +106,47 @@ impl TryFrom<YamlValue> for Dependencies {
 }
 
 impl Dependencies {
-    /// Returns a set of dependencies for the given `image`. This includes common images
-    /// from [COMMON_DEPS_KEY](COMMON_DEPS_KEY).
-    pub fn resolve_names(&self, image: &str) -> HashSet<&str> {
-        let mut deps = HashSet::new();
-        if let Some(common_deps) = self.inner.get(COMMON_DEPS_KEY) {
-            deps.extend(common_deps.iter().map(|s| s.as_str()));
-        }
-        if let Some(image_deps) = self.inner.get(image) {
-            deps.extend(image_deps.iter().map(|s| s.as_str()));
-        }
+    /// Returns a set of dependencies for the given `image` with the `target` built for `arch`.
+    /// This includes common images from [COMMON_DEPS_KEY](COMMON_DEPS_KEY), the dependencies of
+    /// the simple image of the target like `pkger-rpm` so that they apply to all custom images
+    /// with the same target and the dependencies declared for the architecture like `x86_64` or
+    /// `aarch64`.
+    pub fn resolve_names(
+        &self,
+        image: &str,
+        target: BuildTarget,
+        arch: &BuildArch,
+    ) -> HashSet<&str> {
+        self.names_of_keys(&self.matching_keys(image, target, arch))
+    }
 
-        deps
+    /// Returns the dependencies declared under the `keys`.
+    pub fn names_of_keys(&self, keys: &[String]) -> HashSet<&str> {
+        keys.iter()
+            .filter_map(|key| self.inner.get(key))
+            .flat_map(|key_deps| key_deps.iter().map(|s| s.as_str()))
+            .collect()
+    }
+
+    /// Returns the keys of `inner` that apply to the `image` with the `target` built for `arch`.
+    pub fn matching_keys(&self, image: &str, target: BuildTarget, arch: &BuildArch) -> Vec<String> {
+        let simple = Image::simple(target).name;
+        self.inner
+            .keys()
+            .filter(|key| {
+                *key == COMMON_DEPS_KEY
+                    || *key == image
+                    || *key == simple
+                    || Self::is_arch_key(key, arch)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Returns `true` if the `key` names the architecture `arch`, aliases like `amd64` are
+    /// matched as well. Packages without an architecture don't match any key.
+    fn is_arch_key(key: &str, arch: &BuildArch) -> bool {
+        *arch != BuildArch::All && BuildArch::from(key) == *arch
     }
 
     /// Returns `true` if the `image` depends on the `dependency` or the dependency is in common
@@ -179,7 +210,7 @@ mod tests {
             $image.insert($dep);
                 )+
 
-            assert_eq!($image, got.resolve_names(stringify!($image)));
+            assert_eq!($image, got.resolve_names(stringify!($image), BuildTarget::Gzip, &BuildArch::All));
             )+
 
         }
@@ -227,4 +258,80 @@ build_depends:
             ubuntu20 => "libssl-dev"
         );
     }
+
+    #[test]
+    fn resolves_arch_deps() {
+        let input: YamlValue = serde_yaml::from_str(
+            r#"
+all: [gcc]
+rocky: [openssl-devel]
+x86_64: [glibc-devel]
+i686: [glibc-devel.i686]
+aarch64: [libatomic]
+"#,
+        )
+        .unwrap();
+        let deps = Dependencies::try_from(input).unwrap();
+
+        assert_eq!(
+            deps.resolve_names("rocky", BuildTarget::Rpm, &BuildArch::x86_64),
+            HashSet::from(["gcc", "openssl-devel", "glibc-devel"])
+        );
+        assert_eq!(
+            deps.resolve_names("rocky", BuildTarget::Rpm, &BuildArch::Arm64),
+            HashSet::from(["gcc", "openssl-devel", "libatomic"])
+        );
+        assert_eq!(
+            deps.resolve_names("debian", BuildTarget::Deb, &BuildArch::Other("i686".into())),
+            HashSet::from(["gcc", "glibc-devel.i686"])
+        );
+        assert_eq!(
+            deps.resolve_names("debian", BuildTarget::Deb, &BuildArch::from("amd64")),
+            HashSet::from(["gcc", "glibc-devel"])
+        );
+        assert_eq!(
+            deps.resolve_names("rocky", BuildTarget::Rpm, &BuildArch::All),
+            HashSet::from(["gcc", "openssl-devel"])
+        );
+    }
+
+    #[test]
+    fn resolves_simple_image_deps_for_custom_images() {
+        let input: YamlValue = serde_yaml::from_str(
+            r#"
+all: [gcc]
+pkger-rpm: [bison]
+pkger-deb: [flex]
+pkger-pkg+pkger-apk: [m4]
+rocky: [openssl-devel]
+"#,
+        )
+        .unwrap();
+        let deps = Dependencies::try_from(input).unwrap();
+
+        assert_eq!(
+            deps.resolve_names("rocky", BuildTarget::Rpm, &BuildArch::All),
+            HashSet::from(["gcc", "bison", "openssl-devel"])
+        );
+        assert_eq!(
+            deps.resolve_names("debian", BuildTarget::Deb, &BuildArch::All),
+            HashSet::from(["gcc", "flex"])
+        );
+        assert_eq!(
+            deps.resolve_names("arch", BuildTarget::Pkg, &BuildArch::All),
+            HashSet::from(["gcc", "m4"])
+        );
+        assert_eq!(
+            deps.resolve_names("alpine", BuildTarget::Apk, &BuildArch::All),
+            HashSet::from(["gcc", "m4"])
+        );
+        assert_eq!(
+            deps.resolve_names("pkger-rpm", BuildTarget::Rpm, &BuildArch::All),
+            HashSet::from(["gcc", "bison"])
+        );
+        assert_eq!(
+            deps.resolve_names("debian", BuildTarget::Gzip, &BuildArch::All),
+            HashSet::from(["gcc"])
+        );
+    }
 }
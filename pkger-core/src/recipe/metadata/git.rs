@@ -1,13 +1,20 @@
 use crate::{Error, Result};
 
 use serde_yaml::{Mapping, Value as YamlValue};
+use std::cmp::Ordering;
 use std::convert::TryFrom;
 
+/// Maximum number of tags that versions are derived from if `max_tags` is not specified.
+pub const DEFAULT_MAX_TAGS: usize = 10;
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct GitSource {
     url: String,
     // defaults to master
     branch: String,
+    /// Pattern of tags like `v*` to build, every matching tag is built as a separate version.
+    tags: Option<String>,
+    max_tags: Option<usize>,
 }
 
 impl From<&str> for GitSource {
@@ -15,6 +22,8 @@ impl From<&str> for GitSource {
         Self {
             url: s.to_string(),
             branch: "master".to_string(),
+            tags: None,
+            max_tags: None,
         }
     }
 }
@@ -29,18 +38,44 @@ impl TryFrom<Mapping> for GitSource {
 
             let url = url.as_str().unwrap().to_string();
 
-            if let Some(branch) = table.get(&YamlValue::from("branch")) {
-                if !branch.is_string() {
+            let branch = match table.get(&YamlValue::from("branch")) {
+                Some(YamlValue::String(branch)) => Some(branch.to_string()),
+                Some(branch) => {
                     return Err(anyhow!("expected a string as branch, found `{:?}`", branch));
                 }
+                None => None,
+            };
+            let mut source = GitSource::new(url, branch);
 
-                return Ok(GitSource::new(
-                    url,
-                    Some(branch.as_str().unwrap().to_string()),
-                ));
+            match table.get(&YamlValue::from("tags")) {
+                Some(YamlValue::String(tags)) => {
+                    if tags.matches('*').count() != 1 {
+                        return Err(anyhow!(
+                            "tag pattern `{}` must contain exactly one `*` matching the version",
+                            tags
+                        ));
+                    }
+                    source.tags = Some(tags.to_string());
+                }
+                Some(tags) => {
+                    return Err(anyhow!("expected a string as tags, found `{:?}`", tags));
+                }
+                None => {}
+            }
+            match table.get(&YamlValue::from("max_tags")) {
+                Some(YamlValue::Number(max)) if max.as_u64().is_some() => {
+                    source.max_tags = max.as_u64().map(|max| max as usize);
+                }
+                Some(max) => {
+                    return Err(anyhow!(
+                        "expected a positive number as max_tags, found `{:?}`",
+                        max
+                    ));
+                }
+                None => {}
             }
 
-            Ok(GitSource::new(url, None::<&str>))
+            Ok(source)
         } else {
             Err(anyhow!(
                 "expected a url entry in a table, found `{:?}`",
@@ -72,6 +107,8 @@ impl GitSource {
         Self {
             url: url.into(),
             branch: branch.map(B::into).unwrap_or_else(|| "master".to_string()),
+            tags: None,
+            max_tags: None,
         }
     }
     pub fn url(&self) -> &str {
@@ -80,4 +117,65 @@ impl GitSource {
     pub fn branch(&self) -> &str {
         &self.branch
     }
+
+    /// Pattern of tags that are built as separate versions.
+    pub fn tags(&self) -> Option<&str> {
+        self.tags.as_deref()
+    }
+
+    pub fn max_tags(&self) -> usize {
+        self.max_tags.unwrap_or(DEFAULT_MAX_TAGS)
+    }
+
+    /// Returns the parts of the tag pattern before and after the `*`.
+    fn tag_pattern(&self) -> Option<(&str, &str)> {
+        self.tags.as_deref().and_then(|tags| tags.split_once('*'))
+    }
+
+    /// Returns the version built from the given tag if the tag matches the tag pattern.
+    pub fn tag_version<'t>(&self, tag: &'t str) -> Option<&'t str> {
+        let (prefix, suffix) = self.tag_pattern()?;
+        tag.strip_prefix(prefix)
+            .and_then(|tag| tag.strip_suffix(suffix))
+            .filter(|version| !version.is_empty())
+    }
+
+    /// Returns the tag from which the `version` is built.
+    pub fn tag_for_version(&self, version: &str) -> Option<String> {
+        self.tag_pattern()
+            .map(|(prefix, suffix)| format!("{}{}{}", prefix, version, suffix))
+    }
+
+    /// Returns versions of the tags matching the tag pattern, newest first and at most
+    /// `max_tags` of them.
+    pub fn tag_versions<'t>(&self, tags: impl IntoIterator<Item = &'t str>) -> Vec<String> {
+        let mut versions: Vec<_> = tags
+            .into_iter()
+            .filter_map(|tag| self.tag_version(tag))
+            .map(str::to_string)
+            .collect();
+        versions.sort_unstable_by(|a, b| compare_versions(b, a).then_with(|| b.cmp(a)));
+        versions.dedup();
+        versions.truncate(self.max_tags());
+        versions
+    }
+}
+
+/// Compares versions like `1.10.0` and `1.9.2` comparing numeric parts as numbers.
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parts = |v: &'_ str| -> Vec<String> {
+        v.split(|c: char| !c.is_ascii_alphanumeric())
+            .map(str::to_string)
+            .collect()
+    };
+    for (a, b) in parts(a).iter().zip(parts(b).iter()) {
+        let ord = match (a.parse::<u64>(), b.parse::<u64>()) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            _ => a.cmp(b),
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    parts(a).len().cmp(&parts(b).len())
 }
@@ -1,4 +1,5 @@
-use crate::recipe::{BuildTarget, Os};
+use crate::proxy::ProxyOverride;
+use crate::recipe::{BuildArch, BuildTarget, Os};
 use crate::{Error, Result};
 
 use serde::{Deserialize, Serialize};
@@ -12,6 +13,29 @@ pub struct ImageTarget {
     #[serde(rename = "target")]
     pub build_target: BuildTarget,
     pub os: Option<Os>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Container network used by builds on this image
+    pub network: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Platform of the image like `linux/amd64`
+    pub platform: Option<String>,
+    #[serde(rename = "image")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Reference of a prebuilt image like `debian:bookworm` that is pulled instead of building
+    /// the image from a Dockerfile
+    pub reference: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Proxy used by builds on this image instead of the one from the environment
+    pub proxy: Option<ProxyOverride>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Architecture of a build that is one of several builds of the image for different
+    /// architectures requested with `--arch`
+    pub arch: Option<BuildArch>,
 }
 
 impl ImageTarget {
@@ -23,6 +47,20 @@ impl ImageTarget {
             image: image.into(),
             build_target,
             os,
+            network: None,
+            platform: None,
+            reference: None,
+            proxy: None,
+            arch: None,
+        }
+    }
+
+    /// Returns the name of the image in the container runtime, images of builds for different
+    /// architectures get the architecture appended like `rocky-aarch64`.
+    pub fn runtime_image(&self) -> String {
+        match &self.arch {
+            Some(arch) => format!("{}-{}", self.image, arch),
+            None => self.image.clone(),
         }
     }
 }
@@ -89,10 +127,63 @@ impl TryFrom<Mapping> for ImageTarget {
                 None
             };
 
+            let network = if let Some(network) = map.get(&YamlValue::from("network")) {
+                if !network.is_string() {
+                    return Err(anyhow!(
+                        "expected a string as image network, found `{:?}`",
+                        network
+                    ));
+                } else {
+                    Some(network.as_str().unwrap().to_string())
+                }
+            } else {
+                None
+            };
+
+            let platform = if let Some(platform) = map.get(&YamlValue::from("platform")) {
+                if !platform.is_string() {
+                    return Err(anyhow!(
+                        "expected a string as image platform, found `{:?}`",
+                        platform
+                    ));
+                } else {
+                    Some(platform.as_str().unwrap().to_string())
+                }
+            } else {
+                None
+            };
+
+            let reference = if let Some(reference) = map.get(&YamlValue::from("image")) {
+                if !reference.is_string() {
+                    return Err(anyhow!(
+                        "expected a string as image reference, found `{:?}`",
+                        reference
+                    ));
+                } else {
+                    Some(reference.as_str().unwrap().to_string())
+                }
+            } else {
+                None
+            };
+
+            let proxy = if let Some(proxy) = map.get(&YamlValue::from("proxy")) {
+                let proxy: ProxyOverride = serde_yaml::from_value(proxy.clone())
+                    .map_err(|e| anyhow!("invalid proxy of image `{}`: {}", image, e))?;
+                proxy.validate()?;
+                Some(proxy)
+            } else {
+                None
+            };
+
             Ok(ImageTarget {
                 image,
                 build_target: target,
                 os,
+                network,
+                platform,
+                reference,
+                proxy,
+                arch: None,
             })
         } else {
             Err(anyhow!("image name not found in `{:?}`", map))
@@ -109,6 +200,11 @@ impl TryFrom<YamlValue> for ImageTarget {
                 image,
                 build_target: BuildTarget::default(),
                 os: None,
+                network: None,
+                platform: None,
+                reference: None,
+                proxy: None,
+                arch: None,
             }),
             value => Err(anyhow!(
                 "expected a map or string for image, found `{:?}`",
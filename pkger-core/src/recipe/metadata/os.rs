@@ -1,3 +1,5 @@
+use crate::recipe::BuildTarget;
+
 use serde::{Deserialize, Serialize};
 use std::convert::AsRef;
 
@@ -147,6 +149,51 @@ impl PackageManager {
         }
     }
 
+    /// Returns the target whose packages this package manager installs.
+    pub fn build_target(&self) -> Option<BuildTarget> {
+        match self {
+            Self::Apt => Some(BuildTarget::Deb),
+            Self::Dnf | Self::Yum => Some(BuildTarget::Rpm),
+            Self::Pacman => Some(BuildTarget::Pkg),
+            Self::Apk => Some(BuildTarget::Apk),
+            Self::Unknown => None,
+        }
+    }
+
+    /// Arguments that install local package files while resolving their dependencies from
+    /// the configured repositories.
+    pub fn install_local_args(&self) -> Vec<&'static str> {
+        match self {
+            Self::Apt => vec!["install", "-y"],
+            Self::Dnf | Self::Yum => vec!["install", "-y"],
+            Self::Pacman => vec!["-U", "--noconfirm"],
+            Self::Apk => vec!["add", "--allow-untrusted"],
+            Self::Unknown => vec![],
+        }
+    }
+
+    /// Returns a command that installs the given package files in a single transaction so that
+    /// dependencies between them are satisfied locally and the rest is fetched from repositories.
+    pub fn install_local_cmd<P: AsRef<str>>(&self, packages: &[P]) -> Option<String> {
+        if matches!(self, Self::Unknown) || packages.is_empty() {
+            return None;
+        }
+
+        let mut cmd = vec![self.as_ref().to_string()];
+        cmd.extend(self.install_local_args().into_iter().map(String::from));
+        // apt only treats an argument as a file if it looks like a path
+        cmd.extend(packages.iter().map(|pkg| {
+            let pkg = pkg.as_ref();
+            if pkg.contains('/') {
+                pkg.to_string()
+            } else {
+                format!("./{}", pkg)
+            }
+        }));
+
+        Some(cmd.join(" "))
+    }
+
     pub fn update_repos_args(&self) -> Vec<&'static str> {
         match self {
             Self::Apt => vec!["update", "-y"],
@@ -185,3 +232,51 @@ impl PackageManager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_local_install_cmd() {
+        assert_eq!(
+            PackageManager::Apt.install_local_cmd(&["pkg.deb"]).unwrap(),
+            "apt-get install -y ./pkg.deb"
+        );
+        assert_eq!(
+            PackageManager::Dnf
+                .install_local_cmd(&["/out/pkg.rpm", "out/dep.rpm"])
+                .unwrap(),
+            "dnf install -y /out/pkg.rpm out/dep.rpm"
+        );
+        assert_eq!(
+            PackageManager::Yum.install_local_cmd(&["pkg.rpm"]).unwrap(),
+            "yum install -y ./pkg.rpm"
+        );
+        assert_eq!(
+            PackageManager::Pacman
+                .install_local_cmd(&["pkg.pkg.tar.zst"])
+                .unwrap(),
+            "pacman -U --noconfirm ./pkg.pkg.tar.zst"
+        );
+        assert_eq!(
+            PackageManager::Apk.install_local_cmd(&["pkg.apk"]).unwrap(),
+            "apk add --allow-untrusted ./pkg.apk"
+        );
+        assert!(PackageManager::Unknown
+            .install_local_cmd(&["pkg.deb"])
+            .is_none());
+        assert!(PackageManager::Apt.install_local_cmd::<&str>(&[]).is_none());
+    }
+
+    #[test]
+    fn installs_packages_of_own_target() {
+        let os = |name: &str, version: &str| Os::new(name, Some(version)).package_manager();
+        assert_eq!(os("debian", "12").build_target(), Some(BuildTarget::Deb));
+        assert_eq!(os("rocky", "9").build_target(), Some(BuildTarget::Rpm));
+        assert_eq!(os("centos", "7").build_target(), Some(BuildTarget::Rpm));
+        assert_eq!(os("arch", "").build_target(), Some(BuildTarget::Pkg));
+        assert_eq!(os("alpine", "3.19").build_target(), Some(BuildTarget::Apk));
+        assert_eq!(os("gentoo", "").build_target(), None);
+    }
+}
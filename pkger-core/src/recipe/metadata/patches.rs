@@ -41,6 +41,19 @@ impl Patch {
     pub fn strip_level(&self) -> u8 {
         self.strip
     }
+
+    /// Returns `true` if the patch should be applied on the `image`.
+    pub fn applies_to(&self, image: &str) -> bool {
+        match &self.images {
+            Some(images) => images.is_empty() || images.iter().any(|i| i == image),
+            None => true,
+        }
+    }
+
+    /// Returns the name of the patch file, without the directories of the path or URL.
+    pub fn file_name(&self) -> &str {
+        self.patch.rsplit('/').next().unwrap_or(self.patch.as_str())
+    }
 }
 
 impl TryFrom<YamlValue> for Patch {
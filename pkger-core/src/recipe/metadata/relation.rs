@@ -0,0 +1,154 @@
+use crate::{err, Result};
+
+/// Comparison operator of a versioned package relation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VersionOp {
+    Lt,
+    Le,
+    Eq,
+    Ge,
+    Gt,
+}
+
+impl VersionOp {
+    /// Parses operators of RPM, PKG and APK like `<` as well as Debian operators like `<<`.
+    fn parse(op: &str) -> Option<Self> {
+        match op {
+            "<" | "<<" => Some(Self::Lt),
+            "<=" => Some(Self::Le),
+            "=" | "==" => Some(Self::Eq),
+            ">=" => Some(Self::Ge),
+            ">" | ">>" => Some(Self::Gt),
+            _ => None,
+        }
+    }
+
+    fn deb(&self) -> &'static str {
+        match self {
+            Self::Lt => "<<",
+            Self::Le => "<=",
+            Self::Eq => "=",
+            Self::Ge => ">=",
+            Self::Gt => ">>",
+        }
+    }
+
+    fn rpm(&self) -> &'static str {
+        match self {
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Eq => "=",
+            Self::Ge => ">=",
+            Self::Gt => ">",
+        }
+    }
+}
+
+/// A relation to another package like `foo` or `foo < 2.0` declared once in a recipe and
+/// rendered in the syntax of every package format.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Relation {
+    pub name: String,
+    pub constraint: Option<(VersionOp, String)>,
+}
+
+impl Relation {
+    /// Parses entries like `foo`, `foo < 2.0`, `foo<2.0` or the Debian form `foo (<< 2.0)`.
+    pub fn parse(entry: &str) -> Result<Self> {
+        let entry = entry.trim();
+        let name_end = entry
+            .find(|c: char| c.is_whitespace() || matches!(c, '(' | '<' | '=' | '>'))
+            .unwrap_or(entry.len());
+        let (name, rest) = entry.split_at(name_end);
+        if name.is_empty() {
+            return err!("relation `{}` is missing a package name", entry);
+        }
+
+        let mut rest = rest.trim();
+        if let Some(inner) = rest.strip_prefix('(') {
+            match inner.strip_suffix(')') {
+                Some(inner) => rest = inner.trim(),
+                None => return err!("relation `{}` is missing a closing `)`", entry),
+            }
+        }
+        if rest.is_empty() {
+            return Ok(Self {
+                name: name.to_string(),
+                constraint: None,
+            });
+        }
+
+        let op_end = rest
+            .find(|c: char| !matches!(c, '<' | '=' | '>'))
+            .unwrap_or(rest.len());
+        let (op, version) = rest.split_at(op_end);
+        let version = version.trim();
+        match VersionOp::parse(op) {
+            Some(op) if !version.is_empty() && !version.contains(char::is_whitespace) => Ok(Self {
+                name: name.to_string(),
+                constraint: Some((op, version.to_string())),
+            }),
+            _ => err!(
+                "invalid version constraint in relation `{}`, expected an entry like `foo < 2.0`",
+                entry
+            ),
+        }
+    }
+
+    /// Renders the relation like `foo (<< 2.0)` as used in DEB control files.
+    pub fn deb(&self) -> String {
+        match &self.constraint {
+            Some((op, version)) => format!("{} ({} {})", self.name, op.deb(), version),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Renders the relation like `foo < 2.0` as used in RPM specs.
+    pub fn rpm(&self) -> String {
+        match &self.constraint {
+            Some((op, version)) => format!("{} {} {}", self.name, op.rpm(), version),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Renders the relation like `foo<2.0` as used in PKGBUILD and APKBUILD arrays.
+    pub fn pkg(&self) -> String {
+        match &self.constraint {
+            Some((op, version)) => format!("{}{}{}", self.name, op.rpm(), version),
+            None => self.name.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_relations_per_format() {
+        let relation = Relation::parse("foo < 2.0").unwrap();
+        assert_eq!(relation.deb(), "foo (<< 2.0)");
+        assert_eq!(relation.rpm(), "foo < 2.0");
+        assert_eq!(relation.pkg(), "foo<2.0");
+
+        let relation = Relation::parse("foo (>> 1:2.0-1)").unwrap();
+        assert_eq!(relation.deb(), "foo (>> 1:2.0-1)");
+        assert_eq!(relation.rpm(), "foo > 1:2.0-1");
+        assert_eq!(relation.pkg(), "foo>1:2.0-1");
+
+        let relation = Relation::parse("libbar<=1.1").unwrap();
+        assert_eq!(relation.deb(), "libbar (<= 1.1)");
+        assert_eq!(relation.rpm(), "libbar <= 1.1");
+
+        let relation = Relation::parse("baz").unwrap();
+        assert_eq!(relation.constraint, None);
+        assert_eq!(relation.deb(), "baz");
+        assert_eq!(relation.rpm(), "baz");
+        assert_eq!(relation.pkg(), "baz");
+
+        assert!(Relation::parse("foo ~ 2.0").is_err());
+        assert!(Relation::parse("foo (< 2.0").is_err());
+        assert!(Relation::parse("foo <").is_err());
+        assert!(Relation::parse("< 2.0").is_err());
+    }
+}
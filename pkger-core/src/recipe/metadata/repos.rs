@@ -0,0 +1,150 @@
+use crate::image::Image;
+use crate::recipe::BuildTarget;
+use crate::Result;
+
+use serde_yaml::{Mapping, Sequence, Value as YamlValue};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+pub static COMMON_REPOS_KEY: &str = "all";
+
+type ReposMap = HashMap<String, Vec<String>>;
+
+/// Commands enabling additional package repositories before build dependencies are installed,
+/// like `dnf config-manager --add-repo ...` or `add-apt-repository ...`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BuildRepos {
+    inner: ReposMap,
+}
+
+fn commands(value: &YamlValue) -> Result<Vec<String>> {
+    let commands = value
+        .as_sequence()
+        .ok_or_else(|| anyhow!("expected array of repository commands, found `{:?}`", value))?;
+    commands
+        .iter()
+        .map(|cmd| {
+            cmd.as_str().map(str::to_string).ok_or_else(|| {
+                anyhow!("expected a string as repository command, found `{:?}`", cmd)
+            })
+        })
+        .collect()
+}
+
+impl TryFrom<Mapping> for BuildRepos {
+    type Error = crate::Error;
+
+    fn try_from(table: Mapping) -> Result<Self> {
+        let mut repos = Self::default();
+        for (image, image_repos) in table {
+            let image_repos = commands(&image_repos)?;
+            let image = image
+                .as_str()
+                .ok_or_else(|| anyhow!("expected image name, found `{:?}`", image))?;
+            for image in image.split('+') {
+                repos
+                    .inner
+                    .entry(image.to_string())
+                    .or_default()
+                    .extend(image_repos.iter().cloned());
+            }
+        }
+        Ok(repos)
+    }
+}
+
+impl TryFrom<Sequence> for BuildRepos {
+    type Error = crate::Error;
+
+    fn try_from(array: Sequence) -> Result<Self> {
+        let mut repos = Self::default();
+        repos.inner.insert(
+            COMMON_REPOS_KEY.to_string(),
+            commands(&YamlValue::Sequence(array))?,
+        );
+        Ok(repos)
+    }
+}
+
+impl TryFrom<YamlValue> for BuildRepos {
+    type Error = crate::Error;
+
+    fn try_from(repos: YamlValue) -> Result<Self> {
+        match repos {
+            YamlValue::Mapping(table) => Self::try_from(table),
+            YamlValue::Sequence(array) => Self::try_from(array),
+            _ => Err(anyhow!(
+                "expected a map or array of repository commands, found `{:?}`",
+                repos
+            )),
+        }
+    }
+}
+
+impl BuildRepos {
+    /// Returns the commands to run on the `image` with the `target` in order. Common commands
+    /// come first, followed by the ones of the simple image of the target like `pkger-rpm` and
+    /// the ones declared for the `image`.
+    pub fn resolve_names(&self, image: &str, target: BuildTarget) -> Vec<&str> {
+        let simple = Image::simple(target).name;
+        let mut keys = vec![COMMON_REPOS_KEY, simple];
+        if image != COMMON_REPOS_KEY && image != simple {
+            keys.push(image);
+        }
+
+        keys.into_iter()
+            .filter_map(|key| self.inner.get(key))
+            .flat_map(|cmds| cmds.iter().map(String::as_str))
+            .collect()
+    }
+
+    pub fn inner(&self) -> &ReposMap {
+        &self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_build_repos_per_image() {
+        let input: YamlValue = serde_yaml::from_str(
+            r#"
+all: ["echo common"]
+pkger-rpm: ["dnf install -y dnf-plugins-core"]
+rocky+centos: ["dnf config-manager --add-repo https://example.com/el.repo"]
+debian: ["add-apt-repository -y ppa:example/ppa"]
+"#,
+        )
+        .unwrap();
+        let repos = BuildRepos::try_from(input).unwrap();
+
+        assert_eq!(
+            repos.resolve_names("rocky", BuildTarget::Rpm),
+            vec![
+                "echo common",
+                "dnf install -y dnf-plugins-core",
+                "dnf config-manager --add-repo https://example.com/el.repo"
+            ]
+        );
+        assert_eq!(
+            repos.resolve_names("debian", BuildTarget::Deb),
+            vec!["echo common", "add-apt-repository -y ppa:example/ppa"]
+        );
+        assert_eq!(
+            repos.resolve_names("pkger-rpm", BuildTarget::Rpm),
+            vec!["echo common", "dnf install -y dnf-plugins-core"]
+        );
+
+        let input: YamlValue = serde_yaml::from_str(r#"["echo common"]"#).unwrap();
+        let repos = BuildRepos::try_from(input).unwrap();
+        assert_eq!(
+            repos.resolve_names("alpine", BuildTarget::Apk),
+            vec!["echo common"]
+        );
+
+        let input: YamlValue = serde_yaml::from_str("rocky: [1]").unwrap();
+        assert!(BuildRepos::try_from(input).is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use crate::{Error, Result};
+
+use serde_yaml::{Mapping, Value as YamlValue};
+use std::convert::TryFrom;
+
+/// Number of hexadecimal characters of a SHA-256 digest.
+const SHA256_HEX_LEN: usize = 64;
+
+/// Source of a recipe, either a path or an http url of a file or an archive. Http sources may
+/// have mirrors that are tried in order when fetching from the url fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Source {
+    url: String,
+    mirrors: Vec<String>,
+    /// Expected SHA-256 digest of the fetched file.
+    sha256: Option<String>,
+}
+
+impl From<&str> for Source {
+    fn from(s: &str) -> Self {
+        Self {
+            url: s.to_string(),
+            mirrors: vec![],
+            sha256: None,
+        }
+    }
+}
+
+impl TryFrom<Mapping> for Source {
+    type Error = Error;
+    fn try_from(table: Mapping) -> Result<Self> {
+        let url = match table.get(&YamlValue::from("url")) {
+            Some(YamlValue::String(url)) => url.to_string(),
+            Some(url) => return Err(anyhow!("expected a string as url, found `{:?}`", url)),
+            None => {
+                return Err(anyhow!(
+                    "expected a url entry in a table, found `{:?}`",
+                    table
+                ))
+            }
+        };
+
+        let mirrors = match table.get(&YamlValue::from("mirrors")) {
+            Some(YamlValue::Sequence(mirrors)) => mirrors
+                .iter()
+                .map(|mirror| match mirror {
+                    YamlValue::String(mirror) => Ok(mirror.to_string()),
+                    mirror => Err(anyhow!("expected a string as mirror, found `{:?}`", mirror)),
+                })
+                .collect::<Result<Vec<_>>>()?,
+            Some(YamlValue::String(mirror)) => vec![mirror.to_string()],
+            Some(mirrors) => {
+                return Err(anyhow!(
+                    "expected a string or an array as mirrors, found `{:?}`",
+                    mirrors
+                ))
+            }
+            None => vec![],
+        };
+        if !mirrors.is_empty() && !is_http(&url) {
+            return Err(anyhow!(
+                "source `{}` has mirrors but only http sources can have them",
+                url
+            ));
+        }
+        if let Some(mirror) = mirrors.iter().find(|mirror| !is_http(mirror)) {
+            return Err(anyhow!(
+                "mirror `{}` of source `{}` is not an http url",
+                mirror,
+                url
+            ));
+        }
+
+        let sha256 = match table.get(&YamlValue::from("sha256")) {
+            Some(YamlValue::String(sha256)) => {
+                let sha256 = sha256.trim().to_lowercase();
+                if sha256.len() != SHA256_HEX_LEN || !sha256.chars().all(|c| c.is_ascii_hexdigit())
+                {
+                    return Err(anyhow!(
+                        "sha256 of source `{}` must be {} hexadecimal characters, found `{}`",
+                        url,
+                        SHA256_HEX_LEN,
+                        sha256
+                    ));
+                }
+                Some(sha256)
+            }
+            Some(sha256) => {
+                return Err(anyhow!("expected a string as sha256, found `{:?}`", sha256));
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            url,
+            mirrors,
+            sha256,
+        })
+    }
+}
+
+impl TryFrom<YamlValue> for Source {
+    type Error = Error;
+    fn try_from(value: YamlValue) -> Result<Self> {
+        match value {
+            YamlValue::Mapping(table) => Self::try_from(table),
+            YamlValue::String(s) => Ok(Self::from(s.as_str())),
+            value => Err(anyhow!(
+                "expected a table or a string as source, found `{:?}`",
+                value
+            )),
+        }
+    }
+}
+
+impl Source {
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn mirrors(&self) -> &[String] {
+        &self.mirrors
+    }
+
+    pub fn sha256(&self) -> Option<&str> {
+        self.sha256.as_deref()
+    }
+
+    /// Returns the url followed by the mirrors in the order they are tried.
+    pub fn urls(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.url.as_str()).chain(self.mirrors.iter().map(String::as_str))
+    }
+
+    pub fn is_http(&self) -> bool {
+        is_http(&self.url)
+    }
+}
+
+fn is_http(url: &str) -> bool {
+    url.starts_with("http")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_source_with_mirrors() {
+        let source = Source::try_from(YamlValue::from("pkger-0.11.0.tar.gz")).unwrap();
+        assert_eq!(
+            source.urls().collect::<Vec<_>>(),
+            vec!["pkger-0.11.0.tar.gz"]
+        );
+        assert!(!source.is_http());
+
+        let source: YamlValue = serde_yaml::from_str(
+            r#"
+url: https://example.com/pkger-0.11.0.tar.gz
+mirrors:
+  - https://mirror1.example.com/pkger-0.11.0.tar.gz
+  - https://mirror2.example.com/pkger-0.11.0.tar.gz
+sha256: " 9F86D081884C7D659A2FEAA0C55AD015A3BF4F1B2B0B822CD15D6C15B0F00A08 ""#,
+        )
+        .unwrap();
+        let source = Source::try_from(source).unwrap();
+        assert_eq!(
+            source.urls().collect::<Vec<_>>(),
+            vec![
+                "https://example.com/pkger-0.11.0.tar.gz",
+                "https://mirror1.example.com/pkger-0.11.0.tar.gz",
+                "https://mirror2.example.com/pkger-0.11.0.tar.gz",
+            ]
+        );
+        assert_eq!(
+            source.sha256(),
+            Some("9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08")
+        );
+
+        for digest in [
+            "abc123",
+            "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15' && reboot '",
+        ] {
+            let source: YamlValue = serde_yaml::from_str(&format!(
+                "url: https://example.com/pkger.tar.gz\nsha256: \"{}\"",
+                digest
+            ))
+            .unwrap();
+            assert!(Source::try_from(source).is_err());
+        }
+
+        let local: YamlValue = serde_yaml::from_str(
+            "url: pkger.tar.gz\nmirrors: [ https://example.com/pkger.tar.gz ]",
+        )
+        .unwrap();
+        assert!(Source::try_from(local).is_err());
+    }
+}
@@ -1,20 +1,23 @@
+mod changelog;
 mod cmd;
 mod envs;
 mod loader;
 mod metadata;
 mod target;
 
+pub use changelog::{Changelog, ChangelogEntry};
 pub use cmd::Command;
 pub use envs::Env;
-pub use loader::Loader;
+pub use loader::{InheritanceProblem, Loader};
 pub use metadata::{
-    deserialize_images, BuildArch, BuildTarget, BuildTargetInfo, DebInfo, DebRep, Dependencies,
-    Distro, GitSource, ImageTarget, Metadata, MetadataRep, Os, PackageManager, Patch, Patches,
-    PkgInfo, PkgRep, RpmInfo, RpmRep,
+    deserialize_images, BuildArch, BuildRepos, BuildTarget, BuildTargetInfo, Compression, DebInfo,
+    DebRep, Dependencies, Distro, GitSource, ImageTarget, Metadata, MetadataRep, Os,
+    PackageManager, Patch, Patches, PkgInfo, PkgRep, Relation, RpmInfo, RpmRep, Source, VersionOp,
 };
 pub use target::RecipeTarget;
 
 use crate::build::deps;
+use crate::checksum::Checksum;
 use crate::log::{warning, BoxedCollector};
 use crate::{err, ErrContext, Error, Result};
 
@@ -25,25 +28,88 @@ use pkgbuild::PkgBuild;
 use rpmspec::RpmSpec;
 use serde::{Deserialize, Serialize};
 use serde_yaml::Mapping;
+use std::collections::{BTreeMap, HashSet};
 use std::convert::TryFrom;
 use std::fmt::Write;
 use std::fs::{self, DirEntry};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 const DEFAULT_RECIPE_FILE: &str = "recipe.yml";
 
+/// Characters that are interpreted by the shell inside of double quotes, PKGBUILD and APKBUILD
+/// files are shell scripts.
+const SHELL_SPECIAL: &[char] = &['$', '`', '"', '\\'];
+
+/// Prefix of paths of files referenced by recipes that are relative to the recipes directory
+/// instead of the directory of the recipe, like `//common/license.patch`.
+pub const RECIPES_DIR_PREFIX: &str = "//";
+
+/// Resolves the `path` of a file referenced by a recipe. Paths starting with
+/// [`RECIPES_DIR_PREFIX`] are relative to `recipes_dir` and can't point outside of it, not even
+/// through symlinks, other relative paths are relative to `recipe_dir` and absolute paths are used
+/// as they are.
+pub fn resolve_path(path: &str, recipe_dir: &Path, recipes_dir: &Path) -> Result<PathBuf> {
+    let shared = match path.strip_prefix(RECIPES_DIR_PREFIX) {
+        Some(shared) => shared,
+        None => return Ok(recipe_dir.join(path)),
+    };
+
+    let mut resolved = PathBuf::new();
+    for component in Path::new(shared).components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::ParentDir if resolved.pop() => {}
+            Component::ParentDir | Component::Prefix(_) => {
+                return err!("path `{}` points outside of the recipes directory", path);
+            }
+            Component::RootDir | Component::CurDir => {}
+        }
+    }
+    let resolved = recipes_dir.join(resolved);
+
+    // a symlink in the recipes directory can still point outside of it
+    if let (Ok(canonical), Ok(root)) = (resolved.canonicalize(), recipes_dir.canonicalize()) {
+        if !canonical.starts_with(root) {
+            return err!("path `{}` points outside of the recipes directory", path);
+        }
+    }
+    Ok(resolved)
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Recipe {
     pub metadata: Metadata,
     pub env: Env,
+    pub post_fetch_script: Option<PostFetchScript>,
     pub configure_script: Option<ConfigureScript>,
     pub build_script: BuildScript,
     pub install_script: Option<InstallScript>,
+    pub check_script: Option<CheckScript>,
+    pub changelog: Option<Changelog>,
     pub recipe_dir: PathBuf,
+    /// Directory of all recipes that paths starting with [`RECIPES_DIR_PREFIX`] are relative to.
+    pub recipes_dir: PathBuf,
+    /// Warnings about unknown fields of the recipe found when it was loaded.
+    pub field_warnings: Vec<String>,
 }
 
 impl Recipe {
-    pub fn new(mut rep: RecipeRep, recipe_dir: PathBuf) -> Result<Self> {
+    /// Creates a recipe whose recipes directory is the parent directory of `recipe_dir`, it's
+    /// also where the base recipe referenced by `from` is looked up.
+    pub fn new(rep: RecipeRep, recipe_dir: PathBuf) -> Result<Self> {
+        let recipes_dir = recipe_dir.parent().unwrap_or(&recipe_dir).to_path_buf();
+        let base_root = recipe_dir.parent().map(Path::to_path_buf);
+        Self::with_base_root(rep, recipe_dir, recipes_dir, base_root.as_deref())
+    }
+
+    /// Creates a recipe in `recipes_dir` looking up the base recipe referenced by `from` in
+    /// `base_root`.
+    pub fn with_base_root(
+        mut rep: RecipeRep,
+        recipe_dir: PathBuf,
+        recipes_dir: PathBuf,
+        base_root: Option<&Path>,
+    ) -> Result<Self> {
         let is_inherited = match (&rep.metadata, &rep.build, &rep.from) {
             (Some(_), None, None)
             | (None, Some(_), None)
@@ -70,23 +136,40 @@ impl Recipe {
         }
 
         if is_inherited {
-            if let Some(dir) = recipe_dir.parent() {
+            if let Some(dir) = base_root {
                 let loader = Loader::new(dir)?;
-                let base_rep = loader
-                    .load_rep(rep.from.as_ref().unwrap())
-                    .context("failed to load base recipe")?;
-                rep = rep.merge(base_rep).context("failed to merge recipes")?;
+                let name = recipe_dir
+                    .file_name()
+                    .filter(|_| recipe_dir.parent() == Some(dir))
+                    .map(|name| name.to_string_lossy().to_string());
+                rep = rep.merge_bases(&loader, name)?;
             } else {
                 return err!("failed to determine recipes directory");
             }
         }
 
+        let resolve =
+            |file: &Path| resolve_path(&file.to_string_lossy(), &recipe_dir, &recipes_dir);
+        let field_warnings = rep.field_warnings();
+
         Ok(Self {
             metadata: Metadata::try_from(
                 rep.metadata
                     .ok_or_else(|| Error::msg("invalid recipe, `metadata` section required"))?,
             )?,
-            env: Env::from(rep.env),
+            env: {
+                let mut env = match &rep.env_file {
+                    Some(file) => Env::from_file(&resolve(file)?)?,
+                    None => Env::new(),
+                };
+                env.extend(Env::from(rep.env));
+                env
+            },
+            post_fetch_script: if let Some(script) = rep.post_fetch {
+                Some(PostFetchScript::try_from(script)?)
+            } else {
+                None
+            },
             configure_script: if let Some(script) = rep.configure {
                 Some(ConfigureScript::try_from(script)?)
             } else {
@@ -101,17 +184,634 @@ impl Recipe {
             } else {
                 None
             },
+            check_script: if let Some(script) = rep.check {
+                Some(CheckScript::try_from(script)?)
+            } else {
+                None
+            },
+            changelog: match &rep.changelog_file {
+                Some(file) => Some(Changelog::from_file(&resolve(file)?)?),
+                None => None,
+            },
             recipe_dir,
+            recipes_dir,
+            field_warnings,
         })
     }
 
+    /// Resolves the `path` of a file referenced by this recipe, see [`resolve_path`].
+    pub fn resolve_path(&self, path: &str) -> Result<PathBuf> {
+        resolve_path(path, &self.recipe_dir, &self.recipes_dir)
+    }
+
     #[inline]
     pub fn images(&self) -> &[String] {
         &self.metadata.images
     }
+
+    /// Returns warnings about parts of this recipe that are valid but most likely not what the
+    /// author intended. Only covers the recipe itself, runtime warnings are not included.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = self.field_warnings.clone();
+
+        if !is_spdx_expression(&self.metadata.license) {
+            warnings.push(format!(
+                "license `{}` is not a valid SPDX expression",
+                self.metadata.license
+            ));
+        }
+
+        if let Some(url) = &self.metadata.url {
+            if !metadata::has_url_scheme(url) {
+                warnings.push(format!(
+                    "url `{}` has no scheme, `https://{}` will be used instead",
+                    url, url
+                ));
+            }
+            let is_valid = self
+                .metadata
+                .url()
+                .and_then(|url| url.parse::<http::Uri>().ok())
+                .map(|uri| uri.host().map(|h| !h.is_empty()).unwrap_or(false))
+                .unwrap_or(false);
+            if !is_valid {
+                warnings.push(format!("url `{}` is not a valid URL", url));
+            }
+        }
+
+        if let Some(breaks) = &self.metadata.breaks {
+            let mut entries = breaks.inner().values().flatten().collect::<Vec<_>>();
+            entries.sort();
+            entries.dedup();
+            for entry in entries {
+                if let Err(e) = Relation::parse(entry) {
+                    warnings.push(format!("breaks: {}, it will be skipped", e));
+                }
+            }
+        }
+
+        if let Some(summary) = self
+            .metadata
+            .rpm
+            .as_ref()
+            .and_then(|rpm| rpm.summary.as_ref())
+        {
+            if summary.chars().count() > MAX_SUMMARY_LEN {
+                warnings.push(format!(
+                    "RPM summary is longer than {} characters",
+                    MAX_SUMMARY_LEN
+                ));
+            }
+        }
+
+        if let Some(warning) = self.changelog.as_ref().and_then(Changelog::warning) {
+            warnings.push(warning);
+        }
+
+        warnings
+    }
+
+    /// Returns all problems of this recipe that should fail a strict validation. Includes all of
+    /// the warnings as well as scripts without steps or with empty commands.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = self.warnings();
+
+        if self.build_script.all_steps().is_empty() {
+            problems.push("build script has no steps".to_string());
+        }
+
+        let scripts = [
+            (
+                "post_fetch",
+                self.post_fetch_script.as_ref().map(|s| s.all_steps()),
+            ),
+            (
+                "configure",
+                self.configure_script.as_ref().map(|s| s.all_steps()),
+            ),
+            ("build", Some(self.build_script.all_steps())),
+            (
+                "install",
+                self.install_script.as_ref().map(|s| s.all_steps()),
+            ),
+            ("check", self.check_script.as_ref().map(|s| s.all_steps())),
+        ];
+        for (script, steps) in scripts {
+            for (i, step) in steps.into_iter().flatten().enumerate() {
+                if step.cmd.trim().is_empty() {
+                    problems.push(format!("{} step {} has an empty command", script, i + 1));
+                }
+            }
+        }
+
+        problems
+    }
+
+    /// Returns warnings that only apply to packages built for the `target`, like a maintainer
+    /// that is not in the `Name <email>` format required by Debian.
+    pub fn target_warnings(&self, target: BuildTarget) -> Vec<String> {
+        let mut warnings = vec![];
+
+        if target == BuildTarget::Deb {
+            if self.metadata.name.contains('_') {
+                warnings.push(
+                    "Debian package names can't contain `_`, it will be converted to `-`"
+                        .to_string(),
+                );
+            }
+            if let Some(maintainer) = &self.metadata.maintainer {
+                if !is_valid_maintainer(maintainer) {
+                    warnings.push(format!(
+                        "maintainer `{}` is not in the `Name <email>` format required by Debian",
+                        maintainer
+                    ));
+                }
+            }
+        }
+
+        warnings
+    }
+
+    /// Returns warnings about the `version` if it's illegal for packages of the `target`, like a
+    /// version with a `-` that can't be used as `pkgver` of a PKGBUILD. Versions that look like
+    /// they include an epoch or a release get a hint to use the dedicated fields.
+    pub fn version_warnings(&self, target: BuildTarget, version: &str) -> Vec<String> {
+        let hint = if is_epoch_prefixed(version) {
+            ", set the epoch with `epoch` instead"
+        } else if version.contains('-') {
+            ", set the release with `release` instead"
+        } else {
+            ""
+        };
+        version_problems(target, version)
+            .into_iter()
+            .map(|problem| format!("{}{}", problem, hint))
+            .collect()
+    }
+
+    /// Returns problems that would make the manifest of the `target` like the RPM spec or the
+    /// APKBUILD invalid when building the `version`, for example a version with characters the
+    /// package format doesn't allow or a field that would be expanded by the shell.
+    pub fn manifest_problems(&self, target: BuildTarget, version: &str) -> Vec<String> {
+        let mut problems = vec![];
+        let release = self.metadata.release();
+        let name = target.as_ref().to_uppercase();
+
+        match target {
+            BuildTarget::Rpm => {
+                problems.extend(self.version_warnings(target, version));
+                if release.contains('-') {
+                    problems.push(format!("RPM release `{}` can't contain `-`", release));
+                }
+            }
+            BuildTarget::Deb => {
+                problems.extend(self.version_warnings(target, version));
+                if self.metadata.name.chars().any(|c| c.is_ascii_uppercase()) {
+                    problems.push(format!(
+                        "DEB package name `{}` must be lowercase",
+                        self.metadata.name
+                    ));
+                }
+                if self.metadata.maintainer.is_none() {
+                    problems.push("DEB packages require a `maintainer`".to_string());
+                }
+            }
+            BuildTarget::Pkg | BuildTarget::Apk => {
+                problems.extend(self.version_warnings(target, version));
+                let valid_release = if target == BuildTarget::Apk {
+                    release.parse::<u32>().is_ok()
+                } else {
+                    release.parse::<f32>().is_ok()
+                };
+                if !valid_release {
+                    problems.push(format!("{} release `{}` must be a number", name, release));
+                }
+
+                let fields = [
+                    ("description", Some(&self.metadata.description)),
+                    ("license", Some(&self.metadata.license)),
+                    ("url", self.metadata.url.as_ref()),
+                    ("maintainer", self.metadata.maintainer.as_ref()),
+                ];
+                for (field, value) in fields {
+                    if let Some(c) =
+                        value.and_then(|v| v.chars().find(|c| SHELL_SPECIAL.contains(c)))
+                    {
+                        problems.push(format!(
+                            "`{}` contains `{}` that would be interpreted by the shell in the {} manifest",
+                            field, c, name
+                        ));
+                    }
+                }
+            }
+            BuildTarget::Gzip => {}
+        }
+
+        problems
+    }
+
+    /// Returns an error containing all warnings of this recipe if there are any.
+    pub fn deny_warnings(&self) -> Result<()> {
+        let warnings = self.warnings();
+        if warnings.is_empty() {
+            return Ok(());
+        }
+
+        err!(
+            "recipe `{}` has warnings:\n  {}",
+            self.metadata.name,
+            warnings.join("\n  ")
+        )
+    }
+
+    /// Adds versions derived from `tags` of the git repository matching the tag pattern of the
+    /// git source to the declared versions. Returns the added versions.
+    pub fn expand_git_tags<'t>(&mut self, tags: impl IntoIterator<Item = &'t str>) -> Vec<String> {
+        let versions = match &self.metadata.git {
+            Some(git) if git.tags().is_some() => git.tag_versions(tags),
+            _ => return vec![],
+        };
+        self.metadata.version.extend(versions.clone());
+        versions
+    }
+}
+
+const MAX_SUMMARY_LEN: usize = 80;
+
+/// Returns the reasons why the `version` is illegal for packages of the `target`.
+fn version_problems(target: BuildTarget, version: &str) -> Vec<String> {
+    let mut problems = vec![];
+    let name = target.as_ref().to_uppercase();
+    let starts_with_digit = version.starts_with(|c: char| c.is_ascii_digit());
+
+    match target {
+        BuildTarget::Rpm => {
+            if version.contains('-') {
+                problems.push(format!("RPM version `{}` can't contain `-`", version));
+            }
+        }
+        BuildTarget::Deb => {
+            if !starts_with_digit {
+                problems.push(format!("DEB version `{}` must start with a digit", version));
+            }
+        }
+        BuildTarget::Pkg | BuildTarget::Apk => {
+            let invalid = |c: char| c.is_whitespace() || matches!(c, '-' | ':' | '/');
+            if version.contains(invalid) {
+                problems.push(format!(
+                    "{} version `{}` can't contain whitespace, `-`, `:` or `/`",
+                    name, version
+                ));
+            }
+            if target == BuildTarget::Apk && !starts_with_digit {
+                problems.push(format!("APK version `{}` must start with a digit", version));
+            }
+        }
+        BuildTarget::Gzip => {}
+    }
+
+    problems
+}
+
+/// Checks whether the `version` starts with an epoch like `1:2.0.0`.
+fn is_epoch_prefixed(version: &str) -> bool {
+    version
+        .split_once(':')
+        .map(|(epoch, _)| !epoch.is_empty() && epoch.chars().all(|c| c.is_ascii_digit()))
+        .unwrap_or(false)
+}
+
+/// Checks whether `maintainer` is in the `Name <user@domain>` format.
+fn is_valid_maintainer(maintainer: &str) -> bool {
+    let (name, email) = match maintainer
+        .trim()
+        .strip_suffix('>')
+        .and_then(|m| m.split_once('<'))
+    {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let is_email_part = |part: &str| {
+        !part.is_empty()
+            && !part
+                .chars()
+                .any(|c| c.is_whitespace() || matches!(c, '<' | '>' | '@'))
+    };
+    match email.split_once('@') {
+        Some((user, domain)) => {
+            !name.trim().is_empty()
+                && name.ends_with(' ')
+                && is_email_part(user)
+                && is_email_part(domain)
+        }
+        None => false,
+    }
+}
+
+/// Checks whether `license` looks like an SPDX license expression, for example
+/// `MIT`, `GPL-2.0-or-later` or `(MIT OR Apache-2.0) AND BSD-3-Clause`.
+fn is_spdx_expression(license: &str) -> bool {
+    let mut has_id = false;
+    for token in license
+        .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        .filter(|t| !t.is_empty())
+    {
+        match token {
+            "AND" | "OR" | "WITH" => {}
+            id => {
+                if !id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+' | ':'))
+                {
+                    return false;
+                }
+                has_id = true;
+            }
+        }
+    }
+    has_id
+}
+
+/// Replaces `_` with `-` in the package names of a Debian relationship entry like
+/// `foo_bar (>= 1.0) | baz`. Version constraints and architecture qualifiers are left intact.
+fn sanitize_deb_relation(entry: &str) -> String {
+    entry
+        .split('|')
+        .map(|alternative| {
+            let start = alternative.len() - alternative.trim_start().len();
+            let end = alternative[start..]
+                .find(|c: char| c == ':' || c == '(' || c == '[' || c == '<' || c.is_whitespace())
+                .map(|end| start + end)
+                .unwrap_or(alternative.len());
+            format!(
+                "{}{}{}",
+                &alternative[..start],
+                alternative[start..end].replace('_', "-"),
+                &alternative[end..]
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+/// Entry of an explicit files list that adds the auto-discovered files not covered by the
+/// other entries.
+pub const AUTO_FILES: &str = "auto";
+
+/// Splits the files of a package into the `explicit` entries that are used verbatim and the
+/// `discovered` files to add to them. Without an explicit list all discovered files are used,
+/// with one only the discovered files not covered by it and only if it contains [`AUTO_FILES`].
+fn package_files(explicit: Option<&[String]>, discovered: &[String]) -> (Vec<String>, Vec<String>) {
+    let explicit = match explicit {
+        Some(explicit) => explicit,
+        None => return (vec![], discovered.to_vec()),
+    };
+    let (auto, entries): (Vec<_>, Vec<_>) = explicit
+        .iter()
+        .cloned()
+        .partition(|entry| entry.trim() == AUTO_FILES);
+
+    let discovered = if auto.is_empty() {
+        vec![]
+    } else {
+        discovered
+            .iter()
+            .filter(|file| !entries.iter().any(|entry| covers(entry, file)))
+            .cloned()
+            .collect()
+    };
+    (entries, discovered)
+}
+
+/// Returns the path of the files list `entry` without RPM directives like `%config(noreplace)`
+/// or `%attr(0755, root, root)` preceding it.
+pub fn files_entry_path(entry: &str) -> &str {
+    entry.split_whitespace().last().unwrap_or_default()
+}
+
+/// Whether the files list `entry`, like `/usr/bin/foo`, `%config(noreplace) /etc/foo` or a
+/// directory `/usr/share/foo`, includes the `file`. `%dir` entries only include the directory.
+fn covers(entry: &str, file: &str) -> bool {
+    let path = files_entry_path(entry).trim_end_matches('/');
+    if path.is_empty() {
+        return false;
+    }
+    file == path
+        || (!entry.trim_start().starts_with("%dir")
+            && file
+                .strip_prefix(path)
+                .map(|rest| rest.starts_with('/'))
+                .unwrap_or_default())
+}
+
+/// Renders the `%prep` section of an RPM spec that unpacks the first source, an archive without
+/// a top level directory, with `%autosetup` and applies the `patches` in order. `%autosetup`
+/// takes a single strip level so patches with different levels are applied one by one.
+fn rpm_prep(patches: &[&Patch]) -> String {
+    let level = patches.first().map(|patch| patch.strip_level());
+    match level {
+        None => "%autosetup -c".to_string(),
+        Some(level) if patches.iter().all(|patch| patch.strip_level() == level) => {
+            format!("%autosetup -c -p{}", level)
+        }
+        Some(_) => patches.iter().enumerate().fold(
+            "%autosetup -c -N".to_string(),
+            |mut prep, (i, patch)| {
+                let _ = write!(
+                    prep,
+                    "\n%autopatch -p{} -m {} -M {}",
+                    patch.strip_level(),
+                    i,
+                    i
+                );
+                prep
+            },
+        ),
+    }
+}
+
+/// Renders the `steps` of a script that apply to the `image`, `build_target` and `version` as
+/// the body of a phase in a package manifest, every line indented by `indent`. The steps run
+/// in `working_dir` or in the build directory if it's not set.
+fn manifest_steps(
+    steps: Vec<&Command>,
+    working_dir: Option<&Path>,
+    shell: Option<&str>,
+    image: &str,
+    build_target: BuildTarget,
+    version: &str,
+    indent: &str,
+) -> Option<String> {
+    let steps = steps
+        .into_iter()
+        .filter(|cmd| cmd.applies_to(image, &build_target, version))
+        .collect::<Vec<_>>();
+    if steps.is_empty() {
+        return None;
+    }
+
+    let dir = working_dir
+        .map(|dir| dir.to_string_lossy().to_string())
+        .unwrap_or_else(|| "${PKGER_BLD_DIR}".to_string());
+    let mut lines = vec![format!("{}cd \"{}\"", indent, dir)];
+    lines.extend(steps.into_iter().map(|step| {
+        let cmd = match shell {
+            Some(shell) => format!("{} -c '{}'", shell, step.cmd.replace('\'', r"'\''")),
+            None => step.cmd.clone(),
+        };
+        match &step.working_dir {
+            // relative paths resolve against the directory of the script like in the container
+            Some(dir) => format!("{}(cd \"{}\" && {})", indent, dir.display(), cmd),
+            None => format!("{}{}", indent, cmd),
+        }
+    }));
+    Some(lines.join("\n"))
 }
 
 impl Recipe {
+    /// Returns problems of the `check` steps that are rendered into the manifest of the
+    /// `target` when building the `version` on `image`. They run as plain commands of the
+    /// package build, so fields like `user` or `expect_exit_code` would be silently ignored.
+    pub fn manifest_step_problems(
+        &self,
+        image: &str,
+        target: BuildTarget,
+        version: &str,
+    ) -> Vec<String> {
+        if !matches!(
+            target,
+            BuildTarget::Rpm | BuildTarget::Pkg | BuildTarget::Apk
+        ) {
+            return vec![];
+        }
+        let steps = match &self.check_script {
+            Some(script) => script.steps_for_image(image),
+            None => return vec![],
+        };
+        steps
+            .into_iter()
+            .filter(|step| step.applies_to(image, &target, version))
+            .filter_map(|step| {
+                let fields = step.manifest_unsupported_fields();
+                (!fields.is_empty()).then(|| {
+                    format!(
+                        "check step `{}` runs in the {} manifest, which doesn't support `{}`",
+                        step.cmd,
+                        target.as_ref().to_uppercase(),
+                        fields.join("`, `")
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the body of the check phase of a package manifest with the `check` steps that
+    /// apply to the `image`, `build_target` and `version`, every line indented by `indent`.
+    fn check_body(
+        &self,
+        image: &str,
+        build_target: BuildTarget,
+        version: &str,
+        indent: &str,
+    ) -> Option<String> {
+        let script = self.check_script.as_ref()?;
+        manifest_steps(
+            script.steps_for_image(image),
+            script.working_dir.as_deref(),
+            script.shell.as_deref(),
+            image,
+            build_target,
+            version,
+            indent,
+        )
+    }
+
+    /// Returns the body of the prepare phase of a package manifest with the `configure` steps
+    /// that apply to the `image`, `build_target` and `version`, every line indented by `indent`.
+    fn configure_body(
+        &self,
+        image: &str,
+        build_target: BuildTarget,
+        version: &str,
+        indent: &str,
+    ) -> Option<String> {
+        let script = self.configure_script.as_ref()?;
+        manifest_steps(
+            script.steps_for_image(image),
+            script.working_dir.as_deref(),
+            script.shell.as_deref(),
+            image,
+            build_target,
+            version,
+            indent,
+        )
+    }
+
+    /// Returns the explicit files list of the `build_target` if the recipe has one.
+    pub fn explicit_files(&self, build_target: BuildTarget) -> Option<&[String]> {
+        match build_target {
+            BuildTarget::Rpm => self.metadata.rpm.as_ref()?.files.as_deref(),
+            BuildTarget::Deb => self.metadata.deb.as_ref()?.files.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the `build_target` is RPM and the recipe lets RPM apply its patches in
+    /// `%prep` instead of applying them in the build container.
+    pub fn rpm_native_prep(&self, build_target: BuildTarget) -> bool {
+        build_target == BuildTarget::Rpm
+            && self
+                .metadata
+                .rpm
+                .as_ref()
+                .map(|rpm| rpm.native_prep)
+                .unwrap_or_default()
+    }
+
+    /// Returns `true` if any step that runs before packaging, that is a `post_fetch`,
+    /// `configure`, `build` or `install` step, only runs on some targets. Such a build can't be
+    /// shared between package targets.
+    pub fn has_target_scoped_build_steps(&self) -> bool {
+        let post_fetch = self.post_fetch_script.iter().flat_map(|s| s.all_steps());
+        let configure = self.configure_script.iter().flat_map(|s| s.all_steps());
+        let install = self.install_script.iter().flat_map(|s| s.all_steps());
+        post_fetch
+            .chain(configure)
+            .chain(self.build_script.all_steps())
+            .chain(install)
+            .any(Command::has_target_specified)
+    }
+
+    /// Returns the patches of the recipe that apply to the `image` in order.
+    pub fn patches_for_image(&self, image: &str) -> Vec<&Patch> {
+        self.metadata
+            .patches
+            .as_ref()
+            .map(|patches| {
+                patches
+                    .resolve_names(image)
+                    .into_iter()
+                    .filter(|patch| patch.applies_to(image))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the `breaks` relations of the `image`, entries that fail to parse are skipped.
+    fn breaks(&self, build_target: BuildTarget, image: &str) -> Vec<Relation> {
+        let mut breaks = deps::recipe(
+            self.metadata.breaks.as_ref(),
+            build_target,
+            image,
+            &self.metadata.arch,
+        )
+        .into_iter()
+        .filter_map(|entry| Relation::parse(entry).ok())
+        .collect::<Vec<_>>();
+        breaks.sort_by(|a, b| a.name.cmp(&b.name));
+        breaks
+    }
+
     pub fn as_deb_control(
         &self,
         image: &str,
@@ -127,6 +827,18 @@ impl Recipe {
             self.metadata.name.to_owned()
         };
 
+        let mut sanitized = false;
+        let mut deb_names = |entries: HashSet<&str>| -> Vec<String> {
+            entries
+                .into_iter()
+                .map(|entry| {
+                    let sanitized_entry = sanitize_deb_relation(entry);
+                    sanitized |= sanitized_entry != entry;
+                    sanitized_entry
+                })
+                .collect()
+        };
+
         let mut builder = DebControlBuilder::binary_package_builder(&name)
             .version(version)
             .revision(self.metadata.release())
@@ -140,21 +852,55 @@ impl Recipe {
             builder = builder.section(group);
         }
         if let Some(depends) = &self.metadata.depends {
-            let depends = deps::recipe(Some(depends), build_target, image);
+            let depends = deb_names(deps::recipe(
+                Some(depends),
+                build_target,
+                image,
+                &self.metadata.arch,
+            ));
             builder = builder.add_depends_entries(depends);
         }
         if let Some(conflicts) = &self.metadata.conflicts {
-            let conflicts = deps::recipe(Some(conflicts), build_target, image);
+            let conflicts = deb_names(deps::recipe(
+                Some(conflicts),
+                build_target,
+                image,
+                &self.metadata.arch,
+            ));
             builder = builder.add_conflicts_entries(conflicts);
         }
         if let Some(provides) = &self.metadata.provides {
-            let provides = deps::recipe(Some(provides), build_target, image);
+            let provides = deb_names(deps::recipe(
+                Some(provides),
+                build_target,
+                image,
+                &self.metadata.arch,
+            ));
             builder = builder.add_provides_entries(provides);
         }
+        if let Some(obsoletes) = &self.metadata.obsoletes {
+            // Debian has no obsoletes, a package taking over files of another package declares
+            // both `Breaks` and `Replaces` on it
+            let obsoletes = deb_names(deps::recipe(
+                Some(obsoletes),
+                build_target,
+                image,
+                &self.metadata.arch,
+            ));
+            builder = builder
+                .add_breaks_entries(obsoletes.clone())
+                .add_replaces_entries(obsoletes);
+        }
+        let breaks = self.breaks(build_target, image);
+        if !breaks.is_empty() {
+            let breaks = breaks.iter().map(Relation::deb).collect::<Vec<_>>();
+            let breaks = deb_names(breaks.iter().map(String::as_str).collect());
+            builder = builder.add_breaks_entries(breaks);
+        }
         if let Some(maintainer) = &self.metadata.maintainer {
             builder = builder.maintainer(maintainer);
         }
-        if let Some(homepage) = &self.metadata.url {
+        if let Some(homepage) = self.metadata.url() {
             builder = builder.homepage(homepage);
         }
         if let Some(installed_size) = installed_size {
@@ -172,34 +918,94 @@ impl Recipe {
             }
 
             if let Some(pre_depends) = &deb.pre_depends {
-                let pre_depends = deps::recipe(Some(pre_depends), build_target, image);
+                let pre_depends = deb_names(deps::recipe(
+                    Some(pre_depends),
+                    build_target,
+                    image,
+                    &self.metadata.arch,
+                ));
                 builder = builder.add_pre_depends_entries(pre_depends);
             }
             if let Some(recommends) = &deb.recommends {
-                let recommends = deps::recipe(Some(recommends), build_target, image);
+                let recommends = deb_names(deps::recipe(
+                    Some(recommends),
+                    build_target,
+                    image,
+                    &self.metadata.arch,
+                ));
                 builder = builder.add_recommends_entries(recommends);
             }
             if let Some(suggests) = &deb.suggests {
-                let suggests = deps::recipe(Some(suggests), build_target, image);
+                let suggests = deb_names(deps::recipe(
+                    Some(suggests),
+                    build_target,
+                    image,
+                    &self.metadata.arch,
+                ));
                 builder = builder.add_suggests_entries(suggests);
             }
             if let Some(breaks) = &deb.breaks {
-                let breaks = deps::recipe(Some(breaks), build_target, image);
+                let breaks = deb_names(deps::recipe(
+                    Some(breaks),
+                    build_target,
+                    image,
+                    &self.metadata.arch,
+                ));
                 builder = builder.add_breaks_entries(breaks);
             }
             if let Some(replaces) = &deb.replaces {
-                let replaces = deps::recipe(Some(replaces), build_target, image);
+                let replaces = deb_names(deps::recipe(
+                    Some(replaces),
+                    build_target,
+                    image,
+                    &self.metadata.arch,
+                ));
                 builder = builder.add_replaces_entries(replaces);
             }
             if let Some(enchances) = &deb.enhances {
-                let enchances = deps::recipe(Some(enchances), build_target, image);
+                let enchances = deb_names(deps::recipe(
+                    Some(enchances),
+                    build_target,
+                    image,
+                    &self.metadata.arch,
+                ));
                 builder = builder.add_enchances_entries(enchances);
             }
         }
 
+        if sanitized {
+            warning!(logger => "Debian package names can't contain `_`, converting dependencies to use `-`");
+        }
+
         builder.build()
     }
 
+    /// Renders a spec used with `rpmbuild -bp` when the recipe has `rpm.native_prep` set. Its
+    /// `%prep` unpacks the `source` archive of the fetched sources with `%autosetup`, applies
+    /// the patches of the `image` and copies the prepared sources back to `bld_dir` where the
+    /// build continues.
+    pub fn as_rpm_prep_spec(
+        &self,
+        source: &str,
+        image: &str,
+        version: &str,
+        bld_dir: &Path,
+    ) -> RpmSpec {
+        let patches = self.patches_for_image(image);
+        let prep = format!("{}\ncp -a . \"{}\"", rpm_prep(&patches), bld_dir.display());
+        RpmSpec::builder()
+            .name(&self.metadata.name)
+            .version(version)
+            .release(self.metadata.release())
+            .license(&self.metadata.license)
+            .summary(&self.metadata.description)
+            .description(&self.metadata.description)
+            .add_sources_entries([source])
+            .add_patches_entries(patches.iter().map(|patch| patch.file_name()))
+            .prep_script(prep)
+            .build()
+    }
+
     pub fn as_rpm_spec(
         &self,
         sources: &[String],
@@ -217,6 +1023,14 @@ impl Recipe {
                 s
             });
 
+        // with an explicit list without `auto` the files not listed in it are left out of the
+        // package on purpose, rpmbuild would otherwise fail because they're unpackaged
+        let drops_unlisted_files = self
+            .explicit_files(build_target)
+            .map(|files| !files.iter().any(|file| file.trim() == AUTO_FILES))
+            .unwrap_or_default();
+        let (explicit_files, files) = package_files(self.explicit_files(build_target), files);
+
         let mut builder = RpmSpec::builder()
             .name(&self.metadata.name)
             .build_arch(self.metadata.arch.rpm_name())
@@ -224,15 +1038,38 @@ impl Recipe {
             .license(&self.metadata.license)
             .version(version)
             .release(self.metadata.release())
+            .add_raw_files_entries(explicit_files)
             .add_files_entries(files)
             .add_sources_entries(sources)
             .add_macro("__os_install_post", None::<&str>, "%{nil}") // disable binary stripping
             .install_script(&install_script)
             .description(&self.metadata.description);
 
+        if drops_unlisted_files {
+            builder = builder.add_macro("_unpackaged_files_terminate_build", None::<&str>, "0");
+        }
+
+        if let Some(check) = self.check_body(image, build_target, version, "") {
+            builder = builder.check_script(check);
+        }
+
+        if let Some(changelog) = &self.changelog {
+            builder = builder.add_changelog_entries(changelog.rpm_entries(
+                self.metadata.maintainer.as_deref(),
+                version,
+                self.metadata.release(),
+                Changelog::today(),
+            ));
+        }
+
+        if let Some(obsoletes) = &self.metadata.obsoletes {
+            let obsoletes = deps::recipe(Some(obsoletes), build_target, image, &self.metadata.arch);
+            builder = builder.add_obsoletes_entries(obsoletes);
+        }
         if let Some(rpm) = &self.metadata.rpm {
             if let Some(obsoletes) = &rpm.obsoletes {
-                let obsoletes = deps::recipe(Some(obsoletes), build_target, image);
+                let obsoletes =
+                    deps::recipe(Some(obsoletes), build_target, image, &self.metadata.arch);
                 builder = builder.add_obsoletes_entries(obsoletes);
             }
             if let Some(vendor) = &rpm.vendor {
@@ -275,22 +1112,24 @@ impl Recipe {
         if let Some(maintainer) = &self.metadata.maintainer {
             builder = builder.packager(maintainer);
         }
-        if let Some(url) = &self.metadata.url {
+        if let Some(url) = self.metadata.url() {
             builder = builder.url(url);
         }
         if let Some(epoch) = &self.metadata.epoch {
             builder = builder.epoch(epoch);
         }
         if let Some(depends) = &self.metadata.depends {
-            let depends = deps::recipe(Some(depends), build_target, image);
+            let depends = deps::recipe(Some(depends), build_target, image, &self.metadata.arch);
             builder = builder.add_requires_entries(depends);
         }
         if let Some(conflicts) = &self.metadata.conflicts {
-            let conflicts = deps::recipe(Some(conflicts), build_target, image);
+            let conflicts = deps::recipe(Some(conflicts), build_target, image, &self.metadata.arch);
             builder = builder.add_conflicts_entries(conflicts);
         }
+        builder = builder
+            .add_conflicts_entries(self.breaks(build_target, image).iter().map(Relation::rpm));
         if let Some(provides) = &self.metadata.provides {
-            let provides = deps::recipe(Some(provides), build_target, image);
+            let provides = deps::recipe(Some(provides), build_target, image, &self.metadata.arch);
             builder = builder.add_provides_entries(provides);
         }
 
@@ -301,15 +1140,38 @@ impl Recipe {
         &self,
         image: &str,
         sources: &[String],
+        checksum: Checksum,
         checksums: &[String],
         version: &str,
         build_target: BuildTarget,
         _logger: &mut BoxedCollector,
     ) -> PkgBuild {
-        let package_func = sources.iter().fold(String::new(), |mut s, src| {
-            let _ = writeln!(s, "    tar xvf {} -C $pkgdir", src);
-            s
-        });
+        let noextract = self
+            .metadata
+            .pkg
+            .as_ref()
+            .map(|pkg| pkg.noextract.as_slice())
+            .unwrap_or_default();
+        let mut noextract_sources = vec![];
+        let mut package_func = String::new();
+        for src in sources {
+            // sources of files listed in `noextract` are copied to their path in the package
+            let name = Path::new(src).file_name();
+            let entry = name.and_then(|name| {
+                noextract
+                    .iter()
+                    .map(|entry| entry.trim_start_matches('/'))
+                    .find(|entry| Path::new(entry).file_name() == Some(name))
+            });
+            if let (Some(entry), Some(name)) = (entry, name) {
+                let dir = Path::new(entry).parent().unwrap_or_else(|| Path::new(""));
+                let _ = writeln!(package_func, "    mkdir -p \"$pkgdir/{}\"", dir.display());
+                let _ = writeln!(package_func, "    cp -a \"{}\" \"$pkgdir/{}\"", src, entry);
+                noextract_sources.push(name.to_string_lossy().to_string());
+            } else {
+                let _ = writeln!(package_func, "    tar xvf {} -C $pkgdir", src);
+            }
+        }
 
         let mut builder = PkgBuild::builder()
             .pkgname(&self.metadata.name)
@@ -318,25 +1180,41 @@ impl Recipe {
             .add_license_entries(vec![&self.metadata.license])
             .add_arch_entries(vec![self.metadata.arch.pkg_name().to_string()])
             .add_source_entries(sources)
-            .add_md5sums_entries(checksums)
+            .add_noextract_entries(noextract_sources)
             .package_func(package_func);
+        builder = match checksum {
+            Checksum::Md5 => builder.add_md5sums_entries(checksums),
+            Checksum::Sha1 => builder.add_sha1sums_entries(checksums),
+            Checksum::Sha256 => builder.add_sha256sums_entries(checksums),
+            Checksum::Sha512 => builder.add_sha512sums_entries(checksums),
+            Checksum::Blake2 => builder.add_b2sums_entries(checksums),
+        };
 
-        if let Some(url) = &self.metadata.url {
+        if let Some(prepare) = self.configure_body(image, build_target, version, "    ") {
+            builder = builder.prepare_func(prepare);
+        }
+        if let Some(check) = self.check_body(image, build_target, version, "    ") {
+            builder = builder.check_func(check);
+        }
+
+        if let Some(url) = self.metadata.url() {
             builder = builder.url(url);
         }
         if let Some(group) = &self.metadata.group {
             builder = builder.add_groups_entries(vec![group]);
         }
         if let Some(depends) = &self.metadata.depends {
-            let depends = deps::recipe(Some(depends), build_target, image);
+            let depends = deps::recipe(Some(depends), build_target, image, &self.metadata.arch);
             builder = builder.add_depends_entries(depends);
         }
         if let Some(conflicts) = &self.metadata.conflicts {
-            let conflicts = deps::recipe(Some(conflicts), build_target, image);
+            let conflicts = deps::recipe(Some(conflicts), build_target, image, &self.metadata.arch);
             builder = builder.add_conflicts_entries(conflicts);
         }
+        builder = builder
+            .add_conflicts_entries(self.breaks(build_target, image).iter().map(Relation::pkg));
         if let Some(provides) = &self.metadata.provides {
-            let provides = deps::recipe(Some(provides), build_target, image);
+            let provides = deps::recipe(Some(provides), build_target, image, &self.metadata.arch);
             builder = builder.add_provides_entries(provides);
         }
 
@@ -372,14 +1250,27 @@ impl Recipe {
             .package_func(package_func)
             .builddir(builddir.to_string_lossy());
 
-        builder = builder.url(self.metadata.url.as_deref().unwrap_or(" "));
+        if let Some(prepare) = self.configure_body(image, build_target, version, "    ") {
+            builder = builder.prepare_func(prepare);
+        }
+        if let Some(check) = self.check_body(image, build_target, version, "    ") {
+            builder = builder.check_func(check);
+        }
+
+        builder = builder.url(self.metadata.url().unwrap_or_else(|| " ".into()));
 
         if let Some(depends) = &self.metadata.depends {
-            let depends = deps::recipe(Some(depends), build_target, image);
+            let depends = deps::recipe(Some(depends), build_target, image, &self.metadata.arch);
             builder = builder.add_depends_entries(depends);
         }
+        // apk has no conflicts, a negative dependency prevents installing both packages
+        builder = builder.add_depends_entries(
+            self.breaks(build_target, image)
+                .iter()
+                .map(|relation| format!("!{}", relation.pkg())),
+        );
         if let Some(provides) = &self.metadata.provides {
-            let provides = deps::recipe(Some(provides), build_target, image);
+            let provides = deps::recipe(Some(provides), build_target, image, &self.metadata.arch);
             builder = builder.add_provides_entries(provides);
         }
 
@@ -398,11 +1289,28 @@ pub struct RecipeRep {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub env: Option<Mapping>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    /// Path to a file with `KEY=VALUE` lines relative to the recipe directory. Variables defined
+    /// in `env` take precedence.
+    pub env_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Path to a markdown changelog relative to the recipe directory, split into the changelog
+    /// entries of RPM and DEB packages by its version headings.
+    pub changelog_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_fetch: Option<PostFetchRep>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub configure: Option<ConfigureRep>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub build: Option<BuildRep>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub install: Option<InstallRep>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Steps testing the build, like `make check`. They run in the check phase of the package
+    /// format or before packaging formats that have none.
+    pub check: Option<CheckRep>,
+    #[serde(flatten)]
+    /// Fields that are not part of the recipe, only kept to warn about them.
+    pub unknown: BTreeMap<String, serde_yaml::Value>,
 }
 
 impl RecipeRep {
@@ -414,6 +1322,34 @@ impl RecipeRep {
         Self::from_yaml_bytes(&fs::read(&path)?)
     }
 
+    /// Returns warnings about fields of the recipe that pkger doesn't know and would ignore.
+    pub fn field_warnings(&self) -> Vec<String> {
+        let mut warnings = self
+            .unknown
+            .keys()
+            .map(|field| format!("unknown field `{}`, it will be ignored", field))
+            .collect::<Vec<_>>();
+        if let Some(metadata) = &self.metadata {
+            warnings.extend(metadata.field_warnings());
+        }
+        let scripts = [
+            ("post_fetch", self.post_fetch.as_ref().map(|s| &s.unknown)),
+            ("configure", self.configure.as_ref().map(|s| &s.unknown)),
+            ("build", self.build.as_ref().map(|s| &s.unknown)),
+            ("install", self.install.as_ref().map(|s| &s.unknown)),
+            ("check", self.check.as_ref().map(|s| &s.unknown)),
+        ];
+        for (script, unknown) in scripts {
+            for field in unknown.into_iter().flat_map(BTreeMap::keys) {
+                warnings.push(format!(
+                    "unknown field `{}` in the `{}` script, it will be ignored, steps of a single image go under `images`",
+                    field, script
+                ));
+            }
+        }
+        warnings
+    }
+
     pub(crate) fn merge(self, base_rep: RecipeRep) -> Result<RecipeRep> {
         let base_value =
             serde_yaml::to_string(&base_rep).context("failed to serialize base recipe")?;
@@ -425,6 +1361,37 @@ impl RecipeRep {
 
         serde_yaml::from_str(&merged.to_string()).context("failed to deserialize merged recipe")
     }
+
+    /// Resolves the whole chain of base recipes referenced by `from` and merges them bottom-up
+    /// so that every recipe overrides fields of the recipes it inherits from. `name` is the name
+    /// of this recipe in the `loader` directory if it's there, used to detect cycles.
+    fn merge_bases(self, loader: &Loader, name: Option<String>) -> Result<RecipeRep> {
+        let mut chain: Vec<String> = name.into_iter().collect();
+        let mut bases = vec![];
+        let mut from = self.from.clone();
+
+        while let Some(base) = from {
+            if chain.contains(&base) {
+                chain.push(base);
+                return err!("cyclic recipe inheritance `{}`", chain.join(" -> "));
+            }
+            let base_rep = loader
+                .load_rep(&base)
+                .with_context(|| format!("failed to load base recipe `{}`", base))?;
+            chain.push(base);
+            from = base_rep.from.clone();
+            bases.push(base_rep);
+        }
+
+        let mut merged = match bases.pop() {
+            Some(base) => base,
+            None => return Ok(self),
+        };
+        while let Some(base) = bases.pop() {
+            merged = base.merge(merged).context("failed to merge recipes")?;
+        }
+        self.merge(merged).context("failed to merge recipes")
+    }
 }
 
 impl TryFrom<DirEntry> for RecipeRep {
@@ -437,11 +1404,47 @@ impl TryFrom<DirEntry> for RecipeRep {
     }
 }
 
+/// Key of script steps that run on all images, same as the `steps` list.
+const COMMON_STEPS_KEY: &str = "all";
+
+/// Steps of a script that only run on a single image.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImageSteps {
+    pub steps: Vec<Command>,
+    /// Whether the steps replace the common steps instead of following them.
+    pub replace: bool,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum ImageStepsRep {
+    Steps(Vec<Command>),
+    Script {
+        steps: Vec<Command>,
+        #[serde(default)]
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        replace: bool,
+    },
+}
+
+impl From<ImageStepsRep> for ImageSteps {
+    fn from(rep: ImageStepsRep) -> Self {
+        match rep {
+            ImageStepsRep::Steps(steps) => Self {
+                steps,
+                replace: false,
+            },
+            ImageStepsRep::Script { steps, replace } => Self { steps, replace },
+        }
+    }
+}
+
 macro_rules! impl_step_rep {
     ($ty:ident, $ty_rep:ident) => {
         #[derive(Clone, Debug, PartialEq, Eq)]
         pub struct $ty {
             pub steps: Vec<Command>,
+            pub images: BTreeMap<String, ImageSteps>,
             pub working_dir: Option<PathBuf>,
             pub shell: Option<String>,
         }
@@ -450,8 +1453,24 @@ macro_rules! impl_step_rep {
             type Error = Error;
 
             fn try_from(rep: $ty_rep) -> Result<Self> {
+                let mut steps = vec![];
+                let mut images = BTreeMap::new();
+                for (image, image_steps) in rep.images {
+                    let image_steps = ImageSteps::from(image_steps);
+                    if image == COMMON_STEPS_KEY {
+                        steps.extend(image_steps.steps);
+                    } else {
+                        images.insert(image, image_steps);
+                    }
+                }
+                steps.extend(rep.steps);
+                for step in steps.iter().chain(images.values().flat_map(|i| &i.steps)) {
+                    step.validate()?;
+                }
+
                 Ok(Self {
-                    steps: rep.steps,
+                    steps,
+                    images,
                     working_dir: rep.working_dir,
                     shell: rep.shell,
                 })
@@ -467,15 +1486,41 @@ macro_rules! impl_step_rep {
                 });
                 script
             }
+
+            /// Returns the steps to run on `image`. Steps declared for the image either follow
+            /// the common steps or replace them.
+            pub fn steps_for_image(&self, image: &str) -> Vec<&Command> {
+                match self.images.get(image) {
+                    Some(image_steps) if image_steps.replace => image_steps.steps.iter().collect(),
+                    Some(image_steps) => self.steps.iter().chain(&image_steps.steps).collect(),
+                    None => self.steps.iter().collect(),
+                }
+            }
+
+            /// Returns the common steps followed by the steps of all images.
+            pub fn all_steps(&self) -> Vec<&Command> {
+                self.steps
+                    .iter()
+                    .chain(self.images.values().flat_map(|image| &image.steps))
+                    .collect()
+            }
         }
 
         #[derive(Clone, Deserialize, Serialize, Debug, Default, PartialEq, Eq)]
         pub struct $ty_rep {
+            #[serde(default)]
             pub steps: Vec<Command>,
             #[serde(skip_serializing_if = "Option::is_none")]
             pub working_dir: Option<PathBuf>,
             #[serde(skip_serializing_if = "Option::is_none")]
             pub shell: Option<String>,
+            #[serde(default)]
+            #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+            /// Steps keyed by the name of the image they run on.
+            pub images: BTreeMap<String, ImageStepsRep>,
+            #[serde(flatten)]
+            /// Fields that are not part of the script, only kept to warn about them.
+            pub unknown: BTreeMap<String, serde_yaml::Value>,
         }
     };
 }
@@ -483,10 +1528,13 @@ macro_rules! impl_step_rep {
 impl_step_rep!(BuildScript, BuildRep);
 impl_step_rep!(InstallScript, InstallRep);
 impl_step_rep!(ConfigureScript, ConfigureRep);
+impl_step_rep!(PostFetchScript, PostFetchRep);
+impl_step_rep!(CheckScript, CheckRep);
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use pkgspec_core::Manifest;
 
     const TEST_SUITE_RECIPE: &[u8] =
         include_bytes!("../../../example/recipes/test-suite/recipe.yml");
@@ -582,4 +1630,1247 @@ build:
         println!("\n\n\n\n\n\n\n{:?}", res);
         assert!(res.is_err());
     }
+
+    #[test]
+    fn resolves_base_recipe_from_configured_root() {
+        let dir = tempdir::TempDir::new("pkger-recipe-root").unwrap();
+        let base_root = dir.path().join("base");
+        let recipes_dir = dir.path().join("recipes").join("nested");
+        fs::create_dir_all(base_root.join("base-package")).unwrap();
+        fs::create_dir_all(recipes_dir.join("child-package1")).unwrap();
+        fs::write(base_root.join("base-package/recipe.yml"), BASE_RECIPE).unwrap();
+        fs::write(recipes_dir.join("child-package1/recipe.yml"), CHILD1_RECIPE).unwrap();
+
+        let loader = Loader::new(&recipes_dir).unwrap();
+        assert!(loader.load("child-package1").is_err());
+
+        let loader = loader.base_root(&base_root);
+        let recipe = loader.load("child-package1").unwrap();
+        assert_eq!(recipe.metadata.name, "child-package1");
+        assert_eq!(recipe.metadata.license, "MIT");
+        assert_eq!(recipe.recipe_dir, recipes_dir.join("child-package1"));
+        assert_eq!(recipe.recipes_dir, recipes_dir);
+    }
+
+    #[test]
+    fn resolves_inheritance_chains() {
+        let dir = tempdir::TempDir::new("pkger-recipe-chain").unwrap();
+        let recipes = [
+            (
+                "generic",
+                r#"
+metadata:
+  name: generic
+  version: "1.0.0"
+  description: generic package
+  license: MIT
+  arch: x86_64
+build:
+  steps:
+    - cmd: make"#,
+            ),
+            (
+                "python",
+                r#"
+from: generic
+metadata:
+  name: python
+  version: "2.0.0"
+  depends: [python3]"#,
+            ),
+            (
+                "python-requests",
+                r#"
+from: python
+metadata:
+  name: python-requests
+  version: "2.28.1""#,
+            ),
+            ("cycle-a", "from: cycle-b\nmetadata:\n  name: cycle-a"),
+            ("cycle-b", "from: cycle-a\nmetadata:\n  name: cycle-b"),
+        ];
+        for (name, recipe) in recipes {
+            fs::create_dir(dir.path().join(name)).unwrap();
+            fs::write(dir.path().join(name).join("recipe.yml"), recipe).unwrap();
+        }
+
+        let loader = Loader::new(dir.path()).unwrap();
+        let recipe = loader.load("python-requests").unwrap();
+        assert_eq!(recipe.metadata.name, "python-requests");
+        assert_eq!(recipe.metadata.version.versions(), vec!["2.28.1"]);
+        assert_eq!(recipe.metadata.description, "generic package");
+        assert_eq!(recipe.metadata.license, "MIT");
+        assert_eq!(recipe.metadata.arch, BuildArch::x86_64);
+        assert!(recipe
+            .metadata
+            .depends
+            .as_ref()
+            .unwrap()
+            .resolve_names("debian", BuildTarget::Deb, &BuildArch::All)
+            .contains("python3"));
+        assert_eq!(recipe.build_script.steps.len(), 1);
+
+        let err = loader.load("cycle-a").unwrap_err();
+        assert!(format!("{:#}", err).contains("cycle-a -> cycle-b -> cycle-a"));
+    }
+
+    #[test]
+    fn reports_inheritance_problems() {
+        let dir = tempdir::TempDir::new("pkger-inheritance-check").unwrap();
+        let base = r#"
+metadata:
+  name: base
+  version: "1.0.0"
+  description: base package
+  license: MIT
+build:
+  steps: []"#;
+        let recipes = [
+            ("base", base),
+            ("unused-base", base),
+            ("child", "from: base\nmetadata:\n  name: child"),
+            ("orphan", "from: missing\nmetadata:\n  name: orphan"),
+            (
+                "orphan-child",
+                "from: orphan\nmetadata:\n  name: orphan-child",
+            ),
+            ("cycle-a", "from: cycle-b\nmetadata:\n  name: cycle-a"),
+            ("cycle-b", "from: cycle-a\nmetadata:\n  name: cycle-b"),
+            ("into-cycle", "from: cycle-b\nmetadata:\n  name: into-cycle"),
+        ];
+        for (name, recipe) in recipes {
+            fs::create_dir(dir.path().join(name)).unwrap();
+            fs::write(dir.path().join(name).join("recipe.yml"), recipe).unwrap();
+        }
+
+        let problems = Loader::new(dir.path())
+            .unwrap()
+            .inheritance_problems()
+            .unwrap();
+        assert_eq!(problems.len(), 2);
+        assert!(matches!(
+            &problems[0],
+            InheritanceProblem::Dangling { recipe, base, .. } if recipe == "orphan" && base == "missing"
+        ));
+        assert_eq!(
+            problems[1],
+            InheritanceProblem::Cycle(vec![
+                "cycle-a".to_string(),
+                "cycle-b".to_string(),
+                "cycle-a".to_string()
+            ])
+        );
+        assert_eq!(
+            problems[1].to_string(),
+            "cyclic recipe inheritance `cycle-a -> cycle-b -> cycle-a`"
+        );
+    }
+
+    #[test]
+    fn lists_only_failing_recipes() {
+        let dir = tempdir::TempDir::new("pkger-load-errors").unwrap();
+        let recipes_dir = dir.path();
+        for name in ["valid", "invalid-yaml", "no-recipe"] {
+            fs::create_dir_all(recipes_dir.join(name)).unwrap();
+        }
+        fs::write(
+            recipes_dir.join("valid/recipe.yml"),
+            r#"
+metadata:
+  name: valid
+  version: "1.0.0"
+  description: a valid recipe
+  license: MIT
+build:
+  steps: []"#,
+        )
+        .unwrap();
+        fs::write(recipes_dir.join("invalid-yaml/recipe.yml"), "metadata: [").unwrap();
+
+        let loader = Loader::new(recipes_dir).unwrap();
+        let errors = loader.load_errors().unwrap();
+        let names = errors
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["invalid-yaml", "no-recipe"]);
+        assert!(format!("{:#}", errors[1].1).contains("has no recipe.yml or recipe.yaml"));
+    }
+
+    #[test]
+    fn selects_steps_for_image() {
+        let recipe = r#"
+metadata:
+  name: per-image-steps
+  version: "1.0.0"
+  description: a recipe with per image steps
+  license: MIT
+build:
+  steps:
+    - cmd: make
+  images:
+    all:
+      - cmd: ./configure
+    pkger-rpm:
+      - cmd: make rpm-extras
+    rocky:
+      replace: true
+      steps:
+        - cmd: make -f Makefile.rocky
+install:
+  steps:
+    - cmd: make install"#;
+        let rep = RecipeRep::from_yaml_bytes(recipe.as_bytes()).unwrap();
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        let cmds = |steps: Vec<&Command>| {
+            steps
+                .into_iter()
+                .map(|step| step.cmd.as_str())
+                .collect::<Vec<_>>()
+        };
+
+        let build = &recipe.build_script;
+        assert_eq!(
+            cmds(build.steps_for_image("debian")),
+            vec!["./configure", "make"]
+        );
+        assert_eq!(
+            cmds(build.steps_for_image("pkger-rpm")),
+            vec!["./configure", "make", "make rpm-extras"]
+        );
+        assert_eq!(
+            cmds(build.steps_for_image("rocky")),
+            vec!["make -f Makefile.rocky"]
+        );
+        assert_eq!(build.all_steps().len(), 4);
+        assert!(recipe.validate().is_empty());
+
+        let install = recipe.install_script.as_ref().unwrap();
+        assert!(install.images.is_empty());
+        assert_eq!(cmds(install.steps_for_image("rocky")), vec!["make install"]);
+    }
+
+    #[test]
+    fn denies_recipe_warnings() {
+        let rep = RecipeRep::from_yaml_bytes(TEST_SUITE_RECIPE).unwrap();
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        assert!(recipe.warnings().is_empty());
+        assert!(recipe.deny_warnings().is_ok());
+
+        let recipe = r#"
+metadata:
+  name: test_warnings
+  version: "1.0.0"
+  description: a recipe with warnings
+  license: "MIT, but not really"
+  rpm:
+    summary: a summary that is way too long to be accepted as a one-line summary of an RPM package
+build:
+  steps: []"#;
+        let rep = RecipeRep::from_yaml_bytes(recipe.as_bytes()).unwrap();
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        assert_eq!(recipe.warnings().len(), 3);
+        assert!(recipe.deny_warnings().is_err());
+    }
+
+    #[test]
+    fn warns_about_unknown_fields() {
+        let recipe = r#"
+metadata:
+  name: test-fields
+  version: "1.0.0"
+  description: a recipe with unknown fields
+  license: MIT
+  summary: an RPM summary
+  homepage: https://example.com
+  rpm:
+    priority: optional
+    vendr: someone
+  deb:
+    replaces: [other]
+buid:
+  steps: []
+build:
+  steps: []
+  step:
+    - make
+  rocky:
+    - make -f Makefile.rocky"#;
+        let rep = RecipeRep::from_yaml_bytes(recipe.as_bytes()).unwrap();
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        assert_eq!(
+            recipe.warnings(),
+            vec![
+                "unknown field `buid`, it will be ignored".to_string(),
+                "unknown field `homepage` in `metadata`, it will be ignored".to_string(),
+                "field `summary` of `metadata` only applies to the `rpm` section, it will be ignored"
+                    .to_string(),
+                "field `priority` of the `rpm` section only applies to the `deb` section, it will be ignored"
+                    .to_string(),
+                "unknown field `vendr` in the `rpm` section, it will be ignored".to_string(),
+                "unknown field `rocky` in the `build` script, it will be ignored, steps of a single image go under `images`"
+                    .to_string(),
+                "unknown field `step` in the `build` script, it will be ignored, steps of a single image go under `images`"
+                    .to_string(),
+            ]
+        );
+        assert!(recipe.deny_warnings().is_err());
+    }
+
+    #[test]
+    fn reports_manifest_step_problems() {
+        let recipe = r#"
+metadata:
+  name: test
+  version: 1.0.0
+  description: test
+  license: MIT
+build:
+  steps: []
+check:
+  steps:
+    - make check
+    - cmd: ./run-tests
+      expect_exit_code: 1
+      user: root
+    - cmd: ./deb-tests
+      deb: true
+      expect_exit_code: 2"#;
+        let rep = RecipeRep::from_yaml_bytes(recipe.as_bytes()).unwrap();
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+
+        assert_eq!(
+            recipe.manifest_step_problems("rocky", BuildTarget::Rpm, "1.0.0"),
+            vec![
+                "check step `./run-tests` runs in the RPM manifest, which doesn't support `user`, `expect_exit_code`"
+                    .to_string()
+            ]
+        );
+        assert_eq!(
+            recipe
+                .manifest_step_problems("arch", BuildTarget::Pkg, "1.0.0")
+                .len(),
+            1
+        );
+        // the check script runs in the container for the other targets
+        assert!(recipe
+            .manifest_step_problems("debian", BuildTarget::Deb, "1.0.0")
+            .is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_os_of_steps() {
+        let recipe = r#"
+metadata:
+  name: test
+  version: 1.0.0
+  description: test
+  license: MIT
+build:
+  steps:
+    - cmd: make
+      os: rocyk"#;
+        let rep = RecipeRep::from_yaml_bytes(recipe.as_bytes()).unwrap();
+        let err = Recipe::new(rep, PathBuf::new()).unwrap_err();
+        assert_eq!(err.to_string(), "step `make` has an unknown os `rocyk`");
+    }
+
+    #[test]
+    fn reports_manifest_problems() {
+        let recipe = r#"
+metadata:
+  name: Test-Manifest
+  version: ["1.0.0", "1.0.0-rc1", "v2"]
+  release: "1"
+  description: costs $5 with `tax`
+  license: MIT
+build:
+  steps: []"#;
+        let rep = RecipeRep::from_yaml_bytes(recipe.as_bytes()).unwrap();
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+
+        assert!(recipe
+            .manifest_problems(BuildTarget::Rpm, "1.0.0")
+            .is_empty());
+        assert_eq!(
+            recipe.manifest_problems(BuildTarget::Rpm, "1.0.0-rc1"),
+            vec![
+                "RPM version `1.0.0-rc1` can't contain `-`, set the release with `release` instead"
+                    .to_string()
+            ]
+        );
+        assert_eq!(
+            recipe.manifest_problems(BuildTarget::Deb, "v2"),
+            vec![
+                "DEB version `v2` must start with a digit".to_string(),
+                "DEB package name `Test-Manifest` must be lowercase".to_string(),
+                "DEB packages require a `maintainer`".to_string(),
+            ]
+        );
+        assert_eq!(
+            recipe.manifest_problems(BuildTarget::Apk, "1.0.0"),
+            vec![
+                "`description` contains `$` that would be interpreted by the shell in the APK manifest"
+                    .to_string()
+            ]
+        );
+        assert_eq!(
+            recipe
+                .manifest_problems(BuildTarget::Pkg, "1.0.0-rc1")
+                .len(),
+            2
+        );
+        assert!(recipe.manifest_problems(BuildTarget::Gzip, "v2").is_empty());
+    }
+
+    #[test]
+    fn warns_about_versions_illegal_for_target() {
+        let recipe = r#"
+metadata:
+  name: test-versions
+  version: ["1.0.0", "1.0.0-rc1", "1:2.0.0"]
+  description: a recipe with versions
+  license: MIT
+build:
+  steps: []"#;
+        let rep = RecipeRep::from_yaml_bytes(recipe.as_bytes()).unwrap();
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+
+        let warnings = |target| {
+            recipe
+                .metadata
+                .version
+                .versions()
+                .iter()
+                .flat_map(|version| recipe.version_warnings(target, version))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            warnings(BuildTarget::Pkg),
+            vec![
+                "PKG version `1.0.0-rc1` can't contain whitespace, `-`, `:` or `/`, set the release with `release` instead".to_string(),
+                "PKG version `1:2.0.0` can't contain whitespace, `-`, `:` or `/`, set the epoch with `epoch` instead".to_string(),
+            ]
+        );
+        assert_eq!(
+            recipe.version_warnings(BuildTarget::Rpm, "1.0.0-rc1"),
+            vec![
+                "RPM version `1.0.0-rc1` can't contain `-`, set the release with `release` instead"
+                    .to_string()
+            ]
+        );
+        assert!(recipe
+            .version_warnings(BuildTarget::Rpm, "1.0.0")
+            .is_empty());
+        assert!(warnings(BuildTarget::Deb).is_empty());
+        assert!(warnings(BuildTarget::Gzip).is_empty());
+        assert!(recipe.warnings().is_empty());
+    }
+
+    #[test]
+    fn warns_about_malformed_maintainer() {
+        let recipe = |maintainer: &str| {
+            let recipe = format!(
+                r#"
+metadata:
+  name: test-maintainer
+  version: "1.0.0"
+  description: a recipe with a maintainer
+  license: MIT
+  maintainer: "{}"
+build:
+  steps: []"#,
+                maintainer
+            );
+            let rep = RecipeRep::from_yaml_bytes(recipe.as_bytes()).unwrap();
+            Recipe::new(rep, PathBuf::new()).unwrap()
+        };
+
+        let valid = recipe("Jane Doe <jane@example.com>");
+        assert!(valid.target_warnings(BuildTarget::Deb).is_empty());
+
+        for maintainer in [
+            "Jane Doe",
+            "jane@example.com",
+            "<jane@example.com>",
+            "Jane Doe<jane@example.com>",
+            "Jane Doe <jane example.com>",
+            "Jane Doe <jane@example.com",
+        ] {
+            let recipe = recipe(maintainer);
+            assert!(recipe.warnings().is_empty());
+            assert_eq!(recipe.target_warnings(BuildTarget::Deb).len(), 1);
+            assert!(recipe.target_warnings(BuildTarget::Rpm).is_empty());
+        }
+    }
+
+    #[test]
+    fn validates_recipe_steps() {
+        let rep = RecipeRep::from_yaml_bytes(TEST_SUITE_RECIPE).unwrap();
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        assert!(recipe.validate().is_empty());
+
+        let recipe = r#"
+metadata:
+  name: test_validate
+  version: "1.0.0"
+  description: a recipe with empty steps
+  license: MIT
+configure:
+  steps:
+    - cmd: " "
+build:
+  steps: []"#;
+        let rep = RecipeRep::from_yaml_bytes(recipe.as_bytes()).unwrap();
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        assert_eq!(
+            recipe.validate(),
+            vec![
+                "build script has no steps".to_string(),
+                "configure step 1 has an empty command".to_string(),
+            ]
+        );
+        assert_eq!(
+            recipe.target_warnings(BuildTarget::Deb),
+            vec!["Debian package names can't contain `_`, it will be converted to `-`".to_string()]
+        );
+        assert!(recipe.target_warnings(BuildTarget::Rpm).is_empty());
+    }
+
+    #[test]
+    fn expands_versions_from_git_tags() {
+        let recipe = r#"
+metadata:
+  name: test-tags
+  description: a recipe built from git tags
+  license: MIT
+  git:
+    url: https://github.com/vv9k/pkger.git
+    tags: "v*"
+    max_tags: 3
+build:
+  steps:
+    - cmd: make"#;
+        let rep = RecipeRep::from_yaml_bytes(recipe.as_bytes()).unwrap();
+        let mut recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        assert!(recipe.metadata.version.versions().is_empty());
+
+        let tags = [
+            "v0.9.0", "v0.10.0", "0.11.0", "v0.10.1", "v", "v0.1.0", "v0.10.1",
+        ];
+        let expanded = recipe.expand_git_tags(tags);
+        assert_eq!(expanded, vec!["0.10.1", "0.10.0", "0.9.0"]);
+        assert_eq!(recipe.metadata.version.versions(), &expanded[..]);
+
+        let git = recipe.metadata.git.as_ref().unwrap();
+        assert_eq!(git.tag_for_version("0.10.1").unwrap(), "v0.10.1");
+        assert_eq!(recipe.expand_git_tags(["v0.10.1"]), vec!["0.10.1"]);
+        assert_eq!(recipe.metadata.version.versions().len(), 3);
+
+        // recipes without a tag pattern are not expanded
+        let rep = RecipeRep::from_yaml_bytes(TEST_SUITE_RECIPE).unwrap();
+        let mut recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        let versions = recipe.metadata.version.clone();
+        assert!(recipe.expand_git_tags(tags).is_empty());
+        assert_eq!(recipe.metadata.version, versions);
+    }
+
+    #[test]
+    fn warns_about_invalid_urls() {
+        let recipe_with_url = |url: &str| {
+            let recipe = format!(
+                r#"
+metadata:
+  name: test-url
+  version: "1.0.0"
+  description: a recipe with url
+  license: MIT
+  url: "{}"
+build:
+  steps: []"#,
+                url
+            );
+            let rep = RecipeRep::from_yaml_bytes(recipe.as_bytes()).unwrap();
+            Recipe::new(rep, PathBuf::new()).unwrap()
+        };
+
+        let recipe = recipe_with_url("github.com/vv9k/pkger");
+        assert_eq!(recipe.warnings().len(), 1);
+        assert_eq!(
+            recipe.metadata.url().as_deref(),
+            Some("https://github.com/vv9k/pkger")
+        );
+
+        let recipe = recipe_with_url("https://github.com/vv9k/pkger");
+        assert!(recipe.warnings().is_empty());
+        assert_eq!(
+            recipe.metadata.url().as_deref(),
+            Some("https://github.com/vv9k/pkger")
+        );
+
+        let recipe = recipe_with_url("git://git.kernel.org/pub/scm/git/git.git");
+        assert!(recipe.warnings().is_empty());
+
+        let recipe = recipe_with_url("https://not a url");
+        assert_eq!(recipe.warnings().len(), 1);
+    }
+
+    #[test]
+    fn renders_rpm_group() {
+        let recipe_with_group = |group: Option<&str>| {
+            let mut recipe = r#"
+metadata:
+  name: test-group
+  version: "1.0.0"
+  description: a recipe with a group
+  license: MIT
+"#
+            .to_string();
+            if let Some(group) = group {
+                recipe.push_str(&format!("  group: {:?}\n", group));
+            }
+            recipe.push_str("build:\n  steps: []");
+            let rep = RecipeRep::from_yaml_bytes(recipe.as_bytes()).unwrap();
+            Recipe::new(rep, PathBuf::new())
+        };
+        let render = |recipe: Recipe| {
+            let mut logger: BoxedCollector = Box::new(crate::log::Logger::stdout(None));
+            recipe
+                .as_rpm_spec(&[], &[], "rocky", "1.0.0", BuildTarget::Rpm, &mut logger)
+                .render()
+                .unwrap()
+        };
+
+        let spec = render(recipe_with_group(None).unwrap());
+        assert!(!spec.contains("Group:"));
+
+        let spec = render(recipe_with_group(Some("   ")).unwrap());
+        assert!(!spec.contains("Group:"));
+
+        let spec = render(recipe_with_group(Some("Applications/System Tools")).unwrap());
+        assert!(spec.contains("Group:         Applications/System Tools\n"));
+
+        assert!(recipe_with_group(Some("Applications\nSystem")).is_err());
+    }
+
+    #[test]
+    fn uses_explicit_rpm_files_verbatim() {
+        let recipe_with_files = |files: &str| {
+            let recipe = format!(
+                r#"
+metadata:
+  name: test-files
+  version: "1.0.0"
+  description: a recipe with explicit files
+  license: MIT
+  rpm:
+    files: {}
+build:
+  steps: []"#,
+                files
+            );
+            let rep = RecipeRep::from_yaml_bytes(recipe.as_bytes()).unwrap();
+            Recipe::new(rep, PathBuf::new()).unwrap()
+        };
+        let discovered = vec![
+            "/usr/bin/test-files".to_string(),
+            "/etc/test-files/test.conf".to_string(),
+            "/usr/share/test-files/a".to_string(),
+        ];
+        let render_spec = |recipe: Recipe| {
+            let mut logger: BoxedCollector = Box::new(crate::log::Logger::stdout(None));
+            recipe
+                .as_rpm_spec(
+                    &[],
+                    &discovered,
+                    "rocky",
+                    "1.0.0",
+                    BuildTarget::Rpm,
+                    &mut logger,
+                )
+                .render()
+                .unwrap()
+        };
+        let render = |recipe: Recipe| {
+            let spec = render_spec(recipe);
+            spec.split("\n%files\n")
+                .nth(1)
+                .unwrap()
+                .split("\n%changelog")
+                .next()
+                .unwrap()
+                .to_string()
+        };
+
+        let files = render(recipe_with_files(
+            r#"["%attr(0755, root, root) /usr/bin/test-files", "%config(noreplace) /etc/test-files/test.conf"]"#,
+        ));
+        assert_eq!(
+            files,
+            "%attr(0755, root, root) /usr/bin/test-files\n%config(noreplace) /etc/test-files/test.conf\n"
+        );
+        assert!(render_spec(recipe_with_files(r#"["/usr/bin/test-files"]"#))
+            .contains("%global _unpackaged_files_terminate_build 0\n"));
+        assert!(
+            !render_spec(recipe_with_files(r#"["/usr/bin/test-files", "auto"]"#))
+                .contains("_unpackaged_files_terminate_build")
+        );
+
+        let files = render(recipe_with_files(
+            r#"["%config(noreplace) /etc/test-files/test.conf", "%dir /usr/share/test-files", "auto"]"#,
+        ));
+        assert_eq!(
+            files,
+            "\"/usr/bin/test-files\"\n\"/usr/share/test-files/a\"\n%config(noreplace) /etc/test-files/test.conf\n%dir /usr/share/test-files\n"
+        );
+
+        let recipe = recipe_with_files("[/usr/bin/test-files]");
+        assert_eq!(
+            recipe.explicit_files(BuildTarget::Rpm),
+            Some(&["/usr/bin/test-files".to_string()][..])
+        );
+        assert_eq!(recipe.explicit_files(BuildTarget::Deb), None);
+    }
+
+    #[test]
+    fn renders_check_steps() {
+        let recipe = |check: &str| {
+            let recipe = format!(
+                r#"
+metadata:
+  name: test-check
+  version: "1.0.0"
+  description: a recipe with tests
+  license: MIT
+build:
+  steps:
+    - cmd: make
+{}"#,
+                check
+            );
+            let rep = RecipeRep::from_yaml_bytes(recipe.as_bytes()).unwrap();
+            Recipe::new(rep, PathBuf::new()).unwrap()
+        };
+        let mut logger: BoxedCollector = Box::new(crate::log::Logger::stdout(None));
+
+        let with_check = recipe(
+            r#"check:
+  steps:
+    - cmd: make check
+    - cmd: make check-rpm
+      rpm: true"#,
+        );
+        let spec = with_check
+            .as_rpm_spec(&[], &[], "rocky", "1.0.0", BuildTarget::Rpm, &mut logger)
+            .render()
+            .unwrap();
+        assert!(spec.contains("%check\ncd \"${PKGER_BLD_DIR}\"\nmake check\nmake check-rpm\n\n"));
+
+        let pkgbuild = with_check
+            .as_pkgbuild(
+                "arch",
+                &[],
+                Checksum::Sha256,
+                &[],
+                "1.0.0",
+                BuildTarget::Pkg,
+                &mut logger,
+            )
+            .render()
+            .unwrap();
+        assert!(pkgbuild.contains("check() {\n    cd \"${PKGER_BLD_DIR}\"\n    make check\n}\n"));
+
+        let with_dir = recipe(
+            r#"check:
+  working_dir: /tmp/tests
+  shell: /bin/bash
+  steps:
+    - cmd: echo it's ok"#,
+        );
+        let apkbuild = with_dir
+            .as_apkbuild(
+                "alpine",
+                &[],
+                Path::new("/tmp"),
+                "1.0.0",
+                BuildTarget::Apk,
+                &mut logger,
+            )
+            .render()
+            .unwrap();
+        assert!(apkbuild.contains(
+            "check() {\n    cd \"/tmp/tests\"\n    /bin/bash -c 'echo it'\\''s ok'\n}\n"
+        ));
+
+        let with_step_dir = recipe(
+            r#"check:
+  steps:
+    - cmd: make check
+      working_dir: tests"#,
+        );
+        let spec = with_step_dir
+            .as_rpm_spec(&[], &[], "rocky", "1.0.0", BuildTarget::Rpm, &mut logger)
+            .render()
+            .unwrap();
+        assert!(spec.contains("%check\ncd \"${PKGER_BLD_DIR}\"\n(cd \"tests\" && make check)\n\n"));
+
+        let without_check = recipe("");
+        let spec = without_check
+            .as_rpm_spec(&[], &[], "rocky", "1.0.0", BuildTarget::Rpm, &mut logger)
+            .render()
+            .unwrap();
+        assert!(spec.contains("%check\n%pre\n"));
+        let pkgbuild = without_check
+            .as_pkgbuild(
+                "arch",
+                &[],
+                Checksum::Sha256,
+                &[],
+                "1.0.0",
+                BuildTarget::Pkg,
+                &mut logger,
+            )
+            .render()
+            .unwrap();
+        assert!(!pkgbuild.contains("check()"));
+    }
+
+    #[test]
+    fn copies_noextract_sources_in_pkgbuild() {
+        let mut logger: BoxedCollector = Box::new(crate::log::Logger::stdout(None));
+        let rep = RecipeRep::from_yaml_bytes(
+            br#"
+metadata:
+  name: test-noextract
+  version: "1.0.0"
+  description: a recipe with a source that is not extracted
+  license: MIT
+  pkg:
+    noextract: ["/usr/lib/firmware/firmware.bin.gz"]
+build:
+  steps: []"#,
+        )
+        .unwrap();
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        // same sources as the ones generated by the PKG build
+        let sources = [
+            "/tmp/test-noextract/bld/test-noextract-1.0.0.tar.gz".to_string(),
+            "/tmp/test-noextract/bld/firmware.bin.gz".to_string(),
+        ];
+        let pkgbuild = recipe
+            .as_pkgbuild(
+                "arch",
+                &sources,
+                Checksum::Sha256,
+                &["SKIP".to_string(), "SKIP".to_string()],
+                "1.0.0",
+                BuildTarget::Pkg,
+                &mut logger,
+            )
+            .render()
+            .unwrap();
+        assert_eq!(
+            pkgbuild,
+            r#"pkgname=('test-noextract')
+pkgver=1.0.0
+pkgrel=0
+arch=('any')
+pkgdesc='a recipe with a source that is not extracted'
+license=('MIT')
+source=('/tmp/test-noextract/bld/test-noextract-1.0.0.tar.gz' '/tmp/test-noextract/bld/firmware.bin.gz')
+noextract=('firmware.bin.gz')
+sha256sums=('SKIP' 'SKIP')
+
+package() {
+    tar xvf /tmp/test-noextract/bld/test-noextract-1.0.0.tar.gz -C $pkgdir
+    mkdir -p "$pkgdir/usr/lib/firmware"
+    cp -a "/tmp/test-noextract/bld/firmware.bin.gz" "$pkgdir/usr/lib/firmware/firmware.bin.gz"
+
+}
+"#
+        );
+
+        let pkgbuild = recipe
+            .as_pkgbuild(
+                "arch",
+                &sources[..1],
+                Checksum::Sha256,
+                &["SKIP".to_string()],
+                "1.0.0",
+                BuildTarget::Pkg,
+                &mut logger,
+            )
+            .render()
+            .unwrap();
+        assert!(!pkgbuild.contains("noextract"));
+    }
+
+    #[test]
+    fn renders_configure_steps_as_prepare() {
+        let rep = RecipeRep::from_yaml_bytes(
+            br#"
+metadata:
+  name: test-configure
+  version: "1.0.0"
+  description: a recipe with a configure script
+  license: MIT
+configure:
+  steps:
+    - cmd: ./configure --prefix=/usr
+    - cmd: ./configure --with-rpm
+      rpm: true
+    - cmd: ./configure --old
+      versions: [0.9.0]
+build:
+  steps:
+    - cmd: make"#,
+        )
+        .unwrap();
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        let mut logger: BoxedCollector = Box::new(crate::log::Logger::stdout(None));
+
+        let pkgbuild = recipe
+            .as_pkgbuild(
+                "arch",
+                &[],
+                Checksum::Sha256,
+                &[],
+                "1.0.0",
+                BuildTarget::Pkg,
+                &mut logger,
+            )
+            .render()
+            .unwrap();
+        assert!(pkgbuild.contains(
+            "prepare() {\n    cd \"${PKGER_BLD_DIR}\"\n    ./configure --prefix=/usr\n}\n"
+        ));
+
+        let apkbuild = recipe
+            .as_apkbuild(
+                "alpine",
+                &[],
+                Path::new("/tmp"),
+                "1.0.0",
+                BuildTarget::Apk,
+                &mut logger,
+            )
+            .render()
+            .unwrap();
+        assert!(apkbuild.contains(
+            "prepare() {\n    cd \"${PKGER_BLD_DIR}\"\n    ./configure --prefix=/usr\n}\n"
+        ));
+
+        let mut without_configure = recipe;
+        without_configure.configure_script = None;
+        let pkgbuild = without_configure
+            .as_pkgbuild(
+                "arch",
+                &[],
+                Checksum::Sha256,
+                &[],
+                "1.0.0",
+                BuildTarget::Pkg,
+                &mut logger,
+            )
+            .render()
+            .unwrap();
+        assert!(!pkgbuild.contains("prepare()"));
+    }
+
+    #[test]
+    fn passes_arch_qualified_deb_deps_through() {
+        let recipe = r#"
+metadata:
+  name: test-arch-deps
+  version: "1.0.0"
+  description: a recipe with architecture qualified dependencies
+  license: MIT
+  depends:
+    - libfoo:amd64 [amd64]
+    - "libbar:any (>= 1.0)"
+build:
+  steps: []"#;
+        let rep = RecipeRep::from_yaml_bytes(recipe.as_bytes()).unwrap();
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        let mut logger: BoxedCollector = Box::new(crate::log::Logger::stdout(None));
+
+        let control = recipe
+            .as_deb_control("debian", None, "1.0.0", BuildTarget::Deb, &mut logger)
+            .render()
+            .unwrap();
+        let depends = control
+            .lines()
+            .find_map(|line| line.strip_prefix("Depends:"))
+            .unwrap();
+        let mut depends: Vec<_> = depends.split(',').map(str::trim).collect();
+        depends.sort_unstable();
+        assert_eq!(depends, vec!["libbar:any (>= 1.0)", "libfoo:amd64 [amd64]"]);
+    }
+
+    #[test]
+    fn sanitizes_deb_relation_names() {
+        let recipe = r#"
+metadata:
+  name: test_underscores
+  version: "1.0.0"
+  description: a recipe with underscores in dependency names
+  license: MIT
+  depends:
+    - foo_bar
+    - "lib_baz:any (>= 1.0~rc1) | lib_qux"
+  provides: [test_virtual]
+  deb:
+    breaks: [old_package]
+build:
+  steps: []"#;
+        let rep = RecipeRep::from_yaml_bytes(recipe.as_bytes()).unwrap();
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        let mut logger: BoxedCollector = Box::new(crate::log::Logger::stdout(None));
+
+        let control = recipe
+            .as_deb_control("debian", None, "1.0.0", BuildTarget::Deb, &mut logger)
+            .render()
+            .unwrap();
+        let field = |name: &str| {
+            let mut entries: Vec<_> = control
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .unwrap()
+                .split(',')
+                .map(str::trim)
+                .collect();
+            entries.sort_unstable();
+            entries
+        };
+        assert_eq!(
+            field("Depends:"),
+            vec!["foo-bar", "lib-baz:any (>= 1.0~rc1) | lib-qux"]
+        );
+        assert_eq!(field("Provides:"), vec!["test-virtual"]);
+        assert_eq!(field("Breaks:"), vec!["old-package"]);
+        assert_eq!(field("Package:"), vec!["test-underscores"]);
+    }
+
+    #[test]
+    fn maps_breaks_to_every_format() {
+        let recipe = r#"
+metadata:
+  name: test-breaks
+  version: "1.0.0"
+  description: a recipe that breaks other packages
+  license: MIT
+  breaks: ["old_tool < 2.0", "other-tool", "bad ~ 1.0"]
+build:
+  steps: []"#;
+        let rep = RecipeRep::from_yaml_bytes(recipe.as_bytes()).unwrap();
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        let mut logger: BoxedCollector = Box::new(crate::log::Logger::stdout(None));
+
+        assert!(recipe.warnings().iter().any(|warning| warning
+            .starts_with("breaks: invalid version constraint")
+            && warning.contains("`bad ~ 1.0`")));
+
+        let control = recipe
+            .as_deb_control("debian", None, "1.0.0", BuildTarget::Deb, &mut logger)
+            .render()
+            .unwrap();
+        let breaks = control
+            .lines()
+            .find(|line| line.starts_with("Breaks:"))
+            .unwrap();
+        assert!(breaks.contains("old-tool (<< 2.0)"));
+        assert!(breaks.contains("other-tool"));
+        assert!(!breaks.contains("bad"));
+
+        let spec = recipe
+            .as_rpm_spec(&[], &[], "rocky", "1.0.0", BuildTarget::Rpm, &mut logger)
+            .render()
+            .unwrap();
+        assert!(spec.contains("Conflicts:     old_tool < 2.0\n"));
+        assert!(spec.contains("Conflicts:     other-tool\n"));
+
+        let pkgbuild = recipe
+            .as_pkgbuild(
+                "arch",
+                &[],
+                Checksum::Sha256,
+                &[],
+                "1.0.0",
+                BuildTarget::Pkg,
+                &mut logger,
+            )
+            .render()
+            .unwrap();
+        assert!(pkgbuild.contains("conflicts=('old_tool<2.0' 'other-tool')"));
+
+        let apkbuild = recipe
+            .as_apkbuild(
+                "alpine",
+                &[],
+                Path::new("/tmp"),
+                "1.0.0",
+                BuildTarget::Apk,
+                &mut logger,
+            )
+            .render()
+            .unwrap();
+        assert!(apkbuild.contains("depends=\"!old_tool<2.0 !other-tool\""));
+    }
+
+    #[test]
+    fn maps_obsoletes_to_deb_relations() {
+        let recipe = r#"
+metadata:
+  name: test-obsoletes
+  version: "1.0.0"
+  description: a recipe that obsoletes other packages
+  license: MIT
+  obsoletes: ["old-package"]
+build:
+  steps: []"#;
+        let rep = RecipeRep::from_yaml_bytes(recipe.as_bytes()).unwrap();
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        let mut logger: BoxedCollector = Box::new(crate::log::Logger::stdout(None));
+
+        let control = recipe
+            .as_deb_control("debian", None, "1.0.0", BuildTarget::Deb, &mut logger)
+            .render()
+            .unwrap();
+        let has_field = |manifest: &str, field: &str| {
+            manifest
+                .lines()
+                .any(|line| line.starts_with(field) && line.ends_with("old-package"))
+        };
+        assert!(has_field(&control, "Breaks:"));
+        assert!(has_field(&control, "Replaces:"));
+
+        let spec = recipe
+            .as_rpm_spec(&[], &[], "rocky", "1.0.0", BuildTarget::Rpm, &mut logger)
+            .render()
+            .unwrap();
+        assert!(has_field(&spec, "obsoletes:"));
+    }
+
+    #[test]
+    fn validates_spdx_expressions() {
+        assert!(is_spdx_expression("MIT"));
+        assert!(is_spdx_expression("GPL-2.0-or-later"));
+        assert!(is_spdx_expression("(MIT OR Apache-2.0) AND BSD-3-Clause"));
+        assert!(is_spdx_expression("LicenseRef-Proprietary"));
+        assert!(!is_spdx_expression(""));
+        assert!(!is_spdx_expression("MIT, but not really"));
+    }
+
+    #[test]
+    fn renders_native_rpm_prep() {
+        let recipe = |native_prep: bool| {
+            let recipe = format!(
+                r#"
+metadata:
+  name: test-prep
+  version: "1.0.0"
+  description: a recipe patched by rpm
+  license: MIT
+  patches:
+    - fix.patch
+    - patch: https://example.com/patches/rocky.patch
+      strip: 1
+      images: [ rocky ]
+    - patch: patches/debian.patch
+      images: [ debian ]
+  rpm:
+    native_prep: {}
+build:
+  steps: []"#,
+                native_prep
+            );
+            let rep = RecipeRep::from_yaml_bytes(recipe.as_bytes()).unwrap();
+            Recipe::new(rep, PathBuf::new()).unwrap()
+        };
+        let render = |recipe: &Recipe, image: &str| {
+            recipe
+                .as_rpm_prep_spec(
+                    "test-prep-1.0.0-sources.tar.gz",
+                    image,
+                    "1.0.0",
+                    Path::new("/tmp/test-prep/bld"),
+                )
+                .render()
+                .unwrap()
+        };
+
+        let native = recipe(true);
+        assert!(native.rpm_native_prep(BuildTarget::Rpm));
+        assert!(!native.rpm_native_prep(BuildTarget::Deb));
+        assert!(!recipe(false).rpm_native_prep(BuildTarget::Rpm));
+
+        let spec = render(&native, "debian");
+        assert!(spec.contains(
+            "Patch0:        fix.patch\nPatch1:        debian.patch\nSource0:       test-prep-1.0.0-sources.tar.gz\n"
+        ));
+        assert!(spec.contains("%prep\n%autosetup -c -p0\ncp -a . \"/tmp/test-prep/bld\"\n\n"));
+
+        let spec = render(&native, "rocky");
+        assert!(spec.contains("Patch0:        fix.patch\nPatch1:        rocky.patch\n"));
+        assert!(spec.contains(
+            "%prep\n%autosetup -c -N\n%autopatch -p0 -m 0 -M 0\n%autopatch -p1 -m 1 -M 1\ncp -a . \"/tmp/test-prep/bld\"\n\n"
+        ));
+
+        let spec = render(&native, "centos");
+        assert!(!spec.contains("Patch1:"));
+        assert!(spec.contains("%prep\n%autosetup -c -p0\n"));
+    }
+
+    #[test]
+    fn resolves_recipes_dir_relative_paths() {
+        let recipes_dir = Path::new("/home/user/recipes");
+        let recipe_dir = recipes_dir.join("pkger");
+        let resolve = |path| resolve_path(path, &recipe_dir, recipes_dir);
+
+        assert_eq!(
+            resolve("//common/license.patch").unwrap(),
+            recipes_dir.join("common/license.patch")
+        );
+        assert_eq!(
+            resolve("//common/../shared/./fix.patch").unwrap(),
+            recipes_dir.join("shared/fix.patch")
+        );
+        assert_eq!(resolve("fix.patch").unwrap(), recipe_dir.join("fix.patch"));
+        assert_eq!(
+            resolve("/etc/pkger/fix.patch").unwrap(),
+            PathBuf::from("/etc/pkger/fix.patch")
+        );
+
+        let err = resolve("//common/../../etc/passwd").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "path `//common/../../etc/passwd` points outside of the recipes directory"
+        );
+        assert!(resolve("//..").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_symlinks_out_of_recipes_dir() {
+        let dir = tempdir::TempDir::new("pkger-recipes").unwrap();
+        let recipes_dir = dir.path().join("recipes");
+        let recipe_dir = recipes_dir.join("pkger");
+        fs::create_dir_all(recipes_dir.join("common")).unwrap();
+        fs::create_dir_all(&recipe_dir).unwrap();
+        fs::write(dir.path().join("secret"), "secret").unwrap();
+        fs::write(recipes_dir.join("common/fix.patch"), "patch").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("secret"), recipes_dir.join("common/link"))
+            .unwrap();
+
+        assert_eq!(
+            resolve_path("//common/fix.patch", &recipe_dir, &recipes_dir).unwrap(),
+            recipes_dir.join("common/fix.patch")
+        );
+        assert_eq!(
+            resolve_path("//common/link", &recipe_dir, &recipes_dir)
+                .unwrap_err()
+                .to_string(),
+            "path `//common/link` points outside of the recipes directory"
+        );
+    }
 }
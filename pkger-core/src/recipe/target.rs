@@ -1,3 +1,4 @@
+use crate::proxy::ProxyOverride;
 use crate::recipe::metadata::{BuildTarget, ImageTarget, Os};
 
 use serde::{Deserialize, Serialize};
@@ -13,6 +14,10 @@ impl RecipeTarget {
         Self { name, image_target }
     }
 
+    pub fn image_target(&self) -> &ImageTarget {
+        &self.image_target
+    }
+
     pub fn build_target(&self) -> &BuildTarget {
         &self.image_target.build_target
     }
@@ -28,4 +33,26 @@ impl RecipeTarget {
     pub fn image_os(&self) -> &Option<Os> {
         &self.image_target.os
     }
+
+    pub fn image_network(&self) -> Option<&str> {
+        self.image_target.network.as_deref()
+    }
+
+    /// Returns the name of the image in the container runtime, see
+    /// [ImageTarget::runtime_image](ImageTarget::runtime_image).
+    pub fn runtime_image(&self) -> String {
+        self.image_target.runtime_image()
+    }
+
+    pub fn image_platform(&self) -> Option<&str> {
+        self.image_target.platform.as_deref()
+    }
+
+    pub fn image_reference(&self) -> Option<&str> {
+        self.image_target.reference.as_deref()
+    }
+
+    pub fn image_proxy(&self) -> Option<&ProxyOverride> {
+        self.image_target.proxy.as_ref()
+    }
 }
@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+
+pub const USERNAME_ENV: &str = "PKGER_REGISTRY_USERNAME";
+pub const PASSWORD_ENV: &str = "PKGER_REGISTRY_PASSWORD";
+pub const TOKEN_ENV: &str = "PKGER_REGISTRY_TOKEN";
+
+/// Registry used for images without an explicit registry in their name.
+pub const DEFAULT_REGISTRY: &str = "docker.io";
+
+/// Credentials used to pull base images from a container registry.
+#[derive(Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RegistryAuth {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Registry like `registry.example.com:5000` these credentials apply to. If not set the
+    /// credentials are used for every registry without its own entry.
+    pub registry: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+// Credentials must never end up in the logs so they are not included in the debug output.
+impl fmt::Debug for RegistryAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegistryAuth")
+            .field("registry", &self.registry)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl RegistryAuth {
+    /// Reads the credentials from `PKGER_REGISTRY_USERNAME`, `PKGER_REGISTRY_PASSWORD` and
+    /// `PKGER_REGISTRY_TOKEN` environment variables.
+    pub fn from_env() -> Option<Self> {
+        let auth = RegistryAuth {
+            registry: None,
+            username: env::var(USERNAME_ENV).ok(),
+            password: env::var(PASSWORD_ENV).ok(),
+            token: env::var(TOKEN_ENV).ok(),
+        };
+
+        if auth.is_empty() {
+            None
+        } else {
+            Some(auth)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.username.is_none() && self.password.is_none() && self.token.is_none()
+    }
+
+    pub fn as_docker(&self) -> docker_api::opts::RegistryAuth {
+        use docker_api::opts::RegistryAuth;
+        if let Some(token) = &self.token {
+            return RegistryAuth::token(token);
+        }
+
+        let mut builder = RegistryAuth::builder();
+        if let Some(username) = &self.username {
+            builder = builder.username(username);
+        }
+        if let Some(password) = &self.password {
+            builder = builder.password(password);
+        }
+        if let Some(registry) = &self.registry {
+            builder = builder.server_address(registry);
+        }
+        builder.build()
+    }
+
+    pub fn as_podman(&self) -> podman_api::opts::RegistryAuth {
+        use podman_api::opts::RegistryAuth;
+        if let Some(token) = &self.token {
+            return RegistryAuth::token(token);
+        }
+
+        let mut builder = RegistryAuth::builder();
+        if let Some(username) = &self.username {
+            builder = builder.username(username);
+        }
+        if let Some(password) = &self.password {
+            builder = builder.password(password);
+        }
+        if let Some(registry) = &self.registry {
+            builder = builder.server_address(registry);
+        }
+        builder.build()
+    }
+}
+
+/// Returns the credentials for `registry`. Entries without a registry match every registry
+/// that has no entry of its own. If nothing matches the credentials from environment variables
+/// are used.
+pub fn find_auth(auths: &[RegistryAuth], registry: &str) -> Option<RegistryAuth> {
+    auths
+        .iter()
+        .find(|auth| auth.registry.as_deref() == Some(registry))
+        .or_else(|| auths.iter().find(|auth| auth.registry.is_none()))
+        .cloned()
+        .or_else(RegistryAuth::from_env)
+}
+
+/// Returns the registry of an image reference like `registry.example.com/team/image:tag`.
+pub fn registry_of(image: &str) -> &str {
+    match image.split_once('/') {
+        Some((first, _)) if first.contains('.') || first.contains(':') || first == "localhost" => {
+            first
+        }
+        _ => DEFAULT_REGISTRY,
+    }
+}
+
+/// Returns the base images referenced by `FROM` instructions of a Dockerfile. Build stages
+/// referenced by their alias and `scratch` are skipped.
+pub fn base_images(dockerfile: &str) -> Vec<String> {
+    let mut stages = vec![];
+    let mut images = vec![];
+
+    for line in dockerfile.lines() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some(instruction) if instruction.eq_ignore_ascii_case("from") => {}
+            _ => continue,
+        }
+        let image = match words.find(|word| !word.starts_with("--")) {
+            Some(image) => image,
+            None => continue,
+        };
+
+        if image != "scratch" && !stages.iter().any(|stage| stage == image) {
+            images.push(image.to_string());
+        }
+
+        if let (Some(as_), Some(alias)) = (words.next(), words.next()) {
+            if as_.eq_ignore_ascii_case("as") {
+                stages.push(alias.to_string());
+            }
+        }
+    }
+
+    images
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_base_images() {
+        let dockerfile = r#"
+FROM --platform=linux/amd64 registry.example.com/base/rust:1.60 AS builder
+RUN cargo build
+from builder as tester
+FROM scratch
+FROM debian:latest
+"#;
+        assert_eq!(
+            base_images(dockerfile),
+            vec!["registry.example.com/base/rust:1.60", "debian:latest"]
+        );
+    }
+
+    #[test]
+    fn finds_registry_of_image() {
+        assert_eq!(registry_of("debian:latest"), DEFAULT_REGISTRY);
+        assert_eq!(registry_of("rockylinux/rockylinux"), DEFAULT_REGISTRY);
+        assert_eq!(
+            registry_of("registry.example.com/base/rust"),
+            "registry.example.com"
+        );
+        assert_eq!(registry_of("localhost:5000/rust"), "localhost:5000");
+    }
+
+    #[test]
+    fn finds_auth_for_registry() {
+        let default = RegistryAuth {
+            username: Some("user".into()),
+            password: Some("secret".into()),
+            ..Default::default()
+        };
+        let private = RegistryAuth {
+            registry: Some("registry.example.com".into()),
+            token: Some("token".into()),
+            ..Default::default()
+        };
+        let auths = vec![default.clone(), private.clone()];
+
+        assert_eq!(find_auth(&auths, "registry.example.com"), Some(private));
+        assert_eq!(find_auth(&auths, DEFAULT_REGISTRY), Some(default));
+    }
+
+    #[test]
+    fn redacts_credentials() {
+        let auth = RegistryAuth {
+            username: Some("user".into()),
+            password: Some("secret".into()),
+            token: Some("token".into()),
+            ..Default::default()
+        };
+        let debug = format!("{:?}", auth);
+        assert!(debug.contains("user"));
+        assert!(!debug.contains("secret"));
+        assert!(!debug.contains("\"token\""));
+    }
+}
@@ -0,0 +1,383 @@
+//! Generation of package repository metadata. Repository tools run in throwaway containers so
+//! that they don't have to be installed on the host.
+use crate::build::image::pull_image;
+use crate::gpg::GpgKey;
+use crate::image::Image;
+use crate::log::{debug, info, warning, BoxedCollector};
+use crate::recipe::BuildTarget;
+use crate::registry::{self, RegistryAuth};
+use crate::runtime::container::{Container, CreateOpts, ExecOpts};
+use crate::runtime::{DockerContainer, PodmanContainer, RuntimeConnector};
+use crate::{err, unix_timestamp, ErrContext, Result};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory in the container with the packages of the repository.
+const REPO_DIR: &str = "/repo";
+/// Directory with repository metadata generated by `createrepo_c`.
+const RPM_REPODATA: &str = "repodata";
+/// Files of a flat APT repository generated next to the packages.
+const DEB_INDEX_FILES: &[&str] = &["Packages", "Packages.gz", "Release"];
+/// Files with signatures of the `Release` file and the public key.
+const DEB_SIGNATURE_FILES: &[&str] = &["InRelease", "Release.gpg", "Release.key"];
+/// File with the passphrase of the GPG key in the container, so that the passphrase is never a
+/// part of a command.
+const GPG_PASSPHRASE_FILE: &str = "/tmp/GPG-PASSPHRASE";
+
+/// A container running the tools of a repository.
+struct RepoContainer {
+    container: Box<dyn Container + Send + Sync>,
+}
+
+impl RepoContainer {
+    async fn spawn(
+        runtime: &RuntimeConnector,
+        image: &str,
+        registry_auth: &[RegistryAuth],
+        logger: &mut BoxedCollector,
+    ) -> Result<Self> {
+        info!(logger => "pulling image '{}'", image);
+        let auth = registry::find_auth(registry_auth, registry::registry_of(image));
+        pull_image(runtime, image, auth.as_ref(), logger).await?;
+
+        let mut container: Box<dyn Container + Send + Sync> = match runtime {
+            RuntimeConnector::Docker(docker) => Box::new(DockerContainer::new(docker.clone())),
+            RuntimeConnector::Podman(podman) => Box::new(PodmanContainer::new(podman.clone())),
+        };
+        container
+            .spawn(
+                &CreateOpts::new(image)
+                    .name(format!("pkger-repo-{}", unix_timestamp().as_millis()))
+                    .cmd(["sleep infinity"])
+                    .entrypoint(["/bin/sh", "-c"])
+                    .working_dir(REPO_DIR),
+                logger,
+            )
+            .await
+            .context("failed to spawn repository container")?;
+
+        Ok(Self { container })
+    }
+
+    async fn upload_packages(&self, archive: Vec<u8>, logger: &mut BoxedCollector) -> Result<()> {
+        self.container
+            .upload_and_extract_archive(archive, Path::new(REPO_DIR), "packages.tar", logger)
+            .await
+            .context("failed to upload packages")
+    }
+
+    async fn exec(&self, cmd: &str, logger: &mut BoxedCollector) -> Result<()> {
+        self.container
+            .exec(
+                &ExecOpts::default()
+                    .cmd(cmd)
+                    .working_dir(Path::new(REPO_DIR)),
+                logger,
+            )
+            .await?
+            .as_result()
+            .map(|_| ())
+    }
+
+    /// Uploads the `gpg_key` and imports it to the database in the container.
+    async fn import_gpg_key(&self, gpg_key: &GpgKey, logger: &mut BoxedCollector) -> Result<()> {
+        info!(logger => "importing GPG key");
+        let key = fs::read(gpg_key.path()).context("failed reading the gpg key")?;
+        let tmp = Path::new("/tmp");
+        self.container
+            .upload_files(
+                vec![
+                    (PathBuf::from("./GPG-SIGN-KEY").as_path(), key.as_slice()),
+                    (
+                        PathBuf::from("./GPG-PASSPHRASE").as_path(),
+                        gpg_key.pass().as_bytes(),
+                    ),
+                ],
+                tmp,
+                logger,
+            )
+            .await
+            .context("failed to upload gpg key")?;
+
+        self.exec(
+            &format!(
+                "gpg --batch --pinentry-mode=loopback --passphrase-file {} --import {}",
+                GPG_PASSPHRASE_FILE,
+                tmp.join("GPG-SIGN-KEY").display()
+            ),
+            logger,
+        )
+        .await
+        .context("failed to import gpg key")
+    }
+
+    /// Replaces the `name` directory in `dir` on the host with the one from the repository.
+    async fn download_dir(
+        &self,
+        name: &str,
+        dir: &Path,
+        logger: &mut BoxedCollector,
+    ) -> Result<()> {
+        let dest = dir.join(name);
+        if dest.exists() {
+            fs::remove_dir_all(&dest)
+                .with_context(|| format!("failed to remove `{}`", dest.display()))?;
+        }
+        fs::create_dir_all(&dest)
+            .with_context(|| format!("failed to create `{}`", dest.display()))?;
+        self.container
+            .download_files(&Path::new(REPO_DIR).join(name), &dest, logger)
+            .await
+            .with_context(|| format!("failed to download `{}`", name))
+    }
+
+    /// Copies the file `name` from the repository to `dir` on the host.
+    async fn download_file(
+        &self,
+        name: &str,
+        dir: &Path,
+        logger: &mut BoxedCollector,
+    ) -> Result<()> {
+        self.container
+            .download_files(&Path::new(REPO_DIR).join(name), dir, logger)
+            .await
+            .with_context(|| format!("failed to download `{}`", name))
+    }
+
+    async fn remove(self, logger: &mut BoxedCollector) {
+        if let Err(e) = self.container.remove(logger).await {
+            warning!(logger => "failed to remove repository container, reason: {:?}", e);
+        }
+    }
+}
+
+/// Creates a tar archive of all files in `dir` with `extension` keeping their paths relative to
+/// `dir`. Returns the archive and the number of files in it.
+fn packages_archive(dir: &Path, extension: &str) -> Result<(Vec<u8>, usize)> {
+    fn collect(dir: &Path, extension: &str, files: &mut Vec<PathBuf>) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                collect(&path, extension, files)?;
+            } else if path.extension().map(|ext| ext == extension) == Some(true) {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = vec![];
+    collect(dir, extension, &mut files)
+        .with_context(|| format!("failed to read directory `{}`", dir.display()))?;
+    files.sort_unstable();
+
+    let mut archive = tar::Builder::new(vec![]);
+    for file in &files {
+        let name = file.strip_prefix(dir).unwrap_or(file);
+        archive
+            .append_path_with_name(file, name)
+            .with_context(|| format!("failed to add `{}` to archive", file.display()))?;
+    }
+    archive.finish()?;
+
+    Ok((archive.into_inner()?, files.len()))
+}
+
+/// Generates YUM/DNF repository metadata for all RPM packages in `dir` and saves it in the
+/// `repodata` directory. If a `gpg_key` is provided `repomd.xml` is signed with it and the
+/// public key is saved as `repomd.xml.key`.
+pub async fn create_rpm_repo(
+    runtime: &RuntimeConnector,
+    dir: &Path,
+    gpg_key: Option<&GpgKey>,
+    registry_auth: &[RegistryAuth],
+    logger: &mut BoxedCollector,
+) -> Result<()> {
+    let (archive, count) = packages_archive(dir, "rpm")?;
+    if count == 0 {
+        return err!("no RPM packages found in `{}`", dir.display());
+    }
+    info!(logger => "creating RPM repository of {} packages in `{}`", count, dir.display());
+
+    let container = RepoContainer::spawn(
+        runtime,
+        Image::simple(BuildTarget::Rpm).image,
+        registry_auth,
+        logger,
+    )
+    .await?;
+    let result = create_rpm_repo_in(&container, archive, dir, gpg_key, logger).await;
+    container.remove(logger).await;
+    result
+}
+
+async fn create_rpm_repo_in(
+    container: &RepoContainer,
+    archive: Vec<u8>,
+    dir: &Path,
+    gpg_key: Option<&GpgKey>,
+    logger: &mut BoxedCollector,
+) -> Result<()> {
+    container.upload_packages(archive, logger).await?;
+
+    info!(logger => "installing createrepo_c");
+    container
+        .exec("dnf install -y createrepo_c gnupg2", logger)
+        .await
+        .context("failed to install createrepo_c")?;
+
+    info!(logger => "generating repository metadata");
+    container
+        .exec(&format!("createrepo_c {}", REPO_DIR), logger)
+        .await
+        .context("failed to generate repository metadata")?;
+
+    if let Some(gpg_key) = gpg_key {
+        container.import_gpg_key(gpg_key, logger).await?;
+        info!(logger => "signing repomd.xml");
+        debug!(logger => "signing with key '{}'", gpg_key.name());
+        container
+            .exec(
+                &format!(
+                    "gpg --batch --yes --pinentry-mode=loopback --passphrase-file {0} --local-user '{1}' --detach-sign --armor {2}/repomd.xml && gpg --export --armor '{1}' > {2}/repomd.xml.key",
+                    GPG_PASSPHRASE_FILE,
+                    gpg_key.name(),
+                    RPM_REPODATA,
+                ),
+                logger,
+            )
+            .await
+            .context("failed to sign repository metadata")?;
+    }
+
+    container.download_dir(RPM_REPODATA, dir, logger).await
+}
+
+/// Generates a flat APT repository with `Packages`, `Packages.gz` and `Release` files for all
+/// Debian packages in `dir`. If a `gpg_key` is provided the `Release` file is signed as
+/// `InRelease` and `Release.gpg` and the public key is saved as `Release.key`.
+pub async fn create_deb_repo(
+    runtime: &RuntimeConnector,
+    dir: &Path,
+    gpg_key: Option<&GpgKey>,
+    registry_auth: &[RegistryAuth],
+    logger: &mut BoxedCollector,
+) -> Result<()> {
+    let (archive, count) = packages_archive(dir, "deb")?;
+    if count == 0 {
+        return err!("no Debian packages found in `{}`", dir.display());
+    }
+    info!(logger => "creating APT repository of {} packages in `{}`", count, dir.display());
+
+    let container = RepoContainer::spawn(
+        runtime,
+        Image::simple(BuildTarget::Deb).image,
+        registry_auth,
+        logger,
+    )
+    .await?;
+    let result = create_deb_repo_in(&container, archive, dir, gpg_key, logger).await;
+    container.remove(logger).await;
+    result
+}
+
+async fn create_deb_repo_in(
+    container: &RepoContainer,
+    archive: Vec<u8>,
+    dir: &Path,
+    gpg_key: Option<&GpgKey>,
+    logger: &mut BoxedCollector,
+) -> Result<()> {
+    container.upload_packages(archive, logger).await?;
+
+    info!(logger => "installing dpkg-dev and apt-utils");
+    container
+        .exec(
+            "apt-get update && DEBIAN_FRONTEND=noninteractive apt-get install -y dpkg-dev apt-utils gnupg",
+            logger,
+        )
+        .await
+        .context("failed to install repository tools")?;
+
+    info!(logger => "generating repository index");
+    container
+        .exec(
+            "dpkg-scanpackages --multiversion . /dev/null > Packages && gzip -9kf Packages && apt-ftparchive release . > Release",
+            logger,
+        )
+        .await
+        .context("failed to generate repository index")?;
+
+    let mut files = DEB_INDEX_FILES.to_vec();
+    if let Some(gpg_key) = gpg_key {
+        container.import_gpg_key(gpg_key, logger).await?;
+        info!(logger => "signing Release");
+        debug!(logger => "signing with key '{}'", gpg_key.name());
+        container
+            .exec(
+                &format!(
+                    "gpg --batch --yes --pinentry-mode=loopback --passphrase-file {0} --local-user '{1}' --clearsign -o InRelease Release && gpg --batch --yes --pinentry-mode=loopback --passphrase-file {0} --local-user '{1}' --armor --detach-sign -o Release.gpg Release && gpg --export --armor '{1}' > Release.key",
+                    GPG_PASSPHRASE_FILE,
+                    gpg_key.name(),
+                ),
+                logger,
+            )
+            .await
+            .context("failed to sign repository index")?;
+        files.extend(DEB_SIGNATURE_FILES);
+    } else {
+        // signatures of a previous run would no longer match the new `Release`
+        for file in DEB_SIGNATURE_FILES {
+            let path = dir.join(file);
+            if path.exists() {
+                fs::remove_file(&path)
+                    .with_context(|| format!("failed to remove `{}`", path.display()))?;
+            }
+        }
+    }
+
+    for file in files {
+        container.download_file(file, dir, logger).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn archives_only_packages() {
+        let dir = TempDir::new("pkger-repo").unwrap();
+        let arch_dir = dir.path().join("x86_64");
+        fs::create_dir_all(&arch_dir).unwrap();
+        fs::create_dir_all(dir.path().join(RPM_REPODATA)).unwrap();
+        fs::write(arch_dir.join("pkger-0.11.0-0.x86_64.rpm"), "rpm").unwrap();
+        fs::write(dir.path().join("pkger-0.11.0-0.noarch.rpm"), "rpm").unwrap();
+        fs::write(dir.path().join("pkger_0.11.0-0_amd64.deb"), "deb").unwrap();
+        fs::write(dir.path().join(RPM_REPODATA).join("repomd.xml"), "xml").unwrap();
+
+        let (archive, count) = packages_archive(dir.path(), "rpm").unwrap();
+        assert_eq!(count, 2);
+        let mut archive = tar::Archive::new(&archive[..]);
+        let names: Vec<_> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().to_path_buf())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                PathBuf::from("pkger-0.11.0-0.noarch.rpm"),
+                PathBuf::from("x86_64/pkger-0.11.0-0.x86_64.rpm"),
+            ]
+        );
+
+        let (_, count) = packages_archive(dir.path(), "deb").unwrap();
+        assert_eq!(count, 1);
+        let (_, count) = packages_archive(dir.path(), "apk").unwrap();
+        assert_eq!(count, 0);
+    }
+}
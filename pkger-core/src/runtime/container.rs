@@ -3,12 +3,16 @@ use crate::recipe::Env;
 use anyhow::{anyhow, Result};
 
 use async_trait::async_trait;
+use futures::Stream;
 use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::str;
 
 /// Length of significant characters of a container ID.
 static CONTAINER_ID_LEN: usize = 12;
 static DEFAULT_SHELL: &str = "/bin/sh";
+/// Mount options of tmpfs mounts, builds need to execute files from the build directories.
+static TMPFS_OPTIONS: &str = "rw,exec,mode=1777";
 
 pub(crate) fn truncate(id: &str) -> &str {
     if id.len() > CONTAINER_ID_LEN {
@@ -27,6 +31,9 @@ pub fn fix_name(name: &str) -> String {
         .collect()
 }
 
+/// Chunks of a tar archive copied out of a container.
+pub type ChunkStream<'a> = Pin<Box<dyn Stream<Item = Result<Vec<u8>>> + Send + 'a>>;
+
 #[derive(Debug, Default)]
 pub struct Output<T> {
     pub stdout: Vec<T>,
@@ -54,6 +61,15 @@ pub struct CreateOpts {
     volumes: Option<Vec<String>>,
     env: Option<Env>,
     working_dir: Option<String>,
+    memory: Option<u64>,
+    nano_cpus: Option<u64>,
+    network: Option<String>,
+    privileged: bool,
+    cap_add: Option<Vec<String>>,
+    read_only_rootfs: bool,
+    tmpfs: Option<Vec<String>>,
+    dns: Option<Vec<String>>,
+    extra_hosts: Option<Vec<String>>,
 }
 
 impl CreateOpts {
@@ -107,6 +123,69 @@ impl CreateOpts {
         self
     }
 
+    /// Memory limit of the container in bytes.
+    pub fn memory(mut self, memory: u64) -> Self {
+        self.memory = Some(memory);
+        self
+    }
+
+    /// CPU limit of the container in units of 10<sup>-9</sup> CPUs.
+    pub fn nano_cpus(mut self, nano_cpus: u64) -> Self {
+        self.nano_cpus = Some(nano_cpus);
+        self
+    }
+
+    /// Removes the memory and CPU limits of the container.
+    pub fn without_limits(mut self) -> Self {
+        self.memory = None;
+        self.nano_cpus = None;
+        self
+    }
+
+    /// Network the container should connect to, like `host` or a name of a user defined network.
+    pub fn network(mut self, network: impl Into<String>) -> Self {
+        self.network = Some(network.into());
+        self
+    }
+
+    /// Runs the container in privileged mode giving it access to all devices of the host. This
+    /// is a security risk and should only be enabled for builds that can't work without it.
+    pub fn privileged(mut self, privileged: bool) -> Self {
+        self.privileged = privileged;
+        self
+    }
+
+    /// Kernel capabilities added to the container like `SYS_ADMIN`.
+    pub fn cap_add(mut self, capabilities: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.cap_add = Some(capabilities.into_iter().map(|c| c.into()).collect());
+        self
+    }
+
+    /// Mounts the root filesystem of the container as read-only, only the `tmpfs` mounts are
+    /// writable.
+    pub fn read_only_rootfs(mut self, read_only: bool) -> Self {
+        self.read_only_rootfs = read_only;
+        self
+    }
+
+    /// Paths in the container with a writable tmpfs mounted on them.
+    pub fn tmpfs(mut self, paths: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.tmpfs = Some(paths.into_iter().map(|p| p.into()).collect());
+        self
+    }
+
+    /// DNS servers used by the container instead of the ones of the host.
+    pub fn dns(mut self, servers: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.dns = Some(servers.into_iter().map(|s| s.into()).collect());
+        self
+    }
+
+    /// Additional entries of `/etc/hosts` in the container in the form of `host:ip`.
+    pub fn extra_hosts(mut self, hosts: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.extra_hosts = Some(hosts.into_iter().map(|h| h.into()).collect());
+        self
+    }
+
     pub fn build_docker(self) -> docker_api::opts::ContainerCreateOpts {
         let mut builder = docker_api::opts::ContainerCreateOpts::builder().image(self.image);
 
@@ -131,6 +210,33 @@ impl CreateOpts {
         if let Some(working_dir) = self.working_dir {
             builder = builder.working_dir(working_dir);
         }
+        if let Some(memory) = self.memory {
+            builder = builder.memory(memory);
+        }
+        if let Some(nano_cpus) = self.nano_cpus {
+            builder = builder.nano_cpus(nano_cpus);
+        }
+        if let Some(network) = self.network {
+            builder = builder.network_mode(network);
+        }
+        if self.privileged {
+            builder = builder.privileged(true);
+        }
+        if let Some(cap_add) = self.cap_add {
+            builder = builder.capabilities(cap_add);
+        }
+        if self.read_only_rootfs {
+            builder = builder.readonly_rootfs(true);
+        }
+        if let Some(tmpfs) = self.tmpfs {
+            builder = builder.tmpfs(tmpfs.into_iter().map(|path| (path, TMPFS_OPTIONS)));
+        }
+        if let Some(dns) = self.dns {
+            builder = builder.dns(dns);
+        }
+        if let Some(extra_hosts) = self.extra_hosts {
+            builder = builder.extra_hosts(extra_hosts);
+        }
 
         builder.build()
     }
@@ -159,6 +265,61 @@ impl CreateOpts {
             builder = builder.work_dir(working_dir);
             builder = builder.create_working_dir(true);
         }
+        if self.memory.is_some() || self.nano_cpus.is_some() {
+            use podman_api::models::{LinuxCpu, LinuxMemory, LinuxResources};
+            const CPU_PERIOD: u64 = 100_000;
+
+            builder = builder.resource_limits(LinuxResources {
+                memory: self.memory.map(|memory| LinuxMemory {
+                    limit: Some(memory as i64),
+                    ..Default::default()
+                }),
+                cpu: self.nano_cpus.map(|nano_cpus| LinuxCpu {
+                    period: Some(CPU_PERIOD),
+                    quota: Some((nano_cpus * CPU_PERIOD / 1_000_000_000) as i64),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            });
+        }
+        if let Some(network) = self.network {
+            use podman_api::models::{Namespace, PerNetworkOptions};
+
+            builder = match network.as_str() {
+                "bridge" | "host" | "none" | "private" => builder.net_namespace(Namespace {
+                    nsmode: Some(network),
+                    value: None,
+                }),
+                _ => builder.networks([(network, PerNetworkOptions::default())]),
+            };
+        }
+        if self.privileged {
+            builder = builder.privileged(true);
+        }
+        if let Some(cap_add) = self.cap_add {
+            builder = builder.add_capabilities(cap_add);
+        }
+        if self.read_only_rootfs {
+            builder = builder.read_only_filesystem(true);
+        }
+        if let Some(tmpfs) = self.tmpfs {
+            use podman_api::models::ContainerMount;
+
+            builder = builder.mounts(tmpfs.into_iter().map(|path| ContainerMount {
+                destination: Some(path),
+                source: Some("tmpfs".to_string()),
+                _type: Some("tmpfs".to_string()),
+                options: Some(TMPFS_OPTIONS.split(',').map(str::to_string).collect()),
+                ..Default::default()
+            }));
+        }
+        // podman calls the docker `Dns` and `ExtraHosts` options `dns_server` and `hostadd`
+        if let Some(dns) = self.dns {
+            builder = builder.dns_server(dns);
+        }
+        if let Some(extra_hosts) = self.extra_hosts {
+            builder = builder.hostadd(extra_hosts);
+        }
 
         builder.build()
     }
@@ -175,6 +336,8 @@ pub struct ExecOpts<'opts> {
     user: Option<&'opts str>,
     working_dir: Option<&'opts Path>,
     env: Option<Env>,
+    /// Positional parameters of the command, available as `$1`, `$2`... in it.
+    args: Vec<&'opts str>,
 }
 
 impl<'opts> Default for ExecOpts<'opts> {
@@ -189,6 +352,7 @@ impl<'opts> Default for ExecOpts<'opts> {
             user: None,
             working_dir: None,
             env: None,
+            args: vec![],
         }
     }
 }
@@ -240,13 +404,31 @@ impl<'opts> ExecOpts<'opts> {
         self
     }
 
+    /// Passes `args` to the command as positional parameters so that values like file names
+    /// can be used as `"$1"` without being interpreted by the shell.
+    pub fn args(mut self, args: impl IntoIterator<Item = &'opts str>) -> Self {
+        self.args.extend(args);
+        self
+    }
+
+    /// Returns the command line of the exec, the shell is also the name of the script `$0` so
+    /// that the arguments start at `$1`.
+    fn command(&self) -> Vec<&'opts str> {
+        let mut command = vec![self.shell, "-c", self.cmd];
+        if !self.args.is_empty() {
+            command.push(self.shell);
+            command.extend(&self.args);
+        }
+        command
+    }
+
     pub fn build_docker(self) -> docker_api::opts::ExecCreateOpts {
         let mut builder = docker_api::opts::ExecCreateOpts::builder();
 
         trace!("{:?}", self);
 
         builder = builder
-            .command(vec![self.shell, "-c", self.cmd])
+            .command(self.command())
             .tty(self.allocate_tty)
             .attach_stdout(self.attach_stdout)
             .attach_stderr(self.attach_stderr)
@@ -274,7 +456,7 @@ impl<'opts> ExecOpts<'opts> {
         trace!("{:?}", self);
 
         builder = builder
-            .command(vec![self.shell, "-c", self.cmd])
+            .command(self.command())
             .tty(self.allocate_tty)
             .attach_stdout(self.attach_stdout)
             .attach_stderr(self.attach_stderr)
@@ -299,7 +481,13 @@ impl<'opts> ExecOpts<'opts> {
 #[async_trait]
 pub trait Container {
     fn id(&self) -> &str;
-    async fn spawn(&mut self, opts: &CreateOpts, logger: &mut BoxedCollector) -> Result<()>;
+    /// Creates the container and starts it.
+    async fn spawn(&mut self, opts: &CreateOpts, logger: &mut BoxedCollector) -> Result<()> {
+        self.create(opts, logger).await?;
+        self.start(logger).await
+    }
+    async fn create(&mut self, opts: &CreateOpts, logger: &mut BoxedCollector) -> Result<()>;
+    async fn start(&self, logger: &mut BoxedCollector) -> Result<()>;
     async fn remove(&self, logger: &mut BoxedCollector) -> Result<()>;
     async fn exec<'cmd>(
         &self,
@@ -313,6 +501,9 @@ pub trait Container {
         logger: &mut BoxedCollector,
     ) -> Result<Output<u8>>;
     async fn copy_from(&self, path: &Path, logger: &mut BoxedCollector) -> Result<Vec<u8>>;
+    /// Copies files from the `path` in the container as a stream of archive chunks that is only
+    /// read as it is consumed.
+    fn copy_from_stream<'a>(&'a self, path: &'a Path) -> ChunkStream<'a>;
     async fn download_files(
         &self,
         source: &Path,
@@ -340,3 +531,98 @@ pub trait Container {
         logger: &mut BoxedCollector,
     ) -> Result<()>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_privileges_to_create_opts() {
+        let opts = CreateOpts::new("rocky")
+            .privileged(true)
+            .cap_add(["SYS_ADMIN", "MKNOD"]);
+        assert!(opts.privileged);
+        assert_eq!(
+            opts.cap_add.as_deref(),
+            Some(&["SYS_ADMIN".to_string(), "MKNOD".to_string()][..])
+        );
+
+        let docker = opts.build_docker().serialize().unwrap();
+        assert!(docker.contains(r#""Privileged":true"#));
+        assert!(docker.contains(r#""CapAdd":["SYS_ADMIN","MKNOD"]"#));
+
+        let unprivileged = CreateOpts::new("rocky").build_docker().serialize().unwrap();
+        assert!(!unprivileged.contains("Privileged"));
+        assert!(!unprivileged.contains("CapAdd"));
+    }
+
+    #[test]
+    fn passes_read_only_rootfs_to_create_opts() {
+        let opts = CreateOpts::new("rocky")
+            .read_only_rootfs(true)
+            .tmpfs(["/tmp/pkger-build", "/tmp/pkger-home"]);
+        assert!(opts.read_only_rootfs);
+        assert_eq!(
+            opts.tmpfs.as_deref(),
+            Some(
+                &[
+                    "/tmp/pkger-build".to_string(),
+                    "/tmp/pkger-home".to_string()
+                ][..]
+            )
+        );
+
+        let docker = opts.build_docker().serialize().unwrap();
+        assert!(docker.contains(r#""ReadonlyRootfs":true"#));
+        assert!(docker.contains(r#""/tmp/pkger-build":"rw,exec,mode=1777""#));
+        assert!(docker.contains(r#""/tmp/pkger-home":"rw,exec,mode=1777""#));
+
+        let writable = CreateOpts::new("rocky").build_docker().serialize().unwrap();
+        assert!(!writable.contains("ReadonlyRootfs"));
+        assert!(!writable.contains("Tmpfs"));
+    }
+
+    #[test]
+    fn passes_dns_and_hosts_to_create_opts() {
+        let opts = CreateOpts::new("rocky")
+            .dns(["10.0.0.53"])
+            .extra_hosts(["git.internal:10.0.0.2", "mirror.internal:10.0.0.3"]);
+        assert_eq!(opts.dns.as_deref(), Some(&["10.0.0.53".to_string()][..]));
+
+        let docker = opts.clone().build_docker().serialize().unwrap();
+        assert!(docker.contains(r#""Dns":["10.0.0.53"]"#));
+        assert!(
+            docker.contains(r#""ExtraHosts":["git.internal:10.0.0.2","mirror.internal:10.0.0.3"]"#)
+        );
+
+        let podman = opts.build_podman().serialize().unwrap();
+        assert!(podman.contains(r#""dns_server":["10.0.0.53"]"#));
+        assert!(
+            podman.contains(r#""hostadd":["git.internal:10.0.0.2","mirror.internal:10.0.0.3"]"#)
+        );
+
+        let docker = CreateOpts::new("rocky").build_docker().serialize().unwrap();
+        assert!(!docker.contains("Dns"));
+        assert!(!docker.contains("ExtraHosts"));
+    }
+
+    #[test]
+    fn passes_args_of_exec_as_positional_parameters() {
+        let opts = ExecOpts::new().cmd("echo 123");
+        assert_eq!(opts.command(), vec!["/bin/sh", "-c", "echo 123"]);
+
+        let opts = ExecOpts::new()
+            .cmd(r#"rm -f "$1""#)
+            .args(["file'; reboot; '"]);
+        assert_eq!(
+            opts.command(),
+            vec![
+                "/bin/sh",
+                "-c",
+                r#"rm -f "$1""#,
+                "/bin/sh",
+                "file'; reboot; '"
+            ]
+        );
+    }
+}
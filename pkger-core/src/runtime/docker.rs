@@ -1,7 +1,7 @@
-use crate::archive::{create_tarball, unpack_tarball};
+use crate::archive::{create_tarball, unpack_stream};
 use crate::log::{debug, error, info, trace, BoxedCollector};
-use crate::runtime::container::{truncate, Container, CreateOpts, ExecOpts, Output};
-use crate::{unix_timestamp, ErrContext, Result};
+use crate::runtime::container::{truncate, ChunkStream, Container, CreateOpts, ExecOpts, Output};
+use crate::{unix_timestamp, ErrContext, Error, Result};
 
 use async_trait::async_trait;
 use docker_api::{
@@ -49,7 +49,7 @@ impl Container for DockerContainer {
         truncate(self.container.id().as_ref())
     }
 
-    async fn spawn(&mut self, opts: &CreateOpts, logger: &mut BoxedCollector) -> Result<()> {
+    async fn create(&mut self, opts: &CreateOpts, logger: &mut BoxedCollector) -> Result<()> {
         let container = self
             .docker
             .containers()
@@ -57,10 +57,13 @@ impl Container for DockerContainer {
             .await?
             .id()
             .to_owned();
-        info!(logger => "spawning container {}", self.id());
         self.container = self.docker.containers().get(container);
         info!(logger => "created container {}", self.id());
 
+        Ok(())
+    }
+
+    async fn start(&self, logger: &mut BoxedCollector) -> Result<()> {
         self.container.start().await?;
         info!(logger => "started container {}", self.id());
 
@@ -161,11 +164,13 @@ impl Container for DockerContainer {
         logger: &mut BoxedCollector,
     ) -> Result<()> {
         info!(logger => "downloading files from container {}, source: {}, destination: {}", self.id(), source.display(), dest.display());
-        let files = self.copy_from(source, logger).await?;
-
-        let mut archive = tar::Archive::new(&files[..]);
+        unpack_stream(self.copy_from_stream(source), dest, logger)
+            .await
+            .context("failed to copy from container")
+    }
 
-        unpack_tarball(&mut archive, dest, logger)
+    fn copy_from_stream<'a>(&'a self, path: &'a Path) -> ChunkStream<'a> {
+        Box::pin(self.inner().copy_from(path).map_err(Error::from))
     }
 
     async fn upload_files<'files>(
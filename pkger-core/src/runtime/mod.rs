@@ -1,11 +1,13 @@
 pub mod container;
 pub mod docker;
 pub mod podman;
+pub mod retry;
 
 pub use docker::DockerContainer;
 pub use docker_api;
 pub use podman::PodmanContainer;
 pub use podman_api;
+pub use retry::{RetryPolicy, RetryingContainer};
 
 use crate::{ErrContext, Result};
 
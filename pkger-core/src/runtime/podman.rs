@@ -1,7 +1,7 @@
-use crate::archive::{create_tarball, unpack_tarball};
+use crate::archive::{create_tarball, unpack_stream};
 use crate::log::{debug, error, info, trace, BoxedCollector};
-use crate::runtime::container::{truncate, Container, CreateOpts, ExecOpts, Output};
-use crate::{ErrContext, Result};
+use crate::runtime::container::{truncate, ChunkStream, Container, CreateOpts, ExecOpts, Output};
+use crate::{ErrContext, Error, Result};
 
 use async_trait::async_trait;
 use futures::{StreamExt, TryStreamExt};
@@ -19,6 +19,15 @@ pub static PODMAN_SOCK: &str = "unix:///run/user/1000/podman/podman.sock";
 #[cfg(not(unix))]
 pub static PODMAN_SOCK: &str = "tcp://127.0.0.1:8080";
 
+/// Returns the uri of the socket of a rootless Podman service running in the `runtime_dir`
+/// of the user, usually `$XDG_RUNTIME_DIR`.
+pub fn rootless_sock(runtime_dir: &Path) -> String {
+    format!(
+        "unix://{}",
+        runtime_dir.join("podman").join("podman.sock").display()
+    )
+}
+
 /// Wrapper type that allows easier manipulation of Podman containers
 pub struct PodmanContainer {
     container: podman_api::api::Container,
@@ -44,14 +53,17 @@ impl Container for PodmanContainer {
         truncate(self.container.id().as_ref())
     }
 
-    async fn spawn(&mut self, opts: &CreateOpts, logger: &mut BoxedCollector) -> Result<()> {
+    async fn create(&mut self, opts: &CreateOpts, logger: &mut BoxedCollector) -> Result<()> {
         let opts = opts.clone().build_podman();
         let container = self.podman.containers().create(&opts).await?.id;
 
-        info!(logger => "spawning container {}", self.id());
         self.container = self.podman.containers().get(container);
         info!(logger => "created container {}", self.id());
 
+        Ok(())
+    }
+
+    async fn start(&self, logger: &mut BoxedCollector) -> Result<()> {
         self.container.start(None).await?;
         info!(logger => "started container {}", self.id());
 
@@ -164,11 +176,13 @@ impl Container for PodmanContainer {
         logger: &mut BoxedCollector,
     ) -> Result<()> {
         info!(logger => "downloading files from container {}, source: {}, destination: {}", self.id(), source.display(), dest.display());
-        let files = self.copy_from(source, logger).await?;
-
-        let mut archive = tar::Archive::new(&files[..]);
+        unpack_stream(self.copy_from_stream(source), dest, logger)
+            .await
+            .context("failed to copy from container")
+    }
 
-        unpack_tarball(&mut archive, dest, logger)
+    fn copy_from_stream<'a>(&'a self, path: &'a Path) -> ChunkStream<'a> {
+        Box::pin(self.inner().copy_from(path).map_err(Error::from))
     }
 
     async fn upload_files<'files>(
@@ -0,0 +1,299 @@
+use crate::log::{warning, BoxedCollector};
+use crate::runtime::container::{ChunkStream, Container, CreateOpts, ExecOpts, Output};
+use anyhow::{Error, Result};
+
+use async_trait::async_trait;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Decides how many times calls to the container runtime are attempted and how long to wait
+/// between the attempts. The wait doubles after every failed attempt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Creates a policy attempting calls at most `max_attempts` times, 0 is treated as 1.
+    pub fn new(max_attempts: u32, initial_backoff: Duration) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            initial_backoff,
+        }
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Returns the time to wait after the failed `attempt`, attempts are counted from 1.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+    }
+}
+
+/// Returns `true` if the error is caused by a network fault like a timeout or a reset connection
+/// of the connection to the runtime. Only the types of errors in the chain are inspected, errors
+/// reported by the runtime itself and commands exiting with a non-zero code are never transient.
+pub fn is_transient(err: &Error) -> bool {
+    err.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<io::Error>() {
+            matches!(
+                e.kind(),
+                io::ErrorKind::TimedOut
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::UnexpectedEof
+            )
+        } else if let Some(e) = cause.downcast_ref::<hyper::Error>() {
+            e.is_timeout() || e.is_connect() || e.is_incomplete_message() || e.is_closed()
+        } else {
+            false
+        }
+    })
+}
+
+/// Keeps track of attempts of a call made with a [RetryPolicy](RetryPolicy).
+#[derive(Debug)]
+pub struct Attempts {
+    policy: RetryPolicy,
+    attempt: u32,
+}
+
+impl Attempts {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self { policy, attempt: 1 }
+    }
+
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Returns `true` if the call that failed with `err` should be attempted again.
+    pub fn should_retry(&self, err: &Error) -> bool {
+        self.attempt < self.policy.max_attempts && is_transient(err)
+    }
+
+    /// Waits before the next attempt of the call described by `what`.
+    pub async fn wait(&mut self, what: &str, err: &Error, logger: &mut BoxedCollector) {
+        let backoff = self.policy.backoff(self.attempt);
+        warning!(logger => "{} failed on attempt {}/{}, retrying in {:?}, reason: {:?}", what, self.attempt, self.policy.max_attempts, backoff, err);
+        tokio::time::sleep(backoff).await;
+        self.attempt += 1;
+    }
+}
+
+/// Awaits the `call` expression until it succeeds, fails with an error that isn't transient or
+/// runs out of attempts of the `policy`. The expression is evaluated again for every attempt.
+macro_rules! retry {
+    ($policy:expr, $what:expr, $logger:expr, $call:expr) => {{
+        let mut attempts = $crate::runtime::retry::Attempts::new($policy);
+        loop {
+            let result = $call.await;
+            match result {
+                Err(e) if attempts.should_retry(&e) => attempts.wait($what, &e, $logger).await,
+                result => break result,
+            }
+        }
+    }};
+}
+pub(crate) use retry;
+
+/// Container that retries creating and starting the container when it fails with transient
+/// errors of the runtime. Execs and copies aren't idempotent, a script that already ran could run
+/// twice, so they are never retried.
+pub struct RetryingContainer {
+    inner: Box<dyn Container + Send + Sync>,
+    policy: RetryPolicy,
+}
+
+impl RetryingContainer {
+    pub fn new(inner: Box<dyn Container + Send + Sync>, policy: RetryPolicy) -> Self {
+        Self { inner, policy }
+    }
+}
+
+#[async_trait]
+impl Container for RetryingContainer {
+    fn id(&self) -> &str {
+        self.inner.id()
+    }
+
+    async fn create(&mut self, opts: &CreateOpts, logger: &mut BoxedCollector) -> Result<()> {
+        retry!(
+            self.policy,
+            "creating container",
+            logger,
+            self.inner.create(opts, logger)
+        )
+    }
+
+    async fn start(&self, logger: &mut BoxedCollector) -> Result<()> {
+        retry!(
+            self.policy,
+            "starting container",
+            logger,
+            self.inner.start(logger)
+        )
+    }
+
+    async fn remove(&self, logger: &mut BoxedCollector) -> Result<()> {
+        self.inner.remove(logger).await
+    }
+
+    async fn exec<'cmd>(
+        &self,
+        opts: &ExecOpts,
+        logger: &mut BoxedCollector,
+    ) -> Result<Output<String>> {
+        self.inner.exec(opts, logger).await
+    }
+
+    async fn logs(
+        &self,
+        stdout: bool,
+        stderr: bool,
+        logger: &mut BoxedCollector,
+    ) -> Result<Output<u8>> {
+        self.inner.logs(stdout, stderr, logger).await
+    }
+
+    async fn copy_from(&self, path: &Path, logger: &mut BoxedCollector) -> Result<Vec<u8>> {
+        self.inner.copy_from(path, logger).await
+    }
+
+    fn copy_from_stream<'a>(&'a self, path: &'a Path) -> ChunkStream<'a> {
+        self.inner.copy_from_stream(path)
+    }
+
+    async fn download_files(
+        &self,
+        source: &Path,
+        dest: &Path,
+        logger: &mut BoxedCollector,
+    ) -> Result<()> {
+        self.inner.download_files(source, dest, logger).await
+    }
+
+    async fn upload_files<'files>(
+        &self,
+        files: Vec<(&Path, &'files [u8])>,
+        destination: &Path,
+        logger: &mut BoxedCollector,
+    ) -> Result<()> {
+        self.inner.upload_files(files, destination, logger).await
+    }
+
+    async fn upload_archive(
+        &self,
+        tarball: Vec<u8>,
+        destination: &Path,
+        archive_name: &str,
+        logger: &mut BoxedCollector,
+    ) -> Result<PathBuf> {
+        self.inner
+            .upload_archive(tarball, destination, archive_name, logger)
+            .await
+    }
+
+    async fn upload_and_extract_archive(
+        &self,
+        tarball: Vec<u8>,
+        destination: &Path,
+        archive_name: &str,
+        logger: &mut BoxedCollector,
+    ) -> Result<()> {
+        self.inner
+            .upload_and_extract_archive(tarball, destination, archive_name, logger)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use std::cell::Cell;
+
+    #[test]
+    fn recognizes_transient_errors() {
+        let timeout = Error::new(io::Error::new(io::ErrorKind::TimedOut, "no response"));
+        assert!(is_transient(&timeout));
+        assert!(is_transient(
+            &Error::new(io::Error::from(io::ErrorKind::ConnectionReset))
+                .context("failed to pull image")
+        ));
+        // only the type of the error matters, not the message
+        assert!(!is_transient(&anyhow!(
+            "failed to pull image 'rockylinux:9': net/http: TLS handshake timeout"
+        )));
+        assert!(!is_transient(&anyhow!(
+            "command failed with exit code 1\nError:\ntimeout: sending signal to make"
+        )));
+        assert!(!is_transient(&Error::new(io::Error::from(
+            io::ErrorKind::NotFound
+        ))));
+    }
+
+    #[test]
+    fn doubles_backoff() {
+        let policy = RetryPolicy::new(4, Duration::from_millis(500));
+        assert_eq!(policy.backoff(1), Duration::from_millis(500));
+        assert_eq!(policy.backoff(2), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(2));
+        assert_eq!(RetryPolicy::new(0, Duration::ZERO).max_attempts(), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_only_transient_errors() {
+        let mut logger: BoxedCollector = Box::new(crate::log::Logger::stdout(None));
+        let policy = RetryPolicy::new(3, Duration::ZERO);
+
+        let calls = Cell::new(0);
+        let flaky = || {
+            calls.set(calls.get() + 1);
+            let call = calls.get();
+            async move {
+                if call < 3 {
+                    Err(Error::new(io::Error::from(io::ErrorKind::ConnectionReset)))
+                } else {
+                    Ok(call)
+                }
+            }
+        };
+        let result: Result<u32> = retry!(policy, "exec", &mut logger, flaky());
+        assert_eq!(result.unwrap(), 3);
+
+        calls.set(0);
+        let failing = || {
+            calls.set(calls.get() + 1);
+            async { Err::<(), _>(Error::new(io::Error::from(io::ErrorKind::TimedOut))) }
+        };
+        assert!(retry!(policy, "exec", &mut logger, failing()).is_err());
+        assert_eq!(calls.get(), 3);
+
+        calls.set(0);
+        let broken = || {
+            calls.set(calls.get() + 1);
+            async { Err::<(), _>(anyhow!("command failed with exit code 2")) }
+        };
+        assert!(retry!(policy, "exec", &mut logger, broken()).is_err());
+        assert_eq!(calls.get(), 1);
+    }
+}
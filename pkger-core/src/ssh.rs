@@ -7,7 +7,7 @@ use {crate::ErrContext, std::env};
 
 pub const SOCK_ENV: &str = "SSH_AUTH_SOCK";
 
-#[derive(Clone, Deserialize, Debug, Serialize)]
+#[derive(Clone, Default, Deserialize, Debug, Serialize)]
 pub struct SshConfig {
     #[serde(default)]
     pub forward_agent: bool,
@@ -15,12 +15,9 @@ impl<'text> Lexer<'text> {
     }
 
     fn nth(&self, n: usize) -> Option<char> {
-        if self.pos < self.text.len() {
-            // This is much faster than self.text.chars().nth()
-            self.text[n..n + 1].chars().next()
-        } else {
-            None
-        }
+        // This is much faster than self.text.chars().nth(). Bytes of multibyte characters are
+        // never ASCII so they can't be mistaken for any of the characters the lexer looks for.
+        self.text.as_bytes().get(n).map(|byte| *byte as char)
     }
 
     fn next_pos(&mut self) -> bool {
@@ -45,7 +42,12 @@ impl<'text> Lexer<'text> {
     }
 
     fn parse_token(&mut self) -> Token {
-        if self.cur() == '$' {
+        if self.cur() == '$' && self.peek() == Some('$') {
+            // `$$` is an escaped `$`
+            self.next_pos();
+            self.next_pos();
+            Token::Text(&self.text[self.pos - 1..self.pos])
+        } else if self.cur() == '$' {
             self.next_pos();
             self.parse_variable()
         } else if self.is_eof() {
@@ -80,12 +82,46 @@ impl<'text> Lexer<'text> {
                     &self.text[var_start..self.pos],
                     self.text[var_start + 2..self.pos - 1].trim(),
                 ));
+            } else if cur == ':' && self.peek() == Some('-') {
+                return self.parse_default(var_start);
             } else if !Variable::is_valid_name_char(cur) || !self.next_pos() {
                 return Token::Text(&self.text[var_start..self.pos]);
             }
         }
     }
 
+    /// Parses the `:-default` part of a braced variable like `${VAR:-default}`. The default
+    /// value ends at the brace matching the opening one.
+    fn parse_default(&mut self, var_start: usize) -> Token {
+        let name_end = self.pos;
+        self.next_pos();
+        self.next_pos();
+        let default_start = self.pos;
+
+        let mut depth = 0;
+        while !self.is_eof() {
+            match self.cur() {
+                '{' => depth += 1,
+                '}' if depth == 0 => {
+                    let default_end = self.pos;
+                    self.next_pos();
+                    return Token::Variable(
+                        Variable::new(
+                            &self.text[var_start..self.pos],
+                            self.text[var_start + 2..name_end].trim(),
+                        )
+                        .with_default(&self.text[default_start..default_end]),
+                    );
+                }
+                '}' => depth -= 1,
+                _ => {}
+            }
+            self.next_pos();
+        }
+
+        Token::Text(&self.text[var_start..self.pos])
+    }
+
     fn parse_unbraced_variable(&mut self) -> Token {
         let var_start = self.pos - 1;
         loop {
@@ -217,6 +253,46 @@ mod tests {
         assert_eq!(parser.next_token(), Token::EOF);
     }
 
+    #[test]
+    fn defaults() {
+        let text = "make -j${JOBS:-4} ${ A:-} ${B:-{x}} ${C:-${D:-y}}${E:-unclosed";
+        let mut parser = Lexer::new(text);
+        assert_eq!(parser.next_token(), Token::Text("make -j"));
+        assert_eq!(
+            parser.next_token(),
+            Token::Variable(Variable::new("${JOBS:-4}", "JOBS").with_default("4"))
+        );
+        assert_eq!(parser.next_token(), Token::Text(" "));
+        assert_eq!(
+            parser.next_token(),
+            Token::Variable(Variable::new("${ A:-}", "A").with_default(""))
+        );
+        assert_eq!(parser.next_token(), Token::Text(" "));
+        assert_eq!(
+            parser.next_token(),
+            Token::Variable(Variable::new("${B:-{x}}", "B").with_default("{x}"))
+        );
+        assert_eq!(parser.next_token(), Token::Text(" "));
+        assert_eq!(
+            parser.next_token(),
+            Token::Variable(Variable::new("${C:-${D:-y}}", "C").with_default("${D:-y}"))
+        );
+        assert_eq!(parser.next_token(), Token::Text("${E:-unclosed"));
+        assert_eq!(parser.next_token(), Token::EOF);
+    }
+
+    #[test]
+    fn escaped_dollar() {
+        let text = "echo $$ $${HOME}ż";
+        let mut parser = Lexer::new(text);
+        assert_eq!(parser.next_token(), Token::Text("echo "));
+        assert_eq!(parser.next_token(), Token::Text("$"));
+        assert_eq!(parser.next_token(), Token::Text(" "));
+        assert_eq!(parser.next_token(), Token::Text("$"));
+        assert_eq!(parser.next_token(), Token::Text("{HOME}ż"));
+        assert_eq!(parser.next_token(), Token::EOF);
+    }
+
     #[test]
     fn empty_text() {
         let text = "";
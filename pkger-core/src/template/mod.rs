@@ -6,11 +6,26 @@ mod lexer;
 pub struct Variable<'text> {
     text: &'text str,
     name: &'text str,
+    default: Option<&'text str>,
 }
 
 impl<'text> Variable<'text> {
     pub fn new(text: &'text str, name: &'text str) -> Self {
-        Self { text, name }
+        Self {
+            text,
+            name,
+            default: None,
+        }
+    }
+
+    /// Sets the value used when the variable is not defined like in `${VAR:-default}`.
+    pub fn with_default(mut self, default: &'text str) -> Self {
+        self.default = Some(default);
+        self
+    }
+
+    pub fn default(&self) -> Option<&str> {
+        self.default
     }
 
     pub fn name(&self) -> &str {
@@ -47,6 +62,9 @@ where
             Token::Variable(var) => {
                 if let Some(value) = vars.get(var.name()) {
                     rendered.push_str(value.as_ref());
+                } else if let Some(default) = var.default() {
+                    // the default value can reference other variables
+                    rendered.push_str(&render(default, vars));
                 } else {
                     rendered.push_str(var.text());
                 }
@@ -90,4 +108,38 @@ mod tests {
             "cd $TEST_VAR//tmp/test/pkger-test/0.1.0$DOESNT_EXIST".to_string()
         );
     }
+
+    #[test]
+    fn renders_defaults() {
+        let text = "make -j${JOBS:-4} ${CC:-} ${PREFIX:-${PKGER_BLD_DIR}/{usr}} ${RECIPE:-none}";
+        let mut vars = HashMap::new();
+        vars.insert("PKGER_BLD_DIR".to_string(), "/tmp/test".to_string());
+        vars.insert("RECIPE".to_string(), "pkger-test".to_string());
+
+        assert_eq!(
+            render(text, &vars),
+            "make -j4  /tmp/test/{usr} pkger-test".to_string()
+        );
+    }
+
+    #[test]
+    fn renders_escaped_dollar() {
+        let mut vars = HashMap::new();
+        vars.insert("PID".to_string(), "1".to_string());
+
+        assert_eq!(render("echo $$PID $PID $$$PID", &vars), "echo $PID 1 $1");
+    }
+
+    #[test]
+    fn defers_escaped_vars_to_shell() {
+        let text = "cd ${PKGER_BLD_DIR} && echo $${FOO} $$FOO > $${PKGER_BLD_DIR}/foo";
+        let mut vars = HashMap::new();
+        vars.insert("PKGER_BLD_DIR".to_string(), "/tmp/test".to_string());
+        vars.insert("FOO".to_string(), "bar".to_string());
+
+        assert_eq!(
+            render(text, &vars),
+            "cd /tmp/test && echo ${FOO} $FOO > ${PKGER_BLD_DIR}/foo".to_string()
+        );
+    }
 }